@@ -50,6 +50,29 @@ class BlockInputState {
   static RxBool find(String id) => Get.find<RxBool>(tag: tag(id));
 }
 
+class MacroRecordingState {
+  static String tag(String id) => 'macro_recording_$id';
+
+  static void init(String id) {
+    final key = tag(id);
+    if (!Get.isRegistered(tag: key)) {
+      final RxBool state = false.obs;
+      Get.put(state, tag: key);
+    } else {
+      Get.find<RxBool>(tag: key).value = false;
+    }
+  }
+
+  static void delete(String id) {
+    final key = tag(id);
+    if (Get.isRegistered(tag: key)) {
+      Get.delete(tag: key);
+    }
+  }
+
+  static RxBool find(String id) => Get.find<RxBool>(tag: tag(id));
+}
+
 class CurrentDisplayState {
   static String tag(String id) => 'current_display_$id';
 
@@ -311,23 +334,27 @@ class UnreadChatCountState {
 initSharedStates(String id) {
   PrivacyModeState.init(id);
   BlockInputState.init(id);
+  MacroRecordingState.init(id);
   CurrentDisplayState.init(id);
   KeyboardEnabledState.init(id);
   ShowRemoteCursorState.init(id);
   RemoteCursorMovedState.init(id);
   FingerprintState.init(id);
   PeerBoolOption.init(id, 'zoom-cursor', () => false);
+  PeerBoolOption.init(id, 'forward-touch', () => false);
   UnreadChatCountState.init(id);
 }
 
 removeSharedStates(String id) {
   PrivacyModeState.delete(id);
   BlockInputState.delete(id);
+  MacroRecordingState.delete(id);
   CurrentDisplayState.delete(id);
   ShowRemoteCursorState.delete(id);
   KeyboardEnabledState.delete(id);
   RemoteCursorMovedState.delete(id);
   FingerprintState.delete(id);
   PeerBoolOption.delete(id, 'zoom-cursor');
+  PeerBoolOption.delete(id, 'forward-touch');
   UnreadChatCountState.delete(id);
 }
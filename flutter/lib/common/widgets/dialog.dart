@@ -1,4 +1,5 @@
 import 'dart:async';
+import 'dart:convert';
 
 import 'package:debounce_throttle/debounce_throttle.dart';
 import 'package:flutter/material.dart';
@@ -1166,6 +1167,111 @@ showAuditDialog(FFI ffi) async {
   });
 }
 
+void showSaveMacroDialog(String text, OverlayDialogManager dialogManager) {
+  final controller = TextEditingController();
+  var errMsg = '';
+  dialogManager.show((setState, close, context) {
+    submit() async {
+      final err =
+          await bind.mainSaveMacro(name: controller.text.trim(), text: text);
+      if (err.isEmpty) {
+        close();
+      } else {
+        setState(() => errMsg = err);
+      }
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Save macro')),
+      content: TextField(
+        autofocus: true,
+        decoration: InputDecoration(
+            labelText: translate('Name'),
+            errorText: errMsg.isEmpty ? null : errMsg),
+        controller: controller,
+        onChanged: (_) => setState(() => errMsg = ''),
+      ),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        dialogButton('OK', onPressed: submit),
+      ],
+      onSubmit: submit,
+      onCancel: close,
+    );
+  });
+}
+
+void showPlayMacroDialog(
+    SessionID sessionId, OverlayDialogManager dialogManager) async {
+  final names = List<String>.from(jsonDecode(await bind.mainGetMacros()));
+  final playing = bind.sessionIsMacroPlaying(sessionId: sessionId);
+  String? name = names.isEmpty ? null : names.first;
+  double speed = 1;
+  var errMsg = '';
+  dialogManager.show((setState, close, context) {
+    submit() async {
+      if (name == null) return;
+      final text = await bind.mainLoadMacro(name: name!);
+      final err = await bind.sessionPlayMacro(
+          sessionId: sessionId, text: text, vars: '{}', speed: speed);
+      if (err.isEmpty) {
+        close();
+      } else {
+        setState(() => errMsg = err);
+      }
+    }
+
+    stop() {
+      bind.sessionStopMacro(sessionId: sessionId);
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Play macro')),
+      content: Column(
+        mainAxisSize: MainAxisSize.min,
+        crossAxisAlignment: CrossAxisAlignment.start,
+        children: [
+          DropdownButton<String>(
+            value: name,
+            isExpanded: true,
+            items: names
+                .map((e) => DropdownMenuItem(value: e, child: Text(e)))
+                .toList(),
+            onChanged: (v) => setState(() {
+              name = v;
+              errMsg = '';
+            }),
+          ),
+          Row(children: [
+            Text(translate('Speed')),
+            Expanded(
+                child: Slider(
+              value: speed,
+              min: 0.25,
+              max: 4,
+              divisions: 15,
+              onChanged: (v) => setState(() => speed = v),
+            )),
+            Text('${speed.toStringAsFixed(2)}x'),
+          ]),
+          Offstage(
+              offstage: errMsg.isEmpty,
+              child: Text(errMsg, style: const TextStyle(color: Colors.red))),
+        ],
+      ),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        if (playing)
+          dialogButton('Stop macro', onPressed: stop, isOutline: true),
+        dialogButton('OK', onPressed: name == null ? null : submit),
+      ],
+      onSubmit: submit,
+      onCancel: close,
+    );
+  });
+}
+
 void showConfirmSwitchSidesDialog(
     SessionID sessionId, String id, OverlayDialogManager dialogManager) async {
   dialogManager.show((setState, close, context) {
@@ -218,12 +218,36 @@ List<TTextMenu> toolbarControls(BuildContext context, String id, FFI ffi) {
         child: Text(translate('Refresh')),
         onPressed: () => bind.sessionRefresh(sessionId: sessionId)));
   }
+  // macros
+  if (!ffiModel.viewOnly && ffiModel.keyboard) {
+    v.add(TTextMenu(
+        child: Obx(() => Text(translate(MacroRecordingState.find(id).value
+            ? 'Stop recording macro'
+            : 'Record macro'))),
+        onPressed: () async {
+          RxBool recording = MacroRecordingState.find(id);
+          if (recording.value) {
+            recording.value = false;
+            final text =
+                await bind.sessionStopMacroRecording(sessionId: sessionId);
+            showSaveMacroDialog(text, ffi.dialogManager);
+          } else {
+            bind.sessionStartMacroRecording(sessionId: sessionId);
+            recording.value = true;
+          }
+        }));
+    v.add(TTextMenu(
+        child: Text(translate('Play macro')),
+        onPressed: () => showPlayMacroDialog(sessionId, ffi.dialogManager)));
+  }
   // record
   var codecFormat = ffi.qualityMonitorModel.data.codecFormat;
   if (!isDesktop &&
       (ffi.recordingModel.start ||
           (perms["recording"] != false &&
-              (codecFormat == "VP8" || codecFormat == "VP9")))) {
+              (codecFormat == "VP8" ||
+                  codecFormat == "VP9" ||
+                  codecFormat == "AV1")))) {
     v.add(TTextMenu(
         child: Row(
           children: [
@@ -410,6 +434,24 @@ Future<List<TToggleMenu>> toolbarDisplayToggle(
       },
     ));
   }
+  // forward touch
+  if (ffiModel.keyboard) {
+    final option = 'forward-touch';
+    final peerState = PeerBoolOption.find(id, option);
+    final enabled = !ffiModel.viewOnly;
+    v.add(TToggleMenu(
+        child: Text(translate('Forward touch')),
+        value: peerState.value,
+        onChanged: enabled
+            ? (value) async {
+                if (value == null) return;
+                await bind.sessionToggleOption(
+                    sessionId: sessionId, value: option);
+                peerState.value = bind.sessionGetToggleOptionSync(
+                    sessionId: sessionId, arg: option);
+              }
+            : null));
+  }
   // show quality monitor
   final option = 'show-quality-monitor';
   v.add(TToggleMenu(
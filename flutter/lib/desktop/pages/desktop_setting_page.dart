@@ -404,6 +404,11 @@ class _GeneralState extends State<_General> {
       return _Card(title: 'Recording', children: [
         _OptionCheckBox(context, 'Automatically record incoming sessions',
             'allow-auto-record-incoming'),
+        Tooltip(
+          message: translate('record_event_text_tip'),
+          child: _OptionCheckBox(context, 'Record clipboard and chat text',
+              'allow-record-event-text'),
+        ),
         Row(
           children: [
             Text('${translate("Directory")}:'),
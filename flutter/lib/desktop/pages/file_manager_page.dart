@@ -164,6 +164,53 @@ class _FileManagerPageState extends State<FileManagerPage>
     );
   }
 
+  void _showRateLimitDialog(JobProgress item) {
+    final rate = TextEditingController(text: item.rateLimit.toString());
+    _ffi.dialogManager.show((setState, close, context) {
+      submit() {
+        final v = int.tryParse(rate.value.text.trim());
+        if (v != null && v >= 0) {
+          jobController.setJobRateLimit(item.id, v);
+          close();
+        }
+      }
+
+      cancel() => close(false);
+      return CustomAlertDialog(
+        title: Text(translate("Rate limit")),
+        content: Column(
+          mainAxisSize: MainAxisSize.min,
+          children: [
+            TextFormField(
+              decoration: InputDecoration(
+                labelText: translate("rate_limit_tip"),
+              ),
+              controller: rate,
+              keyboardType: TextInputType.number,
+              inputFormatters: [FilteringTextInputFormatter.digitsOnly],
+              autofocus: true,
+            ),
+          ],
+        ),
+        actions: [
+          dialogButton(
+            "Cancel",
+            icon: Icon(Icons.close_rounded),
+            onPressed: cancel,
+            isOutline: true,
+          ),
+          dialogButton(
+            "OK",
+            icon: Icon(Icons.done_rounded),
+            onPressed: submit,
+          ),
+        ],
+        onSubmit: submit,
+        onCancel: cancel,
+      );
+    });
+  }
+
   /// transfer status list
   /// watch transfer status
   Widget statusList() {
@@ -214,7 +261,9 @@ class _FileManagerPageState extends State<FileManagerPage>
                               Offstage(
                                 offstage: item.state != JobState.inProgress,
                                 child: Text(
-                                  '${translate("Speed")} ${readableFileSize(item.speed)}/s',
+                                  item.userPaused
+                                      ? translate("Pause")
+                                      : '${translate("Speed")} ${readableFileSize(item.speed)}/s',
                                   style: TextStyle(
                                     fontSize: 12,
                                     color: MyTheme.darkGray,
@@ -268,6 +317,41 @@ class _FileManagerPageState extends State<FileManagerPage>
                                 hoverColor: MyTheme.accent80,
                               ),
                             ),
+                            Offstage(
+                              offstage: item.state != JobState.inProgress,
+                              child: Row(
+                                children: [
+                                  MenuButton(
+                                    tooltip: translate(
+                                        item.userPaused ? "Resume" : "Pause"),
+                                    onPressed: () {
+                                      jobController.pauseJob(
+                                          item.id, !item.userPaused);
+                                    },
+                                    child: Icon(
+                                      item.userPaused
+                                          ? Icons.play_arrow_rounded
+                                          : Icons.pause_rounded,
+                                      color: Colors.white,
+                                      size: 18,
+                                    ),
+                                    color: MyTheme.accent,
+                                    hoverColor: MyTheme.accent80,
+                                  ),
+                                  MenuButton(
+                                    tooltip: translate("Rate limit"),
+                                    onPressed: () => _showRateLimitDialog(item),
+                                    child: Icon(
+                                      Icons.speed_rounded,
+                                      color: Colors.white,
+                                      size: 18,
+                                    ),
+                                    color: MyTheme.accent,
+                                    hoverColor: MyTheme.accent80,
+                                  ),
+                                ],
+                              ),
+                            ),
                             MenuButton(
                               padding: EdgeInsets.only(right: 15),
                               child: SvgPicture.asset(
@@ -806,6 +890,16 @@ class _FileManagerViewState extends State<FileManagerView> {
     );
   }
 
+  bool _singleSelected() =>
+      selectedItems.items.length == 1 && !selectedItems.items.first.isDrive;
+
+  void _moveSelected({bool copy = false}) {
+    if (_singleSelected()) {
+      controller.moveAction(selectedItems.items.first, copy: copy);
+      selectedItems.clear();
+    }
+  }
+
   Widget menu({bool isLocal = false}) {
     var menuPos = RelativeRect.fill;
 
@@ -833,6 +927,18 @@ class _FileManagerViewState extends State<FileManagerView> {
               Text(translate("Unselect All"), style: style),
           proc: () => selectedItems.clear(),
           padding: kDesktopMenuPadding,
+          dismissOnClicked: true),
+      MenuEntryButton(
+          childBuilder: (style) => Text(translate("Move"), style: style),
+          proc: () => _moveSelected(),
+          enabled: _singleSelected().obs,
+          padding: kDesktopMenuPadding,
+          dismissOnClicked: true),
+      MenuEntryButton(
+          childBuilder: (style) => Text(translate("Copy"), style: style),
+          proc: () => _moveSelected(copy: true),
+          enabled: _singleSelected().obs,
+          padding: kDesktopMenuPadding,
           dismissOnClicked: true)
     ];
 
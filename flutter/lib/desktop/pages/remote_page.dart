@@ -134,6 +134,9 @@ class _RemotePageState extends State<RemotePage>
         sessionId: sessionId, arg: 'show-remote-cursor');
     _zoomCursor.value = bind.sessionGetToggleOptionSync(
         sessionId: sessionId, arg: 'zoom-cursor');
+    PeerBoolOption.find(widget.id, 'forward-touch').value =
+        bind.sessionGetToggleOptionSync(
+            sessionId: sessionId, arg: 'forward-touch');
     DesktopMultiWindow.addListener(this);
     // if (!_isCustomCursorInited) {
     //   customCursorController.registerNeedUpdateCursorCallback(
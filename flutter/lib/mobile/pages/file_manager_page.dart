@@ -482,6 +482,14 @@ class _FileManagerViewState extends State<FileManagerView> {
                                   child: Text(translate("Delete")),
                                   value: "delete",
                                 ),
+                                PopupMenuItem(
+                                  child: Text(translate("Move")),
+                                  value: "move",
+                                ),
+                                PopupMenuItem(
+                                  child: Text(translate("Copy")),
+                                  value: "copy",
+                                ),
                                 PopupMenuItem(
                                   child: Text(translate("Multi Select")),
                                   value: "multi_select",
@@ -498,6 +506,11 @@ class _FileManagerViewState extends State<FileManagerView> {
                                 final items = SelectedItems(isLocal: isLocal);
                                 items.add(entries[index]);
                                 controller.removeAction(items);
+                              } else if (v == "move") {
+                                controller.moveAction(entries[index]);
+                              } else if (v == "copy") {
+                                controller.moveAction(entries[index],
+                                    copy: true);
                               } else if (v == "multi_select") {
                                 _selectedItems.clear();
                                 widget.selectMode.toggle(isLocal);
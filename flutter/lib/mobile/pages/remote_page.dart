@@ -65,6 +65,9 @@ class _RemotePageState extends State<RemotePage> {
     keyboardSubscription =
         keyboardVisibilityController.onChange.listen(onSoftKeyboardChanged);
     initSharedStates(widget.id);
+    PeerBoolOption.find(widget.id, 'forward-touch').value =
+        bind.sessionGetToggleOptionSync(
+            sessionId: sessionId, arg: 'forward-touch');
     gFFI.chatModel
         .changeCurrentKey(MessageKey(widget.id, ChatModel.clientModeID));
   }
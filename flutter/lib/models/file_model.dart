@@ -635,6 +635,66 @@ class FileController {
         path: path,
         isRemote: !isLocal);
   }
+
+  /// Move or copy [entry] to a path of the same side, asked for.
+  Future<void> moveAction(Entry entry, {bool copy = false}) async {
+    final newPath = TextEditingController(text: entry.path);
+    dialogManager?.show((setState, close, context) {
+      submit() {
+        final to = newPath.value.text.trim();
+        if (to.isNotEmpty && to != entry.path) {
+          if (copy) {
+            bind.sessionCopyFile(
+                sessionId: sessionId,
+                actId: JobController.jobID.next(),
+                path: entry.path,
+                newPath: to,
+                isRemote: !isLocal);
+          } else {
+            bind.sessionRenameFile(
+                sessionId: sessionId,
+                actId: JobController.jobID.next(),
+                path: entry.path,
+                newPath: to,
+                isRemote: !isLocal);
+          }
+        }
+        close();
+      }
+
+      cancel() => close(false);
+      return CustomAlertDialog(
+        title: Text(translate(copy ? "Copy" : "Move")),
+        content: Column(
+          mainAxisSize: MainAxisSize.min,
+          children: [
+            TextFormField(
+              decoration: InputDecoration(
+                labelText: translate("Please enter the new path"),
+              ),
+              controller: newPath,
+              autofocus: true,
+            ),
+          ],
+        ),
+        actions: [
+          dialogButton(
+            "Cancel",
+            icon: Icon(Icons.close_rounded),
+            onPressed: cancel,
+            isOutline: true,
+          ),
+          dialogButton(
+            "OK",
+            icon: Icon(Icons.done_rounded),
+            onPressed: submit,
+          ),
+        ],
+        onSubmit: submit,
+        onCancel: cancel,
+      );
+    });
+  }
 }
 
 class JobController {
@@ -769,6 +829,29 @@ class JobController {
     }
   }
 
+  void pauseJob(int jobId, bool paused) {
+    final jobIndex = getJob(jobId);
+    if (jobIndex != -1) {
+      final job = jobTable[jobIndex];
+      bind.sessionPauseJob(sessionId: sessionId, actId: job.id, paused: paused);
+      job.userPaused = paused;
+      job.speed = 0;
+      jobTable.refresh();
+    }
+  }
+
+  /// [rateLimit] in KiB/s, 0 for unlimited.
+  void setJobRateLimit(int jobId, int rateLimit) {
+    final jobIndex = getJob(jobId);
+    if (jobIndex != -1) {
+      final job = jobTable[jobIndex];
+      bind.sessionSetJobRateLimit(
+          sessionId: sessionId, actId: job.id, rateLimit: rateLimit);
+      job.rateLimit = rateLimit;
+      jobTable.refresh();
+    }
+  }
+
   void updateFolderFiles(Map<String, dynamic> evt) {
     // ret: "{\"id\":1,\"num_entries\":12,\"total_size\":1264822.0}"
     Map<String, dynamic> info = json.decode(evt['info']);
@@ -1027,6 +1110,9 @@ class JobProgress {
   var to = "";
   var showHidden = false;
   var err = "";
+  // paused by the user while in progress, and its rate limit in KiB/s
+  var userPaused = false;
+  var rateLimit = 0;
 
   clear() {
     state = JobState.none;
@@ -1040,6 +1126,8 @@ class JobProgress {
     remote = "";
     to = "";
     err = "";
+    userPaused = false;
+    rateLimit = 0;
   }
 
   String display() {
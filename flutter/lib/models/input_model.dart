@@ -12,6 +12,7 @@ import 'package:get/get.dart';
 import '../../models/model.dart';
 import '../../models/platform_model.dart';
 import '../common.dart';
+import '../common/shared_state.dart';
 import '../consts.dart';
 import './state_model.dart';
 
@@ -62,6 +63,10 @@ class InputModel {
   int _lastButtons = 0;
   Offset lastMousePos = Offset.zero;
 
+  // touch and pen
+  final Map<int, Map<String, int>> _touches = {};
+  var _penInRange = false;
+
   get id => parent.target?.id ?? "";
 
   late final SessionID sessionId;
@@ -329,6 +334,7 @@ class InputModel {
 
   void onPointHoverImage(PointerHoverEvent e) {
     _stopFling = true;
+    if (_handlePointer(e)) return;
     if (e.kind != ui.PointerDeviceKind.mouse) return;
     if (!isPhysicalMouse.value) {
       isPhysicalMouse.value = true;
@@ -464,12 +470,14 @@ class InputModel {
         isPhysicalMouse.value = false;
       }
     }
+    if (_handlePointer(e)) return;
     if (isPhysicalMouse.value) {
       handleMouse(getEvent(e, _kMouseEventDown));
     }
   }
 
   void onPointUpImage(PointerUpEvent e) {
+    if (_handlePointer(e)) return;
     if (e.kind != ui.PointerDeviceKind.mouse) return;
     if (isPhysicalMouse.value) {
       handleMouse(getEvent(e, _kMouseEventUp));
@@ -477,12 +485,123 @@ class InputModel {
   }
 
   void onPointMoveImage(PointerMoveEvent e) {
+    if (_handlePointer(e)) return;
     if (e.kind != ui.PointerDeviceKind.mouse) return;
     if (isPhysicalMouse.value) {
       handleMouse(getEvent(e, _kMouseEventMove));
     }
   }
 
+  void onPointCancelImage(PointerCancelEvent e) {
+    _handlePointer(e);
+  }
+
+  /// Sends [e] as it is if it comes from the pen, or from a finger while
+  /// 'forward-touch' is on. Returns false for the others.
+  bool _handlePointer(PointerEvent e) {
+    switch (e.kind) {
+      case ui.PointerDeviceKind.touch:
+        if (!PeerBoolOption.find(id, 'forward-touch').value) return false;
+        _handleTouch(e);
+        return true;
+      case ui.PointerDeviceKind.stylus:
+      case ui.PointerDeviceKind.invertedStylus:
+        _handlePen(e);
+        return true;
+      default:
+        return false;
+    }
+  }
+
+  void _handleTouch(PointerEvent e) {
+    if (e is PointerDownEvent) {
+      // fingers going down off the image, on the toolbars, are left out
+      if (_getPeerPoint(e) == null) return;
+    } else if (!_touches.containsKey(e.pointer)) {
+      return;
+    }
+    if (e is PointerUpEvent || e is PointerCancelEvent) {
+      _touches.remove(e.pointer);
+    } else {
+      final pos = _getPeerPoint(e, clamp: true)!;
+      _touches[e.pointer] = {
+        'id': e.pointer,
+        'x': pos.x,
+        'y': pos.y,
+        'pressure': _getPressure(e),
+      };
+    }
+    _sendPointer({
+      'touch': {'contacts': _touches.values.toList()}
+    });
+  }
+
+  void _handlePen(PointerEvent e) {
+    // a pen off the image is out of range, unless it is drawing off the edge
+    final pos = _getPeerPoint(e, clamp: e.down);
+    final inRange = pos != null && e is! PointerCancelEvent;
+    if (!inRange && !_penInRange) return;
+    _penInRange = inRange;
+    // the pen leans by tilt towards orientation, 0 being the top of the screen
+    final lean = sin(e.tilt);
+    final upright = cos(e.tilt);
+    final tiltX = atan2(lean * sin(e.orientation), upright) * 180 / pi;
+    final tiltY = atan2(-lean * cos(e.orientation), upright) * 180 / pi;
+    var buttons = 0;
+    if ((e.buttons & kPrimaryStylusButton) != 0) buttons |= 1;
+    if ((e.buttons & kSecondaryStylusButton) != 0) buttons |= 2;
+    _sendPointer({
+      'pen': {
+        'in_range': inRange,
+        'touching': inRange && e.down,
+        'x': pos?.x ?? 0,
+        'y': pos?.y ?? 0,
+        'pressure': e.down ? _getPressure(e) : 0,
+        'tilt_x': tiltX.round(),
+        'tilt_y': tiltY.round(),
+        'eraser': e.kind == ui.PointerDeviceKind.invertedStylus,
+        'buttons': buttons,
+      }
+    });
+  }
+
+  /// The pressure of [e] from 1 to 1000, 0 if the device has none.
+  int _getPressure(PointerEvent e) {
+    final range = e.pressureMax - e.pressureMin;
+    if (range <= 0) return 0;
+    final pressure = ((e.pressure - e.pressureMin) / range * 1000).round();
+    return min(max(pressure, 1), 1000);
+  }
+
+  /// The position of [e] on the remote display, null if it is off the display
+  /// and not [clamp]ed to its nearest edge.
+  Point<int>? _getPeerPoint(PointerEvent e, {bool clamp = false}) {
+    final pos = _canvasToPeer(e.position.dx - CanvasModel.leftToEdge,
+        e.position.dy - CanvasModel.topToEdge);
+    final d = parent.target!.ffiModel.display;
+    int minX = d.x.toInt();
+    int maxX = (d.x + d.width).toInt() - 1;
+    int minY = d.y.toInt();
+    int maxY = (d.y + d.height).toInt() - 1;
+    var x = pos.dx.round();
+    var y = pos.dy.round();
+    if (x < minX || y < minY || x > maxX || y > maxY) {
+      if (!clamp) return null;
+      x = min(max(x, minX), maxX);
+      y = min(max(y, minY), maxY);
+    }
+    return Point(x, y);
+  }
+
+  void _sendPointer(Map<String, dynamic> evt) {
+    if (!keyboardPerm) return;
+    if (ctrl) evt['ctrl'] = 'true';
+    if (shift) evt['shift'] = 'true';
+    if (alt) evt['alt'] = 'true';
+    if (command) evt['command'] = 'true';
+    bind.sessionSendPointer(sessionId: sessionId, msg: json.encode(evt));
+  }
+
   void onPointerSignalImage(PointerSignalEvent e) {
     if (e is PointerScrollEvent) {
       var dx = e.scrollDelta.dx.toInt();
@@ -550,6 +669,30 @@ class InputModel {
     return Offset(x, y);
   }
 
+  /// Maps [x] and [y] on the canvas to the remote screen.
+  Offset _canvasToPeer(double x, double y) {
+    final canvasModel = parent.target!.canvasModel;
+    final d = parent.target!.ffiModel.display;
+    final imageWidth = d.width * canvasModel.scale;
+    final imageHeight = d.height * canvasModel.scale;
+    if (canvasModel.scrollStyle == ScrollStyle.scrollbar) {
+      x += imageWidth * canvasModel.scrollX;
+      y += imageHeight * canvasModel.scrollY;
+
+      // boxed size is a center widget
+      if (canvasModel.size.width > imageWidth) {
+        x -= ((canvasModel.size.width - imageWidth) / 2);
+      }
+      if (canvasModel.size.height > imageHeight) {
+        y -= ((canvasModel.size.height - imageHeight) / 2);
+      }
+    } else {
+      x -= canvasModel.x;
+      y -= canvasModel.y;
+    }
+    return Offset(x / canvasModel.scale + d.x, y / canvasModel.scale + d.y);
+  }
+
   void handleMouse(
     Map<String, dynamic> evt, {
     bool onExit = false,
@@ -604,26 +747,9 @@ class InputModel {
       canvasModel.moveDesktopMouse(x, y);
     }
     final d = ffiModel.display;
-    final imageWidth = d.width * canvasModel.scale;
-    final imageHeight = d.height * canvasModel.scale;
-    if (canvasModel.scrollStyle == ScrollStyle.scrollbar) {
-      x += imageWidth * canvasModel.scrollX;
-      y += imageHeight * canvasModel.scrollY;
-
-      // boxed size is a center widget
-      if (canvasModel.size.width > imageWidth) {
-        x -= ((canvasModel.size.width - imageWidth) / 2);
-      }
-      if (canvasModel.size.height > imageHeight) {
-        y -= ((canvasModel.size.height - imageHeight) / 2);
-      }
-    } else {
-      x -= canvasModel.x;
-      y -= canvasModel.y;
-    }
-
-    x /= canvasModel.scale;
-    y /= canvasModel.scale;
+    final pos = _canvasToPeer(x, y);
+    x = pos.dx;
+    y = pos.dy;
     if (canvasModel.scale > 0 && canvasModel.scale < 1) {
       final step = 1.0 / canvasModel.scale - 1;
       if (nearRight) {
@@ -633,8 +759,6 @@ class InputModel {
         y += step;
       }
     }
-    x += d.x;
-    y += d.y;
 
     if (onExit) {
       final pos = setNearestEdge(x, y, d);
@@ -1009,6 +1009,7 @@ impl PeerConfig {
 
     pub fn remove(id: &str) {
         fs::remove_file(Self::path(id)).ok();
+        PinnedKeys::forget(id);
     }
 
     fn path(id: &str) -> PathBuf {
@@ -1328,6 +1329,16 @@ pub struct DiscoveryPeer {
     pub online: bool,
     #[serde(default, deserialize_with = "deserialize_hashmap_string_string")]
     pub ip_mac: HashMap<String, String>,
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub version: String,
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub has_password: bool,
+    /// Names of the accepted `ConnType`s.
+    #[serde(default, deserialize_with = "deserialize_vec_string")]
+    pub conn_types: Vec<String>,
+    /// Unix time in seconds of the last answer.
+    #[serde(default, deserialize_with = "deserialize_i64")]
+    pub last_seen: i64,
 }
 
 impl DiscoveryPeer {
@@ -1336,6 +1347,9 @@ impl DiscoveryPeer {
     }
 }
 
+/// Seconds after which a LAN peer which stopped answering is forgotten.
+pub const LAN_PEER_TTL: i64 = 7 * 24 * 3600;
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct LanPeers {
     #[serde(default, deserialize_with = "deserialize_vec_discoverypeer")]
@@ -1363,6 +1377,17 @@ impl LanPeers {
         }
     }
 
+    /// Drops the peers which have not answered for `LAN_PEER_TTL`. Peers stored
+    /// before the time was recorded count as seen now.
+    pub fn expire(peers: &mut Vec<DiscoveryPeer>, now: i64) {
+        for peer in peers.iter_mut() {
+            if peer.last_seen == 0 {
+                peer.last_seen = now;
+            }
+        }
+        peers.retain(|peer| now - peer.last_seen < LAN_PEER_TTL);
+    }
+
     pub fn modify_time() -> crate::ResultType<u64> {
         let p = Config::file_("_lan_peers");
         Ok(fs::metadata(p)?
@@ -1392,6 +1417,69 @@ impl HwCodecConfig {
     }
 }
 
+#[derive(Debug, PartialEq)]
+pub enum KeyPin {
+    New,
+    Same,
+    Changed,
+}
+
+/// Signing keys of peers, pinned on first use.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct PinnedKeys {
+    #[serde(default, deserialize_with = "deserialize_hashmap_string_string")]
+    keys: HashMap<String, String>,
+}
+
+impl PinnedKeys {
+    fn load() -> PinnedKeys {
+        Config::load_::<PinnedKeys>("_pinned_keys")
+    }
+
+    fn store(&self) {
+        Config::store_(self, "_pinned_keys");
+    }
+
+    fn pin(&mut self, id: &str, pk: &[u8]) -> KeyPin {
+        let pk = base64::encode(pk, base64::Variant::Original);
+        match self.keys.get(id) {
+            Some(pinned) if *pinned == pk => KeyPin::Same,
+            Some(_) => KeyPin::Changed,
+            None => {
+                self.keys.insert(id.to_owned(), pk);
+                KeyPin::New
+            }
+        }
+    }
+
+    /// Compares `pk` with the key pinned for `id`, pinning it if there is none.
+    pub fn check(id: &str, pk: &[u8]) -> KeyPin {
+        let mut keys = Self::load();
+        let res = keys.pin(id, pk);
+        if res == KeyPin::New {
+            keys.store();
+        }
+        res
+    }
+
+    fn contains(&self, id: &str) -> bool {
+        self.keys.contains_key(id)
+    }
+
+    /// Whether a key was pinned for `id`, which then has to prove it.
+    pub fn is_pinned(id: &str) -> bool {
+        Self::load().contains(id)
+    }
+
+    /// So that the next connection pins the key again.
+    pub fn forget(id: &str) {
+        let mut keys = Self::load();
+        if keys.keys.remove(id).is_some() {
+            keys.store();
+        }
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct UserDefaultConfig {
     #[serde(default, deserialize_with = "deserialize_hashmap_string_string")]
@@ -1553,6 +1641,7 @@ macro_rules! deserialize_default {
 deserialize_default!(deserialize_string, String);
 deserialize_default!(deserialize_bool, bool);
 deserialize_default!(deserialize_i32, i32);
+deserialize_default!(deserialize_i64, i64);
 deserialize_default!(deserialize_vec_u8, Vec<u8>);
 deserialize_default!(deserialize_vec_string, Vec<String>);
 deserialize_default!(deserialize_vec_i32_string_i32, Vec<(i32, String, i32)>);
@@ -1655,4 +1744,32 @@ mod tests {
             assert_eq!(cfg, Ok(cfg_to_compare), "Failed to test wrong_field_str");
         }
     }
+
+    #[test]
+    fn test_pinned_keys() {
+        let mut keys = PinnedKeys::default();
+        assert_eq!(keys.pin("123", &[1; 32]), KeyPin::New);
+        assert_eq!(keys.pin("123", &[1; 32]), KeyPin::Same);
+        assert_eq!(keys.pin("123", &[2; 32]), KeyPin::Changed);
+        assert_eq!(keys.pin("456", &[2; 32]), KeyPin::New);
+        assert!(keys.contains("123") && !keys.contains("789"));
+    }
+
+    #[test]
+    fn test_lan_peers_expire() {
+        let peer = |id: &str, last_seen| DiscoveryPeer {
+            id: id.to_owned(),
+            last_seen,
+            ..Default::default()
+        };
+        let now = 1_000_000_000;
+        let mut peers = vec![
+            peer("old", now - LAN_PEER_TTL),
+            peer("recent", now - 60),
+            peer("unknown", 0),
+        ];
+        LanPeers::expire(&mut peers, now);
+        let ids: Vec<_> = peers.iter().map(|p| (p.id.as_str(), p.last_seen)).collect();
+        assert_eq!(ids, vec![("recent", now - 60), ("unknown", now)]);
+    }
 }
@@ -0,0 +1,280 @@
+// rsync style delta transfer. The receiver sends signatures of the blocks of
+// its existing copy, a weak rolling checksum and a strong hash each, and the
+// sender scans its file for them byte by byte, so blocks which only moved are
+// found too. What matches is sent as block indexes, the rest as literal data.
+
+use crate::message_proto::{FileBlockSignature, FileDeltaSignatures};
+use sodiumoxide::crypto::hash::sha256;
+use std::{collections::HashMap, fs::File, io::Read, path::Path};
+
+/// Smaller files are sent whole, the signatures would not pay off.
+pub const MIN_DELTA_SIZE: u64 = 256 * 1024;
+const MIN_BLOCK_SIZE: usize = 4 * 1024;
+const MAX_BLOCK_SIZE: usize = 1024 * 1024;
+/// Same as the blocks of a full transfer.
+const MAX_LITERAL: usize = 128 * 1024;
+const MAX_COPIES: usize = 1024;
+const STRONG_LEN: usize = 16;
+const MOD: u32 = 1 << 16;
+
+/// About the square root of the file size, which keeps both the signatures and
+/// the data resent around a change small.
+pub fn block_size(len: u64) -> usize {
+    ((len as f64).sqrt() as usize)
+        .next_power_of_two()
+        .clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE)
+}
+
+/// Adler-32 like checksum which can slide over the data one byte at a time.
+#[derive(Debug, Clone, Copy)]
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(data: &[u8]) -> Self {
+        let mut a: u32 = 0;
+        let mut b: u32 = 0;
+        let len = data.len() as u32;
+        for (i, x) in data.iter().enumerate() {
+            a = a.wrapping_add(*x as u32);
+            b = b.wrapping_add((len - i as u32).wrapping_mul(*x as u32));
+        }
+        Self {
+            a: a % MOD,
+            b: b % MOD,
+            len,
+        }
+    }
+
+    fn roll(&mut self, out: u8, inp: u8) {
+        self.a = (self.a + MOD - out as u32 + inp as u32) % MOD;
+        self.b = (self.b + MOD * 2 - (self.len * out as u32) % MOD + self.a) % MOD;
+    }
+
+    #[inline]
+    fn digest(&self) -> u32 {
+        self.a | (self.b << 16)
+    }
+}
+
+fn strong(data: &[u8]) -> Vec<u8> {
+    sha256::hash(data).as_ref()[..STRONG_LEN].to_vec()
+}
+
+/// Signatures of the file at `path`, blocking.
+pub fn signatures(path: &Path) -> std::io::Result<FileDeltaSignatures> {
+    let mut file = File::open(path)?;
+    let block_size = block_size(file.metadata()?.len());
+    let mut buf = vec![0; block_size];
+    let mut blocks = Vec::new();
+    loop {
+        let n = read_full(&mut file, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        let data = &buf[..n];
+        blocks.push(FileBlockSignature {
+            weak: Rolling::new(data).digest(),
+            strong: strong(data).into(),
+            ..Default::default()
+        });
+        if n < block_size {
+            break;
+        }
+    }
+    Ok(FileDeltaSignatures {
+        block_size: block_size as _,
+        blocks,
+        ..Default::default()
+    })
+}
+
+fn read_full(file: &mut File, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut offset = 0;
+    while offset < buf.len() {
+        match file.read(&mut buf[offset..])? {
+            0 => break,
+            n => offset += n,
+        }
+    }
+    Ok(offset)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Chunk {
+    Literal(Vec<u8>),
+    /// Indexes of blocks of the receiver's copy.
+    Copy(Vec<u32>),
+}
+
+/// Sender side, turns the data pushed into it into chunks against the signatures.
+pub struct Encoder {
+    block_size: usize,
+    // weak checksum -> (strong hash, block index)
+    table: HashMap<u32, Vec<(Vec<u8>, u32)>>,
+    buf: Vec<u8>,
+    pos: usize,
+    rolling: Option<Rolling>,
+    literal: Vec<u8>,
+    copies: Vec<u32>,
+}
+
+impl Encoder {
+    pub fn new(sigs: &FileDeltaSignatures) -> Self {
+        let block_size = (sigs.block_size as usize).clamp(1, MAX_BLOCK_SIZE);
+        let mut table: HashMap<u32, Vec<(Vec<u8>, u32)>> = HashMap::new();
+        // A short last block never matches a full window, which is fine.
+        for (i, b) in sigs.blocks.iter().enumerate() {
+            table
+                .entry(b.weak)
+                .or_default()
+                .push((b.strong.to_vec(), i as u32));
+        }
+        Self {
+            block_size,
+            table,
+            buf: Vec::new(),
+            pos: 0,
+            rolling: None,
+            literal: Vec::new(),
+            copies: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        if self.pos > 0 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        self.buf.extend_from_slice(data);
+    }
+
+    fn lookup(&self, weak: u32, window: &[u8]) -> Option<u32> {
+        let candidates = self.table.get(&weak)?;
+        let strong = strong(window);
+        candidates
+            .iter()
+            .find(|(s, _)| *s == strong)
+            .map(|(_, index)| *index)
+    }
+
+    /// The next chunk to send. `None` if more data has to be pushed first, or
+    /// once everything is sent if `eof` is set, all data having been pushed.
+    pub fn next(&mut self, eof: bool) -> Option<Chunk> {
+        let bs = self.block_size;
+        loop {
+            if self.buf.len() - self.pos < bs {
+                if !eof {
+                    return None;
+                }
+                if !self.copies.is_empty() {
+                    return Some(Chunk::Copy(std::mem::take(&mut self.copies)));
+                }
+                self.literal.extend_from_slice(&self.buf[self.pos..]);
+                self.pos = self.buf.len();
+                if self.literal.is_empty() {
+                    return None;
+                }
+                return Some(Chunk::Literal(std::mem::take(&mut self.literal)));
+            }
+            let window = &self.buf[self.pos..self.pos + bs];
+            let rolling = *self.rolling.get_or_insert_with(|| Rolling::new(window));
+            if let Some(index) = self.lookup(rolling.digest(), window) {
+                if !self.literal.is_empty() {
+                    return Some(Chunk::Literal(std::mem::take(&mut self.literal)));
+                }
+                self.copies.push(index);
+                self.pos += bs;
+                self.rolling = None;
+                if self.copies.len() >= MAX_COPIES {
+                    return Some(Chunk::Copy(std::mem::take(&mut self.copies)));
+                }
+            } else {
+                if !self.copies.is_empty() {
+                    return Some(Chunk::Copy(std::mem::take(&mut self.copies)));
+                }
+                let out = self.buf[self.pos];
+                self.literal.push(out);
+                self.pos += 1;
+                if self.pos + bs <= self.buf.len() {
+                    if let Some(rolling) = self.rolling.as_mut() {
+                        rolling.roll(out, self.buf[self.pos + bs - 1]);
+                    }
+                } else {
+                    self.rolling = None;
+                }
+                if self.literal.len() >= MAX_LITERAL {
+                    return Some(Chunk::Literal(std::mem::take(&mut self.literal)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rolling() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7 + i / 13) as u8).collect();
+        let mut rolling = Rolling::new(&data[..100]);
+        for i in 0..900 {
+            assert_eq!(rolling.digest(), Rolling::new(&data[i..i + 100]).digest());
+            rolling.roll(data[i], data[i + 100]);
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        let base: Vec<u8> = (0..100_000u32).map(|i| (i * 31 % 251) as u8).collect();
+        let dir = std::env::temp_dir().join(format!("delta-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("base");
+        std::fs::write(&path, &base).unwrap();
+        let sigs = signatures(&path).unwrap();
+        let bs = sigs.block_size as usize;
+        assert_eq!(bs, MIN_BLOCK_SIZE);
+
+        // Insert some bytes in the middle and change the tail.
+        let mut new = base[..30_000].to_vec();
+        new.extend_from_slice(b"inserted");
+        new.extend_from_slice(&base[30_000..90_000]);
+        new.extend_from_slice(&[1; 5000]);
+
+        let mut encoder = Encoder::new(&sigs);
+        let mut chunks = Vec::new();
+        for data in new.chunks(7000) {
+            encoder.push(data);
+            while let Some(chunk) = encoder.next(false) {
+                chunks.push(chunk);
+            }
+        }
+        while let Some(chunk) = encoder.next(true) {
+            chunks.push(chunk);
+        }
+
+        let mut out = Vec::new();
+        let mut literal = 0;
+        for chunk in chunks {
+            match chunk {
+                Chunk::Literal(data) => {
+                    literal += data.len();
+                    out.extend(data);
+                }
+                Chunk::Copy(indexes) => {
+                    for i in indexes {
+                        let start = i as usize * bs;
+                        out.extend_from_slice(&base[start..(start + bs).min(base.len())]);
+                    }
+                }
+            }
+        }
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(out, new);
+        assert!(literal < 3 * bs + 5000, "{}", literal);
+    }
+}
@@ -0,0 +1,301 @@
+// Planning of a one way directory sync. Both trees are listed with
+// `fs::get_recursive_files`, optionally with content hashes, then compared by
+// relative path. Files which are missing or differ are copied with a normal
+// transfer job, files only on the destination are deleted in mirror mode, along
+// with the directories this leaves empty.
+
+use crate::{bail, message_proto::FileEntry, regex::Regex, ResultType};
+use sodiumoxide::crypto::hash::sha256;
+use std::{
+    collections::{HashMap, HashSet},
+    io::Read,
+    path::Path,
+};
+
+/// Include and exclude globs on relative paths, `/` separated.
+///
+/// `*` and `?` do not cross directories, `**` does. A glob without `/` is
+/// matched against the file name only, like in `.gitignore`.
+#[derive(Debug, Default)]
+pub struct Filter {
+    // with whether the glob has a `/` and so matches the whole path
+    include: Vec<(Regex, bool)>,
+    exclude: Vec<(Regex, bool)>,
+}
+
+impl Filter {
+    pub fn new<S: AsRef<str>>(include: &[S], exclude: &[S]) -> ResultType<Self> {
+        let compile = |globs: &[S]| -> ResultType<Vec<(Regex, bool)>> {
+            globs
+                .iter()
+                .map(|g| Ok((glob_to_regex(g.as_ref())?, g.as_ref().contains('/'))))
+                .collect()
+        };
+        Ok(Self {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    /// Excluded files are neither copied nor deleted.
+    pub fn matches(&self, name: &str) -> bool {
+        let name = normalize(name);
+        let file_name = name.rsplit('/').next().unwrap_or_default();
+        let is_match = |(re, whole): &(Regex, bool)| {
+            if *whole {
+                re.is_match(&name)
+            } else {
+                re.is_match(file_name)
+            }
+        };
+        (self.include.is_empty() || self.include.iter().any(is_match))
+            && !self.exclude.iter().any(is_match)
+    }
+}
+
+fn glob_to_regex(glob: &str) -> ResultType<Regex> {
+    let glob = glob.trim().trim_start_matches('/');
+    if glob.is_empty() {
+        bail!("empty glob");
+    }
+    let mut re = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    re.push_str("(?:.*/)?");
+                } else {
+                    re.push_str(".*");
+                }
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            c => re.push_str(&crate::regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    Ok(Regex::new(&re)?)
+}
+
+#[inline]
+fn normalize(name: &str) -> String {
+    name.replace('\\', "/")
+}
+
+#[derive(Debug, Default)]
+pub struct SyncOptions {
+    pub filter: Filter,
+    /// Compare by content hash instead of modification time, the listings
+    /// must have been made with hashes.
+    pub compare_hash: bool,
+    /// Delete destination files which are not in the source.
+    pub mirror_delete: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct SyncPlan {
+    /// Source entries to copy.
+    pub copies: Vec<FileEntry>,
+    /// Destination entries to delete.
+    pub deletes: Vec<FileEntry>,
+    pub unchanged: usize,
+}
+
+impl SyncPlan {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.copies.is_empty() && self.deletes.is_empty()
+    }
+}
+
+fn differs(src: &FileEntry, dst: &FileEntry, compare_hash: bool) -> bool {
+    if src.size != dst.size {
+        return true;
+    }
+    if compare_hash && !src.hash.is_empty() && !dst.hash.is_empty() {
+        src.hash != dst.hash
+    } else {
+        src.modified_time != dst.modified_time
+    }
+}
+
+pub fn plan(source: &[FileEntry], dest: &[FileEntry], options: &SyncOptions) -> SyncPlan {
+    let mut dest: HashMap<String, &FileEntry> = dest
+        .iter()
+        .filter(|e| options.filter.matches(&e.name))
+        .map(|e| (normalize(&e.name), e))
+        .collect();
+    let mut plan = SyncPlan::default();
+    for src in source.iter().filter(|e| options.filter.matches(&e.name)) {
+        match dest.remove(&normalize(&src.name)) {
+            Some(dst) if !differs(src, dst, options.compare_hash) => plan.unchanged += 1,
+            _ => plan.copies.push(src.clone()),
+        }
+    }
+    if options.mirror_delete {
+        plan.deletes = dest.into_values().cloned().collect();
+        plan.deletes.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+    plan.copies.sort_by(|a, b| a.name.cmp(&b.name));
+    plan
+}
+
+// "a/b/c" gives "a/b" and "a".
+fn parents(name: &str) -> impl Iterator<Item = &str> {
+    name.char_indices()
+        .rev()
+        .filter(|(_, c)| *c == '/')
+        .map(move |(i, _)| &name[..i])
+}
+
+/// Directories which are empty once `deletes` are deleted, deepest first.
+/// `dest` is the whole destination listing, excluded files included.
+pub fn emptied_dirs(dest: &[FileEntry], deletes: &[FileEntry]) -> Vec<String> {
+    let deleted: HashSet<String> = deletes.iter().map(|e| normalize(&e.name)).collect();
+    let mut kept = HashSet::new();
+    for name in dest.iter().map(|e| normalize(&e.name)) {
+        if !deleted.contains(&name) {
+            kept.extend(parents(&name).map(|p| p.to_owned()));
+        }
+    }
+    let mut dirs: Vec<String> = deleted
+        .iter()
+        .flat_map(|name| parents(name))
+        .filter(|p| !kept.contains(*p))
+        .map(|p| p.to_owned())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    dirs.sort_by(|a, b| {
+        b.matches('/')
+            .count()
+            .cmp(&a.matches('/').count())
+            .then(a.cmp(b))
+    });
+    dirs
+}
+
+/// `fs::get_recursive_files` with the hashes filled, blocking.
+pub fn get_recursive_files_with_hash(
+    path: &str,
+    include_hidden: bool,
+) -> ResultType<Vec<FileEntry>> {
+    let mut files = crate::fs::get_recursive_files(path, include_hidden)?;
+    hash_entries(&crate::fs::get_path(path), &mut files)?;
+    Ok(files)
+}
+
+/// Fills the SHA-256 of the files listed under `root`, blocking.
+pub fn hash_entries(root: &Path, entries: &mut [FileEntry]) -> ResultType<()> {
+    let mut buf = vec![0; 128 * 1024];
+    for entry in entries.iter_mut() {
+        let path = if entry.name.is_empty() {
+            root.to_path_buf()
+        } else {
+            root.join(&entry.name)
+        };
+        let mut file = std::fs::File::open(path)?;
+        let mut hasher = sha256::State::new();
+        loop {
+            match file.read(&mut buf)? {
+                0 => break,
+                n => hasher.update(&buf[..n]),
+            }
+        }
+        entry.hash = hasher.finalize().as_ref().to_vec().into();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, size: u64, modified_time: u64) -> FileEntry {
+        FileEntry {
+            name: name.to_owned(),
+            size,
+            modified_time,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter::new(&["*.conf", "etc/**"], &["**/tmp/**", "*.bak"]).unwrap();
+        assert!(filter.matches("a.conf"));
+        assert!(filter.matches("x/y/a.conf"));
+        assert!(filter.matches("etc\\nginx\\site"));
+        assert!(!filter.matches("x/a.txt"));
+        assert!(!filter.matches("etc/tmp/a.conf"));
+        assert!(!filter.matches("etc/a.bak"));
+        let filter = Filter::new::<&str>(&[], &["data/?.log"]).unwrap();
+        assert!(filter.matches("data/ab.log"));
+        assert!(!filter.matches("data/a.log"));
+        assert!(filter.matches("x/data/a.log"));
+    }
+
+    #[test]
+    fn test_plan() {
+        let source = vec![
+            entry("same", 1, 10),
+            entry("newer", 1, 20),
+            entry("new", 1, 10),
+            entry("skip.bak", 1, 10),
+        ];
+        let dest = vec![
+            entry("same", 1, 10),
+            entry("newer", 1, 10),
+            entry("gone", 1, 10),
+            entry("keep.bak", 1, 10),
+        ];
+        let mut options = SyncOptions {
+            filter: Filter::new(&[] as &[&str], &["*.bak"]).unwrap(),
+            ..Default::default()
+        };
+        let p = plan(&source, &dest, &options);
+        let names = |v: &[FileEntry]| v.iter().map(|e| e.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(&p.copies), vec!["new", "newer"]);
+        assert!(p.deletes.is_empty());
+        assert_eq!(p.unchanged, 1);
+
+        options.mirror_delete = true;
+        options.compare_hash = true;
+        let mut source = source;
+        let mut dest = dest;
+        source[1].hash = vec![1].into();
+        dest[1].hash = vec![1].into();
+        let p = plan(&source, &dest, &options);
+        assert_eq!(names(&p.copies), vec!["new"]);
+        assert_eq!(names(&p.deletes), vec!["gone"]);
+        assert_eq!(p.unchanged, 2);
+    }
+
+    #[test]
+    fn test_emptied_dirs() {
+        let dest = vec![
+            entry("a/b/1", 1, 10),
+            entry("a/b/2", 1, 10),
+            entry("a/c/1", 1, 10),
+            entry("a/c/keep.bak", 1, 10),
+            entry("d\\1", 1, 10),
+            entry("top", 1, 10),
+        ];
+        let deletes = vec![
+            entry("a/b/1", 1, 10),
+            entry("a/b/2", 1, 10),
+            entry("a/c/1", 1, 10),
+            entry("d\\1", 1, 10),
+            entry("top", 1, 10),
+        ];
+        // a/c still has the excluded file, so a stays too
+        assert_eq!(emptied_dirs(&dest, &deletes), vec!["a/b", "d"]);
+        let deletes = vec![entry("a/c/1", 1, 10)];
+        assert!(emptied_dirs(&dest, &deletes).is_empty());
+        let dest = vec![entry("x/y/z/1", 1, 10)];
+        assert_eq!(emptied_dirs(&dest, &dest), vec!["x/y/z", "x/y", "x"]);
+    }
+}
@@ -1,12 +1,19 @@
 #[cfg(windows)]
 use std::os::windows::prelude::*;
-use std::path::{Path, PathBuf};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use serde_derive::{Deserialize, Serialize};
+use sodiumoxide::crypto::hash::sha256;
 use tokio::{fs::File, io::*};
 
-use crate::{anyhow::anyhow, bail, get_version_number, message_proto::*, ResultType, Stream};
+use crate::{
+    anyhow::anyhow, bail, delta, get_version_number, message_proto::*, ResultType, Stream,
+};
 // https://doc.rust-lang.org/std/os/windows/fs/trait.MetadataExt.html
 use crate::{
     compress::{compress, decompress},
@@ -36,6 +43,7 @@ pub fn read_dir(path: &Path, include_hidden: bool) -> ResultType<FileDirectory>
         }
         return Ok(dir);
     }
+    let mut names = Names::default();
     for entry in path.read_dir()?.flatten() {
         let p = entry.path();
         let name = p
@@ -86,18 +94,218 @@ pub fn read_dir(path: &Path, include_hidden: bool) -> ResultType<FileDirectory>
                     .unwrap_or(0)
             })
             .unwrap_or(0);
-        dir.entries.push(FileEntry {
+        let mut entry = FileEntry {
             name: get_file_name(&p),
             entry_type,
             is_hidden,
             size,
             modified_time,
             ..Default::default()
-        });
+        };
+        if meta.file_type().is_symlink() {
+            entry.link_target = std::fs::read_link(&p)
+                .map(|t| get_string(&t))
+                .unwrap_or_default();
+        }
+        set_attrs(&mut entry, &meta, &mut names);
+        dir.entries.push(entry);
     }
     Ok(dir)
 }
 
+// Permissions, owner and access time of `meta`.
+fn set_attrs(entry: &mut FileEntry, meta: &std::fs::Metadata, names: &mut Names) {
+    entry.accessed_time = meta
+        .accessed()
+        .map(|x| {
+            x.duration_since(std::time::SystemTime::UNIX_EPOCH)
+                .map(|x| x.as_secs())
+                .unwrap_or(0)
+        })
+        .unwrap_or(0);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        entry.mode = meta.mode() & 0o777;
+        entry.owner = names.user(meta.uid());
+        entry.group = names.group(meta.gid());
+    }
+    #[cfg(not(unix))]
+    let _ = names;
+}
+
+// Owner and group names by id, looked up once per listing.
+#[derive(Default)]
+struct Names {
+    #[cfg(unix)]
+    users: std::collections::HashMap<u32, String>,
+    #[cfg(unix)]
+    groups: std::collections::HashMap<u32, String>,
+}
+
+#[cfg(unix)]
+impl Names {
+    fn user(&mut self, uid: u32) -> String {
+        self.users
+            .entry(uid)
+            .or_insert_with(|| {
+                let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+                let mut res = std::ptr::null_mut();
+                let mut buf = vec![0 as libc::c_char; 4096];
+                let ret = unsafe {
+                    libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut res)
+                };
+                if ret != 0 || res.is_null() {
+                    return String::new();
+                }
+                unsafe { std::ffi::CStr::from_ptr(pwd.pw_name) }
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .clone()
+    }
+
+    fn group(&mut self, gid: u32) -> String {
+        self.groups
+            .entry(gid)
+            .or_insert_with(|| {
+                let mut grp: libc::group = unsafe { std::mem::zeroed() };
+                let mut res = std::ptr::null_mut();
+                let mut buf = vec![0 as libc::c_char; 4096];
+                let ret = unsafe {
+                    libc::getgrgid_r(gid, &mut grp, buf.as_mut_ptr(), buf.len(), &mut res)
+                };
+                if ret != 0 || res.is_null() {
+                    return String::new();
+                }
+                unsafe { std::ffi::CStr::from_ptr(grp.gr_name) }
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .clone()
+    }
+}
+
+#[cfg(unix)]
+fn uid_of(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut res = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let ret = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut pwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut res,
+        )
+    };
+    if ret != 0 || res.is_null() {
+        return None;
+    }
+    Some(pwd.pw_uid)
+}
+
+#[cfg(unix)]
+fn gid_of(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut res = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let ret = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut grp,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut res,
+        )
+    };
+    if ret != 0 || res.is_null() {
+        return None;
+    }
+    Some(grp.gr_gid)
+}
+
+/// Whether received files get the owner and group of the sender's, by name.
+/// Needs root and the "preserve-file-owner" option.
+#[cfg(unix)]
+pub fn preserve_ownership() -> bool {
+    Config::get_option("preserve-file-owner") == "Y" && unsafe { libc::geteuid() } == 0
+}
+
+// Restores what `entry` carries besides the content, `path` being in place.
+fn restore_attrs(path: &Path, entry: &FileEntry) {
+    let is_link = !entry.link_target.is_empty();
+    let mtime = filetime::FileTime::from_unix_time(entry.modified_time as _, 0);
+    let atime = if entry.accessed_time > 0 {
+        filetime::FileTime::from_unix_time(entry.accessed_time as _, 0)
+    } else {
+        mtime
+    };
+    #[cfg(unix)]
+    if preserve_ownership() && !(entry.owner.is_empty() && entry.group.is_empty()) {
+        use std::os::unix::ffi::OsStrExt;
+        let uid = uid_of(&entry.owner).unwrap_or(u32::MAX);
+        let gid = gid_of(&entry.group).unwrap_or(u32::MAX);
+        if let Ok(p) = std::ffi::CString::new(path.as_os_str().as_bytes()) {
+            // -1 keeps what is unknown here
+            if unsafe { libc::lchown(p.as_ptr(), uid, gid) } != 0 {
+                log::warn!(
+                    "Failed to set the owner of {}: {}",
+                    path.display(),
+                    std::io::Error::last_os_error()
+                );
+            }
+        }
+    }
+    if is_link {
+        filetime::set_symlink_file_times(path, atime, mtime).ok();
+        return;
+    }
+    // After chown, which clears the setuid bits.
+    #[cfg(unix)]
+    if entry.mode != 0 {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(entry.mode & 0o777)).ok();
+    }
+    filetime::set_file_times(path, atime, mtime).ok();
+}
+
+// Replaces whatever is at `path` by a link to `target`.
+fn create_link(path: &Path, target: &str, is_dir: bool) -> ResultType<()> {
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if meta.is_dir() {
+            bail!("{} is a directory", path.display());
+        }
+        std::fs::remove_file(path)?;
+    }
+    #[cfg(unix)]
+    {
+        let _ = is_dir;
+        std::os::unix::fs::symlink(target, path)?;
+    }
+    #[cfg(windows)]
+    if is_dir {
+        std::os::windows::fs::symlink_dir(target, path)?;
+    } else {
+        std::os::windows::fs::symlink_file(target, path)?;
+    }
+    Ok(())
+}
+
+// A received link may only point below its own directory.
+fn check_link_target(target: &str) -> ResultType<()> {
+    let inside = Path::new(target)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if target.is_empty() || !inside {
+        bail!("Link target {} is not allowed", target);
+    }
+    Ok(())
+}
+
 #[inline]
 pub fn get_file_name(p: &Path) -> String {
     p.file_name()
@@ -138,6 +346,12 @@ fn read_dir_recursive(
                     entry.name = get_string(&prefix.join(entry.name));
                     files.push(entry);
                 }
+                // Sent as links, not followed.
+                Ok(FileType::FileLink) | Ok(FileType::DirLink) => {
+                    let mut entry = entry.clone();
+                    entry.name = get_string(&prefix.join(entry.name));
+                    files.push(entry);
+                }
                 Ok(FileType::Dir) => {
                     if let Ok(mut tmp) = read_dir_recursive(
                         &path.join(&entry.name),
@@ -168,12 +382,16 @@ fn read_dir_recursive(
         } else {
             (0, 0)
         };
-        files.push(FileEntry {
+        let mut entry = FileEntry {
             entry_type: FileType::File.into(),
             size,
             modified_time,
             ..Default::default()
-        });
+        };
+        if let Ok(meta) = std::fs::metadata(path) {
+            set_attrs(&mut entry, &meta, &mut Names::default());
+        }
+        files.push(entry);
         Ok(files)
     } else {
         bail!("Not exists");
@@ -194,6 +412,13 @@ pub fn can_enable_overwrite_detection(version: i64) -> bool {
     version >= get_version_number("1.1.10")
 }
 
+#[inline]
+pub fn can_send_links(version: i64) -> bool {
+    version >= get_version_number("1.2.2")
+}
+
+const BUF_SIZE: usize = 128 * 1024;
+
 #[derive(Default)]
 pub struct TransferJob {
     pub id: i32,
@@ -215,6 +440,22 @@ pub struct TransferJob {
     file_skipped: bool,
     file_is_waiting: bool,
     default_overwrite_strategy: Option<bool>,
+    // of the content of the current file so far
+    hasher: Option<sha256::State>,
+    // whether the peer can take part in a delta transfer, from its digest
+    peer_supports_delta: bool,
+    // sender: signatures confirmed for the current file, and the encoder made of them
+    delta_sigs: Option<FileDeltaSignatures>,
+    encoder: Option<delta::Encoder>,
+    delta_eof: bool,
+    // receiver: file number and block size of the signatures sent, and the existing file
+    delta_base: Option<(i32, usize)>,
+    base: Option<File>,
+    // sender: paused by the user, and the job's own rate limit
+    paused: bool,
+    limiter: RateLimiter,
+    // receiver: links made by this job, nothing is written through them
+    links: Vec<PathBuf>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -233,6 +474,51 @@ pub struct TransferJobMeta {
     pub is_remote: bool,
 }
 
+/// What `FileEntry` carries besides name, size and times, for passing it on.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FileAttrs {
+    #[serde(default)]
+    pub mode: u32,
+    #[serde(default)]
+    pub link_target: String,
+    #[serde(default)]
+    pub is_dir_link: bool,
+    #[serde(default)]
+    pub owner: String,
+    #[serde(default)]
+    pub group: String,
+    #[serde(default)]
+    pub accessed_time: u64,
+}
+
+impl FileAttrs {
+    pub fn of(entry: &FileEntry) -> Self {
+        Self {
+            mode: entry.mode,
+            link_target: entry.link_target.clone(),
+            is_dir_link: entry.entry_type.enum_value() == Ok(FileType::DirLink),
+            owner: entry.owner.clone(),
+            group: entry.group.clone(),
+            accessed_time: entry.accessed_time,
+        }
+    }
+
+    pub fn apply(self, entry: &mut FileEntry) {
+        if !self.link_target.is_empty() {
+            entry.entry_type = if self.is_dir_link {
+                FileType::DirLink.into()
+            } else {
+                FileType::FileLink.into()
+            };
+        }
+        entry.mode = self.mode;
+        entry.link_target = self.link_target;
+        entry.owner = self.owner;
+        entry.group = self.group;
+        entry.accessed_time = self.accessed_time;
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct RemoveJobMeta {
     #[serde(default)]
@@ -329,6 +615,11 @@ impl TransferJob {
         self.files = files;
     }
 
+    /// Leaves the links out, for peers which can not recreate them.
+    pub fn drop_links(&mut self) {
+        self.files.retain(|f| f.link_target.is_empty());
+    }
+
     #[inline]
     pub fn id(&self) -> i32 {
         self.id
@@ -359,13 +650,14 @@ impl TransferJob {
         if file_num < self.files.len() {
             let entry = &self.files[file_num];
             let path = self.join(&entry.name);
-            let download_path = format!("{}.download", get_string(&path));
-            std::fs::rename(download_path, &path).ok();
-            filetime::set_file_mtime(
-                &path,
-                filetime::FileTime::from_unix_time(entry.modified_time as _, 0),
-            )
-            .ok();
+            if entry.link_target.is_empty() {
+                let download_path = format!("{}.download", get_string(&path));
+                std::fs::rename(download_path, &path).ok();
+            } else if !self.links.contains(&path) {
+                // kept what was there
+                return;
+            }
+            restore_attrs(&path, entry);
         }
     }
 
@@ -395,19 +687,52 @@ impl TransferJob {
             self.file_num = block.file_num;
             let entry = &self.files[file_num];
             let path = self.join(&entry.name);
+            if self
+                .links
+                .iter()
+                .any(|link| path != *link && path.starts_with(link))
+            {
+                bail!("{} is under a received link", path.display());
+            }
             if let Some(p) = path.parent() {
                 std::fs::create_dir_all(p).ok();
             }
+            if !entry.link_target.is_empty() {
+                let target = entry.link_target.clone();
+                let is_dir = entry.entry_type.enum_value() == Ok(FileType::DirLink);
+                self.file = None;
+                self.hasher = None;
+                check_link_target(&target)?;
+                // No digest is sent for links, the default choice stands for the user's.
+                if self.enable_overwrite_detection
+                    && self.default_overwrite_strategy != Some(true)
+                    && std::fs::symlink_metadata(&path).is_ok()
+                {
+                    log::info!("Keep {}, not replaced by a link", path.display());
+                    self.file_skipped = true;
+                    return Ok(());
+                }
+                create_link(&path, &target, is_dir)?;
+                self.links.push(path);
+                return Ok(());
+            }
             let path = format!("{}.download", get_string(&path));
             self.file = Some(File::create(&path).await?);
+            self.hasher = Some(sha256::State::new());
+            self.base = None;
         }
-        if block.compressed {
+        if !block.copy_blocks.is_empty() {
+            self.write_copies(&block.copy_blocks).await?;
+        } else if block.compressed {
             let tmp = decompress(&block.data);
             self.file
                 .as_mut()
                 .ok_or(anyhow!("file is None"))?
                 .write_all(&tmp)
                 .await?;
+            if let Some(hasher) = self.hasher.as_mut() {
+                hasher.update(&tmp);
+            }
             self.finished_size += tmp.len() as u64;
         } else {
             self.file
@@ -415,9 +740,73 @@ impl TransferJob {
                 .ok_or(anyhow!("file is None"))?
                 .write_all(&block.data)
                 .await?;
+            if let Some(hasher) = self.hasher.as_mut() {
+                hasher.update(&block.data);
+            }
             self.finished_size += block.data.len() as u64;
         }
         self.transferred += block.data.len() as u64;
+        if !block.hash.is_empty() {
+            self.base = None;
+            self.verify_hash(&block.hash).await?;
+        }
+        Ok(())
+    }
+
+    // Copies blocks of the existing file, which stays in place until `modify_time`.
+    async fn write_copies(&mut self, indexes: &[u32]) -> ResultType<()> {
+        let block_size = match self.delta_base {
+            Some((file_num, block_size)) if file_num == self.file_num => block_size,
+            _ => bail!("Unexpected block references"),
+        };
+        if self.base.is_none() {
+            let path = self.join(&self.files[self.file_num as usize].name);
+            self.base = Some(File::open(&path).await?);
+        }
+        let mut buf = vec![0; block_size];
+        for index in indexes {
+            let base = self.base.as_mut().ok_or(anyhow!("base is None"))?;
+            base.seek(SeekFrom::Start(*index as u64 * block_size as u64))
+                .await?;
+            let mut n = 0;
+            while n < block_size {
+                match base.read(&mut buf[n..]).await? {
+                    0 => break,
+                    m => n += m,
+                }
+            }
+            if n == 0 {
+                bail!("No block {} in the existing file", index);
+            }
+            self.file
+                .as_mut()
+                .ok_or(anyhow!("file is None"))?
+                .write_all(&buf[..n])
+                .await?;
+            if let Some(hasher) = self.hasher.as_mut() {
+                hasher.update(&buf[..n]);
+            }
+            self.finished_size += n as u64;
+        }
+        Ok(())
+    }
+
+    // Checked before `modify_time` moves the `.download` file into place, which
+    // never happens on a mismatch as the file is removed.
+    async fn verify_hash(&mut self, hash: &[u8]) -> ResultType<()> {
+        let actual = match self.hasher.take() {
+            Some(hasher) => hasher.finalize(),
+            None => return Ok(()),
+        };
+        if actual.as_ref() != hash {
+            self.file.take();
+            self.remove_download_file();
+            let name = &self.files[self.file_num as usize].name;
+            bail!("Content hash mismatch of {}, the file is corrupted", name);
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.sync_all().await?;
+        }
         Ok(())
     }
 
@@ -436,6 +825,17 @@ impl TransferJob {
             self.file.take();
             return Ok(None);
         }
+        if !self.files[file_num].link_target.is_empty() {
+            // Recreated from the listing, the empty block only marks it done.
+            self.file_num += 1;
+            self.file_confirmed = false;
+            self.file_is_waiting = false;
+            return Ok(Some(FileTransferBlock {
+                id: self.id,
+                file_num: file_num as _,
+                ..Default::default()
+            }));
+        }
         let name = &self.files[file_num].name;
         if self.file.is_none() {
             match File::open(self.join(name)).await {
@@ -443,6 +843,7 @@ impl TransferJob {
                     self.file = Some(file);
                     self.file_confirmed = false;
                     self.file_is_waiting = false;
+                    self.hasher = Some(sha256::State::new());
                 }
                 Err(err) => {
                     self.file_num += 1;
@@ -459,7 +860,14 @@ impl TransferJob {
             }
             return Ok(None);
         }
-        const BUF_SIZE: usize = 128 * 1024;
+        if let Some(sigs) = self.delta_sigs.take() {
+            self.encoder = Some(delta::Encoder::new(&sigs));
+            self.delta_eof = false;
+        }
+        if self.encoder.is_some() {
+            let can_compress = !is_compressed_file(name);
+            return self.read_delta(can_compress).await;
+        }
         let mut buf: Vec<u8> = vec![0; BUF_SIZE];
         let mut compressed = false;
         let mut offset: usize = 0;
@@ -487,12 +895,19 @@ impl TransferJob {
             }
         }
         unsafe { buf.set_len(offset) };
+        let mut hash = Vec::new();
         if offset == 0 {
             self.file_num += 1;
             self.file = None;
             self.file_confirmed = false;
             self.file_is_waiting = false;
+            if let Some(hasher) = self.hasher.take() {
+                hash = hasher.finalize().as_ref().to_vec();
+            }
         } else {
+            if let Some(hasher) = self.hasher.as_mut() {
+                hasher.update(&buf);
+            }
             self.finished_size += offset as u64;
             if !is_compressed_file(name) {
                 let tmp = compress(&buf);
@@ -508,10 +923,79 @@ impl TransferJob {
             file_num: file_num as _,
             data: buf.into(),
             compressed,
+            hash: hash.into(),
             ..Default::default()
         }))
     }
 
+    async fn read_delta(&mut self, can_compress: bool) -> ResultType<Option<FileTransferBlock>> {
+        let file_num = self.file_num;
+        let mut buf: Vec<u8> = vec![0; BUF_SIZE];
+        let chunk = loop {
+            let encoder = self.encoder.as_mut().ok_or(anyhow!("encoder is None"))?;
+            if let Some(chunk) = encoder.next(self.delta_eof) {
+                break Some(chunk);
+            }
+            if self.delta_eof {
+                break None;
+            }
+            match self
+                .file
+                .as_mut()
+                .ok_or(anyhow!("file is None"))?
+                .read(&mut buf)
+                .await
+            {
+                Err(err) => {
+                    self.file_num += 1;
+                    self.file = None;
+                    self.encoder = None;
+                    self.file_confirmed = false;
+                    self.file_is_waiting = false;
+                    return Err(err.into());
+                }
+                Ok(0) => self.delta_eof = true,
+                Ok(n) => {
+                    if let Some(hasher) = self.hasher.as_mut() {
+                        hasher.update(&buf[..n]);
+                    }
+                    self.finished_size += n as u64;
+                    encoder.push(&buf[..n]);
+                }
+            }
+        };
+        let mut block = FileTransferBlock {
+            id: self.id,
+            file_num,
+            ..Default::default()
+        };
+        match chunk {
+            Some(delta::Chunk::Literal(mut data)) => {
+                if can_compress {
+                    let tmp = compress(&data);
+                    if tmp.len() < data.len() {
+                        data = tmp;
+                        block.compressed = true;
+                    }
+                }
+                self.transferred += data.len() as u64;
+                block.data = data.into();
+            }
+            Some(delta::Chunk::Copy(indexes)) => block.copy_blocks = indexes,
+            None => {
+                self.file_num += 1;
+                self.file = None;
+                self.encoder = None;
+                self.file_confirmed = false;
+                self.file_is_waiting = false;
+                if let Some(hasher) = self.hasher.take() {
+                    block.hash = hasher.finalize().as_ref().to_vec().into();
+                }
+            }
+        }
+        Ok(Some(block))
+    }
+
     async fn send_current_digest(&mut self, stream: &mut Stream) -> ResultType<()> {
         let mut msg = Message::new();
         let mut resp = FileResponse::new();
@@ -530,6 +1014,7 @@ impl TransferJob {
             file_num: self.file_num,
             last_modified,
             file_size: meta.len(),
+            supports_delta: true,
             ..Default::default()
         });
         msg.set_file_response(resp);
@@ -629,12 +1114,106 @@ impl TransferJob {
                 Some(file_transfer_send_confirm_request::Union::OffsetBlk(_offset)) => {
                     self.set_file_confirmed(true);
                 }
+                // Too late once the file is being sent, it would be garbled.
+                Some(file_transfer_send_confirm_request::Union::Delta(ref sigs)) => {
+                    if !self.file_confirmed {
+                        self.delta_sigs = Some(sigs.clone());
+                        self.set_file_confirmed(true);
+                    }
+                }
                 _ => {}
             }
         }
         true
     }
 
+    #[inline]
+    pub fn set_peer_supports_delta(&mut self, supports_delta: bool) {
+        self.peer_supports_delta = supports_delta;
+    }
+
+    #[inline]
+    pub fn peer_supports_delta(&self) -> bool {
+        self.peer_supports_delta
+    }
+
+    #[inline]
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    #[inline]
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// In KiB/s, 0 for unlimited.
+    #[inline]
+    pub fn set_rate_limit(&mut self, kib_per_sec: u32) {
+        self.limiter.set_rate(kib_per_sec as u64 * 1024);
+    }
+
+    pub fn control(&mut self, control: &FileJobControl) {
+        match control.union {
+            Some(file_job_control::Union::Paused(paused)) => self.set_paused(paused),
+            Some(file_job_control::Union::RateLimit(rate)) => self.set_rate_limit(rate),
+            _ => {}
+        }
+    }
+
+    /// The confirm of a receiver which overwrites file `file_num`: a delta against
+    /// the existing copy if the peer supports it and the file is large enough,
+    /// a full transfer otherwise.
+    pub async fn overwrite_confirm(&mut self, file_num: i32) -> FileTransferSendConfirmRequest {
+        let mut req = FileTransferSendConfirmRequest {
+            id: self.id,
+            file_num,
+            union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(0)),
+            ..Default::default()
+        };
+        if let Some(sigs) = self.delta_signatures(file_num).await {
+            req.set_delta(sigs);
+        }
+        req
+    }
+
+    async fn delta_signatures(&mut self, file_num: i32) -> Option<FileDeltaSignatures> {
+        if !self.peer_supports_delta {
+            return None;
+        }
+        let entry = self.files.get(file_num as usize)?;
+        let path = self.join(&entry.name);
+        // Only the existing copy counts, the cm is not told the size of uploads.
+        let len = std::fs::metadata(&path).ok()?.len();
+        if len < delta::MIN_DELTA_SIZE {
+            return None;
+        }
+        match tokio::task::spawn_blocking(move || delta::signatures(&path)).await {
+            Ok(Ok(sigs)) => {
+                log::info!(
+                    "id: {}, file_num: {}, delta of {} blocks",
+                    self.id,
+                    file_num,
+                    sigs.blocks.len()
+                );
+                self.delta_base = Some((file_num, sigs.block_size as usize));
+                Some(sigs)
+            }
+            Ok(Err(err)) => {
+                log::warn!(
+                    "Failed to read the signatures of file {}: {}",
+                    file_num,
+                    err
+                );
+                None
+            }
+            Err(err) => {
+                log::error!("Failed to compute signatures: {}", err);
+                None
+            }
+        }
+    }
+
     #[inline]
     pub fn gen_meta(&self) -> TransferJobMeta {
         TransferJobMeta {
@@ -711,6 +1290,18 @@ pub fn new_receive(id: i32, path: String, file_num: i32, files: Vec<FileEntry>)
 
 #[inline]
 pub fn new_send(id: i32, path: String, file_num: i32, include_hidden: bool) -> Message {
+    new_send_files(id, path, file_num, include_hidden, Vec::new())
+}
+
+/// Like `new_send`, but only `files` of the listing are sent.
+#[inline]
+pub fn new_send_files(
+    id: i32,
+    path: String,
+    file_num: i32,
+    include_hidden: bool,
+    files: Vec<String>,
+) -> Message {
     log::info!("new send: {},id : {}", path, id);
     let mut action = FileAction::new();
     action.set_send(FileTransferSendRequest {
@@ -718,6 +1309,7 @@ pub fn new_send(id: i32, path: String, file_num: i32, include_hidden: bool) -> M
         path,
         include_hidden,
         file_num,
+        files,
         ..Default::default()
     });
     let mut msg_out = Message::new();
@@ -725,6 +1317,25 @@ pub fn new_send(id: i32, path: String, file_num: i32, include_hidden: bool) -> M
     msg_out
 }
 
+#[inline]
+pub fn new_stat(id: i32, mut stat: FileStat) -> Message {
+    stat.id = id;
+    let mut resp = FileResponse::new();
+    resp.set_stat(stat);
+    let mut msg_out = Message::new();
+    msg_out.set_file_response(resp);
+    msg_out
+}
+
+#[inline]
+pub fn new_job_control(control: FileJobControl) -> Message {
+    let mut action = FileAction::new();
+    action.set_job_control(control);
+    let mut msg_out = Message::new();
+    msg_out.set_file_action(action);
+    msg_out
+}
+
 #[inline]
 pub fn new_done(id: i32, file_num: i32) -> Message {
     let mut resp = FileResponse::new();
@@ -748,15 +1359,136 @@ pub fn get_job(id: i32, jobs: &mut [TransferJob]) -> Option<&mut TransferJob> {
     jobs.iter_mut().find(|x| x.id() == id)
 }
 
+/// Nothing to send until one of `jobs` is resumed, the timer driving them may slow down.
+#[inline]
+pub fn all_paused(jobs: &[TransferJob]) -> bool {
+    jobs.iter().all(|job| job.paused)
+}
+
+/// Token bucket, a rate of 0 is unlimited. A block may overdraw it, the
+/// following blocks then wait until it is paid back.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    // bytes per second
+    rate: u64,
+    tokens: f64,
+    last: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_rate(&mut self, rate: u64) {
+        if rate != self.rate {
+            self.rate = rate;
+            self.tokens = self.tokens.min(Self::burst(rate));
+        }
+    }
+
+    #[inline]
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    // a quarter of a second, so that bursts do not delay video and input
+    #[inline]
+    fn burst(rate: u64) -> f64 {
+        rate as f64 / 4.
+    }
+
+    pub fn ready(&mut self, now: Instant) -> bool {
+        if self.rate == 0 {
+            return true;
+        }
+        let burst = Self::burst(self.rate);
+        self.tokens = match self.last {
+            Some(last) => {
+                let elapsed = now.saturating_duration_since(last).as_secs_f64();
+                (self.tokens + elapsed * self.rate as f64).min(burst)
+            }
+            None => burst,
+        };
+        self.last = Some(now);
+        self.tokens > 0.
+    }
+
+    pub fn consume(&mut self, bytes: usize) {
+        if self.rate > 0 {
+            self.tokens -= bytes as f64;
+        }
+    }
+}
+
+/// While interactive traffic is delayed, transfers are held to this rate.
+const CONGESTED_RATE: u64 = 128 * 1024;
+const LIMIT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+static CONGESTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Default)]
+struct GlobalLimiter {
+    limiter: RateLimiter,
+    // from the option "file-transfer-rate-limit" in KiB/s, and when it was read
+    configured: u64,
+    checked: Option<Instant>,
+}
+
+lazy_static::lazy_static! {
+    static ref GLOBAL_LIMITER: Mutex<GlobalLimiter> = Default::default();
+}
+
+/// Set from the video QoS, which sees the delay of the interactive traffic.
+/// It is shared by all connections of the process, like the global limit.
+pub fn set_congested(congested: bool) {
+    if CONGESTED.swap(congested, Ordering::Relaxed) != congested {
+        log::info!("file transfers yield to interactive traffic: {}", congested);
+    }
+}
+
+fn global_ready(now: Instant) -> bool {
+    let mut global = GLOBAL_LIMITER.lock().unwrap();
+    if global
+        .checked
+        .is_none_or(|t| now.saturating_duration_since(t) >= LIMIT_CHECK_INTERVAL)
+    {
+        global.checked = Some(now);
+        global.configured = Config::get_option("file-transfer-rate-limit")
+            .trim()
+            .parse::<u64>()
+            .unwrap_or(0)
+            * 1024;
+    }
+    let mut rate = global.configured;
+    if CONGESTED.load(Ordering::Relaxed) && (rate == 0 || rate > CONGESTED_RATE) {
+        rate = CONGESTED_RATE;
+    }
+    global.limiter.set_rate(rate);
+    global.limiter.ready(now)
+}
+
+#[inline]
+fn global_consume(bytes: usize) {
+    GLOBAL_LIMITER.lock().unwrap().limiter.consume(bytes);
+}
+
 pub async fn handle_read_jobs(
     jobs: &mut Vec<TransferJob>,
     stream: &mut crate::Stream,
 ) -> ResultType<()> {
     let mut finished = Vec::new();
+    let now = Instant::now();
     for job in jobs.iter_mut() {
-        if job.is_last_job {
+        if job.is_last_job || job.paused || !job.limiter.ready(now) {
             continue;
         }
+        if !global_ready(now) {
+            break;
+        }
         match job.read(stream).await {
             Err(err) => {
                 stream
@@ -764,7 +1496,10 @@ pub async fn handle_read_jobs(
                     .await?;
             }
             Ok(Some(block)) => {
+                let len = block.data.len();
                 stream.send(&new_block(block)).await?;
+                job.limiter.consume(len);
+                global_consume(len);
             }
             Ok(None) => {
                 if job.job_completed() {
@@ -818,6 +1553,155 @@ pub fn create_dir(dir: &str) -> ResultType<()> {
     Ok(())
 }
 
+/// Rename or move `from` to `to`, also across file systems.
+pub fn rename(from: &str, to: &str) -> ResultType<()> {
+    let (from, to) = (get_path(from), get_path(to));
+    if to.exists() {
+        bail!("{} already exists", to.display());
+    }
+    if let Some(p) = to.parent() {
+        std::fs::create_dir_all(p)?;
+    }
+    match std::fs::rename(&from, &to) {
+        Ok(()) => return Ok(()),
+        // Another file system, which rename(2) cannot cross.
+        Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {}
+        Err(err) => return Err(err.into()),
+    }
+    copy_path(&from, &to)?;
+    if std::fs::symlink_metadata(&from)?.is_dir() {
+        std::fs::remove_dir_all(&from)?;
+    } else {
+        std::fs::remove_file(&from)?;
+    }
+    Ok(())
+}
+
+/// Copy a file or a whole directory, links are copied as links.
+pub fn copy(from: &str, to: &str, overwrite: bool) -> ResultType<()> {
+    let (from, to) = (get_path(from), get_path(to));
+    if !overwrite && std::fs::symlink_metadata(&to).is_ok() {
+        bail!("{} already exists", to.display());
+    }
+    if let (Ok(a), Ok(b)) = (from.canonicalize(), to.canonicalize()) {
+        if a == b {
+            bail!("Cannot copy {} onto itself", from.display());
+        }
+    }
+    if let (Ok(a), Some(Ok(b))) = (from.canonicalize(), to.parent().map(|p| p.canonicalize())) {
+        if b.starts_with(&a) {
+            bail!("Cannot copy {} into itself", from.display());
+        }
+    }
+    if let Some(p) = to.parent() {
+        std::fs::create_dir_all(p)?;
+    }
+    copy_path(&from, &to)
+}
+
+fn copy_path(from: &Path, to: &Path) -> ResultType<()> {
+    let meta = std::fs::symlink_metadata(from)?;
+    if meta.file_type().is_symlink() {
+        let target = get_string(&std::fs::read_link(from)?);
+        create_link(to, &target, from.is_dir())?;
+    } else if meta.is_dir() {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_path(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        std::fs::set_permissions(to, meta.permissions()).ok();
+    } else {
+        // Keeps the permissions.
+        std::fs::copy(from, to)?;
+        filetime::set_file_mtime(to, filetime::FileTime::from_last_modification_time(&meta)).ok();
+    }
+    Ok(())
+}
+
+/// Metadata of `path` and the space of its file system.
+pub fn stat(path: &str) -> ResultType<FileStat> {
+    let p = get_path(path);
+    let meta = std::fs::symlink_metadata(&p)?;
+    let secs = |t: std::io::Result<SystemTime>| {
+        t.ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    };
+    let mut entry = FileEntry {
+        name: get_file_name(&p),
+        modified_time: secs(meta.modified()),
+        ..Default::default()
+    };
+    if meta.file_type().is_symlink() {
+        entry.link_target = get_string(&std::fs::read_link(&p)?);
+        entry.entry_type = if p.is_dir() {
+            FileType::DirLink.into()
+        } else {
+            FileType::FileLink.into()
+        };
+    } else if meta.is_dir() {
+        entry.entry_type = FileType::Dir.into();
+    } else {
+        entry.entry_type = FileType::File.into();
+        entry.size = meta.len();
+    }
+    set_attrs(&mut entry, &meta, &mut Names::default());
+    let (disk_free, disk_total) = disk_space(&p).unwrap_or_default();
+    Ok(FileStat {
+        path: path.to_owned(),
+        entry: Some(entry).into(),
+        created_time: secs(meta.created()),
+        disk_free,
+        disk_total,
+        ..Default::default()
+    })
+}
+
+// Free and total bytes of the file system holding `path`.
+#[cfg(unix)]
+fn disk_space(path: &Path) -> ResultType<(u64, u64)> {
+    use std::os::unix::ffi::OsStrExt;
+    let dir = if path.is_dir() {
+        path
+    } else {
+        path.parent().unwrap_or(path)
+    };
+    let c = std::ffi::CString::new(dir.as_os_str().as_bytes())?;
+    let mut st: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c.as_ptr(), &mut st) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let frsize = st.f_frsize as u64;
+    Ok((st.f_bavail as u64 * frsize, st.f_blocks as u64 * frsize))
+}
+
+#[cfg(windows)]
+fn disk_space(path: &Path) -> ResultType<(u64, u64)> {
+    use std::os::windows::ffi::OsStrExt;
+    let dir = if path.is_dir() {
+        path
+    } else {
+        path.parent().unwrap_or(path)
+    };
+    let wide: Vec<u16> = dir.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut free: winapi::um::winnt::ULARGE_INTEGER = unsafe { std::mem::zeroed() };
+    let mut total: winapi::um::winnt::ULARGE_INTEGER = unsafe { std::mem::zeroed() };
+    let ok = unsafe {
+        winapi::um::fileapi::GetDiskFreeSpaceExW(
+            wide.as_ptr(),
+            &mut free,
+            &mut total,
+            std::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(unsafe { (*free.QuadPart(), *total.QuadPart()) })
+}
+
 #[inline]
 pub fn transform_windows_path(entries: &mut Vec<FileEntry>) {
     for entry in entries {
@@ -861,3 +1745,290 @@ pub fn is_write_need_confirmation(
         Ok(DigestCheckResult::NoSuchFile)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fs-test-{}-{}", name, std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_job(dir: &Path, files: Vec<FileEntry>) -> TransferJob {
+        TransferJob::new_write(
+            1,
+            "".to_owned(),
+            get_string(dir),
+            0,
+            false,
+            false,
+            files,
+            false,
+        )
+    }
+
+    fn block(file_num: i32, data: &[u8], hash: &[u8]) -> FileTransferBlock {
+        FileTransferBlock {
+            id: 1,
+            file_num,
+            data: data.to_vec().into(),
+            hash: hash.to_vec().into(),
+            ..Default::default()
+        }
+    }
+
+    fn file(name: &str, size: usize) -> FileEntry {
+        FileEntry {
+            name: name.to_owned(),
+            size: size as _,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_hash_match() {
+        let dir = test_dir("hash-match");
+        let data = b"hello world";
+        let mut job = write_job(&dir, vec![file("a.bin", data.len())]);
+        job.write(block(0, &data[..5], &[])).await.unwrap();
+        job.write(block(0, &data[5..], &[])).await.unwrap();
+        // the end of the file carries the hash of all of it
+        let hash = sha256::hash(data);
+        job.write(block(0, &[], hash.as_ref())).await.unwrap();
+        job.modify_time();
+        assert_eq!(std::fs::read(dir.join("a.bin")).unwrap(), data);
+        assert!(!dir.join("a.bin.download").exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_write_hash_mismatch() {
+        let dir = test_dir("hash-mismatch");
+        let data = b"hello world";
+        let mut job = write_job(&dir, vec![file("a.bin", data.len())]);
+        job.write(block(0, b"hello w0rld", &[])).await.unwrap();
+        let hash = sha256::hash(data);
+        let err = job.write(block(0, &[], hash.as_ref())).await.unwrap_err();
+        assert!(err.to_string().contains("a.bin"));
+        job.modify_time();
+        // neither the corrupted file nor its download is left
+        assert!(!dir.join("a.bin").exists());
+        assert!(!dir.join("a.bin.download").exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    fn link(name: &str, target: &str) -> FileEntry {
+        FileEntry {
+            name: name.to_owned(),
+            entry_type: FileType::FileLink.into(),
+            link_target: target.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_write_link() {
+        let dir = test_dir("link");
+        let mut job = write_job(&dir, vec![link("sub/a", "b"), link("c", "./d/e")]);
+        job.write(block(0, &[], &[])).await.unwrap();
+        job.write(block(1, &[], &[])).await.unwrap();
+        job.modify_time();
+        assert_eq!(
+            std::fs::read_link(dir.join("sub/a")).unwrap(),
+            Path::new("b")
+        );
+        assert_eq!(
+            std::fs::read_link(dir.join("c")).unwrap(),
+            Path::new("./d/e")
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_write_link_escaping() {
+        let dir = test_dir("link-escaping");
+        let files = vec![link("a", "/etc"), link("b", "../x"), link("c", "d/../../x")];
+        let mut job = write_job(&dir, files);
+        for i in 0..3 {
+            assert!(job.write(block(i, &[], &[])).await.is_err());
+        }
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_write_under_link() {
+        let dir = test_dir("under-link");
+        std::fs::create_dir_all(dir.join("real")).unwrap();
+        let files = vec![link("a", "real"), file("a/b.txt", 1)];
+        let mut job = write_job(&dir, files);
+        job.write(block(0, &[], &[])).await.unwrap();
+        let err = job.write(block(1, b"x", &[])).await.unwrap_err();
+        assert!(err.to_string().contains("link"));
+        assert_eq!(std::fs::read_dir(dir.join("real")).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_write_link_overwrite() {
+        let dir = test_dir("link-overwrite");
+        std::fs::write(dir.join("a"), b"kept").unwrap();
+        std::fs::write(dir.join("b"), b"replaced").unwrap();
+        let files = vec![link("a", "x"), link("b", "x")];
+        let mut job = TransferJob::new_write(
+            1,
+            "".to_owned(),
+            get_string(&dir),
+            0,
+            false,
+            false,
+            files,
+            true,
+        );
+        // no choice for all yet, the existing file is kept
+        job.write(block(0, &[], &[])).await.unwrap();
+        assert!(job.file_skipped());
+        job.set_overwrite_strategy(Some(true));
+        job.write(block(1, &[], &[])).await.unwrap();
+        job.modify_time();
+        assert_eq!(std::fs::read(dir.join("a")).unwrap(), b"kept");
+        assert_eq!(std::fs::read_link(dir.join("b")).unwrap(), Path::new("x"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_write_attrs() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = test_dir("attrs");
+        let mut entry = file("a.sh", 1);
+        entry.mode = 0o4755;
+        entry.modified_time = 1_000_000;
+        entry.accessed_time = 2_000_000;
+        let mut job = write_job(&dir, vec![entry]);
+        job.write(block(0, b"x", &[])).await.unwrap();
+        job.modify_time();
+        let meta = std::fs::metadata(dir.join("a.sh")).unwrap();
+        // no setuid from the peer
+        assert_eq!(meta.permissions().mode() & 0o7777, 0o755);
+        let mtime = filetime::FileTime::from_last_modification_time(&meta);
+        let atime = filetime::FileTime::from_last_access_time(&meta);
+        assert_eq!(mtime.unix_seconds(), 1_000_000);
+        assert_eq!(atime.unix_seconds(), 2_000_000);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_drop_links() {
+        let mut job = write_job(Path::new("."), vec![file("a", 1), link("b", "c")]);
+        job.drop_links();
+        assert_eq!(job.files().len(), 1);
+        assert!(can_send_links(get_version_number("1.2.2")));
+        assert!(!can_send_links(get_version_number("1.2.1")));
+    }
+
+    #[test]
+    fn test_rename() {
+        let dir = test_dir("rename");
+        std::fs::write(dir.join("a"), b"a").unwrap();
+        std::fs::write(dir.join("b"), b"b").unwrap();
+        let path = |name: &str| get_string(&dir.join(name));
+        rename(&path("a"), &path("sub/c")).unwrap();
+        assert_eq!(std::fs::read(dir.join("sub/c")).unwrap(), b"a");
+        assert!(!dir.join("a").exists());
+        // neither replaced nor copied over
+        assert!(rename(&path("b"), &path("sub/c")).is_err());
+        assert!(rename(&path("missing"), &path("d")).is_err());
+        assert!(!dir.join("d").exists());
+        assert_eq!(std::fs::read(dir.join("b")).unwrap(), b"b");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_copy() {
+        let dir = test_dir("copy");
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        std::fs::write(dir.join("a/b/c"), b"c").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("b/c", dir.join("a/l")).unwrap();
+        let path = |name: &str| get_string(&dir.join(name));
+        copy(&path("a"), &path("d"), false).unwrap();
+        assert_eq!(std::fs::read(dir.join("d/b/c")).unwrap(), b"c");
+        #[cfg(unix)]
+        assert_eq!(
+            std::fs::read_link(dir.join("d/l")).unwrap(),
+            Path::new("b/c")
+        );
+        assert!(copy(&path("a"), &path("d"), false).is_err());
+        assert!(copy(&path("a"), &path("a/b/e"), false).is_err());
+        assert!(!dir.join("a/b/e").exists());
+        copy(&path("a/b/c"), &path("d/b/c"), true).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_stat() {
+        let dir = test_dir("stat");
+        std::fs::write(dir.join("a"), b"abc").unwrap();
+        let st = stat(&get_string(&dir.join("a"))).unwrap();
+        assert_eq!(st.entry.name, "a");
+        assert_eq!(st.entry.size, 3);
+        assert_eq!(st.entry.entry_type.enum_value(), Ok(FileType::File));
+        assert!(stat(&get_string(&dir.join("b"))).is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let now = Instant::now();
+        let ms = |n| now + Duration::from_millis(n);
+        let mut unlimited = RateLimiter::default();
+        unlimited.consume(1 << 30);
+        assert!(unlimited.ready(now));
+
+        // 4 KiB/s, a burst of 1 KiB
+        let mut limiter = RateLimiter::new(4096);
+        assert!(limiter.ready(now));
+        limiter.consume(2048);
+        assert!(!limiter.ready(now));
+        // the overdraft of 1 KiB is paid back after 250 ms
+        assert!(!limiter.ready(ms(250)));
+        assert!(limiter.ready(ms(300)));
+        // idle time does not add up beyond the burst
+        assert!(limiter.ready(ms(10_000)));
+        limiter.consume(1025);
+        assert!(!limiter.ready(ms(10_000)));
+
+        // lowering the rate drops what is above its burst
+        let mut limiter = RateLimiter::new(1 << 20);
+        assert!(limiter.ready(now));
+        limiter.set_rate(4096);
+        limiter.consume(1025);
+        assert!(!limiter.ready(now));
+        limiter.set_rate(0);
+        assert!(limiter.ready(now));
+    }
+
+    #[test]
+    fn test_all_paused() {
+        let mut jobs = vec![write_job(Path::new("."), vec![])];
+        jobs.push(write_job(Path::new("."), vec![]));
+        assert!(!all_paused(&jobs));
+        jobs[0].control(&FileJobControl {
+            id: 1,
+            union: Some(file_job_control::Union::Paused(true)),
+            ..Default::default()
+        });
+        assert!(!all_paused(&jobs));
+        jobs[1].set_paused(true);
+        assert!(all_paused(&jobs));
+        assert!(all_paused(&[]));
+    }
+}
@@ -27,17 +27,21 @@ pub mod quic;
 pub use anyhow::{self, bail};
 pub use futures_util;
 pub mod config;
+pub mod delta;
+pub mod dir_sync;
 pub mod fs;
 pub use lazy_static;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub use mac_address;
 pub use rand;
 pub use regex;
+pub use serde_json;
 pub use sodiumoxide;
 pub use tokio_socks;
 pub use tokio_socks::IntoTargetAddr;
 pub use tokio_socks::TargetAddr;
 pub mod password_security;
+pub mod rekey;
 pub use chrono;
 pub use directories_next;
 pub use libc;
@@ -1,135 +1,581 @@
-use crate::{allow_err, anyhow::anyhow, ResultType};
+// QUIC transport with the surface of `tcp::FramedStream`.
+//
+// Each side sends on one unidirectional stream per lane, so that video frames
+// and file blocks queued on their own streams do not hold back input. Until the
+// key is set everything goes on the control lane, which keeps the handshake in
+// order. A lane stream starts with the number of control messages sent before
+// it was opened, and the receiver delivers nothing from it before having
+// delivered those, so e.g. the first video frame never overtakes the login
+// response.
+//
+// TLS is only there because QUIC requires it, the certificate is self-signed
+// and not verified. The peers authenticate and encrypt with `set_key` as they
+// do over TCP, but the key is never replaced, see the `rekey` module.
+
+use crate::{bail, bytes_codec::BytesCodec, ResultType};
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
 use protobuf::Message;
-use std::{net::SocketAddr, sync::Arc};
-use tokio::{self, stream::StreamExt, sync::mpsc};
+use sodiumoxide::crypto::secretbox::{self, Key, Nonce};
+use std::{
+    convert::TryInto,
+    io::{Error, ErrorKind},
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tokio_util::codec::{FramedRead, FramedWrite};
 
-const QUIC_HBB: &[&[u8]] = &[b"hbb"];
+const QUIC_HBB: &[u8] = b"hbb";
 const SERVER_NAME: &str = "hbb";
+const KEEP_ALIVE: Duration = Duration::from_secs(5);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+// for what is still queued when a connection is dropped
+const CLOSE_TIMEOUT: u64 = 3_000;
+// frames buffered per lane, beyond that QUIC flow control holds the sender back
+const LANE_CAPACITY: usize = 32;
+// lane and number of control messages sent before
+const HEADER_LEN: usize = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    Control = 0,
+    Input = 1,
+    Video = 2,
+    File = 3,
+}
+
+const LANES: usize = 4;
+
+impl Lane {
+    fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(Lane::Control),
+            1 => Some(Lane::Input),
+            2 => Some(Lane::Video),
+            3 => Some(Lane::File),
+            _ => None,
+        }
+    }
 
-type Sender = mpsc::UnboundedSender<Value>;
-type Receiver = mpsc::UnboundedReceiver<Value>;
+    /// Of a serialized `message_proto::Message`, whose only field is the one
+    /// set in its union, so the lane follows from the first tag.
+    pub fn of(data: &[u8]) -> Self {
+        let mut tag: u64 = 0;
+        for (i, b) in data.iter().take(5).enumerate() {
+            tag |= ((b & 0x7f) as u64) << (7 * i);
+            if b & 0x80 == 0 {
+                return Self::of_field(tag >> 3);
+            }
+        }
+        Lane::Control
+    }
+
+    // field numbers of `Message` in message.proto
+    fn of_field(field: u64) -> Self {
+        match field {
+            // video_frame, audio_frame
+            6 | 11 => Lane::Video,
+            // mouse_event, cursor_data, cursor_position, cursor_id, key_event,
+            // pointer_device_event
+            10 | 12 | 13 | 14 | 15 | 26 => Lane::Input,
+            // file_action, file_response
+            17 | 18 => Lane::File,
+            _ => Lane::Control,
+        }
+    }
+
+    fn priority(self) -> i32 {
+        match self {
+            Lane::Input => 3,
+            Lane::Control => 2,
+            Lane::Video => 1,
+            Lane::File => 0,
+        }
+    }
+}
 
 pub fn new_server(socket: std::net::UdpSocket) -> ResultType<(Server, SocketAddr)> {
-    let mut transport_config = quinn::TransportConfig::default();
-    transport_config.stream_window_uni(0);
-    let mut server_config = quinn::ServerConfig::default();
-    server_config.transport = Arc::new(transport_config);
-    let mut server_config = quinn::ServerConfigBuilder::new(server_config);
-    server_config.protocols(QUIC_HBB);
-    // server_config.enable_keylog();
-    // server_config.use_stateless_retry(true);
-    let mut endpoint = quinn::Endpoint::builder();
-    endpoint.listen(server_config.build());
-    let (end, incoming) = endpoint.with_socket(socket)?;
-    Ok((Server { incoming }, end.local_addr()?))
+    let cert = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_owned()])?;
+    let key = rustls::PrivateKey(cert.serialize_private_key_der());
+    let cert = rustls::Certificate(cert.serialize_der()?);
+    let mut crypto = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert], key)?;
+    crypto.alpn_protocols = vec![QUIC_HBB.to_vec()];
+    let mut server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    server_config.transport = Arc::new(transport_config());
+    let endpoint = quinn::Endpoint::new(
+        quinn::EndpointConfig::default(),
+        Some(server_config),
+        socket,
+        quinn::TokioRuntime,
+    )?;
+    let addr = endpoint.local_addr()?;
+    Ok((Server { endpoint }, addr))
 }
 
 pub async fn new_client(local_addr: &SocketAddr, peer: &SocketAddr) -> ResultType<Connection> {
-    let mut endpoint = quinn::Endpoint::builder();
-    let mut client_config = quinn::ClientConfigBuilder::default();
-    client_config.protocols(QUIC_HBB);
-    //client_config.enable_keylog();
-    endpoint.default_client_config(client_config.build());
-    let (endpoint, _) = endpoint.bind(local_addr)?;
-    let new_conn = endpoint.connect(peer, SERVER_NAME)?.await?;
-    Connection::new_for_client(new_conn.connection).await
+    let mut crypto = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(SkipServerVerification))
+        .with_no_client_auth();
+    crypto.alpn_protocols = vec![QUIC_HBB.to_vec()];
+    let mut client_config = quinn::ClientConfig::new(Arc::new(crypto));
+    client_config.transport_config(Arc::new(transport_config()));
+    let mut endpoint = quinn::Endpoint::new(
+        quinn::EndpointConfig::default(),
+        None,
+        std::net::UdpSocket::bind(local_addr)?,
+        quinn::TokioRuntime,
+    )?;
+    endpoint.set_default_client_config(client_config);
+    let conn = endpoint.connect(*peer, SERVER_NAME)?.await?;
+    let local_addr = endpoint.local_addr()?;
+    Ok(Connection::new(conn, Some(endpoint), local_addr))
+}
+
+fn transport_config() -> quinn::TransportConfig {
+    let mut config = quinn::TransportConfig::default();
+    config
+        .keep_alive_interval(Some(KEEP_ALIVE))
+        .max_idle_timeout(IDLE_TIMEOUT.try_into().ok())
+        .max_concurrent_uni_streams((LANES as u32).into())
+        .max_concurrent_bidi_streams(0u32.into());
+    config
+}
+
+struct SkipServerVerification;
+
+impl rustls::client::ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
 }
 
 pub struct Server {
-    incoming: quinn::Incoming,
+    endpoint: quinn::Endpoint,
 }
 
 impl Server {
     #[inline]
     pub async fn next(&mut self) -> ResultType<Option<Connection>> {
-        Connection::new_for_server(&mut self.incoming).await
+        match self.endpoint.accept().await {
+            Some(connecting) => {
+                let conn = connecting.await?;
+                let local_addr = self.endpoint.local_addr()?;
+                Ok(Some(Connection::new(conn, None, local_addr)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+enum Item {
+    // first on every lane, the number of control messages to deliver before
+    Open(u64),
+    Frame(BytesMut),
+}
+
+type LaneReceiver = mpsc::Receiver<Result<Item, Error>>;
+
+struct Encrypt {
+    key: Key,
+    sent: [u64; LANES],
+    received: [u64; LANES],
+}
+
+impl Encrypt {
+    fn new(key: Key) -> Self {
+        Self {
+            key,
+            sent: [0; LANES],
+            received: [0; LANES],
+        }
+    }
+
+    // Every lane counts on its own, the lane is part of the nonce.
+    fn get_nonce(seqnum: u64, lane: Lane) -> Nonce {
+        let mut nonce = Nonce([0u8; secretbox::NONCEBYTES]);
+        nonce.0[..std::mem::size_of_val(&seqnum)].copy_from_slice(&seqnum.to_le_bytes());
+        nonce.0[std::mem::size_of_val(&seqnum)] = lane as u8;
+        nonce
+    }
+
+    fn enc(&mut self, data: &[u8], lane: Lane) -> Vec<u8> {
+        self.sent[lane as usize] += 1;
+        let nonce = Self::get_nonce(self.sent[lane as usize], lane);
+        secretbox::seal(data, &nonce, &self.key)
+    }
+
+    fn dec(&mut self, bytes: &mut BytesMut, lane: Lane) -> Result<(), Error> {
+        self.received[lane as usize] += 1;
+        let nonce = Self::get_nonce(self.received[lane as usize], lane);
+        match secretbox::open(bytes, &nonce, &self.key) {
+            Ok(res) => {
+                bytes.clear();
+                bytes.extend_from_slice(&res);
+                Ok(())
+            }
+            Err(()) => Err(Error::new(ErrorKind::Other, "decryption error")),
+        }
     }
 }
 
 pub struct Connection {
     conn: quinn::Connection,
-    tx: quinn::SendStream,
-    rx: Receiver,
+    // only for client connections, which have an endpoint of their own
+    endpoint: Option<quinn::Endpoint>,
+    local_addr: SocketAddr,
+    tx: [Option<mpsc::Sender<Bytes>>; LANES],
+    writers: Vec<JoinHandle<()>>,
+    control_sent: u64,
+    rx: [LaneReceiver; LANES],
+    rx_closed: [bool; LANES],
+    barriers: [Option<u64>; LANES],
+    control_received: u64,
+    encrypt: Option<Encrypt>,
+    send_timeout: u64,
 }
 
-type Value = ResultType<Vec<u8>>;
-
 impl Connection {
-    async fn new_for_server(incoming: &mut quinn::Incoming) -> ResultType<Option<Self>> {
-        if let Some(conn) = incoming.next().await {
-            let quinn::NewConnection {
-                connection: conn,
-                // uni_streams,
-                mut bi_streams,
-                ..
-            } = conn.await?;
-            let (tx, rx) = mpsc::unbounded_channel::<Value>();
-            tokio::spawn(async move {
-                loop {
-                    let stream = bi_streams.next().await;
-                    if let Some(stream) = stream {
-                        let stream = match stream {
-                            Err(e) => {
-                                tx.send(Err(e.into())).ok();
-                                break;
-                            }
-                            Ok(s) => s,
-                        };
-                        let cloned = tx.clone();
-                        tokio::spawn(async move {
-                            allow_err!(handle_request(stream.1, cloned).await);
-                        });
-                    } else {
-                        tx.send(Err(anyhow!("Reset by the peer"))).ok();
-                        break;
-                    }
-                }
-                log::info!("Exit connection outer loop");
-            });
-            let tx = conn.open_uni().await?;
-            Ok(Some(Self { conn, tx, rx }))
-        } else {
-            Ok(None)
+    fn new(
+        conn: quinn::Connection,
+        endpoint: Option<quinn::Endpoint>,
+        local_addr: SocketAddr,
+    ) -> Self {
+        let (tx0, rx0) = mpsc::channel(LANE_CAPACITY);
+        let (tx1, rx1) = mpsc::channel(LANE_CAPACITY);
+        let (tx2, rx2) = mpsc::channel(LANE_CAPACITY);
+        let (tx3, rx3) = mpsc::channel(LANE_CAPACITY);
+        tokio::spawn(accept_lanes(
+            conn.clone(),
+            [Some(tx0), Some(tx1), Some(tx2), Some(tx3)],
+        ));
+        Self {
+            conn,
+            endpoint,
+            local_addr,
+            tx: Default::default(),
+            writers: Vec::new(),
+            control_sent: 0,
+            rx: [rx0, rx1, rx2, rx3],
+            rx_closed: [false; LANES],
+            barriers: [None; LANES],
+            control_received: 0,
+            encrypt: None,
+            send_timeout: 0,
         }
     }
 
-    async fn new_for_client(conn: quinn::Connection) -> ResultType<Self> {
-        let (tx, rx_quic) = conn.open_bi().await?;
-        let (tx_mpsc, rx) = mpsc::unbounded_channel::<Value>();
-        tokio::spawn(async move {
-            allow_err!(handle_request(rx_quic, tx_mpsc).await);
-        });
-        Ok(Self { conn, tx, rx })
+    #[inline]
+    pub fn remote_address(&self) -> SocketAddr {
+        self.conn.remote_address()
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn set_send_timeout(&mut self, ms: u64) {
+        self.send_timeout = ms;
+    }
+
+    /// Frames keep their boundaries anyway, so this only drops the key.
+    pub fn set_raw(&mut self) {
+        self.encrypt = None;
+    }
+
+    pub fn is_secured(&self) -> bool {
+        self.encrypt.is_some()
     }
 
+    pub fn set_key(&mut self, key: Key) {
+        self.encrypt = Some(Encrypt::new(key));
+    }
+
+    /// The lanes are not ordered against each other, so there is no point
+    /// from which on all messages could use a new key.
     #[inline]
-    pub async fn next(&mut self) -> Option<Value> {
-        // None is returned when all Sender halves have dropped,
-        // indicating that no further values can be sent on the channel.
-        self.rx.recv().await
+    pub fn can_rekey(&self) -> bool {
+        false
+    }
+
+    pub fn rekey_send(&mut self, _secret: &[u8]) -> ResultType<()> {
+        bail!("rekeying is not supported over QUIC");
+    }
+
+    pub fn rekey_recv(&mut self, _secret: &[u8]) -> ResultType<()> {
+        bail!("rekeying is not supported over QUIC");
     }
 
     #[inline]
-    pub fn remote_address(&self) -> SocketAddr {
-        self.conn.remote_address()
+    pub async fn send(&mut self, msg: &impl Message) -> ResultType<()> {
+        self.send_raw(msg.write_to_bytes()?).await
     }
 
     #[inline]
-    pub async fn send_raw(&mut self, bytes: &[u8]) -> ResultType<()> {
-        self.tx.write_all(bytes).await?;
-        Ok(())
+    pub async fn send_raw(&mut self, msg: Vec<u8>) -> ResultType<()> {
+        let (lane, msg) = match self.encrypt.as_mut() {
+            Some(key) => {
+                let lane = Lane::of(&msg);
+                (lane, key.enc(&msg, lane))
+            }
+            None => (Lane::Control, msg),
+        };
+        self.send_lane(lane, Bytes::from(msg)).await
     }
 
+    /// Sent as is on the control lane.
     #[inline]
-    pub async fn send(&mut self, msg: &dyn Message) -> ResultType<()> {
-        match msg.write_to_bytes() {
-            Ok(bytes) => self.send_raw(&bytes).await?,
-            err => allow_err!(err),
+    pub async fn send_bytes(&mut self, bytes: Bytes) -> ResultType<()> {
+        self.send_lane(Lane::Control, bytes).await
+    }
+
+    async fn send_lane(&mut self, lane: Lane, bytes: Bytes) -> ResultType<()> {
+        let tx = match &self.tx[lane as usize] {
+            Some(tx) => tx.clone(),
+            None => self.open_lane(lane).await?,
+        };
+        let res = if self.send_timeout > 0 {
+            crate::timeout(self.send_timeout, tx.send(bytes)).await?
+        } else {
+            tx.send(bytes).await
+        };
+        if res.is_err() {
+            bail!("QUIC stream of {:?} closed", lane);
+        }
+        if lane == Lane::Control {
+            self.control_sent += 1;
         }
         Ok(())
     }
+
+    async fn open_lane(&mut self, lane: Lane) -> ResultType<mpsc::Sender<Bytes>> {
+        let stream = self.conn.open_uni().await?;
+        stream.set_priority(lane.priority()).ok();
+        let barrier = if lane == Lane::Control {
+            0
+        } else {
+            self.control_sent
+        };
+        let (tx, rx) = mpsc::channel(LANE_CAPACITY);
+        self.writers.push(tokio::spawn(async move {
+            if let Err(err) = write_lane(stream, lane, barrier, rx).await {
+                log::debug!("QUIC stream of {:?}: {}", lane, err);
+            }
+        }));
+        self.tx[lane as usize] = Some(tx.clone());
+        Ok(tx)
+    }
+
+    #[inline]
+    pub async fn next(&mut self) -> Option<Result<BytesMut, Error>> {
+        loop {
+            let ready: Vec<bool> = (0..LANES)
+                .map(|i| {
+                    !self.rx_closed[i]
+                        && self.barriers[i].map_or(true, |b| b <= self.control_received)
+                })
+                .collect();
+            let [rx0, rx1, rx2, rx3] = &mut self.rx;
+            // input first, file data last
+            let (lane, res) = tokio::select! {
+                biased;
+                res = rx1.recv(), if ready[1] => (Lane::Input, res),
+                res = rx0.recv(), if ready[0] => (Lane::Control, res),
+                res = rx2.recv(), if ready[2] => (Lane::Video, res),
+                res = rx3.recv(), if ready[3] => (Lane::File, res),
+                else => return None,
+            };
+            match res {
+                None => self.rx_closed[lane as usize] = true,
+                Some(Ok(Item::Open(barrier))) => self.barriers[lane as usize] = Some(barrier),
+                Some(Ok(Item::Frame(mut bytes))) => {
+                    if lane == Lane::Control {
+                        self.control_received += 1;
+                    }
+                    if let Some(key) = self.encrypt.as_mut() {
+                        if let Err(err) = key.dec(&mut bytes, lane) {
+                            return Some(Err(err));
+                        }
+                    }
+                    return Some(Ok(bytes));
+                }
+                Some(Err(err)) => return Some(Err(err)),
+            }
+        }
+    }
+
+    #[inline]
+    pub async fn next_timeout(&mut self, ms: u64) -> Option<Result<BytesMut, Error>> {
+        if let Ok(res) = crate::timeout(ms, self.next()).await {
+            res
+        } else {
+            None
+        }
+    }
 }
 
-async fn handle_request(rx: quinn::RecvStream, tx: Sender) -> ResultType<()> {
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // Closing right away would discard what is still queued, so the lane
+        // streams are finished first.
+        self.tx = Default::default();
+        let writers = std::mem::take(&mut self.writers);
+        let conn = self.conn.clone();
+        let endpoint = self.endpoint.take();
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    crate::timeout(CLOSE_TIMEOUT, futures::future::join_all(writers))
+                        .await
+                        .ok();
+                    conn.close(0u32.into(), b"");
+                    if let Some(endpoint) = endpoint {
+                        endpoint.wait_idle().await;
+                    }
+                });
+            }
+            Err(_) => conn.close(0u32.into(), b""),
+        }
+    }
+}
+
+async fn write_lane(
+    mut stream: quinn::SendStream,
+    lane: Lane,
+    barrier: u64,
+    mut rx: mpsc::Receiver<Bytes>,
+) -> ResultType<()> {
+    let mut header = [0u8; HEADER_LEN];
+    header[0] = lane as u8;
+    header[1..].copy_from_slice(&barrier.to_le_bytes());
+    stream.write_all(&header).await?;
+    let mut framed = FramedWrite::new(stream, BytesCodec::new());
+    while let Some(bytes) = rx.recv().await {
+        framed.send(bytes).await?;
+    }
+    framed.into_inner().finish().await?;
     Ok(())
 }
+
+async fn accept_lanes(
+    conn: quinn::Connection,
+    mut txs: [Option<mpsc::Sender<Result<Item, Error>>>; LANES],
+) {
+    loop {
+        let mut stream = match conn.accept_uni().await {
+            Ok(stream) => stream,
+            Err(err) => {
+                log::debug!("QUIC connection closed: {}", err);
+                break;
+            }
+        };
+        let mut header = [0u8; HEADER_LEN];
+        if let Err(err) = stream.read_exact(&mut header).await {
+            log::error!("Failed to read QUIC stream header: {}", err);
+            continue;
+        }
+        let tx = match Lane::from_u8(header[0]).and_then(|lane| txs[lane as usize].take()) {
+            Some(tx) => tx,
+            None => {
+                log::error!("Unexpected QUIC stream of lane {}", header[0]);
+                stream.stop(0u32.into()).ok();
+                continue;
+            }
+        };
+        let mut barrier = [0u8; 8];
+        barrier.copy_from_slice(&header[1..]);
+        let barrier = u64::from_le_bytes(barrier);
+        tokio::spawn(async move {
+            if tx.send(Ok(Item::Open(barrier))).await.is_err() {
+                return;
+            }
+            let mut framed = FramedRead::new(stream, BytesCodec::new());
+            while let Some(res) = framed.next().await {
+                let failed = res.is_err();
+                if tx.send(res.map(Item::Frame)).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_proto::{FileResponse, Message as Msg, Misc, MouseEvent, VideoFrame};
+
+    #[test]
+    fn test_lane() {
+        let mut msg = Msg::new();
+        msg.set_misc(Misc::new());
+        assert_eq!(Lane::of(&msg.write_to_bytes().unwrap()), Lane::Control);
+        msg.set_video_frame(VideoFrame::new());
+        assert_eq!(Lane::of(&msg.write_to_bytes().unwrap()), Lane::Video);
+        msg.set_mouse_event(MouseEvent::new());
+        assert_eq!(Lane::of(&msg.write_to_bytes().unwrap()), Lane::Input);
+        msg.set_file_response(FileResponse::new());
+        assert_eq!(Lane::of(&msg.write_to_bytes().unwrap()), Lane::File);
+    }
+
+    #[tokio::test]
+    async fn test_loopback() {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (mut server, addr) = new_server(socket).unwrap();
+        let client = tokio::spawn(async move {
+            new_client(&"127.0.0.1:0".parse().unwrap(), &addr)
+                .await
+                .unwrap()
+        });
+        let mut server_conn = server.next().await.unwrap().unwrap();
+        let mut client_conn = client.await.unwrap();
+
+        // the handshake, in the clear
+        client_conn.send_raw(b"hello".to_vec()).await.unwrap();
+        assert_eq!(&server_conn.next().await.unwrap().unwrap()[..], b"hello");
+
+        let key = secretbox::gen_key();
+        server_conn.set_key(key.clone());
+        client_conn.set_key(key);
+        let mut misc = Msg::new();
+        misc.set_misc(Misc::new());
+        let mut file = Msg::new();
+        file.set_file_response(FileResponse::new());
+        let mut mouse = Msg::new();
+        mouse.set_mouse_event(MouseEvent {
+            x: 1,
+            ..Default::default()
+        });
+        server_conn.send(&misc).await.unwrap();
+        for _ in 0..10 {
+            server_conn.send(&file).await.unwrap();
+        }
+        server_conn.send(&mouse).await.unwrap();
+
+        // the control message comes first, as the other lanes were opened after it
+        let mut received = Vec::new();
+        for _ in 0..12 {
+            let bytes = client_conn.next_timeout(3_000).await.unwrap().unwrap();
+            received.push(Msg::parse_from_bytes(&bytes).unwrap());
+        }
+        assert_eq!(received[0], misc);
+        assert_eq!(received.iter().filter(|m| **m == file).count(), 10);
+        assert!(received.contains(&mouse));
+
+        drop(server_conn);
+        assert!(client_conn.next_timeout(3_000).await.is_none());
+    }
+}
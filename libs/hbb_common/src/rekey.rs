@@ -0,0 +1,184 @@
+// Periodic replacement of the session key. Both sides generate an ephemeral
+// key pair per round and derive the next keys from their shared secret and the
+// current keys, then forget the secret keys, so a key found later decrypts
+// neither what was sent before it nor what is sent after the next round.
+//
+// The controlling side starts a round, see the `Rekey` message. Each direction
+// gets its own key, labelled by who sends with it.
+//
+// Only TCP connections are rekeyed. Over QUIC every lane is a stream of its
+// own and they are not ordered against each other, so the receiver could not
+// tell whether a frame of another lane was sealed before or after the switch
+// on the control lane. Such connections keep the key of the handshake for
+// their whole life, `can_rekey` is false and no round is ever started or
+// accepted on them.
+
+use crate::{
+    bail,
+    message_proto::{Message, Rekey},
+    ResultType, Stream,
+};
+use sodiumoxide::crypto::box_;
+use std::time::{Duration, Instant};
+
+pub const REKEY_INTERVAL: Duration = Duration::from_secs(3600);
+
+const FROM_INITIATOR: &[u8] = b"initiator";
+const FROM_RESPONDER: &[u8] = b"responder";
+
+#[derive(Default)]
+pub struct Rekeying {
+    // initiator: the secret key of a round waiting for the answer
+    secret_key: Option<box_::SecretKey>,
+    // responder: the shared secret to switch receiving to on the acknowledgement
+    shared: Option<box_::PrecomputedKey>,
+    last: Option<Instant>,
+}
+
+fn secret(shared: &box_::PrecomputedKey, label: &[u8]) -> Vec<u8> {
+    let mut secret = shared.0.to_vec();
+    secret.extend_from_slice(label);
+    secret
+}
+
+fn new_rekey(public_key: Option<box_::PublicKey>, done: bool) -> Message {
+    let mut msg_out = Message::new();
+    msg_out.set_rekey(Rekey {
+        public_key: public_key
+            .map(|pk| pk.0.to_vec())
+            .unwrap_or_default()
+            .into(),
+        done,
+        ..Default::default()
+    });
+    msg_out
+}
+
+impl Rekeying {
+    /// Whether the initiator should start a round, counted from the first call.
+    pub fn due(&mut self, stream: &Stream) -> bool {
+        if !stream.can_rekey() || self.secret_key.is_some() {
+            return false;
+        }
+        match self.last {
+            Some(last) => last.elapsed() >= REKEY_INTERVAL,
+            None => {
+                self.last = Some(Instant::now());
+                false
+            }
+        }
+    }
+
+    /// Peers which do not know rekeying never answer, which leaves the round
+    /// pending and the old key in use.
+    pub async fn start(&mut self, stream: &mut Stream) -> ResultType<()> {
+        let (pk, sk) = box_::gen_keypair();
+        self.secret_key = Some(sk);
+        stream.send(&new_rekey(Some(pk), false)).await
+    }
+
+    pub async fn handle(&mut self, rekey: &Rekey, stream: &mut Stream) -> ResultType<()> {
+        if !stream.can_rekey() {
+            bail!("rekey on a connection without key");
+        }
+        let their_pk = if rekey.public_key.is_empty() {
+            None
+        } else {
+            match box_::PublicKey::from_slice(&rekey.public_key) {
+                Some(pk) => Some(pk),
+                None => bail!("invalid rekey public key length"),
+            }
+        };
+        match (their_pk, rekey.done) {
+            // responder, the round starts
+            (Some(their_pk), false) => {
+                let (pk, sk) = box_::gen_keypair();
+                let shared = box_::precompute(&their_pk, &sk);
+                stream.send(&new_rekey(Some(pk), true)).await?;
+                stream.rekey_send(&secret(&shared, FROM_RESPONDER))?;
+                self.shared = Some(shared);
+            }
+            // initiator, the answer
+            (Some(their_pk), true) => {
+                let sk = match self.secret_key.take() {
+                    Some(sk) => sk,
+                    None => bail!("unexpected rekey answer"),
+                };
+                let shared = box_::precompute(&their_pk, &sk);
+                stream.rekey_recv(&secret(&shared, FROM_RESPONDER))?;
+                stream.send(&new_rekey(None, true)).await?;
+                stream.rekey_send(&secret(&shared, FROM_INITIATOR))?;
+                self.last = Some(Instant::now());
+                log::info!("Session key replaced");
+            }
+            // responder, the acknowledgement
+            (None, true) => match self.shared.take() {
+                Some(shared) => {
+                    stream.rekey_recv(&secret(&shared, FROM_INITIATOR))?;
+                    self.last = Some(Instant::now());
+                    log::info!("Session key replaced");
+                }
+                None => bail!("unexpected rekey acknowledgement"),
+            },
+            (None, false) => bail!("empty rekey"),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, not(feature = "quic")))]
+mod tests {
+    use super::*;
+    use crate::message_proto::{message, TestDelay};
+    use protobuf::Message as _;
+    use sodiumoxide::crypto::secretbox;
+
+    async fn recv(stream: &mut Stream) -> Message {
+        let bytes = stream.next().await.unwrap().unwrap();
+        Message::parse_from_bytes(&bytes).unwrap()
+    }
+
+    async fn handle(rekeying: &mut Rekeying, stream: &mut Stream) {
+        match recv(stream).await.union {
+            Some(message::Union::Rekey(rekey)) => rekeying.handle(&rekey, stream).await.unwrap(),
+            _ => panic!("not a rekey"),
+        }
+    }
+
+    fn test_delay(time: i64) -> Message {
+        let mut msg = Message::new();
+        msg.set_test_delay(TestDelay {
+            time,
+            ..Default::default()
+        });
+        msg
+    }
+
+    #[tokio::test]
+    async fn test_rekey() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        let mut a = Stream::from(client, addr);
+        let mut b = Stream::from(server, addr);
+        let key = secretbox::gen_key();
+        a.set_key(key.clone());
+        b.set_key(key);
+        let mut ra = Rekeying::default();
+        let mut rb = Rekeying::default();
+
+        ra.start(&mut a).await.unwrap();
+        // Sent before the round reaches either side, under the old keys.
+        a.send(&test_delay(1)).await.unwrap();
+        handle(&mut rb, &mut b).await;
+        b.send(&test_delay(2)).await.unwrap();
+        assert_eq!(recv(&mut b).await, test_delay(1));
+        handle(&mut ra, &mut a).await;
+        assert_eq!(recv(&mut a).await, test_delay(2));
+        a.send(&test_delay(3)).await.unwrap();
+        handle(&mut rb, &mut b).await;
+        assert_eq!(recv(&mut b).await, test_delay(3));
+        assert!(ra.secret_key.is_none() && rb.shared.is_none());
+    }
+}
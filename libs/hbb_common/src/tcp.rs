@@ -5,6 +5,7 @@ use futures::{SinkExt, StreamExt};
 use protobuf::Message;
 use sodiumoxide::crypto::{
     box_,
+    hash::sha256,
     secretbox::{self, Key, Nonce},
 };
 use std::{
@@ -24,8 +25,10 @@ use tokio_util::codec::Framed;
 pub trait TcpStreamTrait: AsyncRead + AsyncWrite + Unpin {}
 pub struct DynTcpStream(Box<dyn TcpStreamTrait + Send + Sync>);
 
+// Keys and sequence numbers of sending and receiving, which only differ after
+// a rekey round.
 #[derive(Clone)]
-pub struct Encrypt(Key, u64, u64);
+pub struct Encrypt(Key, u64, u64, Key);
 
 pub struct FramedStream(
     Framed<DynTcpStream, BytesCodec>,
@@ -233,6 +236,29 @@ impl FramedStream {
         self.2 = Some(Encrypt::new(key));
     }
 
+    #[inline]
+    pub fn can_rekey(&self) -> bool {
+        self.2.is_some()
+    }
+
+    /// Switches the key of the messages sent from now on, see the `rekey` module.
+    pub fn rekey_send(&mut self, secret: &[u8]) -> ResultType<()> {
+        match self.2.as_mut() {
+            Some(key) => key.rekey_send(secret),
+            None => bail!("not secured"),
+        }
+        Ok(())
+    }
+
+    /// Switches the key of the messages received from now on.
+    pub fn rekey_recv(&mut self, secret: &[u8]) -> ResultType<()> {
+        match self.2.as_mut() {
+            Some(key) => key.rekey_recv(secret),
+            None => bail!("not secured"),
+        }
+        Ok(())
+    }
+
     fn get_nonce(seqnum: u64) -> Nonce {
         let mut nonce = Nonce([0u8; secretbox::NONCEBYTES]);
         nonce.0[..std::mem::size_of_val(&seqnum)].copy_from_slice(&seqnum.to_le_bytes());
@@ -326,13 +352,32 @@ impl<R: AsyncRead + AsyncWrite + Unpin> TcpStreamTrait for R {}
 
 impl Encrypt {
     pub fn new(key: Key) -> Self {
-        Self(key, 0, 0)
+        Self(key.clone(), 0, 0, key)
+    }
+
+    // The old key goes into the new one, which so stays bound to the
+    // authenticated handshake, but cannot be computed back from it.
+    fn next_key(key: &Key, secret: &[u8]) -> Key {
+        let mut state = sha256::State::new();
+        state.update(secret);
+        state.update(&key.0);
+        Key(state.finalize().0)
+    }
+
+    pub fn rekey_send(&mut self, secret: &[u8]) {
+        self.0 = Self::next_key(&self.0, secret);
+        self.1 = 0;
+    }
+
+    pub fn rekey_recv(&mut self, secret: &[u8]) {
+        self.3 = Self::next_key(&self.3, secret);
+        self.2 = 0;
     }
 
     pub fn dec(&mut self, bytes: &mut BytesMut) -> Result<(), Error> {
         self.2 += 1;
         let nonce = FramedStream::get_nonce(self.2);
-        match secretbox::open(bytes, &nonce, &self.0) {
+        match secretbox::open(bytes, &nonce, &self.3) {
             Ok(res) => {
                 bytes.clear();
                 bytes.put_slice(&res);
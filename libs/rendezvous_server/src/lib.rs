@@ -0,0 +1,372 @@
+// Rendezvous and relay server for small private deployments and offline tests.
+// It is the server side of `rendezvous.proto` as the client code in
+// `rendezvous_mediator.rs` and `Client::_start` uses it, with the registered
+// peers kept in a local file.
+//
+// The ports follow the usual layout: rendezvous over UDP and TCP on the main
+// port, NAT tests and online queries on the port below, the relay on the port
+// above.
+
+mod relay;
+mod rendezvous;
+pub mod store;
+
+use hbb_common::{
+    config::RENDEZVOUS_PORT,
+    log,
+    sodiumoxide::{
+        base64::{self, Variant},
+        crypto::sign,
+    },
+    tcp::new_listener,
+    tokio::{self, net::TcpListener},
+    udp::FramedSocket,
+    ResultType,
+};
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+use store::PeerStore;
+
+const STORE_FILE: &str = "peers.toml";
+/// Same files as other rendezvous servers use, so a key pair can be moved over.
+const KEY_FILE: &str = "id_ed25519";
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub rendezvous_port: u16,
+    pub nat_port: u16,
+    pub relay_port: u16,
+    /// Where the peer store and the key pair are kept.
+    pub dir: PathBuf,
+    /// Relay server handed to peers. If empty, peers use the rendezvous host
+    /// with the port above, and clients the address they reached us on.
+    pub relay_server: String,
+    /// Refuse clients and relay requests which do not carry our public key.
+    pub require_key: bool,
+}
+
+impl Options {
+    pub fn new(port: u16, dir: PathBuf) -> Self {
+        Self {
+            rendezvous_port: port,
+            nat_port: port - 1,
+            relay_port: port + 1,
+            dir,
+            relay_server: "".to_owned(),
+            require_key: false,
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self::new(RENDEZVOUS_PORT as _, PathBuf::from("."))
+    }
+}
+
+pub struct Server {
+    udp: FramedSocket,
+    rendezvous: TcpListener,
+    nat: TcpListener,
+    relay: TcpListener,
+    options: Options,
+    sk: sign::SecretKey,
+    public_key: String,
+    store: PeerStore,
+}
+
+fn any_addr(port: u16) -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], port))
+}
+
+/// Loads the key pair from `dir`, or creates it on first start.
+fn load_key_pair(dir: &Path) -> ResultType<(sign::SecretKey, String)> {
+    let path = dir.join(KEY_FILE);
+    if path.exists() {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path)?.permissions().mode();
+            if mode & 0o077 != 0 {
+                hbb_common::bail!(
+                    "{} can be read by others (mode {:o}), chmod 600 it",
+                    path.display(),
+                    mode & 0o777
+                );
+            }
+        }
+        let sk = base64::decode(std::fs::read_to_string(&path)?.trim(), Variant::Original)
+            .ok()
+            .and_then(|sk| sign::SecretKey::from_slice(&sk));
+        return match sk {
+            Some(sk) => {
+                let pk = base64::encode(sk.public_key().0, Variant::Original);
+                Ok((sk, pk))
+            }
+            None => hbb_common::bail!("invalid secret key in {}", path.display()),
+        };
+    }
+    let (pk, sk) = sign::gen_keypair();
+    let pk = base64::encode(pk.0, Variant::Original);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&path)?
+        .write_all(base64::encode(&sk.0[..], Variant::Original).as_bytes())?;
+    std::fs::write(path.with_extension("pub"), &pk)?;
+    log::info!("Key pair generated in {}", path.display());
+    Ok((sk, pk))
+}
+
+impl Server {
+    /// Binds all the ports, a port of 0 picks a free one.
+    pub async fn bind(options: Options) -> ResultType<Self> {
+        std::fs::create_dir_all(&options.dir)?;
+        let (sk, public_key) = load_key_pair(&options.dir)?;
+        let store = PeerStore::load(&options.dir.join(STORE_FILE))?;
+        let udp = FramedSocket::new(any_addr(options.rendezvous_port)).await?;
+        // the same port for both when a free one was asked for
+        let port = match udp.local_addr() {
+            Some(addr) => addr.port(),
+            None => options.rendezvous_port,
+        };
+        Ok(Self {
+            udp,
+            rendezvous: new_listener(any_addr(port), false).await?,
+            nat: new_listener(any_addr(options.nat_port), false).await?,
+            relay: new_listener(any_addr(options.relay_port), false).await?,
+            options,
+            sk,
+            public_key,
+            store,
+        })
+    }
+
+    /// What clients set as `key` to trust this server.
+    pub fn public_key(&self) -> &str {
+        &self.public_key
+    }
+
+    /// Bound addresses of the rendezvous, NAT test and relay ports.
+    pub fn local_addrs(&self) -> ResultType<(SocketAddr, SocketAddr, SocketAddr)> {
+        Ok((
+            self.rendezvous.local_addr()?,
+            self.nat.local_addr()?,
+            self.relay.local_addr()?,
+        ))
+    }
+
+    pub async fn run(self) -> ResultType<()> {
+        let (rendezvous, nat, relay) = self.local_addrs()?;
+        log::info!(
+            "Rendezvous server on {}, NAT test on {}, relay on {}",
+            rendezvous,
+            nat,
+            relay
+        );
+        log::info!("Key: {}", self.public_key);
+        let relay_key = if self.options.require_key {
+            self.public_key.clone()
+        } else {
+            "".to_owned()
+        };
+        let mut options = self.options;
+        options.relay_port = relay.port();
+        let (state, udp_rx) = rendezvous::State::new(options, self.sk, self.public_key, self.store);
+        tokio::spawn(rendezvous::run_tcp(state.clone(), self.rendezvous, true));
+        tokio::spawn(rendezvous::run_tcp(state.clone(), self.nat, false));
+        tokio::spawn(relay::run(relay::Relay::new(relay_key), self.relay));
+        rendezvous::run_udp(state, self.udp, udp_rx).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::{
+        message_proto::IdPk,
+        protobuf::Message as _,
+        rendezvous_proto::*,
+        sodiumoxide::crypto::{box_, secretbox},
+        tcp::FramedStream,
+        timeout, AddrMangle,
+    };
+
+    type Message = RendezvousMessage;
+
+    async fn recv_udp(socket: &mut FramedSocket) -> Message {
+        let (bytes, _) = timeout(3_000, socket.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        Message::parse_from_bytes(&bytes).unwrap()
+    }
+
+    async fn recv(stream: &mut FramedStream) -> Message {
+        let bytes = timeout(3_000, stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        Message::parse_from_bytes(&bytes).unwrap()
+    }
+
+    async fn connect(addr: SocketAddr) -> FramedStream {
+        let addr = SocketAddr::from(([127, 0, 0, 1], addr.port()));
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let local_addr = stream.local_addr().unwrap();
+        FramedStream::from(stream, local_addr)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_key_file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("rendezvous-key-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (_, pk) = load_key_pair(&dir).unwrap();
+        let path = dir.join(KEY_FILE);
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(load_key_pair(&dir).unwrap().1, pk);
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(load_key_pair(&dir).is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_server() {
+        let dir = std::env::temp_dir().join(format!("rendezvous-test-{}", std::process::id()));
+        let server = Server::bind(Options {
+            rendezvous_port: 0,
+            nat_port: 0,
+            relay_port: 0,
+            ..Options::new(RENDEZVOUS_PORT as _, dir.clone())
+        })
+        .await
+        .unwrap();
+        let rs_pk = base64::decode(server.public_key(), Variant::Original).unwrap();
+        let rs_pk = sign::PublicKey::from_slice(&rs_pk).unwrap();
+        let (rendezvous, nat, relay) = server.local_addrs().unwrap();
+        tokio::spawn(server.run());
+
+        // A peer registers.
+        let udp_addr = SocketAddr::from(([127, 0, 0, 1], rendezvous.port()));
+        let mut peer = FramedSocket::new("127.0.0.1:0").await.unwrap();
+        let (peer_pk, _) = sign::gen_keypair();
+        let mut msg = Message::new();
+        msg.set_register_peer(RegisterPeer {
+            id: "123456789".to_owned(),
+            ..Default::default()
+        });
+        peer.send(&msg, udp_addr).await.unwrap();
+        assert!(
+            recv_udp(&mut peer)
+                .await
+                .register_peer_response()
+                .request_pk
+        );
+        let mut msg_pk = Message::new();
+        msg_pk.set_register_pk(RegisterPk {
+            id: "123456789".to_owned(),
+            uuid: b"uuid".to_vec().into(),
+            pk: peer_pk.0.to_vec().into(),
+            ..Default::default()
+        });
+        peer.send(&msg_pk, udp_addr).await.unwrap();
+        let result = recv_udp(&mut peer).await.register_pk_response().result;
+        assert_eq!(result, register_pk_response::Result::OK.into());
+        peer.send(&msg, udp_addr).await.unwrap();
+        assert!(
+            !recv_udp(&mut peer)
+                .await
+                .register_peer_response()
+                .request_pk
+        );
+
+        // Online states, one bit per peer from the left.
+        let mut conn = connect(nat).await;
+        let mut msg = Message::new();
+        msg.set_online_request(OnlineRequest {
+            peers: vec!["123456789".to_owned(), "987654321".to_owned()],
+            ..Default::default()
+        });
+        conn.send(&msg).await.unwrap();
+        assert_eq!(&recv(&mut conn).await.online_response().states[..], &[0x80]);
+
+        // A client secures its connection and asks for the peer, which is on
+        // the same host and so is asked for its local address.
+        let mut client = connect(rendezvous).await;
+        let ex = recv(&mut client).await;
+        let their_pk = sign::verify(&ex.key_exchange().keys[0], &rs_pk).unwrap();
+        let their_pk = box_::PublicKey::from_slice(&their_pk).unwrap();
+        let (our_pk, our_sk) = box_::gen_keypair();
+        let key = secretbox::gen_key();
+        let nonce = box_::Nonce([0u8; box_::NONCEBYTES]);
+        let mut msg = Message::new();
+        msg.set_key_exchange(KeyExchange {
+            keys: vec![
+                our_pk.0.to_vec().into(),
+                box_::seal(&key.0, &nonce, &their_pk, &our_sk).into(),
+            ],
+            ..Default::default()
+        });
+        client.send(&msg).await.unwrap();
+        client.set_key(key);
+        let mut msg = Message::new();
+        msg.set_punch_hole_request(PunchHoleRequest {
+            id: "987654321".to_owned(),
+            ..Default::default()
+        });
+        client.send(&msg).await.unwrap();
+        let failure = recv(&mut client).await.punch_hole_response().failure;
+        assert_eq!(failure, punch_hole_response::Failure::ID_NOT_EXIST.into());
+        msg.mut_punch_hole_request().id = "123456789".to_owned();
+        client.send(&msg).await.unwrap();
+        let fla = recv_udp(&mut peer).await.fetch_local_addr().clone();
+        assert_eq!(AddrMangle::decode(&fla.socket_addr), client.local_addr());
+
+        let peer_local_addr: SocketAddr = "192.168.1.2:30000".parse().unwrap();
+        let mut conn = connect(rendezvous).await;
+        let mut msg = Message::new();
+        msg.set_local_addr(LocalAddr {
+            id: "123456789".to_owned(),
+            socket_addr: fla.socket_addr,
+            local_addr: AddrMangle::encode(peer_local_addr).into(),
+            ..Default::default()
+        });
+        conn.send(&msg).await.unwrap();
+        let ph = recv(&mut client).await.punch_hole_response().clone();
+        assert!(ph.is_local());
+        assert_eq!(AddrMangle::decode(&ph.socket_addr), peer_local_addr);
+        assert_eq!(ph.relay_server, format!("127.0.0.1:{}", relay.port()));
+        let id_pk = IdPk::parse_from_bytes(&sign::verify(&ph.pk, &rs_pk).unwrap()).unwrap();
+        assert_eq!(id_pk.id, "123456789");
+        assert_eq!(&id_pk.pk[..], &peer_pk.0[..]);
+
+        // Both sides meet at the relay.
+        let mut a = connect(relay).await;
+        let mut b = connect(relay).await;
+        let mut msg = Message::new();
+        msg.set_request_relay(RequestRelay {
+            uuid: "uuid".to_owned(),
+            ..Default::default()
+        });
+        a.send(&msg).await.unwrap();
+        a.send_raw(b"hello".to_vec()).await.unwrap();
+        b.send(&msg).await.unwrap();
+        let bytes = timeout(3_000, b.next()).await.unwrap().unwrap().unwrap();
+        assert_eq!(&bytes[..], b"hello");
+        b.send_raw(b"world".to_vec()).await.unwrap();
+        let bytes = timeout(3_000, a.next()).await.unwrap().unwrap().unwrap();
+        assert_eq!(&bytes[..], b"world");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
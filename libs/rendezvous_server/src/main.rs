@@ -0,0 +1,53 @@
+use hbb_common::{env_logger, tokio, ResultType};
+use rendezvous_server::{Options, Server};
+
+const USAGE: &str = "Usage: rendezvous_server [options]
+
+Options:
+  -p, --port <port>           rendezvous port, NAT test on the one below, relay on the one above [21116]
+  -d, --dir <dir>             directory of the peer store and the key pair [.]
+  -r, --relay-server <host>   relay server handed to peers
+  -k, --require-key           refuse clients which are not configured with our key
+  -h, --help                  print this help";
+
+fn parse_args() -> ResultType<Options> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || match args.next() {
+            Some(v) => Ok(v),
+            None => hbb_common::bail!("missing value of {}", arg),
+        };
+        match arg.as_str() {
+            "-p" | "--port" => {
+                let port: u16 = value()?.parse()?;
+                if port < 2 || port == u16::MAX {
+                    hbb_common::bail!("invalid port {}", port);
+                }
+                options = Options {
+                    dir: options.dir,
+                    relay_server: options.relay_server,
+                    require_key: options.require_key,
+                    ..Options::new(port, Default::default())
+                };
+            }
+            "-d" | "--dir" => options.dir = value()?.into(),
+            "-r" | "--relay-server" => options.relay_server = value()?,
+            "-k" | "--require-key" => options.require_key = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ => hbb_common::bail!("unknown option {}\n\n{}", arg, USAGE),
+        }
+    }
+    Ok(options)
+}
+
+#[tokio::main]
+async fn main() -> ResultType<()> {
+    env_logger::init_from_env(env_logger::Env::default().filter_or("RUST_LOG", "info"));
+    let options = parse_args()?;
+    let server = Server::bind(options).await?;
+    server.run().await
+}
@@ -0,0 +1,145 @@
+// Relay for peers which cannot reach each other. Both sides connect and name
+// the same uuid in a `RequestRelay`, the first one waits for the second, then
+// the bytes are copied as they come, framing and encryption are the peers'.
+
+use hbb_common::{
+    bail,
+    bytes_codec::BytesCodec,
+    futures::StreamExt,
+    log,
+    protobuf::Message as _,
+    rendezvous_proto::*,
+    timeout,
+    tokio::{
+        self,
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+        sync::oneshot,
+    },
+    tokio_util::codec::{Framed, FramedParts},
+    ResultType,
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// How long the first side waits for the second.
+const PAIR_TIMEOUT: u64 = 30_000;
+
+type Parts = FramedParts<TcpStream, BytesCodec>;
+
+/// The first side of a relay, until the second one takes it.
+struct Waiter {
+    // tells a timed out waiter whether the entry is still its own
+    id: u64,
+    parts: Parts,
+    // dropped when taken, which wakes the waiting task
+    _paired: oneshot::Sender<()>,
+}
+
+pub(crate) struct Relay {
+    // empty if any key is accepted
+    key: String,
+    next_id: AtomicU64,
+    waiting: Mutex<HashMap<String, Waiter>>,
+}
+
+impl Relay {
+    pub(crate) fn new(key: String) -> Arc<Self> {
+        Arc::new(Self {
+            key,
+            next_id: Default::default(),
+            waiting: Default::default(),
+        })
+    }
+}
+
+pub(crate) async fn run(relay: Arc<Relay>, listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let relay = relay.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = handle(&relay, stream, addr).await {
+                        log::debug!("Relay connection from {}: {}", addr, err);
+                    }
+                });
+            }
+            Err(err) => log::error!("Failed to accept: {}", err),
+        }
+    }
+}
+
+async fn handle(relay: &Relay, stream: TcpStream, addr: SocketAddr) -> ResultType<()> {
+    stream.set_nodelay(true).ok();
+    let mut framed = Framed::new(stream, BytesCodec::new());
+    let bytes = match timeout(PAIR_TIMEOUT, framed.next()).await? {
+        Some(res) => res?,
+        None => return Ok(()),
+    };
+    let rr = match RendezvousMessage::parse_from_bytes(&bytes)?.union {
+        Some(rendezvous_message::Union::RequestRelay(rr)) => rr,
+        _ => bail!("not a relay request"),
+    };
+    if rr.uuid.is_empty() {
+        bail!("relay request without uuid");
+    }
+    if !relay.key.is_empty() && rr.licence_key != relay.key {
+        bail!("relay request with a wrong key");
+    }
+    // Whatever the peer sent right after the request is in the read buffer.
+    let parts = framed.into_parts();
+    // taken and parked under one lock, so two sides never both wait
+    let pair = {
+        let mut waiting = relay.waiting.lock().unwrap();
+        match waiting.remove(&rr.uuid) {
+            Some(other) => Ok((other.parts, parts)),
+            None => {
+                let id = relay.next_id.fetch_add(1, Ordering::Relaxed);
+                let (tx, rx) = oneshot::channel();
+                waiting.insert(
+                    rr.uuid.clone(),
+                    Waiter {
+                        id,
+                        parts,
+                        _paired: tx,
+                    },
+                );
+                Err((id, rx))
+            }
+        }
+    };
+    match pair {
+        Ok((other, parts)) => {
+            log::info!("Relaying {} for {}", rr.uuid, addr);
+            pipe(other, parts).await?;
+            log::info!("Relay {} closed", rr.uuid);
+        }
+        Err((id, rx)) => {
+            // The second side pipes, this task only cleans up if nobody came.
+            if timeout(PAIR_TIMEOUT, rx).await.is_ok() {
+                return Ok(());
+            }
+            let mut waiting = relay.waiting.lock().unwrap();
+            // a later request for the same uuid may have been parked meanwhile
+            if waiting.get(&rr.uuid).map(|w| w.id) == Some(id) {
+                waiting.remove(&rr.uuid);
+                log::info!("Relay {} of {} timed out", rr.uuid, addr);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn pipe(a: Parts, b: Parts) -> ResultType<()> {
+    let (mut a_io, mut b_io) = (a.io, b.io);
+    b_io.write_all(&a.read_buf).await?;
+    a_io.write_all(&b.read_buf).await?;
+    tokio::io::copy_bidirectional(&mut a_io, &mut b_io).await?;
+    Ok(())
+}
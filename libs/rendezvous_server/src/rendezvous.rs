@@ -0,0 +1,437 @@
+// Server side of the rendezvous protocol. Peers register over UDP and are
+// reached through the address they register from. A client asking for a peer
+// over TCP is remembered by its address, which travels in the `socket_addr` of
+// the request to the peer and back in the answer, and the answer is forwarded
+// on the client's connection.
+
+use crate::{store::PeerStore, Options};
+use hbb_common::{
+    bail, log,
+    message_proto::IdPk,
+    protobuf::Message as _,
+    rendezvous_proto::*,
+    sodiumoxide::crypto::{box_, secretbox, sign},
+    tcp::FramedStream,
+    timeout,
+    tokio::{
+        self,
+        net::{TcpListener, TcpStream},
+        select,
+        sync::mpsc,
+    },
+    try_into_v4,
+    udp::FramedSocket,
+    AddrMangle, ResultType, TargetAddr,
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+type Message = RendezvousMessage;
+
+/// A peer which has not registered for this long is offline, peers register
+/// every `REG_INTERVAL`.
+const REG_TIMEOUT: u128 = 30_000;
+/// Clients keep their connection only until they are answered.
+const TCP_TIMEOUT: u64 = 30_000;
+
+struct Online {
+    addr: SocketAddr,
+    last: Instant,
+}
+
+pub(crate) struct State {
+    options: Options,
+    sk: sign::SecretKey,
+    public_key: String,
+    store: Mutex<PeerStore>,
+    online: Mutex<HashMap<String, Online>>,
+    // clients waiting for a peer's answer, by their address
+    waiting: Mutex<HashMap<SocketAddr, mpsc::UnboundedSender<Message>>>,
+    udp_tx: mpsc::UnboundedSender<(Message, SocketAddr)>,
+}
+
+impl State {
+    pub(crate) fn new(
+        options: Options,
+        sk: sign::SecretKey,
+        public_key: String,
+        store: PeerStore,
+    ) -> (Arc<Self>, mpsc::UnboundedReceiver<(Message, SocketAddr)>) {
+        let (udp_tx, udp_rx) = mpsc::unbounded_channel();
+        let state = Self {
+            options,
+            sk,
+            public_key,
+            store: Mutex::new(store),
+            online: Default::default(),
+            waiting: Default::default(),
+            udp_tx,
+        };
+        (Arc::new(state), udp_rx)
+    }
+
+    /// The address the peer registered from, if it is online.
+    fn peer_addr(&self, id: &str) -> Option<SocketAddr> {
+        self.online
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|x| x.last.elapsed().as_millis() < REG_TIMEOUT)
+            .map(|x| x.addr)
+    }
+
+    fn set_online(&self, id: &str, addr: SocketAddr) {
+        self.online.lock().unwrap().insert(
+            id.to_owned(),
+            Online {
+                addr,
+                last: Instant::now(),
+            },
+        );
+    }
+
+    /// `IdPk` of the peer signed with our key, which lets the client check the
+    /// key the peer presents.
+    fn signed_id_pk(&self, id: &str) -> Vec<u8> {
+        let pk = match self.store.lock().unwrap().get(id) {
+            Some(record) => record.pk.clone(),
+            None => return Vec::new(),
+        };
+        let id_pk = IdPk {
+            id: id.to_owned(),
+            pk: pk.into(),
+            ..Default::default()
+        };
+        sign::sign(&id_pk.write_to_bytes().unwrap_or_default(), &self.sk)
+    }
+
+    fn check_key(&self, licence_key: &str) -> bool {
+        !self.options.require_key || licence_key == self.public_key
+    }
+
+    fn send_to_peer(&self, msg: Message, addr: SocketAddr) {
+        self.udp_tx.send((msg, addr)).ok();
+    }
+
+    fn send_to_client(&self, socket_addr: &[u8], msg: Message) {
+        let addr = AddrMangle::decode(socket_addr);
+        match self.waiting.lock().unwrap().get(&addr) {
+            Some(tx) => {
+                tx.send(msg).ok();
+            }
+            None => log::debug!("No client waiting at {}", addr),
+        }
+    }
+}
+
+pub(crate) async fn run_udp(
+    state: Arc<State>,
+    mut socket: FramedSocket,
+    mut udp_rx: mpsc::UnboundedReceiver<(Message, SocketAddr)>,
+) -> ResultType<()> {
+    loop {
+        select! {
+            res = socket.next() => {
+                match res {
+                    Some(Ok((bytes, TargetAddr::Ip(addr)))) => {
+                        if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
+                            if let Some(msg_out) = handle_udp(&state, msg_in, try_into_v4(addr)) {
+                                socket.send(&msg_out, addr).await.ok();
+                            }
+                        }
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(err)) => log::debug!("UDP receive error: {}", err),
+                    None => bail!("UDP socket closed"),
+                }
+            }
+            Some((msg, addr)) = udp_rx.recv() => {
+                socket.send(&msg, addr).await.ok();
+            }
+        }
+    }
+}
+
+fn handle_udp(state: &State, msg_in: Message, addr: SocketAddr) -> Option<Message> {
+    let mut msg_out = Message::new();
+    match msg_in.union {
+        Some(rendezvous_message::Union::RegisterPeer(rp)) => {
+            let known = state.store.lock().unwrap().get(&rp.id).is_some();
+            if known {
+                state.set_online(&rp.id, addr);
+            }
+            msg_out.set_register_peer_response(RegisterPeerResponse {
+                request_pk: !known,
+                ..Default::default()
+            });
+        }
+        Some(rendezvous_message::Union::RegisterPk(rk)) => {
+            let result = state
+                .store
+                .lock()
+                .unwrap()
+                .register(&rk.id, &rk.uuid, &rk.pk);
+            if result == register_pk_response::Result::OK {
+                state.set_online(&rk.id, addr);
+            } else {
+                log::info!("RegisterPk of {} from {}: {:?}", rk.id, addr, result);
+            }
+            msg_out.set_register_pk_response(RegisterPkResponse {
+                result: result.into(),
+                ..Default::default()
+            });
+        }
+        _ => return None,
+    }
+    Some(msg_out)
+}
+
+pub(crate) async fn run_tcp(state: Arc<State>, listener: TcpListener, secure: bool) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = handle_tcp(&state, stream, addr, secure).await {
+                        log::debug!("Rendezvous connection from {}: {}", addr, err);
+                    }
+                });
+            }
+            Err(err) => log::error!("Failed to accept: {}", err),
+        }
+    }
+}
+
+async fn handle_tcp(
+    state: &State,
+    stream: TcpStream,
+    addr: SocketAddr,
+    secure: bool,
+) -> ResultType<()> {
+    stream.set_nodelay(true).ok();
+    let addr = try_into_v4(addr);
+    let local_addr = stream.local_addr()?;
+    let mut stream = FramedStream::from(stream, local_addr);
+    // Offered first thing, clients with a token take it, see
+    // `secure_punch_connection`, the others skip it.
+    let mut our_sk = None;
+    if secure {
+        let (pk, sk) = box_::gen_keypair();
+        let mut msg_out = Message::new();
+        msg_out.set_key_exchange(KeyExchange {
+            keys: vec![sign::sign(&pk.0, &state.sk).into()],
+            ..Default::default()
+        });
+        stream.send(&msg_out).await?;
+        our_sk = Some(sk);
+    }
+    let res = serve(state, &mut stream, addr, local_addr, our_sk).await;
+    state.waiting.lock().unwrap().remove(&addr);
+    res
+}
+
+async fn serve(
+    state: &State,
+    stream: &mut FramedStream,
+    addr: SocketAddr,
+    local_addr: SocketAddr,
+    mut our_sk: Option<box_::SecretKey>,
+) -> ResultType<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    loop {
+        select! {
+            res = timeout(TCP_TIMEOUT, stream.next()) => {
+                let bytes = match res {
+                    Ok(Some(Ok(bytes))) => bytes,
+                    _ => break,
+                };
+                let msg_in = match Message::parse_from_bytes(&bytes) {
+                    Ok(msg_in) => msg_in,
+                    Err(_) => break,
+                };
+                if let Some(rendezvous_message::Union::KeyExchange(ex)) = &msg_in.union {
+                    stream.set_key(open_key(ex, our_sk.take())?);
+                    continue;
+                }
+                if let Some(msg_out) = handle_tcp_message(state, msg_in, addr, local_addr, &tx) {
+                    stream.send(&msg_out).await?;
+                }
+            }
+            Some(msg_out) = rx.recv() => {
+                stream.send(&msg_out).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn open_key(ex: &KeyExchange, our_sk: Option<box_::SecretKey>) -> ResultType<secretbox::Key> {
+    let our_sk = match our_sk {
+        Some(sk) => sk,
+        None => bail!("unexpected key exchange"),
+    };
+    if ex.keys.len() != 2 {
+        bail!("invalid key exchange");
+    }
+    let their_pk = match box_::PublicKey::from_slice(&ex.keys[0]) {
+        Some(pk) => pk,
+        None => bail!("invalid public key in key exchange"),
+    };
+    let nonce = box_::Nonce([0u8; box_::NONCEBYTES]);
+    let key = match box_::open(&ex.keys[1], &nonce, &their_pk, &our_sk) {
+        Ok(key) => key,
+        Err(_) => bail!("failed to open the key"),
+    };
+    match secretbox::Key::from_slice(&key) {
+        Some(key) => Ok(key),
+        None => bail!("invalid key length"),
+    }
+}
+
+/// Relay server for the answer to `addr`, whose connection came in on `local_addr`.
+fn relay_server(state: &State, provided: &str, local_addr: SocketAddr) -> String {
+    if !provided.is_empty() {
+        provided.to_owned()
+    } else if !state.options.relay_server.is_empty() {
+        state.options.relay_server.clone()
+    } else {
+        SocketAddr::new(local_addr.ip(), state.options.relay_port).to_string()
+    }
+}
+
+fn handle_tcp_message(
+    state: &State,
+    msg_in: Message,
+    addr: SocketAddr,
+    local_addr: SocketAddr,
+    tx: &mpsc::UnboundedSender<Message>,
+) -> Option<Message> {
+    let mut msg_out = Message::new();
+    match msg_in.union {
+        Some(rendezvous_message::Union::PunchHoleRequest(ph)) => {
+            let failure = if !state.check_key(&ph.licence_key) {
+                Some(punch_hole_response::Failure::LICENSE_MISMATCH)
+            } else if state.store.lock().unwrap().get(&ph.id).is_none() {
+                Some(punch_hole_response::Failure::ID_NOT_EXIST)
+            } else {
+                None
+            };
+            let peer_addr = match (failure, state.peer_addr(&ph.id)) {
+                (None, Some(peer_addr)) => peer_addr,
+                (failure, _) => {
+                    msg_out.set_punch_hole_response(PunchHoleResponse {
+                        failure: failure
+                            .unwrap_or(punch_hole_response::Failure::OFFLINE)
+                            .into(),
+                        ..Default::default()
+                    });
+                    return Some(msg_out);
+                }
+            };
+            state.waiting.lock().unwrap().insert(addr, tx.clone());
+            let socket_addr = AddrMangle::encode(addr).into();
+            let relay_server = state.options.relay_server.clone();
+            // Behind the same NAT punching does not work, the peer is asked
+            // for its local address instead.
+            if peer_addr.ip() == addr.ip() {
+                msg_out.set_fetch_local_addr(FetchLocalAddr {
+                    socket_addr,
+                    relay_server,
+                    ..Default::default()
+                });
+            } else {
+                msg_out.set_punch_hole(PunchHole {
+                    socket_addr,
+                    relay_server,
+                    nat_type: ph.nat_type,
+                    ..Default::default()
+                });
+            }
+            state.send_to_peer(msg_out, peer_addr);
+            None
+        }
+        Some(rendezvous_message::Union::RequestRelay(mut rr)) => {
+            if !state.check_key(&rr.licence_key) {
+                msg_out.set_relay_response(RelayResponse {
+                    refuse_reason: "Key mismatch".to_owned(),
+                    ..Default::default()
+                });
+                return Some(msg_out);
+            }
+            let peer_addr = match state.peer_addr(&rr.id) {
+                Some(peer_addr) => peer_addr,
+                None => {
+                    msg_out.set_relay_response(RelayResponse {
+                        refuse_reason: format!("Remote desktop {} is offline", rr.id),
+                        ..Default::default()
+                    });
+                    return Some(msg_out);
+                }
+            };
+            state.waiting.lock().unwrap().insert(addr, tx.clone());
+            rr.socket_addr = AddrMangle::encode(addr).into();
+            msg_out.set_request_relay(rr);
+            state.send_to_peer(msg_out, peer_addr);
+            None
+        }
+        Some(rendezvous_message::Union::PunchHoleSent(phs)) => {
+            msg_out.set_punch_hole_response(PunchHoleResponse {
+                socket_addr: AddrMangle::encode(addr).into(),
+                pk: state.signed_id_pk(&phs.id).into(),
+                relay_server: relay_server(state, &phs.relay_server, local_addr),
+                union: Some(punch_hole_response::Union::NatType(phs.nat_type)),
+                ..Default::default()
+            });
+            state.send_to_client(&phs.socket_addr, msg_out);
+            None
+        }
+        Some(rendezvous_message::Union::LocalAddr(la)) => {
+            msg_out.set_punch_hole_response(PunchHoleResponse {
+                socket_addr: la.local_addr,
+                pk: state.signed_id_pk(&la.id).into(),
+                relay_server: relay_server(state, &la.relay_server, local_addr),
+                union: Some(punch_hole_response::Union::IsLocal(true)),
+                ..Default::default()
+            });
+            state.send_to_client(&la.socket_addr, msg_out);
+            None
+        }
+        Some(rendezvous_message::Union::RelayResponse(mut rr)) => {
+            // The peer only names itself when it starts the relay on its own,
+            // the client then still waits for the answer to its punch request.
+            let socket_addr = std::mem::take(&mut rr.socket_addr);
+            if let Some(relay_response::Union::Id(id)) = rr.union.take() {
+                rr.set_pk(state.signed_id_pk(&id).into());
+            }
+            msg_out.set_relay_response(rr);
+            state.send_to_client(&socket_addr, msg_out);
+            None
+        }
+        Some(rendezvous_message::Union::TestNatRequest(_)) => {
+            msg_out.set_test_nat_response(TestNatResponse {
+                port: addr.port() as _,
+                ..Default::default()
+            });
+            Some(msg_out)
+        }
+        Some(rendezvous_message::Union::OnlineRequest(or)) => {
+            let mut states = vec![0u8; (or.peers.len() + 7) / 8];
+            for (i, id) in or.peers.iter().enumerate() {
+                if state.peer_addr(id).is_some() {
+                    // bytes index from left to right
+                    states[i / 8] |= 0x01 << (7 - i % 8);
+                }
+            }
+            msg_out.set_online_response(OnlineResponse {
+                states: states.into(),
+                ..Default::default()
+            });
+            Some(msg_out)
+        }
+        _ => None,
+    }
+}
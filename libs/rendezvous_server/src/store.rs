@@ -0,0 +1,176 @@
+// Registered peers, kept in a TOML file so an id stays bound to the machine
+// which registered it across restarts. Addresses and liveness change all the
+// time and are not stored.
+
+use hbb_common::{
+    log,
+    rendezvous_proto::register_pk_response::Result as RegisterResult,
+    sodiumoxide::base64::{self, Variant},
+    toml, ResultType,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerRecord {
+    #[serde(with = "base64_bytes")]
+    pub uuid: Vec<u8>,
+    /// Sign public key of the peer, handed out signed by the server.
+    #[serde(with = "base64_bytes")]
+    pub pk: Vec<u8>,
+}
+
+mod base64_bytes {
+    use super::*;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &[u8], s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&base64::encode(v, Variant::Original))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(d)?;
+        base64::decode(s, Variant::Original).map_err(|_| D::Error::custom("invalid base64"))
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StoreFile {
+    #[serde(default)]
+    peers: HashMap<String, PeerRecord>,
+}
+
+#[derive(Debug, Default)]
+pub struct PeerStore {
+    // none for a store which only lives in memory
+    path: Option<PathBuf>,
+    peers: HashMap<String, PeerRecord>,
+}
+
+/// Same rule as the ids the clients generate or let the user pick.
+fn is_valid_id(id: &str) -> bool {
+    (6..=16).contains(&id.len())
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl PeerStore {
+    /// A missing file is an empty store.
+    pub fn load(path: &Path) -> ResultType<Self> {
+        let peers = if path.exists() {
+            toml::from_str::<StoreFile>(&std::fs::read_to_string(path)?)?.peers
+        } else {
+            Default::default()
+        };
+        log::info!("{} peers loaded from {}", peers.len(), path.display());
+        Ok(Self {
+            path: Some(path.to_owned()),
+            peers,
+        })
+    }
+
+    pub fn get(&self, id: &str) -> Option<&PeerRecord> {
+        self.peers.get(id)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Binds `id` to the machine with `uuid` on first use. A known machine may
+    /// replace its key, another machine may not take the id over.
+    pub fn register(&mut self, id: &str, uuid: &[u8], pk: &[u8]) -> RegisterResult {
+        if !is_valid_id(id) || uuid.is_empty() || pk.is_empty() {
+            return RegisterResult::INVALID_ID_FORMAT;
+        }
+        if let Some(record) = self.peers.get(id) {
+            if record.uuid != uuid {
+                return RegisterResult::UUID_MISMATCH;
+            }
+            if record.pk == pk {
+                return RegisterResult::OK;
+            }
+        }
+        let old = self.peers.insert(
+            id.to_owned(),
+            PeerRecord {
+                uuid: uuid.to_vec(),
+                pk: pk.to_vec(),
+            },
+        );
+        match self.save() {
+            Ok(()) => RegisterResult::OK,
+            Err(err) => {
+                log::error!("Failed to save the peer store: {}", err);
+                match old {
+                    Some(old) => self.peers.insert(id.to_owned(), old),
+                    None => self.peers.remove(id),
+                };
+                RegisterResult::SERVER_ERROR
+            }
+        }
+    }
+
+    fn save(&self) -> ResultType<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let file = StoreFile {
+            peers: self.peers.clone(),
+        };
+        // written aside and renamed so a crash never leaves half a store
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, toml::to_string(&file)?)?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register() {
+        let dir = std::env::temp_dir().join(format!("peer-store-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("peers.toml");
+        let mut store = PeerStore::load(&path).unwrap();
+        assert!(store.is_empty());
+        assert_eq!(
+            store.register("123", b"a", b"pk"),
+            RegisterResult::INVALID_ID_FORMAT
+        );
+        assert_eq!(store.register("123456789", b"a", b"pk"), RegisterResult::OK);
+        assert_eq!(
+            store.register("123456789", b"a", b"pk2"),
+            RegisterResult::OK
+        );
+        assert_eq!(
+            store.register("123456789", b"b", b"pk"),
+            RegisterResult::UUID_MISMATCH
+        );
+
+        let store = PeerStore::load(&path).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(store.len(), 1);
+        assert_eq!(
+            store.get("123456789"),
+            Some(&PeerRecord {
+                uuid: b"a".to_vec(),
+                pk: b"pk2".to_vec(),
+            })
+        );
+    }
+}
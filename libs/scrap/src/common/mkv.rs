@@ -0,0 +1,538 @@
+// Minimal Matroska/WebM muxer used by the recorder. One video track and an
+// optional Opus audio track, millisecond timecodes, a cluster per video key
+// frame and cues written on finalize so the output is seekable.
+
+use hbb_common::{bail, ResultType};
+use std::io::{Seek, SeekFrom, Write};
+
+const EBML_HEADER: u32 = 0x1A45DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549A966;
+const TIMECODE_SCALE: u32 = 0x2AD7B1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const CODEC_DELAY: u32 = 0x56AA;
+const SEEK_PRE_ROLL: u32 = 0x56BB;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const CLUSTER: u32 = 0x1F43B675;
+const CLUSTER_TIMECODE: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const CUES: u32 = 0x1C53BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+
+const VIDEO_TRACK: u64 = 1;
+const AUDIO_TRACK: u64 = 2;
+const OPUS_SEEK_PRE_ROLL: u64 = 80_000_000;
+// Lookahead of the restricted low delay opus encoders the audio service uses,
+// 2.5 ms counted in 48 kHz samples whatever the input rate is.
+pub const OPUS_PRE_SKIP: u16 = 120;
+const MAX_CLUSTER_DURATION: i64 = i16::MAX as _;
+const MAX_CLUSTER_SIZE: usize = 8 * 1024 * 1024;
+
+const OBU_SEQUENCE_HEADER: u8 = 1;
+const OBU_TEMPORAL_DELIMITER: u8 = 2;
+
+pub struct MkvWriter<W: Write + Seek> {
+    out: W,
+    codec_id: &'static str,
+    codec_private: Vec<u8>,
+    width: u64,
+    height: u64,
+    audio: Option<(u32, u32)>,
+    header_written: bool,
+    segment_size_pos: u64,
+    segment_data_pos: u64,
+    duration_pos: u64,
+    cluster: Vec<u8>,
+    cluster_timecode: i64,
+    last_pts: i64,
+    cues: Vec<(i64, u64)>,
+}
+
+impl<W: Write + Seek> MkvWriter<W> {
+    pub fn new(out: W, width: usize, height: usize, codec_id: &'static str) -> Self {
+        MkvWriter {
+            out,
+            codec_id,
+            codec_private: vec![],
+            width: width as _,
+            height: height as _,
+            audio: None,
+            header_written: false,
+            segment_size_pos: 0,
+            segment_data_pos: 0,
+            duration_pos: 0,
+            cluster: vec![],
+            cluster_timecode: 0,
+            last_pts: 0,
+            cues: vec![],
+        }
+    }
+
+    /// Must be called before the first frame, the track header is written lazily
+    /// so that it can depend on the first key frame.
+    pub fn set_codec_private(&mut self, codec_private: Vec<u8>) {
+        self.codec_private = codec_private;
+    }
+
+    /// Adds an Opus audio track, only possible before the first video frame.
+    pub fn add_audio_track(&mut self, sample_rate: u32, channels: u32) -> bool {
+        if self.header_written {
+            return false;
+        }
+        self.audio = Some((sample_rate, channels));
+        true
+    }
+
+    /// `pts` in milliseconds. Frames before the first key frame are dropped.
+    pub fn add_frame(&mut self, data: &[u8], pts: i64, key: bool) -> ResultType<bool> {
+        if !self.header_written {
+            if !key {
+                return Ok(false);
+            }
+            self.write_header()?;
+        }
+        self.add_block(VIDEO_TRACK, data, pts, key)
+    }
+
+    /// `pts` in milliseconds on the video timeline. Audio before the first video
+    /// key frame, or too late for the current cluster, is dropped.
+    pub fn add_audio_frame(&mut self, data: &[u8], pts: i64) -> ResultType<bool> {
+        if !self.header_written || self.audio.is_none() {
+            return Ok(false);
+        }
+        self.add_block(AUDIO_TRACK, data, pts, true)
+    }
+
+    fn add_block(&mut self, track: u64, data: &[u8], pts: i64, key: bool) -> ResultType<bool> {
+        if pts - self.cluster_timecode < i16::MIN as i64 {
+            return Ok(false);
+        }
+        if !self.cluster.is_empty()
+            && ((track == VIDEO_TRACK && key)
+                || pts - self.cluster_timecode > MAX_CLUSTER_DURATION
+                || self.cluster.len() > MAX_CLUSTER_SIZE)
+        {
+            self.flush_cluster()?;
+        }
+        if self.cluster.is_empty() {
+            self.cluster_timecode = pts;
+            if track == VIDEO_TRACK && key {
+                let pos = self.out.stream_position()? - self.segment_data_pos;
+                self.cues.push((pts, pos));
+            }
+        }
+        let mut payload = Vec::with_capacity(data.len() + 4);
+        write_size(&mut payload, track);
+        payload.extend_from_slice(&((pts - self.cluster_timecode) as i16).to_be_bytes());
+        payload.push(if key { 0x80 } else { 0 });
+        payload.extend_from_slice(data);
+        write_element(&mut self.cluster, SIMPLE_BLOCK, &payload);
+        self.last_pts = self.last_pts.max(pts);
+        Ok(true)
+    }
+
+    pub fn finalize(mut self) -> ResultType<W> {
+        if !self.header_written {
+            return Ok(self.out);
+        }
+        self.flush_cluster()?;
+        let mut cues = vec![];
+        for (time, pos) in self.cues.iter() {
+            let mut positions = vec![];
+            write_uint(&mut positions, CUE_TRACK, VIDEO_TRACK);
+            write_uint(&mut positions, CUE_CLUSTER_POSITION, *pos);
+            let mut point = vec![];
+            write_uint(&mut point, CUE_TIME, *time as _);
+            write_element(&mut point, CUE_TRACK_POSITIONS, &positions);
+            write_element(&mut cues, CUE_POINT, &point);
+        }
+        let mut buf = vec![];
+        write_element(&mut buf, CUES, &cues);
+        self.out.write_all(&buf)?;
+        let end = self.out.stream_position()?;
+        self.out.seek(SeekFrom::Start(self.duration_pos))?;
+        self.out
+            .write_all(&(self.last_pts as f64).to_bits().to_be_bytes())?;
+        self.out.seek(SeekFrom::Start(self.segment_size_pos))?;
+        let size = end - self.segment_data_pos;
+        self.out.write_all(&(size | (1u64 << 56)).to_be_bytes())?;
+        self.out.seek(SeekFrom::Start(end))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn write_header(&mut self) -> ResultType<()> {
+        let mut header = vec![];
+        write_uint(&mut header, EBML_VERSION, 1);
+        write_uint(&mut header, EBML_READ_VERSION, 1);
+        write_uint(&mut header, EBML_MAX_ID_LENGTH, 4);
+        write_uint(&mut header, EBML_MAX_SIZE_LENGTH, 8);
+        write_element(&mut header, DOC_TYPE, b"webm");
+        write_uint(&mut header, DOC_TYPE_VERSION, 4);
+        write_uint(&mut header, DOC_TYPE_READ_VERSION, 2);
+        let mut buf = vec![];
+        write_element(&mut buf, EBML_HEADER, &header);
+        self.out.write_all(&buf)?;
+
+        // Segment size is unknown until finalize, reserve 8 bytes.
+        buf.clear();
+        write_id(&mut buf, SEGMENT);
+        self.out.write_all(&buf)?;
+        self.segment_size_pos = self.out.stream_position()?;
+        self.out
+            .write_all(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF])?;
+        self.segment_data_pos = self.out.stream_position()?;
+
+        let mut info = vec![];
+        write_uint(&mut info, TIMECODE_SCALE, 1_000_000);
+        write_element(&mut info, MUXING_APP, b"scrap");
+        write_element(&mut info, WRITING_APP, b"scrap");
+        write_id(&mut info, DURATION);
+        write_size(&mut info, 8);
+        let duration_offset = info.len();
+        info.extend_from_slice(&0f64.to_bits().to_be_bytes());
+        buf.clear();
+        write_id(&mut buf, INFO);
+        write_size(&mut buf, info.len() as _);
+        let info_start = self.out.stream_position()? + buf.len() as u64;
+        buf.extend_from_slice(&info);
+        self.duration_pos = info_start + duration_offset as u64;
+
+        let mut video = vec![];
+        write_uint(&mut video, PIXEL_WIDTH, self.width);
+        write_uint(&mut video, PIXEL_HEIGHT, self.height);
+        let mut entry = vec![];
+        write_uint(&mut entry, TRACK_NUMBER, VIDEO_TRACK);
+        write_uint(&mut entry, TRACK_UID, VIDEO_TRACK);
+        write_uint(&mut entry, TRACK_TYPE, 1);
+        write_element(&mut entry, CODEC_ID, self.codec_id.as_bytes());
+        if !self.codec_private.is_empty() {
+            write_element(&mut entry, CODEC_PRIVATE, &self.codec_private);
+        }
+        write_element(&mut entry, VIDEO, &video);
+        let mut tracks = vec![];
+        write_element(&mut tracks, TRACK_ENTRY, &entry);
+        if let Some((sample_rate, channels)) = self.audio {
+            let mut audio = vec![];
+            write_float(&mut audio, SAMPLING_FREQUENCY, sample_rate as _);
+            write_uint(&mut audio, CHANNELS, channels as _);
+            let mut entry = vec![];
+            write_uint(&mut entry, TRACK_NUMBER, AUDIO_TRACK);
+            write_uint(&mut entry, TRACK_UID, AUDIO_TRACK);
+            write_uint(&mut entry, TRACK_TYPE, 2);
+            write_element(&mut entry, CODEC_ID, b"A_OPUS");
+            write_element(&mut entry, CODEC_PRIVATE, &opus_head(sample_rate, channels));
+            write_uint(
+                &mut entry,
+                CODEC_DELAY,
+                OPUS_PRE_SKIP as u64 * 1_000_000_000 / 48_000,
+            );
+            write_uint(&mut entry, SEEK_PRE_ROLL, OPUS_SEEK_PRE_ROLL);
+            write_element(&mut entry, AUDIO, &audio);
+            write_element(&mut tracks, TRACK_ENTRY, &entry);
+        }
+        write_element(&mut buf, TRACKS, &tracks);
+        self.out.write_all(&buf)?;
+        self.header_written = true;
+        Ok(())
+    }
+
+    fn flush_cluster(&mut self) -> ResultType<()> {
+        if self.cluster.is_empty() {
+            return Ok(());
+        }
+        let mut payload = vec![];
+        write_uint(&mut payload, CLUSTER_TIMECODE, self.cluster_timecode as _);
+        payload.append(&mut self.cluster);
+        let mut buf = vec![];
+        write_element(&mut buf, CLUSTER, &payload);
+        self.out.write_all(&buf)?;
+        Ok(())
+    }
+}
+
+fn write_id(buf: &mut Vec<u8>, id: u32) {
+    let len = (4 - id.leading_zeros() / 8) as usize;
+    buf.extend_from_slice(&id.to_be_bytes()[4 - len..]);
+}
+
+fn write_size(buf: &mut Vec<u8>, size: u64) {
+    let mut len = 1;
+    // all ones is reserved for unknown size
+    while len < 8 && size >= (1u64 << (7 * len)) - 1 {
+        len += 1;
+    }
+    let v = size | (1u64 << (7 * len));
+    buf.extend_from_slice(&v.to_be_bytes()[8 - len..]);
+}
+
+fn write_element(buf: &mut Vec<u8>, id: u32, payload: &[u8]) {
+    write_id(buf, id);
+    write_size(buf, payload.len() as _);
+    buf.extend_from_slice(payload);
+}
+
+fn write_uint(buf: &mut Vec<u8>, id: u32, v: u64) {
+    let len = ((64 - v.leading_zeros() as usize + 7) / 8).max(1);
+    write_element(buf, id, &v.to_be_bytes()[8 - len..]);
+}
+
+fn write_float(buf: &mut Vec<u8>, id: u32, v: f64) {
+    write_element(buf, id, &v.to_bits().to_be_bytes());
+}
+
+// https://www.rfc-editor.org/rfc/rfc7845#section-5.1
+pub fn opus_head(sample_rate: u32, channels: u32) -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1);
+    head.push(channels as u8);
+    head.extend_from_slice(&OPUS_PRE_SKIP.to_le_bytes());
+    head.extend_from_slice(&sample_rate.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes());
+    head.push(0);
+    head
+}
+
+// (obu type, whole obu including header) for every obu in a temporal unit.
+// libaom always sets obu_has_size_field.
+fn av1_obus(data: &[u8]) -> ResultType<Vec<(u8, &[u8], &[u8])>> {
+    let mut obus = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = data[pos];
+        let kind = (header >> 3) & 0x0F;
+        let mut payload_pos = pos + 1 + ((header >> 2) & 1) as usize;
+        if header & 0x02 == 0 {
+            bail!("av1 obu without size field");
+        }
+        let mut size = 0usize;
+        for i in 0..8 {
+            if payload_pos >= data.len() {
+                bail!("truncated av1 obu");
+            }
+            let b = data[payload_pos];
+            payload_pos += 1;
+            size |= ((b & 0x7F) as usize) << (7 * i);
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        let end = payload_pos + size;
+        if end > data.len() {
+            bail!("truncated av1 obu");
+        }
+        obus.push((kind, &data[pos..end], &data[payload_pos..end]));
+        pos = end;
+    }
+    Ok(obus)
+}
+
+/// Matroska stores AV1 temporal units without temporal delimiter OBUs.
+pub fn av1_strip_temporal_delimiters(data: &[u8]) -> ResultType<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    for (kind, obu, _) in av1_obus(data)? {
+        if kind != OBU_TEMPORAL_DELIMITER {
+            out.extend_from_slice(obu);
+        }
+    }
+    Ok(out)
+}
+
+/// Builds the `av1C` CodecPrivate from the sequence header OBU of a key frame.
+/// Only profile/level/tier are parsed, color config assumes the 8 bit 4:2:0
+/// output of `AomEncoder`.
+pub fn av1_codec_private(key_frame: &[u8]) -> ResultType<Vec<u8>> {
+    let (obu, payload) = match av1_obus(key_frame)?
+        .into_iter()
+        .find(|(kind, _, _)| *kind == OBU_SEQUENCE_HEADER)
+    {
+        Some((_, obu, payload)) => (obu, payload),
+        None => bail!("no av1 sequence header in key frame"),
+    };
+    let mut r = BitReader {
+        data: payload,
+        pos: 0,
+    };
+    let seq_profile = r.bits(3)? as u8;
+    let _still_picture = r.bits(1)?;
+    let reduced_still_picture_header = r.bits(1)? == 1;
+    let (seq_level_idx, seq_tier) = if reduced_still_picture_header {
+        (r.bits(5)? as u8, 0)
+    } else {
+        let mut decoder_model_info_present = false;
+        let mut buffer_delay_length = 0;
+        if r.bits(1)? == 1 {
+            // timing_info
+            r.bits(32)?;
+            r.bits(32)?;
+            if r.bits(1)? == 1 {
+                r.uvlc()?;
+            }
+            decoder_model_info_present = r.bits(1)? == 1;
+            if decoder_model_info_present {
+                buffer_delay_length = r.bits(5)? + 1;
+                r.bits(32)?;
+                r.bits(5)?;
+                r.bits(5)?;
+            }
+        }
+        let initial_display_delay_present = r.bits(1)? == 1;
+        let operating_points = r.bits(5)? + 1;
+        let mut first = (0, 0);
+        for i in 0..operating_points {
+            r.bits(12)?;
+            let level = r.bits(5)? as u8;
+            let tier = if level > 7 { r.bits(1)? as u8 } else { 0 };
+            if decoder_model_info_present && r.bits(1)? == 1 {
+                r.bits(buffer_delay_length)?;
+                r.bits(buffer_delay_length)?;
+                r.bits(1)?;
+            }
+            if initial_display_delay_present && r.bits(1)? == 1 {
+                r.bits(4)?;
+            }
+            if i == 0 {
+                first = (level, tier);
+            }
+        }
+        first
+    };
+    let mut av1c = vec![
+        0x81,
+        (seq_profile << 5) | (seq_level_idx & 0x1F),
+        (seq_tier << 7) | (1 << 3) | (1 << 2),
+        0,
+    ];
+    av1c.extend_from_slice(obu);
+    Ok(av1c)
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, n: u32) -> ResultType<u32> {
+        let mut v = 0u32;
+        for _ in 0..n {
+            let byte = match self.data.get(self.pos / 8) {
+                Some(b) => *b,
+                None => bail!("truncated av1 sequence header"),
+            };
+            v = (v << 1) | ((byte >> (7 - self.pos % 8)) & 1) as u32;
+            self.pos += 1;
+        }
+        Ok(v)
+    }
+
+    fn uvlc(&mut self) -> ResultType<u32> {
+        let mut leading_zeros = 0;
+        while self.bits(1)? == 0 {
+            leading_zeros += 1;
+            if leading_zeros >= 32 {
+                bail!("invalid uvlc");
+            }
+        }
+        Ok(self.bits(leading_zeros)? + (1u32 << leading_zeros) - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{playback::Playback, CodecFormat};
+
+    // temporal delimiter + sequence header (profile 0, level 8, one operating point)
+    // + a fake frame obu
+    fn av1_key_frame(n: u8) -> Vec<u8> {
+        vec![
+            0x12, 0x00, 0x0A, 0x04, 0x00, 0x00, 0x00, 0x40, 0x32, 0x02, n, n,
+        ]
+    }
+
+    #[test]
+    fn test_opus_head() {
+        let head = opus_head(48000, 2);
+        assert_eq!(head.len(), 19);
+        assert_eq!(&head[..8], b"OpusHead");
+        assert_eq!(head[9], 2);
+        assert_eq!(u16::from_le_bytes([head[10], head[11]]), OPUS_PRE_SKIP);
+        assert_eq!(
+            u32::from_le_bytes([head[12], head[13], head[14], head[15]]),
+            48000
+        );
+    }
+
+    #[test]
+    fn test_av1_codec_private() {
+        let frame = av1_key_frame(1);
+        assert_eq!(
+            av1_strip_temporal_delimiters(&frame).unwrap(),
+            frame[2..].to_vec()
+        );
+        let av1c = av1_codec_private(&frame).unwrap();
+        assert_eq!(&av1c[..4], &[0x81, 0x08, 0x0C, 0x00]);
+        assert_eq!(&av1c[4..], &frame[2..8]);
+    }
+
+    #[test]
+    fn test_mkv_av1_roundtrip() {
+        let path = std::env::temp_dir().join("scrap_mkv_test.webm");
+        let mut mkv = MkvWriter::new(std::fs::File::create(&path).unwrap(), 32, 16, "V_AV1");
+        // dropped, no key frame yet
+        assert!(!mkv.add_frame(&[0x32, 0x01, 0xFF], 0, false).unwrap());
+        mkv.set_codec_private(av1_codec_private(&av1_key_frame(0)).unwrap());
+        assert!(mkv.add_audio_track(48000, 2));
+        // dropped, the file starts at the first video key frame
+        assert!(!mkv.add_audio_frame(&[0xFC, 0x00], 50).unwrap());
+        for i in 0..50i64 {
+            let data = if i % 20 == 0 {
+                av1_strip_temporal_delimiters(&av1_key_frame(i as _)).unwrap()
+            } else {
+                vec![0x32, 0x01, i as u8]
+            };
+            assert!(mkv.add_frame(&data, 100 + i * 40, i % 20 == 0).unwrap());
+            assert!(mkv.add_audio_frame(&[0xFC, i as u8], 110 + i * 40).unwrap());
+        }
+        assert!(!mkv.add_audio_track(48000, 2));
+        mkv.finalize().unwrap();
+
+        let playback = Playback::open(&path).unwrap();
+        assert!(playback.format == CodecFormat::AV1);
+        assert_eq!((playback.width, playback.height), (32, 16));
+        assert_eq!(playback.samples().len(), 50);
+        assert_eq!(
+            playback.keyframes().map(|s| s.pts).collect::<Vec<_>>(),
+            vec![100, 900, 1700]
+        );
+        assert_eq!(playback.audio(), Some((48000, 2)));
+        assert_eq!(playback.audio_samples().len(), 50);
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -43,6 +43,8 @@ pub const STRIDE_ALIGN: usize = 64; // commonly used in libvpx vpx_img_alloc cal
 pub const HW_STRIDE_ALIGN: usize = 0; // recommended by av_frame_get_buffer
 
 pub mod aom;
+mod mkv;
+pub mod playback;
 pub mod record;
 mod vpx;
 
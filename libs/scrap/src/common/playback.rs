@@ -0,0 +1,1062 @@
+// Reading side of `record`: opens the .webm/.mp4 files written by `Recorder`,
+// indexes every video sample, and lets callers seek, cut and decode them
+// without a media player.
+
+use crate::{
+    codec::Decoder,
+    mkv::{av1_codec_private, MkvWriter},
+    CodecFormat, ImageFormat, ImageRgb,
+};
+#[cfg(feature = "hwcodec")]
+use hbb_common::anyhow::anyhow;
+use hbb_common::{
+    bail,
+    bytes::Bytes,
+    log,
+    message_proto::{video_frame, EncodedVideoFrame, EncodedVideoFrames},
+    ResultType,
+};
+#[cfg(feature = "hwcodec")]
+use hwcodec::mux::{MuxContext, Muxer};
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+const EBML_HEADER: u32 = 0x1A45DFA3;
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549A966;
+const TIMECODE_SCALE: u32 = 0x2AD7B1;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const CLUSTER: u32 = 0x1F43B675;
+const CLUSTER_TIMECODE: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+const REFERENCE_BLOCK: u32 = 0xFB;
+const TRACK_TYPE_VIDEO: u64 = 1;
+const TRACK_TYPE_AUDIO: u64 = 2;
+const UNKNOWN_SIZE: u64 = u64::MAX;
+const DEFAULT_TIMECODE_SCALE: u64 = 1_000_000;
+
+/// One encoded video frame inside a recording.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// Presentation time in milliseconds from the start of the recording.
+    pub pts: i64,
+    pub key: bool,
+    offset: u64,
+    size: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    WebM,
+    Mp4,
+}
+
+pub struct Playback {
+    pub filename: PathBuf,
+    pub format: CodecFormat,
+    pub width: usize,
+    pub height: usize,
+    samples: Vec<Sample>,
+    // Opus (sample rate, channels) and packets, webm only
+    audio: Option<(u32, u32)>,
+    audio_samples: Vec<Sample>,
+    container: Container,
+    // mp4 only: nalu length prefix size and the parameter sets from avcC/hvcC,
+    // in annex-b form, prepended to every key frame.
+    nalu_length_size: usize,
+    parameter_sets: Vec<u8>,
+    reader: BufReader<File>,
+    file_len: u64,
+}
+
+impl Playback {
+    pub fn open<P: AsRef<Path>>(path: P) -> ResultType<Self> {
+        let filename = path.as_ref().to_path_buf();
+        let file = File::open(&filename)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        reader.seek(SeekFrom::Start(0))?;
+        let mut playback = Playback {
+            filename,
+            format: CodecFormat::Unknown,
+            width: 0,
+            height: 0,
+            samples: vec![],
+            audio: None,
+            audio_samples: vec![],
+            container: Container::WebM,
+            nalu_length_size: 4,
+            parameter_sets: vec![],
+            reader,
+            file_len,
+        };
+        if u32::from_be_bytes([magic[0], magic[1], magic[2], magic[3]]) == EBML_HEADER {
+            playback.parse_webm()?;
+        } else if &magic[4..8] == b"ftyp" {
+            playback.container = Container::Mp4;
+            playback.parse_mp4()?;
+        } else {
+            bail!("unknown recording container");
+        }
+        if playback.format == CodecFormat::Unknown {
+            bail!("no supported video track found");
+        }
+        // Blocks are stored in decode order, which is also presentation order
+        // for the codecs we record, but be tolerant of muxers that interleave.
+        playback.samples.sort_by_key(|s| s.pts);
+        playback.audio_samples.sort_by_key(|s| s.pts);
+        log::info!(
+            "opened recording {:?}: {} {}x{}, {} frames, {} key frames",
+            playback.filename,
+            playback.format.to_string(),
+            playback.width,
+            playback.height,
+            playback.samples.len(),
+            playback.keyframes().count()
+        );
+        Ok(playback)
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    pub fn keyframes(&self) -> impl Iterator<Item = &Sample> {
+        self.samples.iter().filter(|s| s.key)
+    }
+
+    pub fn audio(&self) -> Option<(u32, u32)> {
+        self.audio
+    }
+
+    pub fn audio_samples(&self) -> &[Sample] {
+        &self.audio_samples
+    }
+
+    /// Duration in milliseconds, measured as the pts of the last frame.
+    pub fn duration(&self) -> i64 {
+        self.samples.last().map(|s| s.pts).unwrap_or(0)
+    }
+
+    /// Index of the last key frame at or before `pts`, the point decoding has
+    /// to start from to reach `pts`.
+    pub fn seek(&self, pts: i64) -> Option<usize> {
+        let end = self.samples.partition_point(|s| s.pts <= pts);
+        self.samples[..end]
+            .iter()
+            .rposition(|s| s.key)
+            .or_else(|| self.samples.iter().position(|s| s.key))
+    }
+
+    /// Reads the encoded data of a sample. H264/H265 samples from mp4 files
+    /// are converted to annex-b so that they can be fed to the decoders.
+    pub fn read_sample(&mut self, index: usize) -> ResultType<Vec<u8>> {
+        let sample = match self.samples.get(index) {
+            Some(s) => *s,
+            None => bail!("sample {} out of range", index),
+        };
+        let mut data = self.read_raw(sample.offset, sample.size)?;
+        if self.container == Container::Mp4 {
+            data = self.to_annexb(&data, sample.key)?;
+        }
+        Ok(data)
+    }
+
+    fn read_raw(&mut self, offset: u64, size: usize) -> ResultType<Vec<u8>> {
+        if offset.saturating_add(size as u64) > self.file_len {
+            bail!("sample past the end of the file");
+        }
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let mut data = vec![0u8; size];
+        self.reader.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Re-muxes the frames between `from` and `to` (milliseconds) into a new file,
+    /// starting at the key frame preceding `from` so the output is decodable.
+    pub fn export_range<P: AsRef<Path>>(&mut self, from: i64, to: i64, out: P) -> ResultType<usize> {
+        let start = match self.seek(from) {
+            Some(i) => i,
+            None => bail!("no key frame in recording"),
+        };
+        let end = self.samples.partition_point(|s| s.pts <= to);
+        if start >= end {
+            bail!("empty range");
+        }
+        let base = self.samples[start].pts;
+        let mut written = 0;
+        match self.format {
+            CodecFormat::VP8 | CodecFormat::VP9 | CodecFormat::AV1 => {
+                let codec_id = match self.format {
+                    CodecFormat::VP8 => "V_VP8",
+                    CodecFormat::VP9 => "V_VP9",
+                    _ => "V_AV1",
+                };
+                let mut mkv = MkvWriter::new(File::create(out)?, self.width, self.height, codec_id);
+                if let Some((sample_rate, channels)) = self.audio {
+                    mkv.add_audio_track(sample_rate, channels);
+                }
+                let last = self.samples[end - 1].pts;
+                let mut audio = self
+                    .audio_samples
+                    .iter()
+                    .filter(|s| s.pts >= base && s.pts <= last)
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .into_iter()
+                    .peekable();
+                for i in start..end {
+                    let data = self.read_sample(i)?;
+                    let sample = self.samples[i];
+                    if i == start && self.format == CodecFormat::AV1 {
+                        if let Ok(av1c) = av1_codec_private(&data) {
+                            mkv.set_codec_private(av1c);
+                        }
+                    }
+                    if mkv.add_frame(&data, sample.pts - base, sample.key)? {
+                        written += 1;
+                    }
+                    while let Some(a) = audio.next_if(|a| a.pts <= sample.pts) {
+                        let data = self.read_raw(a.offset, a.size)?;
+                        mkv.add_audio_frame(&data, a.pts - base)?;
+                    }
+                }
+                for a in audio {
+                    let data = self.read_raw(a.offset, a.size)?;
+                    mkv.add_audio_frame(&data, a.pts - base)?;
+                }
+                mkv.finalize()?;
+            }
+            #[cfg(feature = "hwcodec")]
+            CodecFormat::H264 | CodecFormat::H265 => {
+                let mut muxer = Muxer::new(MuxContext {
+                    filename: out.as_ref().to_string_lossy().to_string(),
+                    width: self.width,
+                    height: self.height,
+                    is265: self.format == CodecFormat::H265,
+                    framerate: crate::hwcodec::DEFAULT_TIME_BASE[1] as _,
+                })
+                .map_err(|_| anyhow!("Failed to create hardware muxer"))?;
+                for i in start..end {
+                    let data = self.read_sample(i)?;
+                    if muxer.write_video(&data, self.samples[i].key).is_ok() {
+                        written += 1;
+                    }
+                }
+                muxer.write_tail().ok();
+            }
+            _ => bail!("unsupported codec type"),
+        }
+        Ok(written)
+    }
+
+    /// Decodes the frame shown at `pts` (milliseconds) into RGBA.
+    pub fn export_frame(&mut self, pts: i64) -> ResultType<ImageRgb> {
+        let start = match self.seek(pts) {
+            Some(i) => i,
+            None => bail!("no key frame in recording"),
+        };
+        let end = self.samples.partition_point(|s| s.pts <= pts).max(start + 1);
+        let mut decoder = Decoder::new();
+        let mut rgb = ImageRgb::new(ImageFormat::ABGR, 1);
+        let mut decoded = false;
+        for i in start..end {
+            let data = self.read_sample(i)?;
+            let sample = self.samples[i];
+            let frames = EncodedVideoFrames {
+                frames: vec![EncodedVideoFrame {
+                    data: Bytes::from(data),
+                    key: sample.key,
+                    pts: sample.pts,
+                    ..Default::default()
+                }]
+                .into(),
+                ..Default::default()
+            };
+            let frame = match self.format {
+                CodecFormat::VP8 => video_frame::Union::Vp8s(frames),
+                CodecFormat::VP9 => video_frame::Union::Vp9s(frames),
+                CodecFormat::AV1 => video_frame::Union::Av1s(frames),
+                CodecFormat::H264 => video_frame::Union::H264s(frames),
+                CodecFormat::H265 => video_frame::Union::H265s(frames),
+                CodecFormat::Unknown => bail!("unsupported codec type"),
+            };
+            if decoder.handle_video_frame(&frame, &mut rgb)? {
+                decoded = true;
+            }
+        }
+        if !decoded {
+            bail!("failed to decode frame at {}ms", pts);
+        }
+        Ok(rgb)
+    }
+
+    fn parse_webm(&mut self) -> ResultType<()> {
+        let file_len = self.reader.seek(SeekFrom::End(0))?;
+        self.reader.seek(SeekFrom::Start(0))?;
+        let mut timecode_scale = DEFAULT_TIMECODE_SCALE;
+        let mut tracks = WebmTracks::default();
+        while self.reader.stream_position()? < file_len {
+            let (id, size) = self.read_element_header()?;
+            match id {
+                // Master elements whose children we want.
+                SEGMENT => {}
+                INFO => {
+                    let end = self.element_end(size, file_len)?;
+                    while self.reader.stream_position()? < end {
+                        let (id, size) = self.read_element_header()?;
+                        if id == TIMECODE_SCALE {
+                            timecode_scale = self.read_uint(size)?;
+                        } else {
+                            self.skip(size)?;
+                        }
+                    }
+                }
+                TRACKS => {
+                    let end = self.element_end(size, file_len)?;
+                    while self.reader.stream_position()? < end {
+                        let (id, size) = self.read_element_header()?;
+                        if id == TRACK_ENTRY {
+                            self.parse_webm_track(size, &mut tracks)?;
+                        } else {
+                            self.skip(size)?;
+                        }
+                    }
+                }
+                CLUSTER => {
+                    let end = self.element_end(size, file_len)?;
+                    self.parse_webm_cluster(end, &tracks, timecode_scale)?;
+                }
+                _ => {
+                    if size == UNKNOWN_SIZE {
+                        bail!("unknown-sized element {:#x}", id);
+                    }
+                    self.skip(size)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Picks the first supported video track and the first Opus track.
+    fn parse_webm_track(&mut self, size: u64, tracks: &mut WebmTracks) -> ResultType<()> {
+        let end = self.reader.stream_position()? + size;
+        let (mut number, mut kind, mut codec) = (0, 0, String::new());
+        let (mut sample_rate, mut channels) = (48000, 2);
+        while self.reader.stream_position()? < end {
+            let (id, size) = self.read_element_header()?;
+            match id {
+                TRACK_NUMBER => number = self.read_uint(size)?,
+                TRACK_TYPE => kind = self.read_uint(size)?,
+                CODEC_ID => codec = String::from_utf8_lossy(&self.read_bytes(size)?).to_string(),
+                CODEC_PRIVATE => self.skip(size)?,
+                VIDEO => {
+                    let video_end = self.reader.stream_position()? + size;
+                    while self.reader.stream_position()? < video_end {
+                        let (id, size) = self.read_element_header()?;
+                        match id {
+                            PIXEL_WIDTH => self.width = self.read_uint(size)? as _,
+                            PIXEL_HEIGHT => self.height = self.read_uint(size)? as _,
+                            _ => self.skip(size)?,
+                        }
+                    }
+                }
+                AUDIO => {
+                    let audio_end = self.reader.stream_position()? + size;
+                    while self.reader.stream_position()? < audio_end {
+                        let (id, size) = self.read_element_header()?;
+                        match id {
+                            SAMPLING_FREQUENCY => {
+                                sample_rate = self.read_float(size)? as _;
+                            }
+                            CHANNELS => channels = self.read_uint(size)? as _,
+                            _ => self.skip(size)?,
+                        }
+                    }
+                }
+                _ => self.skip(size)?,
+            }
+        }
+        if kind == TRACK_TYPE_AUDIO && tracks.audio == 0 {
+            if codec == "A_OPUS" {
+                self.audio = Some((sample_rate, channels));
+                tracks.audio = number;
+            }
+            return Ok(());
+        }
+        if kind != TRACK_TYPE_VIDEO || tracks.video != 0 {
+            return Ok(());
+        }
+        self.format = match codec.as_str() {
+            "V_VP8" => CodecFormat::VP8,
+            "V_VP9" => CodecFormat::VP9,
+            "V_AV1" => CodecFormat::AV1,
+            _ => {
+                log::warn!("unsupported video codec {}", codec);
+                return Ok(());
+            }
+        };
+        tracks.video = number;
+        Ok(())
+    }
+
+    fn parse_webm_cluster(
+        &mut self,
+        mut end: u64,
+        tracks: &WebmTracks,
+        timecode_scale: u64,
+    ) -> ResultType<()> {
+        let mut cluster_timecode = 0i64;
+        while self.reader.stream_position()? < end {
+            let (id, size) = self.read_element_header()?;
+            match id {
+                CLUSTER_TIMECODE => cluster_timecode = self.read_uint(size)? as _,
+                SIMPLE_BLOCK => {
+                    self.parse_webm_block(size, tracks, cluster_timecode, timecode_scale, None)?
+                }
+                BLOCK_GROUP => {
+                    let group_end = self.reader.stream_position()? + size;
+                    let mut block = None;
+                    let mut referenced = false;
+                    while self.reader.stream_position()? < group_end {
+                        let (id, size) = self.read_element_header()?;
+                        match id {
+                            BLOCK => block = Some((self.reader.stream_position()?, size)),
+                            REFERENCE_BLOCK => referenced = true,
+                            _ => {}
+                        }
+                        self.skip(size)?;
+                    }
+                    if let Some((pos, size)) = block {
+                        self.reader.seek(SeekFrom::Start(pos))?;
+                        self.parse_webm_block(
+                            size,
+                            tracks,
+                            cluster_timecode,
+                            timecode_scale,
+                            Some(!referenced),
+                        )?;
+                        self.reader.seek(SeekFrom::Start(group_end))?;
+                    }
+                }
+                // An unfinished recording leaves the last cluster unknown-sized,
+                // the next cluster header ends it.
+                CLUSTER => {
+                    cluster_timecode = 0;
+                    end = self.element_end(size, end)?;
+                }
+                _ => self.skip(size)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_webm_block(
+        &mut self,
+        size: u64,
+        tracks: &WebmTracks,
+        cluster_timecode: i64,
+        timecode_scale: u64,
+        key: Option<bool>,
+    ) -> ResultType<()> {
+        let start = self.reader.stream_position()?;
+        let (number, len) = self.read_vint(false)?;
+        let mut header = [0u8; 3];
+        self.reader.read_exact(&mut header)?;
+        let header_len = len as u64 + 3;
+        if number == 0
+            || (number != tracks.video && number != tracks.audio)
+            || size < header_len
+        {
+            self.reader.seek(SeekFrom::Start(start + size))?;
+            return Ok(());
+        }
+        if header[2] & 0x06 != 0 {
+            bail!("laced blocks are not supported");
+        }
+        let timecode = cluster_timecode + i16::from_be_bytes([header[0], header[1]]) as i64;
+        let sample = Sample {
+            pts: timecode * timecode_scale as i64 / 1_000_000,
+            key: key.unwrap_or(header[2] & 0x80 != 0),
+            offset: start + header_len,
+            size: (size - header_len) as _,
+        };
+        if number == tracks.video {
+            self.samples.push(sample);
+        } else {
+            self.audio_samples.push(sample);
+        }
+        self.reader.seek(SeekFrom::Start(start + size))?;
+        Ok(())
+    }
+
+    fn read_element_header(&mut self) -> ResultType<(u32, u64)> {
+        let (id, _) = self.read_vint(true)?;
+        let (size, len) = self.read_vint(false)?;
+        let size = if size == (1u64 << (7 * len)) - 1 {
+            UNKNOWN_SIZE
+        } else {
+            size
+        };
+        Ok((id as _, size))
+    }
+
+    // EBML variable length integer, ids keep their length marker bits.
+    fn read_vint(&mut self, keep_marker: bool) -> ResultType<(u64, usize)> {
+        let mut first = [0u8; 1];
+        self.reader.read_exact(&mut first)?;
+        let len = first[0].leading_zeros() as usize + 1;
+        if len > 8 {
+            bail!("invalid ebml vint");
+        }
+        let mut value = if keep_marker {
+            first[0] as u64
+        } else {
+            (first[0] as u64) & (0xFF >> len)
+        };
+        let mut rest = [0u8; 7];
+        self.reader.read_exact(&mut rest[..len - 1])?;
+        for b in &rest[..len - 1] {
+            value = (value << 8) | *b as u64;
+        }
+        Ok((value, len))
+    }
+
+    fn read_uint(&mut self, size: u64) -> ResultType<u64> {
+        if size > 8 {
+            bail!("invalid ebml uint size {}", size);
+        }
+        Ok(self
+            .read_bytes(size)?
+            .iter()
+            .fold(0u64, |acc, b| (acc << 8) | *b as u64))
+    }
+
+    fn read_float(&mut self, size: u64) -> ResultType<f64> {
+        let v = self.read_uint(size)?;
+        Ok(match size {
+            4 => f32::from_bits(v as u32) as f64,
+            8 => f64::from_bits(v),
+            _ => 0.,
+        })
+    }
+
+    // Sizes come from the file, never read past its end.
+    fn read_bytes(&mut self, size: u64) -> ResultType<Vec<u8>> {
+        let remain = self.file_len.saturating_sub(self.reader.stream_position()?);
+        if size > remain {
+            bail!("{} bytes past the end of the file", size);
+        }
+        let mut buf = vec![0u8; size as usize];
+        self.reader.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn skip(&mut self, size: u64) -> ResultType<()> {
+        if size == UNKNOWN_SIZE {
+            bail!("can't skip unknown-sized element");
+        }
+        self.reader.seek(SeekFrom::Current(size as _))?;
+        Ok(())
+    }
+
+    fn element_end(&mut self, size: u64, parent_end: u64) -> ResultType<u64> {
+        if size == UNKNOWN_SIZE {
+            Ok(parent_end)
+        } else {
+            Ok((self.reader.stream_position()? + size).min(parent_end))
+        }
+    }
+
+    fn parse_mp4(&mut self) -> ResultType<()> {
+        let file_len = self.file_len;
+        let mut pos = 0;
+        while pos + 8 <= file_len {
+            self.reader.seek(SeekFrom::Start(pos))?;
+            let (kind, size, header) = self.read_box_header(file_len - pos)?;
+            if &kind == b"moov" {
+                let moov = self.read_bytes(size - header)?;
+                return self.parse_moov(&moov);
+            }
+            pos += size;
+        }
+        bail!("moov box not found, the recording may be incomplete");
+    }
+
+    // A box of size 0 runs to the end of the file, `remain` bytes from its start.
+    fn read_box_header(&mut self, remain: u64) -> ResultType<([u8; 4], u64, u64)> {
+        let mut buf = [0u8; 8];
+        self.reader.read_exact(&mut buf)?;
+        let kind = [buf[4], buf[5], buf[6], buf[7]];
+        let size = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as u64;
+        let (size, header) = match size {
+            0 => (remain, 8),
+            1 => {
+                self.reader.read_exact(&mut buf)?;
+                (u64::from_be_bytes(buf), 16)
+            }
+            _ => (size, 8),
+        };
+        if size < header || size > remain {
+            bail!("invalid mp4 box size {}", size);
+        }
+        Ok((kind, size, header))
+    }
+
+    fn parse_moov(&mut self, moov: &[u8]) -> ResultType<()> {
+        for trak in mp4_children(moov, b"trak") {
+            let mdia = match mp4_child(trak, b"mdia") {
+                Some(v) => v,
+                None => continue,
+            };
+            match mp4_child(mdia, b"hdlr") {
+                Some(hdlr) if hdlr.len() >= 12 && &hdlr[8..12] == b"vide" => {}
+                _ => continue,
+            }
+            let timescale = match mp4_child(mdia, b"mdhd") {
+                Some(mdhd) if mdhd.len() >= 24 => {
+                    if mdhd[0] == 1 {
+                        be_u32(&mdhd[20..])
+                    } else {
+                        be_u32(&mdhd[12..])
+                    }
+                }
+                _ => bail!("invalid mdhd"),
+            } as i64;
+            let stbl = match mp4_child(mdia, b"minf").and_then(|minf| mp4_child(minf, b"stbl")) {
+                Some(v) => v,
+                None => bail!("stbl not found"),
+            };
+            self.parse_stsd(stbl)?;
+            let sizes = mp4_stsz(mp4_child(stbl, b"stsz"))?;
+            let count = sizes.len();
+            let mut pts = Vec::with_capacity(count);
+            let mut t = 0i64;
+            for (n, delta) in mp4_pairs(mp4_child(stbl, b"stts"))? {
+                for _ in 0..n {
+                    pts.push(t * 1000 / timescale.max(1));
+                    t += delta as i64;
+                }
+            }
+            let sync: Option<Vec<u64>> = match mp4_child(stbl, b"stss") {
+                Some(stss) => Some(mp4_table(Some(stss), 4)?),
+                None => None,
+            };
+            let chunks = match mp4_child(stbl, b"co64") {
+                Some(co64) => mp4_table(Some(co64), 8)?,
+                None => mp4_table(mp4_child(stbl, b"stco"), 4)?,
+            };
+            let stsc = mp4_triples(mp4_child(stbl, b"stsc"))?;
+            let mut sample = 0usize;
+            for (i, chunk_offset) in chunks.iter().enumerate() {
+                let chunk = i as u32 + 1;
+                let per_chunk = stsc
+                    .iter()
+                    .rev()
+                    .find(|(first, _, _)| *first <= chunk)
+                    .map(|(_, n, _)| *n)
+                    .unwrap_or(0);
+                let mut offset = *chunk_offset;
+                for _ in 0..per_chunk {
+                    if sample >= count {
+                        break;
+                    }
+                    let size = sizes[sample];
+                    self.samples.push(Sample {
+                        pts: pts.get(sample).cloned().unwrap_or(0),
+                        key: sync
+                            .as_ref()
+                            .map_or(true, |s| s.contains(&(sample as u64 + 1))),
+                        offset,
+                        size: size as _,
+                    });
+                    offset += size;
+                    sample += 1;
+                }
+            }
+            return Ok(());
+        }
+        Ok(())
+    }
+
+    fn parse_stsd(&mut self, stbl: &[u8]) -> ResultType<()> {
+        let stsd = match mp4_child(stbl, b"stsd") {
+            Some(v) if v.len() >= 16 => v,
+            _ => bail!("stsd not found"),
+        };
+        // full box header + entry count, then the first sample entry
+        let entry = &stsd[8..];
+        let entry_size = (be_u32(entry) as usize).min(entry.len());
+        let kind = &entry[4..8];
+        self.format = match kind {
+            b"avc1" | b"avc3" => CodecFormat::H264,
+            b"hvc1" | b"hev1" => CodecFormat::H265,
+            _ => {
+                log::warn!("unsupported mp4 sample entry {:?}", kind);
+                return Ok(());
+            }
+        };
+        // 8 bytes box header, 78 bytes visual sample entry
+        if entry_size < 86 {
+            bail!("invalid visual sample entry");
+        }
+        self.width = u16::from_be_bytes([entry[32], entry[33]]) as _;
+        self.height = u16::from_be_bytes([entry[34], entry[35]]) as _;
+        let children = &entry[86..entry_size];
+        if let Some(avcc) = mp4_child(children, b"avcC") {
+            self.parse_avcc(avcc)?;
+        } else if let Some(hvcc) = mp4_child(children, b"hvcC") {
+            self.parse_hvcc(hvcc)?;
+        }
+        Ok(())
+    }
+
+    fn parse_avcc(&mut self, avcc: &[u8]) -> ResultType<()> {
+        if avcc.len() < 7 {
+            bail!("invalid avcC");
+        }
+        self.nalu_length_size = (avcc[4] & 0x03) as usize + 1;
+        let mut pos = 5;
+        for mask in [0x1F, 0xFF] {
+            let n = avcc[pos] & mask;
+            pos += 1;
+            for _ in 0..n {
+                pos = self.push_parameter_set(avcc, pos)?;
+            }
+            if pos >= avcc.len() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_hvcc(&mut self, hvcc: &[u8]) -> ResultType<()> {
+        if hvcc.len() < 23 {
+            bail!("invalid hvcC");
+        }
+        self.nalu_length_size = (hvcc[21] & 0x03) as usize + 1;
+        let arrays = hvcc[22];
+        let mut pos = 23;
+        for _ in 0..arrays {
+            if pos + 3 > hvcc.len() {
+                bail!("invalid hvcC");
+            }
+            let n = u16::from_be_bytes([hvcc[pos + 1], hvcc[pos + 2]]);
+            pos += 3;
+            for _ in 0..n {
+                pos = self.push_parameter_set(hvcc, pos)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn push_parameter_set(&mut self, buf: &[u8], pos: usize) -> ResultType<usize> {
+        if pos + 2 > buf.len() {
+            bail!("invalid parameter set");
+        }
+        let len = u16::from_be_bytes([buf[pos], buf[pos + 1]]) as usize;
+        if pos + 2 + len > buf.len() {
+            bail!("invalid parameter set");
+        }
+        self.parameter_sets.extend_from_slice(&[0, 0, 0, 1]);
+        self.parameter_sets
+            .extend_from_slice(&buf[pos + 2..pos + 2 + len]);
+        Ok(pos + 2 + len)
+    }
+
+    fn to_annexb(&self, data: &[u8], key: bool) -> ResultType<Vec<u8>> {
+        let mut out = Vec::with_capacity(data.len() + self.parameter_sets.len() + 16);
+        if key {
+            out.extend_from_slice(&self.parameter_sets);
+        }
+        let n = self.nalu_length_size;
+        let mut pos = 0;
+        while pos + n <= data.len() {
+            let len = data[pos..pos + n]
+                .iter()
+                .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+            pos += n;
+            if pos + len > data.len() {
+                bail!("invalid nalu length");
+            }
+            out.extend_from_slice(&[0, 0, 0, 1]);
+            out.extend_from_slice(&data[pos..pos + len]);
+            pos += len;
+        }
+        Ok(out)
+    }
+}
+
+#[derive(Default)]
+struct WebmTracks {
+    video: u64,
+    audio: u64,
+}
+
+fn be_u32(buf: &[u8]) -> u32 {
+    u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]])
+}
+
+// Payloads of the direct children of `buf` with the given box type.
+fn mp4_children<'a>(buf: &'a [u8], kind: &'a [u8; 4]) -> impl Iterator<Item = &'a [u8]> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        while pos + 8 <= buf.len() {
+            let size = be_u32(&buf[pos..]) as usize;
+            let (size, header) = match size {
+                0 => (buf.len() - pos, 8),
+                1 if pos + 16 <= buf.len() => {
+                    let mut b = [0u8; 8];
+                    b.copy_from_slice(&buf[pos + 8..pos + 16]);
+                    (u64::from_be_bytes(b) as usize, 16)
+                }
+                _ => (size, 8),
+            };
+            if size < header || size > buf.len() - pos {
+                return None;
+            }
+            let start = pos;
+            pos += size;
+            if &buf[start + 4..start + 8] == kind {
+                return Some(&buf[start + header..start + size]);
+            }
+        }
+        None
+    })
+}
+
+fn mp4_child<'a>(buf: &'a [u8], kind: &'a [u8; 4]) -> Option<&'a [u8]> {
+    mp4_children(buf, kind).next()
+}
+
+fn mp4_stsz(full_box: Option<&[u8]>) -> ResultType<Vec<u64>> {
+    let b = match full_box {
+        Some(b) if b.len() >= 12 => b,
+        _ => bail!("missing stsz"),
+    };
+    let (size, count) = (be_u32(&b[4..]), be_u32(&b[8..]) as usize);
+    if size != 0 {
+        return Ok(vec![size as u64; count]);
+    }
+    if b.len() < 12 + count * 4 {
+        bail!("truncated stsz");
+    }
+    Ok((0..count).map(|i| be_u32(&b[12 + i * 4..]) as u64).collect())
+}
+
+// Entries of stss/stco (4 bytes) or co64 (8 bytes).
+fn mp4_table(full_box: Option<&[u8]>, width: usize) -> ResultType<Vec<u64>> {
+    let b = match full_box {
+        Some(b) if b.len() >= 8 => b,
+        _ => bail!("missing sample table"),
+    };
+    let count = be_u32(&b[4..]) as usize;
+    if b.len() < 8 + count * width {
+        bail!("truncated sample table");
+    }
+    Ok((0..count)
+        .map(|i| {
+            b[8 + i * width..8 + (i + 1) * width]
+                .iter()
+                .fold(0u64, |acc, b| (acc << 8) | *b as u64)
+        })
+        .collect())
+}
+
+fn mp4_pairs(full_box: Option<&[u8]>) -> ResultType<Vec<(u32, u32)>> {
+    let b = match full_box {
+        Some(b) if b.len() >= 8 => b,
+        _ => bail!("missing stts"),
+    };
+    let count = be_u32(&b[4..]) as usize;
+    if b.len() < 8 + count * 8 {
+        bail!("truncated stts");
+    }
+    Ok((0..count)
+        .map(|i| (be_u32(&b[8 + i * 8..]), be_u32(&b[12 + i * 8..])))
+        .collect())
+}
+
+fn mp4_triples(full_box: Option<&[u8]>) -> ResultType<Vec<(u32, u32, u32)>> {
+    let b = match full_box {
+        Some(b) if b.len() >= 8 => b,
+        _ => bail!("missing stsc"),
+    };
+    let count = be_u32(&b[4..]) as usize;
+    if b.len() < 8 + count * 12 {
+        bail!("truncated stsc");
+    }
+    Ok((0..count)
+        .map(|i| {
+            let e = &b[8 + i * 12..];
+            (be_u32(e), be_u32(&e[4..]), be_u32(&e[8..]))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webm::mux::{self, Track};
+
+    fn write_test_webm(path: &Path) {
+        let mut webm = mux::Segment::new(mux::Writer::new(File::create(path).unwrap())).unwrap();
+        let mut vt = webm.add_video_track(64, 48, None, mux::VideoCodecId::VP9);
+        for i in 0..30u64 {
+            let data = vec![i as u8; 100 + i as usize];
+            assert!(vt.add_frame(&data, i * 100 * 1_000_000, i % 10 == 0));
+        }
+        webm.finalize(None);
+    }
+
+    #[test]
+    fn test_webm_index_seek_and_cut() {
+        let dir = std::env::temp_dir();
+        let src = dir.join("scrap_playback_test_src.webm");
+        let dst = dir.join("scrap_playback_test_dst.webm");
+        write_test_webm(&src);
+
+        let mut playback = Playback::open(&src).unwrap();
+        assert!(playback.format == CodecFormat::VP9);
+        assert_eq!((playback.width, playback.height), (64, 48));
+        assert_eq!(playback.samples().len(), 30);
+        assert_eq!(
+            playback.keyframes().map(|s| s.pts).collect::<Vec<_>>(),
+            vec![0, 1000, 2000]
+        );
+        assert_eq!(playback.duration(), 2900);
+        assert_eq!(playback.seek(1550), Some(10));
+        assert_eq!(playback.read_sample(12).unwrap(), vec![12u8; 112]);
+
+        assert_eq!(playback.export_range(1550, 2500, &dst).unwrap(), 16);
+        let cut = Playback::open(&dst).unwrap();
+        assert_eq!(cut.samples().len(), 16);
+        assert_eq!(cut.keyframes().map(|s| s.pts).collect::<Vec<_>>(), vec![0, 1000]);
+
+        std::fs::remove_file(&src).ok();
+        std::fs::remove_file(&dst).ok();
+    }
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut v = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        v.extend_from_slice(kind);
+        v.extend_from_slice(payload);
+        v
+    }
+
+    fn full_box(kind: &[u8; 4], fields: &[u32]) -> Vec<u8> {
+        let mut payload = vec![0u8; 4];
+        for f in fields {
+            payload.extend_from_slice(&f.to_be_bytes());
+        }
+        mp4_box(kind, &payload)
+    }
+
+    // ftyp, mdat with three H264 samples, then moov, the last one sized 0.
+    fn test_mp4() -> Vec<u8> {
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\0\0isom");
+        let samples: Vec<Vec<u8>> = (0..3u8).map(|i| vec![0, 0, 0, 2, 0x65, i]).collect();
+        let mdat = mp4_box(b"mdat", &samples.concat());
+        let chunk_offset = (ftyp.len() + 8) as u32;
+
+        let hdlr = mp4_box(b"hdlr", &[&[0u8; 8][..], b"vide", &[0u8; 13]].concat());
+        let mdhd = full_box(b"mdhd", &[0, 0, 1000, 300, 0]);
+        let avcc = mp4_box(
+            b"avcC",
+            &[
+                1, 0x42, 0, 0x1E, 0xFF, 0xE1, 0, 2, 0x67, 1, 1, 0, 2, 0x68, 2,
+            ],
+        );
+        let mut entry = vec![0u8; 78];
+        entry[24..26].copy_from_slice(&64u16.to_be_bytes());
+        entry[26..28].copy_from_slice(&48u16.to_be_bytes());
+        entry.extend_from_slice(&avcc);
+        let stsd = mp4_box(
+            b"stsd",
+            &[&[0u8, 0, 0, 0, 0, 0, 0, 1][..], &mp4_box(b"avc1", &entry)].concat(),
+        );
+        let stbl = mp4_box(
+            b"stbl",
+            &[
+                stsd,
+                full_box(b"stsz", &[0, 3, 6, 6, 6]),
+                full_box(b"stts", &[1, 3, 100]),
+                full_box(b"stss", &[1, 1]),
+                full_box(b"stco", &[1, chunk_offset]),
+                full_box(b"stsc", &[1, 1, 3, 1]),
+            ]
+            .concat(),
+        );
+        let minf = mp4_box(b"minf", &stbl);
+        let mdia = mp4_box(b"mdia", &[hdlr, mdhd, minf].concat());
+        let mut moov = mp4_box(b"moov", &mp4_box(b"trak", &mdia));
+        moov[..4].copy_from_slice(&[0; 4]);
+        [ftyp, mdat, moov].concat()
+    }
+
+    fn open_mp4(name: &str, data: &[u8]) -> ResultType<Playback> {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, data).unwrap();
+        let res = Playback::open(&path);
+        std::fs::remove_file(&path).ok();
+        res
+    }
+
+    #[test]
+    fn test_mp4_index() {
+        let path = std::env::temp_dir().join("scrap_playback_test.mp4");
+        std::fs::write(&path, test_mp4()).unwrap();
+        let mut playback = Playback::open(&path).unwrap();
+        assert!(playback.format == CodecFormat::H264);
+        assert_eq!((playback.width, playback.height), (64, 48));
+        assert_eq!(
+            playback.samples().iter().map(|s| s.pts).collect::<Vec<_>>(),
+            vec![0, 100, 200]
+        );
+        assert_eq!(playback.keyframes().count(), 1);
+        assert_eq!(
+            playback.read_sample(0).unwrap(),
+            vec![0, 0, 0, 1, 0x67, 1, 0, 0, 0, 1, 0x68, 2, 0, 0, 0, 1, 0x65, 0]
+        );
+        assert_eq!(playback.read_sample(2).unwrap(), vec![0, 0, 0, 1, 0x65, 2]);
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_mp4_invalid_boxes() {
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\0\0isom");
+        // smaller than its header
+        let small = [&ftyp[..], &[0, 0, 0, 4], b"free"].concat();
+        assert!(open_mp4("scrap_playback_test_small.mp4", &small).is_err());
+        // extended size of 0
+        let extended = [&ftyp[..], &[0, 0, 0, 1], b"free", &[0; 8]].concat();
+        assert!(open_mp4("scrap_playback_test_extended.mp4", &extended).is_err());
+        // bigger than the file
+        let big = [&ftyp[..], &[0xFF, 0xFF, 0xFF, 0xF0], b"moov", &[0; 8]].concat();
+        assert!(open_mp4("scrap_playback_test_big.mp4", &big).is_err());
+        // samples past the end of the file
+        let mut data = test_mp4();
+        let stco = data.windows(4).position(|w| w == b"stco").unwrap();
+        data[stco + 12..stco + 16].copy_from_slice(&0xFFFFu32.to_be_bytes());
+        let mut playback = open_mp4("scrap_playback_test_offset.mp4", &data).unwrap();
+        assert!(playback.read_sample(0).is_err());
+    }
+}
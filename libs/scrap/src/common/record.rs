@@ -1,26 +1,37 @@
-use crate::CodecFormat;
+use crate::{
+    mkv::{av1_codec_private, av1_strip_temporal_delimiters, opus_head},
+    CodecFormat,
+};
 #[cfg(feature = "hwcodec")]
 use hbb_common::anyhow::anyhow;
 use hbb_common::{
     bail, chrono,
+    compress::decompress,
     config::Config,
     log,
-    message_proto::{message, video_frame, EncodedVideoFrame, Message},
+    message_proto::{
+        file_action, file_response, message, misc, video_frame, AudioFormat, EncodedVideoFrame,
+        Message,
+    },
+    serde_json::{json, Value},
     ResultType,
 };
 #[cfg(feature = "hwcodec")]
 use hwcodec::mux::{MuxContext, Muxer};
 use std::{
     fs::{File, OpenOptions},
-    io,
+    io::{self, Write},
     ops::{Deref, DerefMut},
     path::PathBuf,
     sync::mpsc::Sender,
     time::Instant,
 };
-use webm::mux::{self, Segment, Track, VideoTrack, Writer};
+use webm::mux::{self, AudioTrack, Segment, Track, VideoTrack, Writer};
 
 const MIN_SECS: u64 = 1;
+const MAX_EVENT_TEXT: usize = 4096;
+const VIDEO_TRACK: i32 = 1;
+const AUDIO_TRACK: i32 = 2;
 
 #[derive(Debug, Clone)]
 pub struct RecorderContext {
@@ -51,7 +62,10 @@ impl RecorderContext {
             + &self.id.clone()
             + &chrono::Local::now().format("_%Y%m%d%H%M%S_").to_string()
             + &self.format.to_string()
-            + if self.format == CodecFormat::VP9 || self.format == CodecFormat::VP8 {
+            + if self.format == CodecFormat::VP9
+                || self.format == CodecFormat::VP8
+                || self.format == CodecFormat::AV1
+            {
                 ".webm"
             } else {
                 ".mp4"
@@ -70,6 +84,13 @@ pub trait RecorderApi {
     where
         Self: Sized;
     fn write_video(&mut self, frame: &EncodedVideoFrame) -> bool;
+    // Adds an opus track, false if the container can't take one (any more).
+    fn set_audio_format(&mut self, _format: &AudioFormat) -> bool {
+        false
+    }
+    fn write_audio(&mut self, _data: &[u8], _pts: i64) -> bool {
+        false
+    }
 }
 
 #[derive(Debug)]
@@ -82,7 +103,22 @@ pub enum RecordState {
 
 pub struct Recorder {
     pub inner: Box<dyn RecorderApi>,
+    // Declared after `inner` so that it is dropped after the video file is
+    // finalized, and can follow it if the video file is removed.
+    events: EventLog,
     ctx: RecorderContext,
+    audio_format: Option<AudioFormat>,
+    // Chat and clipboard text only go into the sidecar if the user opted in
+    // with `allow-record-event-text` (off by default), otherwise just their
+    // size does.
+    event_text: bool,
+    // (first video pts, when it was written), maps audio and events onto the
+    // video timeline.
+    clock: Option<(i64, Instant)>,
+    // Encoders restart their pts when they are reconfigured, the offset added
+    // to later frames keeps the pts in the file increasing.
+    last_pts: Option<i64>,
+    pts_offset: i64,
 }
 
 impl Deref for Recorder {
@@ -101,23 +137,26 @@ impl DerefMut for Recorder {
 
 impl Recorder {
     pub fn new(mut ctx: RecorderContext) -> ResultType<Self> {
-        if ctx.format == CodecFormat::AV1 {
-            bail!("not support av1 recording");
-        }
         ctx.set_filename()?;
-        let recorder = match ctx.format {
-            CodecFormat::VP8 | CodecFormat::VP9 => Recorder {
-                inner: Box::new(WebmRecorder::new(ctx.clone())?),
-                ctx,
-            },
+        let inner: Box<dyn RecorderApi> = match ctx.format {
+            CodecFormat::VP8 | CodecFormat::VP9 | CodecFormat::AV1 => {
+                Box::new(WebmRecorder::new(ctx.clone())?)
+            }
             #[cfg(feature = "hwcodec")]
-            _ => Recorder {
-                inner: Box::new(HwRecorder::new(ctx.clone())?),
-                ctx,
-            },
+            _ => Box::new(HwRecorder::new(ctx.clone())?),
             #[cfg(not(feature = "hwcodec"))]
             _ => bail!("unsupported codec type"),
         };
+        let recorder = Recorder {
+            inner,
+            events: EventLog::new(&ctx.filename),
+            ctx,
+            audio_format: None,
+            event_text: Config::get_option("allow-record-event-text") == "Y",
+            clock: None,
+            last_pts: None,
+            pts_offset: 0,
+        };
         recorder.send_state(RecordState::NewFile(recorder.ctx.filename.clone()));
         Ok(recorder)
     }
@@ -125,25 +164,156 @@ impl Recorder {
     fn change(&mut self, mut ctx: RecorderContext) -> ResultType<()> {
         ctx.set_filename()?;
         self.inner = match ctx.format {
-            CodecFormat::VP8 | CodecFormat::VP9 => Box::new(WebmRecorder::new(ctx.clone())?),
+            CodecFormat::VP8 | CodecFormat::VP9 | CodecFormat::AV1 => {
+                Box::new(WebmRecorder::new(ctx.clone())?)
+            }
             #[cfg(feature = "hwcodec")]
             _ => Box::new(HwRecorder::new(ctx.clone())?),
             #[cfg(not(feature = "hwcodec"))]
             _ => bail!("unsupported codec type"),
         };
+        if let Some(format) = &self.audio_format {
+            self.inner.set_audio_format(format);
+        }
+        self.events = EventLog::new(&ctx.filename);
+        self.clock = None;
+        self.last_pts = None;
+        self.pts_offset = 0;
         self.ctx = ctx;
         self.send_state(RecordState::NewFile(self.ctx.filename.clone()));
         Ok(())
     }
 
+    /// Records a message from the side being recorded: video, audio and session events.
     pub fn write_message(&mut self, msg: &Message) {
-        if let Some(message::Union::VideoFrame(vf)) = &msg.union {
-            if let Some(frame) = &vf.union {
-                self.write_frame(frame).ok();
+        self.write_message_from(msg, "host");
+    }
+
+    /// Records a message from the viewing side, only session events are kept.
+    pub fn write_viewer_message(&mut self, msg: &Message) {
+        self.write_message_from(msg, "viewer");
+    }
+
+    fn write_message_from(&mut self, msg: &Message, from: &str) {
+        let host = from == "host";
+        match &msg.union {
+            Some(message::Union::VideoFrame(vf)) if host => {
+                if let Some(frame) = &vf.union {
+                    self.write_frame(frame).ok();
+                }
+            }
+            Some(message::Union::AudioFrame(af)) if host => {
+                if let Some(pts) = self.pts() {
+                    self.inner.write_audio(&af.data, pts);
+                }
+            }
+            Some(message::Union::Misc(misc)) => match &misc.union {
+                Some(misc::Union::AudioFormat(f)) if host => {
+                    if !self.inner.set_audio_format(f) {
+                        log::info!("audio format arrived too late for {}", self.ctx.filename);
+                    }
+                    self.audio_format = Some(f.clone());
+                }
+                Some(misc::Union::ChatMessage(c)) => {
+                    let detail = self.text_detail(c.text.as_bytes());
+                    self.write_event(from, "chat", detail);
+                }
+                _ => {}
+            },
+            Some(message::Union::Clipboard(cb)) => {
+                let content = if cb.compress {
+                    decompress(&cb.content)
+                } else {
+                    cb.content.to_vec()
+                };
+                let detail = self.text_detail(&content);
+                self.write_event(from, "clipboard", detail);
+            }
+            // one event per format of a copy
+            Some(message::Union::ClipboardChunk(c)) if c.offset == 0 => {
+                self.write_event(
+                    from,
+                    "clipboard",
+                    json!({ "mime_type": c.mime_type, "size": c.total_size }),
+                );
             }
+            Some(message::Union::FileAction(fa)) => {
+                let detail = match &fa.union {
+                    Some(file_action::Union::Send(s)) => {
+                        json!({ "action": "send", "id": s.id, "path": s.path })
+                    }
+                    Some(file_action::Union::Receive(r)) => json!({
+                        "action": "receive",
+                        "id": r.id,
+                        "path": r.path,
+                        "files": r.files.iter().map(|f| f.name.clone()).collect::<Vec<_>>(),
+                    }),
+                    Some(file_action::Union::Create(c)) => {
+                        json!({ "action": "create_dir", "id": c.id, "path": c.path })
+                    }
+                    Some(file_action::Union::RemoveDir(d)) => json!({
+                        "action": "remove_dir",
+                        "id": d.id,
+                        "path": d.path,
+                        "recursive": d.recursive,
+                    }),
+                    Some(file_action::Union::RemoveFile(f)) => {
+                        json!({ "action": "remove_file", "id": f.id, "path": f.path })
+                    }
+                    Some(file_action::Union::Cancel(c)) => {
+                        json!({ "action": "cancel", "id": c.id })
+                    }
+                    _ => return,
+                };
+                self.write_event(from, "file", detail);
+            }
+            Some(message::Union::FileResponse(fr)) => {
+                let detail = match &fr.union {
+                    Some(file_response::Union::Done(d)) => {
+                        json!({ "result": "done", "id": d.id, "file_num": d.file_num })
+                    }
+                    Some(file_response::Union::Error(e)) => json!({
+                        "result": "error",
+                        "id": e.id,
+                        "file_num": e.file_num,
+                        "error": e.error,
+                    }),
+                    _ => return,
+                };
+                self.write_event(from, "file", detail);
+            }
+            _ => {}
         }
     }
 
+    fn text_detail(&self, content: &[u8]) -> Value {
+        let mut detail = json!({ "size": content.len() });
+        if self.event_text {
+            if let Ok(text) = std::str::from_utf8(content) {
+                detail["text"] = json!(text.chars().take(MAX_EVENT_TEXT).collect::<String>());
+            }
+        }
+        detail
+    }
+
+    /// Appends an entry to the `.events.jsonl` sidecar of the current file.
+    pub fn write_event(&mut self, from: &str, kind: &str, detail: Value) {
+        let pts = self.pts().unwrap_or(0);
+        self.events.write(json!({
+            "pts": pts,
+            "time": chrono::Local::now().to_rfc3339(),
+            "from": from,
+            "type": kind,
+            "detail": detail,
+        }));
+    }
+
+    // Current position on the video timeline in milliseconds.
+    fn pts(&self) -> Option<i64> {
+        self.clock
+            .map(|(pts, start)| pts + start.elapsed().as_millis() as i64)
+    }
+
     pub fn write_frame(&mut self, frame: &video_frame::Union) -> ResultType<()> {
         match frame {
             video_frame::Union::Vp8s(vp8s) => {
@@ -164,6 +334,15 @@ impl Recorder {
                 }
                 vp9s.frames.iter().map(|f| self.write_video(f)).count();
             }
+            video_frame::Union::Av1s(av1s) => {
+                if self.ctx.format != CodecFormat::AV1 {
+                    self.change(RecorderContext {
+                        format: CodecFormat::AV1,
+                        ..self.ctx.clone()
+                    })?;
+                }
+                av1s.frames.iter().map(|f| self.write_video(f)).count();
+            }
             #[cfg(feature = "hwcodec")]
             video_frame::Union::H264s(h264s) => {
                 if self.ctx.format != CodecFormat::H264 {
@@ -194,13 +373,83 @@ impl Recorder {
         Ok(())
     }
 
+    fn write_video(&mut self, frame: &EncodedVideoFrame) -> bool {
+        let mut pts = frame.pts + self.pts_offset;
+        if let Some(last) = self.last_pts {
+            if pts < last {
+                log::debug!("video pts went back from {} to {}", last, pts);
+                self.pts_offset += last - pts;
+                pts = last;
+            }
+        }
+        let frame = EncodedVideoFrame {
+            pts,
+            ..frame.clone()
+        };
+        let ok = self.inner.write_video(&frame);
+        if ok {
+            self.last_pts = Some(pts);
+            if self.clock.is_none() {
+                self.clock = Some((pts, Instant::now()));
+            }
+        }
+        ok
+    }
+
     fn send_state(&self, state: RecordState) {
         self.ctx.tx.as_ref().map(|tx| tx.send(state));
     }
 }
 
+// JSON lines next to the video file, created on the first event and removed
+// together with a video file that was too short to keep.
+struct EventLog {
+    path: PathBuf,
+    video: PathBuf,
+    file: Option<File>,
+}
+
+impl EventLog {
+    fn new(video: &str) -> Self {
+        let video = PathBuf::from(video);
+        EventLog {
+            path: video.with_extension("events.jsonl"),
+            video,
+            file: None,
+        }
+    }
+
+    fn write(&mut self, event: Value) {
+        if self.file.is_none() {
+            match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+            {
+                Ok(file) => self.file = Some(file),
+                Err(e) => {
+                    log::error!("Failed to open {:?}: {}", self.path, e);
+                    return;
+                }
+            }
+        }
+        if let Some(file) = self.file.as_mut() {
+            writeln!(file, "{}", event).ok();
+        }
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        if self.file.take().is_some() && !self.video.exists() {
+            std::fs::remove_file(&self.path).ok();
+        }
+    }
+}
+
 struct WebmRecorder {
     vt: VideoTrack,
+    at: Option<AudioTrack>,
     webm: Option<Segment<Writer<File>>>,
     ctx: RecorderContext,
     key: bool,
@@ -210,32 +459,25 @@ struct WebmRecorder {
 
 impl RecorderApi for WebmRecorder {
     fn new(ctx: RecorderContext) -> ResultType<Self> {
-        let out = match {
-            OpenOptions::new()
-                .write(true)
-                .create_new(true)
-                .open(&ctx.filename)
-        } {
-            Ok(file) => file,
-            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => File::create(&ctx.filename)?,
-            Err(e) => return Err(e.into()),
-        };
-        let mut webm = match mux::Segment::new(mux::Writer::new(out)) {
-            Some(v) => v,
-            None => bail!("Failed to create webm mux"),
+        let mut webm = new_segment(&ctx.filename)?;
+        let codec = match ctx.format {
+            CodecFormat::VP8 => mux::VideoCodecId::VP8,
+            CodecFormat::VP9 => mux::VideoCodecId::VP9,
+            CodecFormat::AV1 => mux::VideoCodecId::AV1,
+            _ => bail!("unsupported codec type"),
         };
-        let vt = webm.add_video_track(
+        let vt = match webm.add_video_track_opt(
             ctx.width as _,
             ctx.height as _,
-            None,
-            if ctx.format == CodecFormat::VP9 {
-                mux::VideoCodecId::VP9
-            } else {
-                mux::VideoCodecId::VP8
-            },
-        );
+            Some(VIDEO_TRACK),
+            codec,
+        ) {
+            Some(vt) => vt,
+            None => bail!("Failed to add webm video track"),
+        };
         Ok(WebmRecorder {
             vt,
+            at: None,
             webm: Some(webm),
             ctx,
             key: false,
@@ -245,26 +487,68 @@ impl RecorderApi for WebmRecorder {
     }
 
     fn write_video(&mut self, frame: &EncodedVideoFrame) -> bool {
-        if frame.key {
+        if frame.key && !self.key {
+            // the av1C box has to be in place before libwebm writes the header
+            // with the first frame
+            if self.ctx.format == CodecFormat::AV1 {
+                match (av1_codec_private(&frame.data), self.webm.as_mut()) {
+                    (Ok(av1c), Some(webm)) => {
+                        webm.set_codec_private(self.vt.track_number(), &av1c);
+                    }
+                    (Err(e), _) => log::warn!("av1 recording without codec private: {}", e),
+                    _ => {}
+                }
+            }
             self.key = true;
         }
-        if self.key {
-            let ok = self
-                .vt
-                .add_frame(&frame.data, frame.pts as u64 * 1_000_000, frame.key);
-            if ok {
-                self.written = true;
+        if !self.key {
+            return false;
+        }
+        let ts = frame.pts as u64 * 1_000_000;
+        let ok = if self.ctx.format == CodecFormat::AV1 {
+            match av1_strip_temporal_delimiters(&frame.data) {
+                Ok(data) => self.vt.add_frame(&data, ts, frame.key),
+                Err(e) => {
+                    log::error!("Invalid av1 frame at {}: {}", frame.pts, e);
+                    false
+                }
             }
-            ok
         } else {
-            false
+            self.vt.add_frame(&frame.data, ts, frame.key)
+        };
+        if ok {
+            self.written = true;
+        } else {
+            log::error!("Failed to write video frame at {}", frame.pts);
+        }
+        ok
+    }
+
+    fn set_audio_format(&mut self, format: &AudioFormat) -> bool {
+        if self.at.is_none() && !self.written {
+            self.at = self
+                .webm
+                .as_mut()
+                .and_then(|webm| add_opus_track(webm, format));
+        }
+        self.at.is_some()
+    }
+
+    fn write_audio(&mut self, data: &[u8], pts: i64) -> bool {
+        match self.at.as_mut() {
+            Some(at) if self.written => at.add_frame(data, pts as u64 * 1_000_000, true),
+            _ => false,
         }
     }
 }
 
 impl Drop for WebmRecorder {
     fn drop(&mut self) {
-        std::mem::replace(&mut self.webm, None).map_or(false, |webm| webm.finalize(None));
+        if let Some(webm) = self.webm.take() {
+            if !webm.finalize(None) {
+                log::error!("Failed to finalize webm");
+            }
+        }
         let mut state = RecordState::WriteTail;
         if !self.written || self.start.elapsed().as_secs() < MIN_SECS {
             std::fs::remove_file(&self.ctx.filename).ok();
@@ -274,12 +558,98 @@ impl Drop for WebmRecorder {
     }
 }
 
+fn new_segment(filename: &str) -> ResultType<Segment<Writer<File>>> {
+    let out = match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(filename)
+    {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => File::create(filename)?,
+        Err(e) => return Err(e.into()),
+    };
+    match mux::Segment::new(mux::Writer::new(out)) {
+        Some(v) => Ok(v),
+        None => bail!("Failed to create webm mux"),
+    }
+}
+
+// The OpusHead codec private carries the pre-skip players need to drop the
+// encoder delay.
+fn add_opus_track(webm: &mut Segment<Writer<File>>, format: &AudioFormat) -> Option<AudioTrack> {
+    let at = webm.add_audio_track_opt(
+        format.sample_rate as _,
+        format.channels as _,
+        Some(AUDIO_TRACK),
+        mux::AudioCodecId::Opus,
+    )?;
+    if !webm.set_codec_private(
+        AUDIO_TRACK as _,
+        &opus_head(format.sample_rate, format.channels),
+    ) {
+        log::warn!("opus track without codec private");
+    }
+    Some(at)
+}
+
+// The hardware muxer only takes video, so its recordings are two files: the
+// mp4 with the video and `<name>.audio.webm` next to it with the Opus audio.
+#[cfg(feature = "hwcodec")]
+struct AudioSidecar {
+    at: AudioTrack,
+    webm: Option<Segment<Writer<File>>>,
+    path: String,
+    written: bool,
+}
+
+#[cfg(feature = "hwcodec")]
+impl AudioSidecar {
+    fn new(video: &str, format: &AudioFormat) -> ResultType<Self> {
+        let path = PathBuf::from(video)
+            .with_extension("audio.webm")
+            .to_string_lossy()
+            .to_string();
+        let mut webm = new_segment(&path)?;
+        let at = match add_opus_track(&mut webm, format) {
+            Some(at) => at,
+            None => bail!("Failed to add webm audio track"),
+        };
+        Ok(AudioSidecar {
+            at,
+            webm: Some(webm),
+            path,
+            written: false,
+        })
+    }
+
+    fn write(&mut self, data: &[u8], pts: i64) -> bool {
+        let ok = self.at.add_frame(data, pts as u64 * 1_000_000, true);
+        self.written |= ok;
+        ok
+    }
+
+    // Finalizes the file, or removes it together with a video file that was not kept.
+    fn finish(&mut self, keep: bool) {
+        if let Some(webm) = self.webm.take() {
+            if !webm.finalize(None) {
+                log::error!("Failed to finalize webm");
+            }
+        }
+        if !keep || !self.written {
+            std::fs::remove_file(&self.path).ok();
+        }
+    }
+}
+
 #[cfg(feature = "hwcodec")]
 struct HwRecorder {
     muxer: Muxer,
+    audio: Option<AudioSidecar>,
     ctx: RecorderContext,
     written: bool,
     key: bool,
+    // pts of the first frame, the muxer starts its timeline there
+    first_pts: Option<i64>,
     start: Instant,
 }
 
@@ -296,9 +666,11 @@ impl RecorderApi for HwRecorder {
         .map_err(|_| anyhow!("Failed to create hardware muxer"))?;
         Ok(HwRecorder {
             muxer,
+            audio: None,
             ctx,
             written: false,
             key: false,
+            first_pts: None,
             start: Instant::now(),
         })
     }
@@ -311,12 +683,30 @@ impl RecorderApi for HwRecorder {
             let ok = self.muxer.write_video(&frame.data, frame.key).is_ok();
             if ok {
                 self.written = true;
+                self.first_pts.get_or_insert(frame.pts);
             }
             ok
         } else {
             false
         }
     }
+
+    fn set_audio_format(&mut self, format: &AudioFormat) -> bool {
+        if self.audio.is_none() {
+            match AudioSidecar::new(&self.ctx.filename, format) {
+                Ok(audio) => self.audio = Some(audio),
+                Err(e) => log::error!("Failed to create audio file: {}", e),
+            }
+        }
+        self.audio.is_some()
+    }
+
+    fn write_audio(&mut self, data: &[u8], pts: i64) -> bool {
+        match (self.audio.as_mut(), self.first_pts) {
+            (Some(audio), Some(first)) if pts >= first => audio.write(data, pts - first),
+            _ => false,
+        }
+    }
 }
 
 #[cfg(feature = "hwcodec")]
@@ -328,6 +718,9 @@ impl Drop for HwRecorder {
             std::fs::remove_file(&self.ctx.filename).ok();
             state = RecordState::RemoveFile;
         }
+        if let Some(audio) = self.audio.as_mut() {
+            audio.finish(matches!(state, RecordState::WriteTail));
+        }
         self.ctx.tx.as_ref().map(|tx| tx.send(state));
     }
 }
@@ -2,15 +2,32 @@ use crate::client::*;
 use hbb_common::{
     config::PeerConfig,
     config::READ_TIMEOUT,
+    dir_sync,
+    fs::{self, can_enable_overwrite_detection, get_string, DigestCheckResult},
     futures::{SinkExt, StreamExt},
-    log,
+    get_version_number, log,
     message_proto::*,
     protobuf::Message as _,
     rendezvous_proto::ConnType,
-    tokio::{self, sync::mpsc},
-    Stream,
+    timeout,
+    tokio::{self, sync::mpsc, time},
+    ResultType, Stream,
 };
-use std::sync::{Arc, RwLock};
+use scrap::{codec::Decoder, CodecFormat, ImageFormat, ImageRgb};
+use serde_json::{json, Value};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, RwLock,
+};
+
+// Exit codes of the headless commands, scripts rely on them so never renumber.
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_CONNECT: i32 = 3;
+pub const EXIT_AUTH: i32 = 4;
+pub const EXIT_TIMEOUT: i32 = 5;
+pub const EXIT_REMOTE: i32 = 6;
 
 #[derive(Clone)]
 pub struct Session {
@@ -18,6 +35,7 @@ pub struct Session {
     lc: Arc<RwLock<LoginConfigHandler>>,
     sender: mpsc::UnboundedSender<Data>,
     password: String,
+    interactive: bool,
 }
 
 impl Session {
@@ -26,17 +44,38 @@ impl Session {
         if PeerConfig::load(id).password.is_empty() {
             password = rpassword::prompt_password("Enter password: ").unwrap();
         }
+        Self::with_conn_type(id, password, true, ConnType::PORT_FORWARD, sender)
+    }
+
+    /// A session which never prompts, login failures are reported to the caller instead.
+    pub fn new_headless(
+        id: &str,
+        password: Option<String>,
+        conn_type: ConnType,
+        sender: mpsc::UnboundedSender<Data>,
+    ) -> Self {
+        Self::with_conn_type(id, password.unwrap_or_default(), false, conn_type, sender)
+    }
+
+    fn with_conn_type(
+        id: &str,
+        password: String,
+        interactive: bool,
+        conn_type: ConnType,
+        sender: mpsc::UnboundedSender<Data>,
+    ) -> Self {
         let session = Self {
             id: id.to_owned(),
             sender,
             password,
+            interactive,
             lc: Default::default(),
         };
         session
             .lc
             .write()
             .unwrap()
-            .initialize(id.to_owned(), ConnType::PORT_FORWARD, None);
+            .initialize(id.to_owned(), conn_type, None);
         session
     }
 }
@@ -49,16 +88,22 @@ impl Interface for Session {
 
     fn msgbox(&self, msgtype: &str, title: &str, text: &str, link: &str) {
         match msgtype {
-            "input-password" => {
+            "input-password" if self.interactive => {
                 self.sender
-                    .send(Data::Login((self.password.clone(), true)))
+                    .send(Data::Login((
+                        "".to_owned(),
+                        "".to_owned(),
+                        self.password.clone(),
+                        true,
+                    )))
                     .ok();
             }
-            "re-input-password" => {
+            "re-input-password" if self.interactive => {
                 log::error!("{}: {}", title, text);
                 match rpassword::prompt_password("Enter password: ") {
                     Ok(password) => {
-                        let login_data = Data::Login((password, true));
+                        let login_data =
+                            Data::Login(("".to_owned(), "".to_owned(), password, true));
                         self.sender.send(login_data).ok();
                     }
                     Err(e) => {
@@ -84,10 +129,6 @@ impl Interface for Session {
     }
 
     async fn handle_hash(&mut self, pass: &str, hash: Hash, peer: &mut Stream) {
-        log::info!(
-            "password={}",
-            hbb_common::password_security::temporary_password()
-        );
         handle_hash(self.lc.clone(), &pass, hash, self, peer).await;
     }
 
@@ -119,41 +160,998 @@ impl Interface for Session {
     }
 }
 
-#[tokio::main(flavor = "current_thread")]
-pub async fn connect_test(id: &str, key: String, token: String) {
-    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
-    let handler = Session::new(&id, sender);
-    match crate::client::Client::start(id, &key, &token, ConnType::PORT_FORWARD, handler).await {
+/// The peer to drive and the credentials to log in with.
+pub struct Target {
+    pub id: String,
+    pub password: Option<String>,
+    /// Log in to this account of the peer, `password` is then its password.
+    pub account: Option<String>,
+    pub key: String,
+    pub token: String,
+}
+
+/// Why a headless command failed, `code` becomes the exit code.
+#[derive(Debug)]
+pub struct Failure {
+    pub code: i32,
+    pub text: String,
+}
+
+impl Failure {
+    fn new(code: i32, text: impl ToString) -> Self {
+        Self {
+            code,
+            text: text.to_string(),
+        }
+    }
+}
+
+impl From<hbb_common::anyhow::Error> for Failure {
+    fn from(err: hbb_common::anyhow::Error) -> Self {
+        Self::new(EXIT_FAILURE, err)
+    }
+}
+
+impl From<std::io::Error> for Failure {
+    fn from(err: std::io::Error) -> Self {
+        Self::new(EXIT_FAILURE, err)
+    }
+}
+
+fn exit_code(res: Result<Value, Failure>) -> i32 {
+    match res {
+        Ok(out) => {
+            println!("{}", out);
+            EXIT_OK
+        }
         Err(err) => {
-            log::error!("Failed to connect {}: {}", &id, err);
-        }
-        Ok((mut stream, direct)) => {
-            log::info!("direct: {}", direct);
-            // rpassword::prompt_password("Input anything to exit").ok();
-            loop {
-                tokio::select! {
-                    res = hbb_common::timeout(READ_TIMEOUT, stream.next()) => match res {
-                        Err(_) => {
-                            log::error!("Timeout");
-                            break;
+            log::error!("{}", err.text);
+            eprintln!("{}", json!({ "error": err.text, "code": err.code }));
+            err.code
+        }
+    }
+}
+
+// A logged in connection used by the headless commands.
+struct Connection {
+    stream: Stream,
+    session: Session,
+    direct: bool,
+    pi: PeerInfo,
+}
+
+impl Connection {
+    async fn open(target: &Target, conn_type: ConnType) -> Result<Self, Failure> {
+        let (sender, _) = mpsc::unbounded_channel::<Data>();
+        let session = Session::new_headless(&target.id, target.password.clone(), conn_type, sender);
+        if let Some(account) = target.account.as_ref() {
+            let password = match target.password.as_ref() {
+                Some(password) => password.clone(),
+                None => return Err(Failure::new(EXIT_USAGE, "--account needs --password")),
+            };
+            session.lc.write().unwrap().account = Some((account.clone(), password));
+        }
+        let (stream, direct, _) = Client::start(
+            &target.id,
+            &target.key,
+            &target.token,
+            conn_type,
+            session.clone(),
+        )
+        .await
+        .map_err(|err| Failure::new(EXIT_CONNECT, err))?;
+        session.update_direct(Some(direct));
+        let mut conn = Self {
+            stream,
+            session,
+            direct,
+            pi: Default::default(),
+        };
+        conn.login().await?;
+        Ok(conn)
+    }
+
+    async fn login(&mut self) -> Result<(), Failure> {
+        let password = self.session.password.clone();
+        loop {
+            let msg_in = self.next().await?;
+            self.session.update_received(true);
+            match msg_in.union {
+                Some(message::Union::Hash(hash)) => {
+                    self.session
+                        .handle_hash(&password, hash, &mut self.stream)
+                        .await;
+                }
+                Some(message::Union::LoginResponse(lr)) => match lr.union {
+                    Some(login_response::Union::Error(err)) => {
+                        self.session.handle_login_error(&err);
+                        return Err(Failure::new(EXIT_AUTH, err));
+                    }
+                    Some(login_response::Union::PeerInfo(pi)) => {
+                        self.session.handle_peer_info(pi.clone());
+                        self.pi = pi;
+                        return Ok(());
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+    }
+
+    // Next message from the peer, test delays are answered on the way.
+    async fn next(&mut self) -> Result<Message, Failure> {
+        loop {
+            match timeout(READ_TIMEOUT, self.stream.next()).await {
+                Err(_) => return Err(Failure::new(EXIT_TIMEOUT, "Timeout")),
+                Ok(Some(Ok(bytes))) => {
+                    let msg_in = match Message::parse_from_bytes(&bytes) {
+                        Ok(msg_in) => msg_in,
+                        Err(_) => continue,
+                    };
+                    match msg_in.union {
+                        Some(message::Union::TestDelay(t)) => {
+                            self.session.handle_test_delay(t, &mut self.stream).await;
                         }
-                        Ok(Some(Ok(bytes))) => {
-                            if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
-                                match msg_in.union {
-                                    Some(message::Union::Hash(hash)) => {
-                                        log::info!("Got hash");
-                                        break;
-                                    }
-                                    _ => {}
-                                }
+                        _ => return Ok(msg_in),
+                    }
+                }
+                Ok(Some(Err(err))) => {
+                    return Err(Failure::new(
+                        EXIT_CONNECT,
+                        format!("Connection closed: {}", err),
+                    ))
+                }
+                _ => return Err(Failure::new(EXIT_CONNECT, "Reset by the peer")),
+            }
+        }
+    }
+
+    async fn send(&mut self, msg: &Message) -> Result<(), Failure> {
+        self.stream
+            .send(msg)
+            .await
+            .map_err(|err| Failure::new(EXIT_CONNECT, err))
+    }
+
+    fn peer_is_windows(&self) -> bool {
+        self.pi.platform == "Windows"
+    }
+
+    fn overwrite_detection(&self) -> bool {
+        can_enable_overwrite_detection(get_version_number(&self.pi.version))
+    }
+
+    fn can_send_links(&self) -> bool {
+        fs::can_send_links(get_version_number(&self.pi.version))
+    }
+
+    // `name` relative to the remote directory `dir`, with the peer's separators.
+    fn remote_join(&self, dir: &str, name: &str) -> String {
+        let (sep, other) = if self.peer_is_windows() {
+            ('\\', '/')
+        } else {
+            ('/', '\\')
+        };
+        if name.is_empty() {
+            return dir.to_owned();
+        }
+        let name = name.replace(other, &sep.to_string());
+        format!("{}{}{}", dir.trim_end_matches(sep), sep, name)
+    }
+}
+
+fn peer_info_json(id: &str, direct: bool, pi: &PeerInfo) -> Value {
+    json!({
+        "id": id,
+        "direct": direct,
+        "username": pi.username,
+        "hostname": pi.hostname,
+        "platform": pi.platform,
+        "version": pi.version,
+        "sas_enabled": pi.sas_enabled,
+        "privacy_mode": pi.features.privacy_mode,
+        "encoding": {
+            "vp8": pi.encoding.vp8,
+            "h264": pi.encoding.h264,
+            "h265": pi.encoding.h265,
+            "av1": pi.encoding.av1,
+        },
+        "current_display": pi.current_display,
+        "displays": pi.displays.iter().enumerate().map(|(i, d)| json!({
+            "index": i,
+            "name": d.name,
+            "x": d.x,
+            "y": d.y,
+            "width": d.width,
+            "height": d.height,
+            "online": d.online,
+            "cursor_embedded": d.cursor_embedded,
+            "original_resolution": {
+                "width": d.original_resolution.width,
+                "height": d.original_resolution.height,
+            },
+        })).collect::<Vec<_>>(),
+        "resolutions": pi.resolutions.resolutions.iter().map(|r| json!({
+            "width": r.width,
+            "height": r.height,
+        })).collect::<Vec<_>>(),
+        "platform_additions": serde_json::from_str::<Value>(&pi.platform_additions)
+            .unwrap_or(Value::Null),
+    })
+}
+
+/// Connect, log in and print a short summary of the peer.
+#[tokio::main(flavor = "current_thread")]
+pub async fn connect(target: Target) -> i32 {
+    exit_code(connect_(&target).await)
+}
+
+async fn connect_(target: &Target) -> Result<Value, Failure> {
+    let conn = Connection::open(target, ConnType::DEFAULT_CONN).await?;
+    Ok(json!({
+        "id": target.id,
+        "direct": conn.direct,
+        "hostname": conn.pi.hostname,
+        "platform": conn.pi.platform,
+        "version": conn.pi.version,
+    }))
+}
+
+/// Print the displays and the rest of `PeerInfo` as JSON.
+#[tokio::main(flavor = "current_thread")]
+pub async fn info(target: Target) -> i32 {
+    exit_code(info_(&target).await)
+}
+
+async fn info_(target: &Target) -> Result<Value, Failure> {
+    let conn = Connection::open(target, ConnType::DEFAULT_CONN).await?;
+    Ok(peer_info_json(&target.id, conn.direct, &conn.pi))
+}
+
+/// Save the first decodable frame of `display` (the current one if `None`) as PNG.
+#[tokio::main(flavor = "current_thread")]
+pub async fn screenshot(target: Target, display: Option<i32>, output: String) -> i32 {
+    exit_code(screenshot_(&target, display, &output).await)
+}
+
+async fn screenshot_(
+    target: &Target,
+    display: Option<i32>,
+    output: &str,
+) -> Result<Value, Failure> {
+    let mut conn = Connection::open(target, ConnType::DEFAULT_CONN).await?;
+    let display = display.unwrap_or(conn.pi.current_display);
+    if display < 0 || display as usize >= conn.pi.displays.len() {
+        return Err(Failure::new(
+            EXIT_USAGE,
+            format!(
+                "No display {}, the peer has {}",
+                display,
+                conn.pi.displays.len()
+            ),
+        ));
+    }
+    let switching = display != conn.pi.current_display;
+    if switching {
+        let mut misc = Misc::new();
+        misc.set_switch_display(SwitchDisplay {
+            display,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        conn.send(&msg_out).await?;
+    }
+    let mut capture = Capture::new(display, switching);
+    // one deadline for the whole wait, the peer may keep sending frames which
+    // never decode
+    let deadline = time::Instant::now() + SEC30;
+    loop {
+        let msg_in = match time::timeout_at(deadline, conn.next()).await {
+            Ok(msg_in) => msg_in?,
+            Err(_) => {
+                return Err(match capture.failed {
+                    Some(codec) => {
+                        Failure::new(EXIT_FAILURE, format!("cannot decode {}", codec.to_string()))
+                    }
+                    None => Failure::new(EXIT_TIMEOUT, "Timeout"),
+                })
+            }
+        };
+        if capture.handle(msg_in) {
+            break;
+        }
+    }
+    let rgb = capture.rgb;
+    repng::encode(
+        std::fs::File::create(output)?,
+        rgb.w as _,
+        rgb.h as _,
+        &rgb.raw,
+    )?;
+    Ok(json!({
+        "file": output,
+        "display": display,
+        "width": rgb.w,
+        "height": rgb.h,
+    }))
+}
+
+// Waits for the first frame of `display` which decodes, frames sent before the
+// peer confirmed the switch to it are skipped.
+struct Capture {
+    display: i32,
+    switching: bool,
+    decoder: Decoder,
+    rgb: ImageRgb,
+    // codec of the last frame which did not decode
+    failed: Option<CodecFormat>,
+}
+
+impl Capture {
+    fn new(display: i32, switching: bool) -> Self {
+        Self {
+            display,
+            switching,
+            decoder: Decoder::new(),
+            rgb: ImageRgb::new(ImageFormat::ABGR, 1),
+            failed: None,
+        }
+    }
+
+    // `true` once `rgb` holds the frame.
+    fn handle(&mut self, msg_in: Message) -> bool {
+        match msg_in.union {
+            Some(message::Union::VideoFrame(vf)) if !self.switching => {
+                if let Some(frame) = &vf.union {
+                    match self.decoder.handle_video_frame(frame, &mut self.rgb) {
+                        Ok(true) => return true,
+                        Ok(false) => {}
+                        Err(err) => {
+                            log::debug!("Failed to decode: {}", err);
+                            self.failed = Some(CodecFormat::from(&vf));
+                        }
+                    }
+                }
+            }
+            Some(message::Union::Misc(misc)) => match misc.union {
+                Some(misc::Union::SwitchDisplay(s)) if s.display == self.display => {
+                    self.switching = false;
+                    self.decoder = Decoder::new();
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        false
+    }
+}
+
+/// Send key chords such as `ctrl+alt+VK_DELETE` one after another, then type `text` if any.
+#[tokio::main(flavor = "current_thread")]
+pub async fn send_keys(target: Target, chords: Vec<String>, text: Option<String>) -> i32 {
+    exit_code(send_keys_(&target, &chords, text).await)
+}
+
+async fn send_keys_(
+    target: &Target,
+    chords: &[String],
+    text: Option<String>,
+) -> Result<Value, Failure> {
+    let mut events = Vec::new();
+    for chord in chords {
+        events.push(parse_key_chord(chord).map_err(|err| Failure::new(EXIT_USAGE, err))?);
+    }
+    if let Some(text) = text {
+        let mut key_event = KeyEvent::new();
+        key_event.set_seq(text);
+        events.push(key_event);
+    }
+    if events.is_empty() {
+        return Err(Failure::new(EXIT_USAGE, "Nothing to send"));
+    }
+    let mut conn = Connection::open(target, ConnType::DEFAULT_CONN).await?;
+    for evt in events.iter() {
+        let mut msg_out = Message::new();
+        msg_out.set_key_event(evt.clone());
+        conn.send(&msg_out).await?;
+    }
+    Ok(json!({ "sent": events.len() }))
+}
+
+// `ctrl+shift+VK_ESCAPE`, `alt+f4` or `a`: modifiers first, the key last.
+fn parse_key_chord(chord: &str) -> ResultType<KeyEvent> {
+    let (mut alt, mut ctrl, mut shift, mut command) = (false, false, false, false);
+    let parts: Vec<&str> = chord.split('+').filter(|p| !p.is_empty()).collect();
+    let (key, modifiers) = match parts.split_last() {
+        Some(v) => v,
+        None => hbb_common::bail!("empty key chord"),
+    };
+    for m in modifiers {
+        match m.to_uppercase().trim_start_matches("VK_") {
+            "ALT" | "MENU" => alt = true,
+            "CTRL" | "CONTROL" => ctrl = true,
+            "SHIFT" => shift = true,
+            "META" | "CMD" | "COMMAND" | "WIN" | "SUPER" => command = true,
+            _ => hbb_common::bail!("unknown modifier {} in {}", m, chord),
+        }
+    }
+    let mut key_event = KeyEvent::new();
+    if key.chars().count() == 1 {
+        key_event.set_chr(key.chars().next().unwrap_or_default() as _);
+    } else {
+        let upper = key.to_uppercase();
+        let name = match upper.as_str() {
+            "CTRL" => "VK_CONTROL".to_owned(),
+            "ALT" => "VK_MENU".to_owned(),
+            "ESC" => "VK_ESCAPE".to_owned(),
+            "DEL" => "VK_DELETE".to_owned(),
+            name if name.starts_with("VK_") => name.to_owned(),
+            name => format!("VK_{}", name),
+        };
+        match KEY_MAP.get(name.as_str()) {
+            Some(Key::ControlKey(k)) => key_event.set_control_key(k.clone()),
+            Some(Key::Chr(chr)) | Some(Key::_Raw(chr)) => key_event.set_chr(*chr),
+            None => hbb_common::bail!("unknown key {} in {}", key, chord),
+        }
+    }
+    key_event.press = true;
+    crate::keyboard::client::legacy_modifiers(&mut key_event, alt, ctrl, shift, command);
+    Ok(key_event)
+}
+
+/// Replay the macro in `file`, or the saved one of that name, at `speed`.
+#[tokio::main(flavor = "current_thread")]
+pub async fn play_macro(target: Target, file: String, vars: Vec<String>, speed: f64) -> i32 {
+    exit_code(play_macro_(&target, &file, &vars, speed).await)
+}
+
+async fn play_macro_(
+    target: &Target,
+    file: &str,
+    vars: &[String],
+    speed: f64,
+) -> Result<Value, Failure> {
+    let text = match std::fs::read_to_string(file) {
+        Ok(text) => text,
+        Err(_) => macros::load(file).map_err(|err| Failure::new(EXIT_USAGE, err))?,
+    };
+    let mut values = std::collections::HashMap::new();
+    for var in vars {
+        match var.split_once('=') {
+            Some((name, value)) => values.insert(name.trim().to_owned(), value.to_owned()),
+            None => {
+                return Err(Failure::new(
+                    EXIT_USAGE,
+                    format!("{} is not NAME=VALUE", var),
+                ))
+            }
+        };
+    }
+    let steps = macros::parse(&text, &values).map_err(|err| Failure::new(EXIT_USAGE, err))?;
+    let mut conn = Connection::open(target, ConnType::DEFAULT_CONN).await?;
+    // the steps are timed on a blocking thread and sent from here
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let stop = Arc::new(AtomicBool::new(false));
+    let player = {
+        let stop = stop.clone();
+        tokio::task::spawn_blocking(move || {
+            macros::play(&steps, speed, &stop, |msg| {
+                tx.send(msg).ok();
+            })
+        })
+    };
+    let mut sent = 0;
+    while let Some(msg) = rx.recv().await {
+        if let Err(err) = conn.send(&msg).await {
+            stop.store(true, Ordering::SeqCst);
+            return Err(err);
+        }
+        sent += 1;
+    }
+    player
+        .await
+        .map_err(|err| Failure::new(EXIT_FAILURE, err))?;
+    Ok(json!({ "sent": sent }))
+}
+
+/// Upload a local file or directory to `remote`.
+#[tokio::main(flavor = "current_thread")]
+pub async fn push(target: Target, local: String, remote: String, overwrite: bool) -> i32 {
+    exit_code(push_(&target, local, remote, overwrite).await)
+}
+
+async fn push_(
+    target: &Target,
+    local: String,
+    remote: String,
+    overwrite: bool,
+) -> Result<Value, Failure> {
+    let mut conn = Connection::open(target, ConnType::FILE_TRANSFER).await?;
+    upload(&mut conn, local, remote, overwrite, None).await
+}
+
+// Uploads all files under `local`, or only those of `only`.
+async fn upload(
+    conn: &mut Connection,
+    local: String,
+    remote: String,
+    overwrite: bool,
+    only: Option<Vec<FileEntry>>,
+) -> Result<Value, Failure> {
+    let id = 1;
+    let mut job = fs::TransferJob::new_read(
+        id,
+        remote.clone(),
+        local.clone(),
+        0,
+        true,
+        false,
+        conn.overwrite_detection(),
+    )?;
+    if let Some(files) = only {
+        job.set_files(files);
+    }
+    if !conn.can_send_links() {
+        job.drop_links();
+    }
+    job.set_overwrite_strategy(Some(overwrite));
+    #[cfg(not(windows))]
+    let files = job.files().clone();
+    #[cfg(windows)]
+    let mut files = job.files().clone();
+    #[cfg(windows)]
+    if !conn.peer_is_windows() {
+        fs::transform_windows_path(&mut files);
+    }
+    let summary = json!({
+        "from": local,
+        "to": remote,
+        "files": files.len(),
+        "size": files.iter().map(|f| f.size).sum::<u64>(),
+    });
+    conn.send(&fs::new_receive(id, remote, 0, files)).await?;
+    let mut jobs = vec![job];
+    let mut timer = time::interval(MILLI1);
+    loop {
+        tokio::select! {
+            res = conn.next() => match res?.union {
+                Some(message::Union::FileAction(action)) => {
+                    if let Some(file_action::Union::SendConfirm(c)) = action.union {
+                        if let Some(job) = fs::get_job(c.id, &mut jobs) {
+                            job.confirm(&c);
+                        }
+                    }
+                }
+                Some(message::Union::FileResponse(fr)) => match fr.union {
+                    Some(file_response::Union::Digest(digest)) if digest.is_upload => {
+                        if let Some(job) = fs::get_job(digest.id, &mut jobs) {
+                            let req = FileTransferSendConfirmRequest {
+                                id: digest.id,
+                                file_num: digest.file_num,
+                                union: Some(if overwrite {
+                                    file_transfer_send_confirm_request::Union::OffsetBlk(0)
+                                } else {
+                                    file_transfer_send_confirm_request::Union::Skip(true)
+                                }),
+                                ..Default::default()
+                            };
+                            // Otherwise the peer answers with the confirm, a delta if it can.
+                            if !(overwrite && digest.supports_delta) {
+                                job.confirm(&req);
                             }
+                            conn.send(&fs::new_send_confirm(req)).await?;
                         }
-                        _ => {}
                     }
+                    Some(file_response::Union::Error(e)) => {
+                        return Err(Failure::new(EXIT_REMOTE, e.error));
+                    }
+                    _ => {}
+                },
+                _ => {}
+            },
+            _ = timer.tick() => {
+                fs::handle_read_jobs(&mut jobs, &mut conn.stream).await?;
+                if jobs.is_empty() {
+                    break;
                 }
             }
         }
     }
+    Ok(summary)
+}
+
+/// Download a remote file or directory to `local`.
+#[tokio::main(flavor = "current_thread")]
+pub async fn pull(target: Target, remote: String, local: String, overwrite: bool) -> i32 {
+    exit_code(pull_(&target, remote, local, overwrite).await)
+}
+
+async fn pull_(
+    target: &Target,
+    remote: String,
+    local: String,
+    overwrite: bool,
+) -> Result<Value, Failure> {
+    let mut conn = Connection::open(target, ConnType::FILE_TRANSFER).await?;
+    download(&mut conn, remote, local, overwrite, Vec::new()).await
+}
+
+// Downloads all files under `remote`, or only those named in `only`.
+async fn download(
+    conn: &mut Connection,
+    remote: String,
+    local: String,
+    overwrite: bool,
+    only: Vec<String>,
+) -> Result<Value, Failure> {
+    let id = 1;
+    let mut job = fs::TransferJob::new_write(
+        id,
+        remote.clone(),
+        local.clone(),
+        0,
+        true,
+        true,
+        Vec::new(),
+        conn.overwrite_detection(),
+    );
+    job.set_overwrite_strategy(Some(overwrite));
+    conn.send(&fs::new_send_files(id, remote.clone(), 0, true, only))
+        .await?;
+    loop {
+        let fr = match conn.next().await?.union {
+            Some(message::Union::FileResponse(fr)) => fr,
+            _ => continue,
+        };
+        match fr.union {
+            Some(file_response::Union::Dir(fd)) => {
+                #[cfg(windows)]
+                let entries = fd.entries.to_vec();
+                #[cfg(not(windows))]
+                let mut entries = fd.entries.to_vec();
+                #[cfg(not(windows))]
+                if conn.peer_is_windows() {
+                    fs::transform_windows_path(&mut entries);
+                }
+                job.set_files(entries);
+            }
+            Some(file_response::Union::Digest(digest)) => {
+                let write_path = match job.files().get(digest.file_num as usize) {
+                    Some(file) => get_string(&job.join(&file.name)),
+                    None => continue,
+                };
+                job.set_peer_supports_delta(digest.supports_delta);
+                let union = match fs::is_write_need_confirmation(&write_path, &digest)? {
+                    DigestCheckResult::IsSame => {
+                        Some(file_transfer_send_confirm_request::Union::Skip(true))
+                    }
+                    DigestCheckResult::NeedConfirm(_) if !overwrite => {
+                        Some(file_transfer_send_confirm_request::Union::Skip(true))
+                    }
+                    DigestCheckResult::NeedConfirm(_) => None,
+                    DigestCheckResult::NoSuchFile => {
+                        Some(file_transfer_send_confirm_request::Union::OffsetBlk(0))
+                    }
+                };
+                let req = match union {
+                    Some(union) => FileTransferSendConfirmRequest {
+                        id: digest.id,
+                        file_num: digest.file_num,
+                        union: Some(union),
+                        ..Default::default()
+                    },
+                    None => job.overwrite_confirm(digest.file_num).await,
+                };
+                job.confirm(&req);
+                conn.send(&fs::new_send_confirm(req)).await?;
+            }
+            Some(file_response::Union::Block(block)) => {
+                job.write(block).await?;
+            }
+            Some(file_response::Union::Done(_)) => {
+                job.modify_time();
+                break;
+            }
+            Some(file_response::Union::Error(e)) => {
+                return Err(Failure::new(EXIT_REMOTE, e.error));
+            }
+            _ => {}
+        }
+    }
+    Ok(json!({
+        "from": remote,
+        "to": local,
+        "files": job.files().len(),
+        "size": job.files().iter().map(|f| f.size).sum::<u64>(),
+    }))
+}
+
+/// Rename or move a remote file or directory.
+#[tokio::main(flavor = "current_thread")]
+pub async fn rename(target: Target, path: String, new_path: String) -> i32 {
+    let mut action = FileAction::new();
+    action.set_rename(FileRename {
+        id: 1,
+        path: path.clone(),
+        new_path: new_path.clone(),
+        ..Default::default()
+    });
+    exit_code(
+        file_request(&target, action)
+            .await
+            .map(|_| json!({ "from": path, "to": new_path })),
+    )
+}
+
+/// Copy a remote file or directory on the peer.
+#[tokio::main(flavor = "current_thread")]
+pub async fn copy(target: Target, path: String, new_path: String, overwrite: bool) -> i32 {
+    let mut action = FileAction::new();
+    action.set_copy(FileCopy {
+        id: 1,
+        path: path.clone(),
+        new_path: new_path.clone(),
+        overwrite,
+        ..Default::default()
+    });
+    exit_code(
+        file_request(&target, action)
+            .await
+            .map(|_| json!({ "from": path, "to": new_path })),
+    )
+}
+
+/// Print the metadata of a remote path.
+#[tokio::main(flavor = "current_thread")]
+pub async fn stat(target: Target, path: String) -> i32 {
+    let mut action = FileAction::new();
+    action.set_stat(FileStatRequest {
+        id: 1,
+        path,
+        ..Default::default()
+    });
+    exit_code(file_request(&target, action).await.and_then(|stat| {
+        let stat = stat.ok_or_else(|| Failure::new(EXIT_REMOTE, "No stat in the answer"))?;
+        let e = stat.entry.clone().unwrap_or_default();
+        Ok(json!({
+            "path": stat.path,
+            "type": format!("{:?}", e.entry_type.enum_value_or_default()),
+            "size": e.size,
+            "mode": format!("{:o}", e.mode),
+            "owner": e.owner,
+            "group": e.group,
+            "link_target": e.link_target,
+            "modified_time": e.modified_time,
+            "accessed_time": e.accessed_time,
+            "created_time": stat.created_time,
+            "disk_free": stat.disk_free,
+            "disk_total": stat.disk_total,
+        }))
+    }))
+}
+
+// Sends `action` with id 1 and waits for its answer, `Some` for a stat.
+async fn file_request(target: &Target, action: FileAction) -> Result<Option<FileStat>, Failure> {
+    let id = 1;
+    let mut conn = Connection::open(target, ConnType::FILE_TRANSFER).await?;
+    let mut msg = Message::new();
+    msg.set_file_action(action);
+    conn.send(&msg).await?;
+    loop {
+        if let Some(message::Union::FileResponse(fr)) = conn.next().await?.union {
+            match fr.union {
+                Some(file_response::Union::Done(d)) if d.id == id => return Ok(None),
+                Some(file_response::Union::Stat(s)) if s.id == id => return Ok(Some(s)),
+                Some(file_response::Union::Error(e)) if e.id == id => {
+                    return Err(Failure::new(EXIT_REMOTE, e.error));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// What `sync` does, see `dir_sync`.
+pub struct SyncArgs {
+    pub local: String,
+    pub remote: String,
+    /// Make `local` like `remote`, the other way round by default.
+    pub pull: bool,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub delete: bool,
+    pub checksum: bool,
+    pub dry_run: bool,
+}
+
+/// Make one directory like the other, only copying what differs.
+#[tokio::main(flavor = "current_thread")]
+pub async fn sync(target: Target, args: SyncArgs) -> i32 {
+    exit_code(sync_(&target, args).await)
+}
+
+async fn sync_(target: &Target, args: SyncArgs) -> Result<Value, Failure> {
+    let options = dir_sync::SyncOptions {
+        filter: dir_sync::Filter::new(&args.include, &args.exclude)
+            .map_err(|err| Failure::new(EXIT_USAGE, err))?,
+        compare_hash: args.checksum,
+        mirror_delete: args.delete,
+    };
+    let mut conn = Connection::open(target, ConnType::FILE_TRANSFER).await?;
+    let local = list_local(&args.local, args.checksum)?;
+    let remote = list_remote(&mut conn, &args.remote, args.checksum).await?;
+    let (source, dest) = if args.pull {
+        (remote, local)
+    } else {
+        (local, remote)
+    };
+    let source = match source {
+        Some(source) => source,
+        None => {
+            let (code, path) = if args.pull {
+                (EXIT_REMOTE, &args.remote)
+            } else {
+                (EXIT_FAILURE, &args.local)
+            };
+            return Err(Failure::new(code, format!("{} does not exist", path)));
+        }
+    };
+    let dest = dest.unwrap_or_default();
+    let plan = dir_sync::plan(&source, &dest, &options);
+    let dirs = dir_sync::emptied_dirs(&dest, &plan.deletes);
+    let names = |v: &[FileEntry]| v.iter().map(|f| f.name.clone()).collect::<Vec<_>>();
+    let summary = json!({
+        "from": if args.pull { &args.remote } else { &args.local },
+        "to": if args.pull { &args.local } else { &args.remote },
+        "copy": names(&plan.copies),
+        "delete": names(&plan.deletes),
+        "delete_dirs": dirs,
+        "unchanged": plan.unchanged,
+        "size": plan.copies.iter().map(|f| f.size).sum::<u64>(),
+        "dry_run": args.dry_run,
+    });
+    if args.dry_run || plan.is_empty() {
+        return Ok(summary);
+    }
+    if !plan.copies.is_empty() {
+        if args.pull {
+            let only = names(&plan.copies);
+            download(
+                &mut conn,
+                args.remote.clone(),
+                args.local.clone(),
+                true,
+                only,
+            )
+            .await?;
+        } else {
+            let only = Some(plan.copies.clone());
+            upload(
+                &mut conn,
+                args.local.clone(),
+                args.remote.clone(),
+                true,
+                only,
+            )
+            .await?;
+        }
+    }
+    for (i, entry) in plan.deletes.iter().enumerate() {
+        if args.pull {
+            std::fs::remove_file(fs::get_path(&args.local).join(&entry.name))?;
+        } else {
+            let path = conn.remote_join(&args.remote, &entry.name);
+            remove_remote(&mut conn, i as _, path).await?;
+        }
+    }
+    for dir in dirs {
+        if args.pull {
+            std::fs::remove_dir(fs::get_path(&args.local).join(&dir))?;
+        } else {
+            let path = conn.remote_join(&args.remote, &dir);
+            remove_remote_dir(&mut conn, path).await?;
+        }
+    }
+    Ok(summary)
+}
+
+fn list_local(local: &str, with_hash: bool) -> ResultType<Option<Vec<FileEntry>>> {
+    let path = fs::get_path(local);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let files = if with_hash {
+        dir_sync::get_recursive_files_with_hash(local, true)?
+    } else {
+        fs::get_recursive_files(local, true)?
+    };
+    Ok(Some(files))
+}
+
+// All files under `remote`, `None` if it cannot be listed.
+async fn list_remote(
+    conn: &mut Connection,
+    remote: &str,
+    with_hash: bool,
+) -> Result<Option<Vec<FileEntry>>, Failure> {
+    let id = 1;
+    let mut action = FileAction::new();
+    action.set_all_files(ReadAllFiles {
+        id,
+        path: remote.to_owned(),
+        include_hidden: true,
+        with_hash,
+        ..Default::default()
+    });
+    let mut msg = Message::new();
+    msg.set_file_action(action);
+    conn.send(&msg).await?;
+    loop {
+        if let Some(message::Union::FileResponse(fr)) = conn.next().await?.union {
+            match fr.union {
+                Some(file_response::Union::Dir(fd)) if fd.id == id => {
+                    return Ok(Some(fd.entries.to_vec()));
+                }
+                Some(file_response::Union::Error(e)) if e.id == id => {
+                    log::info!("Failed to list {}: {}", remote, e.error);
+                    return Ok(None);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+async fn remove_remote(conn: &mut Connection, file_num: i32, path: String) -> Result<(), Failure> {
+    let id = 2;
+    let mut action = FileAction::new();
+    action.set_remove_file(FileRemoveFile {
+        id,
+        path,
+        file_num,
+        ..Default::default()
+    });
+    remote_action(conn, id, file_num, action).await
+}
+
+// Only if it is empty.
+async fn remove_remote_dir(conn: &mut Connection, path: String) -> Result<(), Failure> {
+    let id = 3;
+    let mut action = FileAction::new();
+    action.set_remove_dir(FileRemoveDir {
+        id,
+        path,
+        recursive: false,
+        ..Default::default()
+    });
+    remote_action(conn, id, 0, action).await
+}
+
+// Send `action` and wait for its done or error.
+async fn remote_action(
+    conn: &mut Connection,
+    id: i32,
+    file_num: i32,
+    action: FileAction,
+) -> Result<(), Failure> {
+    let mut msg = Message::new();
+    msg.set_file_action(action);
+    conn.send(&msg).await?;
+    loop {
+        if let Some(message::Union::FileResponse(fr)) = conn.next().await?.union {
+            match fr.union {
+                Some(file_response::Union::Done(d)) if d.id == id && d.file_num == file_num => {
+                    return Ok(());
+                }
+                Some(file_response::Union::Error(e)) if e.id == id => {
+                    return Err(Failure::new(EXIT_REMOTE, e.error));
+                }
+                _ => {}
+            }
+        }
+    }
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -187,3 +1185,170 @@ pub async fn start_one_port_forward(
     }
     log::info!("port forward (:{}) exit", port);
 }
+
+/// Like [`start_one_port_forward`] for UDP on `bind_ip`, flows idle for `idle_timeout` seconds are dropped.
+#[tokio::main(flavor = "current_thread")]
+pub async fn start_one_udp_port_forward(
+    id: String,
+    port: i32,
+    remote_host: String,
+    remote_port: i32,
+    idle_timeout: u32,
+    bind_ip: std::net::IpAddr,
+    key: String,
+    token: String,
+) {
+    let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new(&id, sender);
+    if let Err(err) = crate::port_forward::listen_udp(
+        handler.id.clone(),
+        handler.password.clone(),
+        port,
+        handler.clone(),
+        receiver,
+        &key,
+        &token,
+        handler.lc.clone(),
+        remote_host,
+        remote_port,
+        idle_timeout,
+        bind_ip,
+    )
+    .await
+    {
+        log::error!("UDP port forward (:{}) failed: {}", port, err);
+    }
+    log::info!("udp port forward (:{}) exit", port);
+}
+
+/// Let the peer listen on `remote_port` and connect what it accepts to `local_host:local_port`.
+#[tokio::main(flavor = "current_thread")]
+pub async fn start_one_reverse_port_forward(
+    id: String,
+    remote_port: i32,
+    local_host: String,
+    local_port: i32,
+    key: String,
+    token: String,
+) {
+    let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new(&id, sender);
+    if let Err(err) = crate::port_forward::listen_reverse(
+        handler.id.clone(),
+        handler.password.clone(),
+        remote_port,
+        handler.clone(),
+        receiver,
+        &key,
+        &token,
+        handler.lc.clone(),
+        local_host,
+        local_port,
+    )
+    .await
+    {
+        log::error!("Reverse port forward (:{}) failed: {}", remote_port, err);
+    }
+    log::info!("reverse port forward (:{}) exit", remote_port);
+}
+
+/// Serve every rule of a forwarding profile file over one session, until a listener fails.
+#[tokio::main(flavor = "current_thread")]
+pub async fn start_port_forward_profile(path: String, key: String, token: String) -> i32 {
+    let profile = match crate::port_forward::ForwardProfile::load(&path) {
+        Ok(profile) => profile,
+        Err(err) => {
+            log::error!("Failed to load {}: {}", path, err);
+            return EXIT_USAGE;
+        }
+    };
+    crate::common::test_rendezvous_server();
+    crate::common::test_nat_type();
+    let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = if profile.password.is_empty() {
+        Session::new(&profile.id, sender)
+    } else {
+        Session::with_conn_type(
+            &profile.id,
+            profile.password.clone(),
+            false,
+            ConnType::PORT_FORWARD,
+            sender,
+        )
+    };
+    let password = handler.password.clone();
+    if let Err(err) =
+        crate::port_forward::listen_profile(profile, password, handler, receiver, &key, &token)
+            .await
+    {
+        log::error!("Port forward profile {} stopped: {}", path, err);
+        return EXIT_FAILURE;
+    }
+    EXIT_OK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key_chord() {
+        let evt = parse_key_chord("ctrl+alt+VK_DELETE").unwrap();
+        assert_eq!(evt.control_key(), ControlKey::Delete);
+        assert!(evt.press);
+        assert_eq!(evt.modifiers.len(), 2);
+
+        let evt = parse_key_chord("shift+a").unwrap();
+        assert_eq!(evt.chr(), 'a' as u32);
+        assert_eq!(evt.modifiers, vec![ControlKey::Shift.into()]);
+
+        let evt = parse_key_chord("enter").unwrap();
+        assert_eq!(evt.control_key(), ControlKey::Return);
+        assert!(evt.modifiers.is_empty());
+
+        assert!(parse_key_chord("hyper+a").is_err());
+        assert!(parse_key_chord("VK_NOPE").is_err());
+        assert!(parse_key_chord("").is_err());
+    }
+
+    // a VP9 frame which does not decode
+    fn video_frame() -> Message {
+        let mut vf = VideoFrame::new();
+        vf.set_vp9s(EncodedVideoFrames {
+            frames: vec![EncodedVideoFrame {
+                data: vec![0u8; 16].into(),
+                key: true,
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        let mut msg = Message::new();
+        msg.set_video_frame(vf);
+        msg
+    }
+
+    fn switch_display(display: i32) -> Message {
+        let mut misc = Misc::new();
+        misc.set_switch_display(SwitchDisplay {
+            display,
+            ..Default::default()
+        });
+        let mut msg = Message::new();
+        msg.set_misc(misc);
+        msg
+    }
+
+    #[test]
+    fn test_capture_switch_display() {
+        let mut capture = Capture::new(1, true);
+        // frames of the old display are not even decoded
+        assert!(!capture.handle(video_frame()));
+        assert!(capture.failed.is_none());
+        assert!(!capture.handle(switch_display(0)));
+        assert!(capture.switching);
+        assert!(!capture.handle(switch_display(1)));
+        assert!(!capture.switching);
+        assert!(!capture.handle(video_frame()));
+        assert_eq!(capture.failed, Some(CodecFormat::VP9));
+    }
+}
@@ -34,8 +34,8 @@ use hbb_common::{
     anyhow::{anyhow, Context},
     bail,
     config::{
-        Config, LocalConfig, PeerConfig, PeerInfoSerde, Resolution, CONNECT_TIMEOUT, READ_TIMEOUT,
-        RELAY_PORT,
+        Config, KeyPin, LocalConfig, PeerConfig, PeerInfoSerde, PinnedKeys, Resolution,
+        CONNECT_TIMEOUT, READ_TIMEOUT, RELAY_PORT,
     },
     get_version_number, log,
     message_proto::{option_message::BoolOption, *},
@@ -63,13 +63,14 @@ use crate::is_keyboard_mode_supported;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use crate::ui_session_interface::SessionPermissionConfig;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
-use crate::{check_clipboard, ClipboardContext, CLIPBOARD_INTERVAL};
+use crate::{check_clipboard, clipboard_data::LastClipboard, ClipboardContext, CLIPBOARD_INTERVAL};
 
 pub use super::lang::*;
 
 pub mod file_trait;
 pub mod helper;
 pub mod io_loop;
+pub mod macros;
 
 pub const MILLI1: Duration = Duration::from_millis(1);
 pub const SEC30: Duration = Duration::from_secs(30);
@@ -129,14 +130,14 @@ lazy_static::lazy_static! {
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 lazy_static::lazy_static! {
     static ref ENIGO: Arc<Mutex<enigo::Enigo>> = Arc::new(Mutex::new(enigo::Enigo::new()));
-    static ref OLD_CLIPBOARD_TEXT: Arc<Mutex<String>> = Default::default();
+    static ref OLD_CLIPBOARD: Arc<Mutex<LastClipboard>> = Default::default();
     static ref TEXT_CLIPBOARD_STATE: Arc<Mutex<TextClipboardState>> = Arc::new(Mutex::new(TextClipboardState::new()));
 }
 
 #[inline]
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
-pub fn get_old_clipboard_text() -> &'static Arc<Mutex<String>> {
-    &OLD_CLIPBOARD_TEXT
+pub fn get_old_clipboard() -> &'static Arc<Mutex<LastClipboard>> {
+    &OLD_CLIPBOARD
 }
 
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -229,8 +230,42 @@ impl Client {
                     return Err(err);
                 }
             }
-            Ok(x) => Ok(x),
+            Ok((conn, direct, pk)) => {
+                if let Some(pk) = pk.as_ref() {
+                    Self::check_pinned_key(peer, pk)?;
+                }
+                // No key, or one the peer could not prove, is no better than a changed one.
+                if (pk.is_none() || !conn.is_secured()) && PinnedKeys::is_pinned(peer) {
+                    log::error!("WARNING: {} did not prove its pinned key", peer);
+                    bail!(
+                        "WARNING: {} did not prove the identity key pinned at the first connection, someone may be intercepting it. If the remote device was reinstalled, remove it from your peers and connect again.",
+                        peer
+                    );
+                }
+                Ok((conn, direct, pk))
+            }
+        }
+    }
+
+    // Trust on first use, a changed key means either a reinstalled peer or
+    // someone in the middle, e.g. with a compromised rendezvous server.
+    fn check_pinned_key(peer: &str, pk: &[u8]) -> ResultType<()> {
+        match PinnedKeys::check(peer, pk) {
+            KeyPin::New => log::info!("Pinned the key of {}", peer),
+            KeyPin::Same => {}
+            KeyPin::Changed => {
+                log::error!(
+                    "WARNING: the key of {} changed, fingerprint now {}",
+                    peer,
+                    crate::common::pk_to_fingerprint(pk.to_vec())
+                );
+                bail!(
+                    "WARNING: The identity key of {} has changed since the first connection, someone may be intercepting it. If the remote device was reinstalled, remove it from your peers and connect again.",
+                    peer
+                );
+            }
         }
+        Ok(())
     }
 
     /// Start a new connection.
@@ -696,11 +731,14 @@ impl Client {
             Ok(mut ctx) => {
                 clipboard_lock.running = true;
                 // ignore clipboard update before service start
-                check_clipboard(&mut ctx, Some(&OLD_CLIPBOARD_TEXT));
+                check_clipboard(&mut ctx, Some(&OLD_CLIPBOARD));
                 std::thread::spawn(move || {
                     log::info!("Start text clipboard loop");
+                    let mut watcher = crate::clipboard_watcher::new();
+                    // kept while the clipboard is not required, to check once it is again
+                    let mut changed = false;
                     loop {
-                        std::thread::sleep(Duration::from_millis(CLIPBOARD_INTERVAL));
+                        changed |= watcher.wait(Duration::from_millis(CLIPBOARD_INTERVAL));
                         if !TEXT_CLIPBOARD_STATE.lock().unwrap().running {
                             break;
                         }
@@ -708,14 +746,19 @@ impl Client {
                         if !TEXT_CLIPBOARD_STATE.lock().unwrap().is_required {
                             continue;
                         }
+                        if !std::mem::take(&mut changed) {
+                            continue;
+                        }
 
-                        if let Some(msg) = check_clipboard(&mut ctx, Some(&OLD_CLIPBOARD_TEXT)) {
-                            #[cfg(feature = "flutter")]
-                            crate::flutter::send_text_clipboard_msg(msg);
-                            #[cfg(not(feature = "flutter"))]
-                            if let Some(ctx) = &_ctx {
-                                if ctx.cfg.is_text_clipboard_required() {
-                                    let _ = ctx.tx.send(Data::Message(msg));
+                        if let Some(msgs) = check_clipboard(&mut ctx, Some(&OLD_CLIPBOARD)) {
+                            for msg in msgs {
+                                #[cfg(feature = "flutter")]
+                                crate::flutter::send_text_clipboard_msg(msg);
+                                #[cfg(not(feature = "flutter"))]
+                                if let Some(ctx) = &_ctx {
+                                    if ctx.cfg.is_text_clipboard_required() {
+                                        let _ = ctx.tx.send(Data::Message(msg));
+                                    }
                                 }
                             }
                         }
@@ -731,13 +774,8 @@ impl Client {
 
     #[inline]
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
-    fn get_current_text_clipboard_msg() -> Option<Message> {
-        let txt = &*OLD_CLIPBOARD_TEXT.lock().unwrap();
-        if txt.is_empty() {
-            None
-        } else {
-            Some(crate::create_clipboard_msg(txt.clone()))
-        }
+    fn get_current_clipboard_msgs() -> Vec<Message> {
+        OLD_CLIPBOARD.lock().unwrap().data.to_messages()
     }
 }
 
@@ -1053,6 +1091,20 @@ impl VideoHandler {
 
         self.record = start;
     }
+
+    /// Write audio or a session event into the screen record.
+    pub fn record_message(&mut self, msg: &Message, viewer: bool) {
+        if !self.record {
+            return;
+        }
+        if let Some(r) = self.recorder.lock().unwrap().as_mut() {
+            if viewer {
+                r.write_viewer_message(msg);
+            } else {
+                r.write_message(msg);
+            }
+        }
+    }
 }
 
 /// Login config handler for [`Client`].
@@ -1065,6 +1117,18 @@ pub struct LoginConfigHandler {
     pub remember: bool,
     config: PeerConfig,
     pub port_forward: (String, i32),
+    /// Remote port to listen on for a reverse port forward.
+    pub reverse_port_forward: Option<i32>,
+    /// Forward UDP datagrams instead of a TCP stream.
+    pub port_forward_udp: bool,
+    /// Carry many TCP connections, each to its own target, over the session.
+    pub port_forward_mux: bool,
+    /// The `host:port` targets of a multiplexed session.
+    pub port_forward_targets: Vec<String>,
+    /// Idle timeout in seconds of a forwarded UDP flow, 0 for the default.
+    pub udp_idle_timeout: u32,
+    /// Name and password of an account on the peer, instead of its shared password.
+    pub account: Option<(String, String)>,
     pub version: i64,
     features: Option<Features>,
     pub session_id: u64, // used for local <-> server communication
@@ -1711,6 +1775,19 @@ impl LoginConfigHandler {
                 ..Default::default()
             })
             .into(),
+            account: self
+                .account
+                .as_ref()
+                .map(|(name, password)| AccountLogin {
+                    name: name.clone(),
+                    password: if self.hash.account_password {
+                        password.clone()
+                    } else {
+                        "".to_owned()
+                    },
+                    ..Default::default()
+                })
+                .into(),
             ..Default::default()
         };
         match self.conn_type {
@@ -1719,11 +1796,30 @@ impl LoginConfigHandler {
                 show_hidden: !self.get_option("remote_show_hidden").is_empty(),
                 ..Default::default()
             }),
-            ConnType::PORT_FORWARD | ConnType::RDP => lr.set_port_forward(PortForward {
-                host: self.port_forward.0.clone(),
-                port: self.port_forward.1,
-                ..Default::default()
-            }),
+            ConnType::PORT_FORWARD | ConnType::RDP => {
+                if let Some(port) = self.reverse_port_forward {
+                    lr.set_reverse_port_forward(ReversePortForward {
+                        port,
+                        ..Default::default()
+                    })
+                } else {
+                    let protocol = if self.port_forward_mux {
+                        port_forward::Protocol::MULTIPLEX
+                    } else if self.port_forward_udp {
+                        port_forward::Protocol::UDP
+                    } else {
+                        port_forward::Protocol::TCP
+                    };
+                    lr.set_port_forward(PortForward {
+                        host: self.port_forward.0.clone(),
+                        port: self.port_forward.1,
+                        protocol: protocol.into(),
+                        idle_timeout: self.udp_idle_timeout,
+                        targets: self.port_forward_targets.clone(),
+                        ..Default::default()
+                    })
+                }
+            }
             _ => {}
         }
 
@@ -1761,6 +1857,8 @@ pub enum MediaData {
     AudioFormat(AudioFormat),
     Reset,
     RecordScreen(bool, i32, i32, String),
+    // Message to write into the recording, `true` if it was sent by this side.
+    RecordMessage(Box<Message>, bool),
 }
 
 pub type MediaSender = mpsc::Sender<MediaData>;
@@ -1839,6 +1937,9 @@ where
                     MediaData::RecordScreen(start, w, h, id) => {
                         video_handler.record_screen(start, w, h, id)
                     }
+                    MediaData::RecordMessage(msg, viewer) => {
+                        video_handler.record_message(&msg, viewer)
+                    }
                     _ => {}
                 }
             } else {
@@ -2224,6 +2325,14 @@ pub async fn handle_hash(
             return;
         }
     }
+    let account = lc.read().unwrap().account.clone();
+    // the account password goes in LoginRequest.account
+    if account.is_some() {
+        let os_username = lc.read().unwrap().get_option("os-username");
+        let os_password = lc.read().unwrap().get_option("os-password");
+        send_login(lc.clone(), os_username, os_password, Vec::new(), peer).await;
+        return;
+    }
     let mut password = lc.read().unwrap().password.clone();
     if password.is_empty() {
         if !password_preset.is_empty() {
@@ -2435,7 +2544,10 @@ pub enum Data {
     RemoveDir((i32, String)),
     RemoveFile((i32, String, i32, bool)),
     CreateDir((i32, String, bool)),
+    RenameFile((i32, String, String, bool)),
+    CopyFile((i32, String, String, bool)),
     CancelJob(i32),
+    JobControl(FileJobControl),
     RemovePortForward(i32),
     AddPortForward((i32, String, i32)),
     #[cfg(not(feature = "flutter"))]
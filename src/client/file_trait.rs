@@ -33,6 +33,23 @@ pub trait FileManager: Interface {
         self.send(Data::CancelJob(id));
     }
 
+    fn pause_job(&self, id: i32, paused: bool) {
+        self.send(Data::JobControl(FileJobControl {
+            id,
+            union: Some(file_job_control::Union::Paused(paused)),
+            ..Default::default()
+        }));
+    }
+
+    /// In KiB/s, 0 for unlimited.
+    fn set_job_rate_limit(&self, id: i32, rate_limit: i32) {
+        self.send(Data::JobControl(FileJobControl {
+            id,
+            union: Some(file_job_control::Union::RateLimit(rate_limit.max(0) as _)),
+            ..Default::default()
+        }));
+    }
+
     fn read_remote_dir(&self, path: String, include_hidden: bool) {
         let mut msg_out = Message::new();
         let mut file_action = FileAction::new();
@@ -73,6 +90,16 @@ pub trait FileManager: Interface {
         self.send(Data::CreateDir((id, path, is_remote)));
     }
 
+    /// Rename or move, within the local or the remote side.
+    fn rename_file(&self, id: i32, path: String, new_path: String, is_remote: bool) {
+        self.send(Data::RenameFile((id, path, new_path, is_remote)));
+    }
+
+    /// Copy a file or directory to `new_path`, which must not exist.
+    fn copy_file(&self, id: i32, path: String, new_path: String, is_remote: bool) {
+        self.send(Data::CopyFile((id, path, new_path, is_remote)));
+    }
+
     fn send_files(
         &self,
         id: i32,
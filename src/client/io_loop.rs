@@ -27,14 +27,15 @@ use hbb_common::tokio::{
     sync::mpsc,
     time::{self, Duration, Instant, Interval},
 };
-use hbb_common::{allow_err, fs, get_time, log, message_proto::*, Stream};
+use hbb_common::{allow_err, fs, get_time, log, message_proto::*, rekey::Rekeying, Stream};
 use scrap::CodecFormat;
 
 use crate::client::{
     new_voice_call_request, Client, MediaData, MediaSender, QualityStatus, MILLI1, SEC30,
 };
+use crate::clipboard_data::{self, ChunkAssembler};
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
-use crate::common::{self, update_clipboard};
+use crate::common::{self, update_clipboard, update_clipboard_data};
 use crate::common::{get_default_sound_input, set_sound_input};
 use crate::ui_session_interface::{InvokeUiSession, Session};
 #[cfg(not(any(target_os = "ios")))]
@@ -66,6 +67,12 @@ pub struct Remote<T: InvokeUiSession> {
     elevation_requested: bool,
     fps_control: FpsControl,
     decode_fps: Arc<AtomicUsize>,
+    recording: bool,
+    audio_format: Option<AudioFormat>,
+    rekeying: Rekeying,
+    // rekeying only starts once the login has been accepted
+    logged_in: bool,
+    clipboard_chunks: ChunkAssembler,
 }
 
 impl<T: InvokeUiSession> Remote<T> {
@@ -103,6 +110,11 @@ impl<T: InvokeUiSession> Remote<T> {
             elevation_requested: false,
             fps_control: Default::default(),
             decode_fps,
+            recording: false,
+            audio_format: None,
+            rekeying: Default::default(),
+            logged_in: false,
+            clipboard_chunks: Default::default(),
         }
     }
 
@@ -204,7 +216,7 @@ impl<T: InvokeUiSession> Remote<T> {
                                 self.handler.msgbox("error", "Connection Error", "Timeout", "");
                                 break;
                             }
-                            if !self.read_jobs.is_empty() {
+                            if !fs::all_paused(&self.read_jobs) {
                                 if let Err(err) = fs::handle_read_jobs(&mut self.read_jobs, &mut peer).await {
                                     self.handler.msgbox("error", "Connection Error", &err.to_string(), "");
                                     break;
@@ -215,6 +227,9 @@ impl<T: InvokeUiSession> Remote<T> {
                             }
                         }
                         _ = status_timer.tick() => {
+                            if self.logged_in && self.rekeying.due(&peer) {
+                                allow_err!(self.rekeying.start(&mut peer).await);
+                            }
                             self.fps_control(direct);
                             let elapsed = fps_instant.elapsed().as_millis();
                             if elapsed < 1000 {
@@ -425,7 +440,11 @@ impl<T: InvokeUiSession> Remote<T> {
                 self.check_clipboard_file_context();
             }
             Data::Message(msg) => {
-                allow_err!(peer.send(&msg).await);
+                let version = self.handler.lc.read().unwrap().version;
+                if clipboard_data::is_for_peer(&msg, version) {
+                    self.record_message(&msg, true);
+                    allow_err!(peer.send(&msg).await);
+                }
             }
             Data::SendFiles((id, path, to, file_num, include_hidden, is_remote)) => {
                 log::info!("send files, is remote {}", is_remote);
@@ -459,7 +478,10 @@ impl<T: InvokeUiSession> Remote<T> {
                         Err(err) => {
                             self.handle_job_status(id, -1, Some(err.to_string()));
                         }
-                        Ok(job) => {
+                        Ok(mut job) => {
+                            if !fs::can_send_links(self.handler.lc.read().unwrap().version) {
+                                job.drop_links();
+                            }
                             log::debug!(
                                 "New job {}, read {} to remote {}, {} files",
                                 id,
@@ -525,6 +547,9 @@ impl<T: InvokeUiSession> Remote<T> {
                             self.handle_job_status(id, -1, Some(err.to_string()));
                         }
                         Ok(mut job) => {
+                            if !fs::can_send_links(self.handler.lc.read().unwrap().version) {
+                                job.drop_links();
+                            }
                             log::debug!(
                                 "new read waiting job {}, read {} to remote {}, {} files",
                                 id,
@@ -598,7 +623,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         if remember {
                             job.set_overwrite_strategy(Some(need_override));
                         }
-                        job.confirm(&FileTransferSendConfirmRequest {
+                        let req = FileTransferSendConfirmRequest {
                             id,
                             file_num,
                             union: if need_override {
@@ -607,7 +632,13 @@ impl<T: InvokeUiSession> Remote<T> {
                                 Some(file_transfer_send_confirm_request::Union::Skip(true))
                             },
                             ..Default::default()
-                        });
+                        };
+                        // The peer answers with the confirm, a delta if it can.
+                        if need_override && job.peer_supports_delta() {
+                            allow_err!(peer.send(&new_send_confirm(req)).await);
+                        } else {
+                            job.confirm(&req);
+                        }
                     }
                 } else {
                     if let Some(job) = fs::get_job(id, &mut self.write_jobs) {
@@ -616,15 +647,15 @@ impl<T: InvokeUiSession> Remote<T> {
                         }
                         let mut msg = Message::new();
                         let mut file_action = FileAction::new();
-                        let req = FileTransferSendConfirmRequest {
-                            id,
-                            file_num,
-                            union: if need_override {
-                                Some(file_transfer_send_confirm_request::Union::OffsetBlk(0))
-                            } else {
-                                Some(file_transfer_send_confirm_request::Union::Skip(true))
-                            },
-                            ..Default::default()
+                        let req = if need_override {
+                            job.overwrite_confirm(file_num).await
+                        } else {
+                            FileTransferSendConfirmRequest {
+                                id,
+                                file_num,
+                                union: Some(file_transfer_send_confirm_request::Union::Skip(true)),
+                                ..Default::default()
+                            }
                         };
                         job.confirm(&req);
                         file_action.set_send_confirm(req);
@@ -683,6 +714,17 @@ impl<T: InvokeUiSession> Remote<T> {
                 fs::remove_job(id, &mut self.read_jobs);
                 self.remove_jobs.remove(&id);
             }
+            Data::JobControl(control) => {
+                // Uploads are read here, downloads by the peer.
+                if let Some(job) = fs::get_job(control.id, &mut self.read_jobs) {
+                    job.control(&control);
+                    if !job.is_paused() {
+                        self.timer = time::interval(MILLI1);
+                    }
+                } else {
+                    allow_err!(peer.send(&fs::new_job_control(control)).await);
+                }
+            }
             Data::RemoveDir((id, path)) => {
                 let mut msg_out = Message::new();
                 let mut file_action = FileAction::new();
@@ -740,10 +782,61 @@ impl<T: InvokeUiSession> Remote<T> {
                     }
                 }
             }
+            Data::RenameFile((id, path, new_path, is_remote)) => {
+                if is_remote {
+                    let mut msg_out = Message::new();
+                    let mut file_action = FileAction::new();
+                    file_action.set_rename(FileRename {
+                        id,
+                        path,
+                        new_path,
+                        ..Default::default()
+                    });
+                    msg_out.set_file_action(file_action);
+                    allow_err!(peer.send(&msg_out).await);
+                } else {
+                    let res = tokio::task::spawn_blocking(move || fs::rename(&path, &new_path));
+                    match res.await {
+                        Ok(Ok(())) => self.handle_job_status(id, -1, None),
+                        Ok(Err(err)) => self.handle_job_status(id, -1, Some(err.to_string())),
+                        Err(err) => self.handle_job_status(id, -1, Some(err.to_string())),
+                    }
+                }
+            }
+            Data::CopyFile((id, path, new_path, is_remote)) => {
+                if is_remote {
+                    let mut msg_out = Message::new();
+                    let mut file_action = FileAction::new();
+                    file_action.set_copy(FileCopy {
+                        id,
+                        path,
+                        new_path,
+                        ..Default::default()
+                    });
+                    msg_out.set_file_action(file_action);
+                    allow_err!(peer.send(&msg_out).await);
+                } else {
+                    let res =
+                        tokio::task::spawn_blocking(move || fs::copy(&path, &new_path, false));
+                    match res.await {
+                        Ok(Ok(())) => self.handle_job_status(id, -1, None),
+                        Ok(Err(err)) => self.handle_job_status(id, -1, Some(err.to_string())),
+                        Err(err) => self.handle_job_status(id, -1, Some(err.to_string())),
+                    }
+                }
+            }
             Data::RecordScreen(start, w, h, id) => {
                 let _ = self
                     .video_sender
                     .send(MediaData::RecordScreen(start, w, h, id));
+                self.recording = start;
+                if let Some(f) = self.audio_format.clone() {
+                    let mut misc = Misc::new();
+                    misc.set_audio_format(f);
+                    let mut msg = Message::new();
+                    msg.set_misc(misc);
+                    self.record_message(&msg, false);
+                }
             }
             Data::ElevateDirect => {
                 let mut request = ElevationRequest::new();
@@ -968,8 +1061,32 @@ impl<T: InvokeUiSession> Remote<T> {
         }
     }
 
+    // Video frames are recorded by the video thread itself, everything else that
+    // is worth keeping in a recording is forwarded here.
+    fn record_message(&self, msg: &Message, viewer: bool) {
+        if !self.recording {
+            return;
+        }
+        match &msg.union {
+            Some(message::Union::AudioFrame(_))
+            | Some(message::Union::Clipboard(_))
+            | Some(message::Union::ClipboardChunk(_))
+            | Some(message::Union::FileAction(_))
+            | Some(message::Union::FileResponse(_)) => {}
+            Some(message::Union::Misc(misc)) => match &misc.union {
+                Some(misc::Union::AudioFormat(_)) | Some(misc::Union::ChatMessage(_)) => {}
+                _ => return,
+            },
+            _ => return,
+        }
+        self.video_sender
+            .send(MediaData::RecordMessage(Box::new(msg.clone()), viewer))
+            .ok();
+    }
+
     async fn handle_msg_from_peer(&mut self, data: &[u8], peer: &mut Stream) -> bool {
         if let Ok(msg_in) = Message::parse_from_bytes(&data) {
+            self.record_message(&msg_in, false);
             match msg_in.union {
                 Some(message::Union::VideoFrame(vf)) => {
                     if !self.first_frame {
@@ -1008,6 +1125,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         }
                     }
                     Some(login_response::Union::PeerInfo(pi)) => {
+                        self.logged_in = true;
                         self.handler.handle_peer_info(pi);
                         #[cfg(not(feature = "flutter"))]
                         self.check_clipboard_file_context();
@@ -1025,16 +1143,21 @@ impl<T: InvokeUiSession> Remote<T> {
                             ));
 
                             #[cfg(not(any(target_os = "android", target_os = "ios")))]
-                            if let Some(msg_out) = Client::get_current_text_clipboard_msg() {
-                                let sender = self.sender.clone();
-                                let permission_config = self.handler.get_permission_config();
-                                tokio::spawn(async move {
-                                    // due to clipboard service interval time
-                                    sleep(common::CLIPBOARD_INTERVAL as f32 / 1_000.).await;
-                                    if permission_config.is_text_clipboard_required() {
-                                        sender.send(Data::Message(msg_out)).ok();
-                                    }
-                                });
+                            {
+                                let msgs = Client::get_current_clipboard_msgs();
+                                if !msgs.is_empty() {
+                                    let sender = self.sender.clone();
+                                    let permission_config = self.handler.get_permission_config();
+                                    tokio::spawn(async move {
+                                        // due to clipboard service interval time
+                                        sleep(common::CLIPBOARD_INTERVAL as f32 / 1_000.).await;
+                                        if permission_config.is_text_clipboard_required() {
+                                            for msg_out in msgs {
+                                                sender.send(Data::Message(msg_out)).ok();
+                                            }
+                                        }
+                                    });
+                                }
                             }
 
                             // on connection established client
@@ -1066,7 +1189,7 @@ impl<T: InvokeUiSession> Remote<T> {
                 Some(message::Union::Clipboard(cb)) => {
                     if !self.handler.lc.read().unwrap().disable_clipboard.v {
                         #[cfg(not(any(target_os = "android", target_os = "ios")))]
-                        update_clipboard(cb, Some(&crate::client::get_old_clipboard_text()));
+                        update_clipboard(cb, Some(&crate::client::get_old_clipboard()));
                         #[cfg(any(target_os = "android", target_os = "ios"))]
                         {
                             let content = if cb.compress {
@@ -1080,6 +1203,18 @@ impl<T: InvokeUiSession> Remote<T> {
                         }
                     }
                 }
+                Some(message::Union::ClipboardChunk(chunk)) => {
+                    if !self.handler.lc.read().unwrap().disable_clipboard.v {
+                        if let Some(data) = self.clipboard_chunks.add(chunk) {
+                            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                            update_clipboard_data(data, Some(&crate::client::get_old_clipboard()));
+                            #[cfg(any(target_os = "android", target_os = "ios"))]
+                            if let Some(text) = data.get_text() {
+                                self.handler.clipboard(text.to_owned());
+                            }
+                        }
+                    }
+                }
                 #[cfg(windows)]
                 Some(message::Union::Cliprdr(clip)) => {
                     self.handle_cliprdr_msg(clip);
@@ -1109,10 +1244,20 @@ impl<T: InvokeUiSession> Remote<T> {
                         Some(file_response::Union::Digest(digest)) => {
                             if digest.is_upload {
                                 if let Some(job) = fs::get_job(digest.id, &mut self.read_jobs) {
+                                    job.set_peer_supports_delta(digest.supports_delta);
                                     if let Some(file) = job.files().get(digest.file_num as usize) {
                                         let read_path = get_string(&job.join(&file.name));
                                         let overwrite_strategy = job.default_overwrite_strategy();
-                                        if let Some(overwrite) = overwrite_strategy {
+                                        if overwrite_strategy == Some(true) && digest.supports_delta
+                                        {
+                                            let req = FileTransferSendConfirmRequest {
+                                                id: digest.id,
+                                                file_num: digest.file_num,
+                                                union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(0)),
+                                                ..Default::default()
+                                            };
+                                            allow_err!(peer.send(&new_send_confirm(req)).await);
+                                        } else if let Some(overwrite) = overwrite_strategy {
                                             let req = FileTransferSendConfirmRequest {
                                                 id: digest.id,
                                                 file_num: digest.file_num,
@@ -1141,6 +1286,7 @@ impl<T: InvokeUiSession> Remote<T> {
                                 }
                             } else {
                                 if let Some(job) = fs::get_job(digest.id, &mut self.write_jobs) {
+                                    job.set_peer_supports_delta(digest.supports_delta);
                                     if let Some(file) = job.files().get(digest.file_num as usize) {
                                         let write_path = get_string(&job.join(&file.name));
                                         let overwrite_strategy = job.default_overwrite_strategy();
@@ -1159,15 +1305,16 @@ impl<T: InvokeUiSession> Remote<T> {
                                                 }
                                                 DigestCheckResult::NeedConfirm(digest) => {
                                                     if let Some(overwrite) = overwrite_strategy {
-                                                        let req = FileTransferSendConfirmRequest {
-                                                            id: digest.id,
-                                                            file_num: digest.file_num,
-                                                            union: Some(if overwrite {
-                                                                file_transfer_send_confirm_request::Union::OffsetBlk(0)
-                                                            } else {
-                                                                file_transfer_send_confirm_request::Union::Skip(true)
-                                                            }),
-                                                            ..Default::default()
+                                                        let req = if overwrite {
+                                                            job.overwrite_confirm(digest.file_num)
+                                                                .await
+                                                        } else {
+                                                            FileTransferSendConfirmRequest {
+                                                                id: digest.id,
+                                                                file_num: digest.file_num,
+                                                                union: Some(file_transfer_send_confirm_request::Union::Skip(true)),
+                                                                ..Default::default()
+                                                            }
                                                         };
                                                         job.confirm(&req);
                                                         let msg = new_send_confirm(req);
@@ -1203,12 +1350,27 @@ impl<T: InvokeUiSession> Remote<T> {
                             }
                         }
                         Some(file_response::Union::Block(block)) => {
-                            if let Some(job) = fs::get_job(block.id, &mut self.write_jobs) {
-                                if let Err(_err) = job.write(block).await {
-                                    // to-do: add "skip" for writing job
+                            let (id, file_num) = (block.id, block.file_num);
+                            let mut err = None;
+                            if let Some(job) = fs::get_job(id, &mut self.write_jobs) {
+                                if let Err(e) = job.write(block).await {
+                                    err = Some(e.to_string());
                                 }
                                 self.update_jobs_status();
                             }
+                            if let Some(err) = err {
+                                // e.g. a content hash mismatch, the sender is told to stop.
+                                let mut msg_out = Message::new();
+                                let mut file_action = FileAction::new();
+                                file_action.set_cancel(FileTransferCancel {
+                                    id,
+                                    ..Default::default()
+                                });
+                                msg_out.set_file_action(file_action);
+                                allow_err!(peer.send(&msg_out).await);
+                                fs::remove_job(id, &mut self.write_jobs);
+                                self.handle_job_status(id, file_num, Some(err));
+                            }
                         }
                         Some(file_response::Union::Done(d)) => {
                             let mut err: Option<String> = None;
@@ -1223,6 +1385,8 @@ impl<T: InvokeUiSession> Remote<T> {
                             if let Some(_job) = fs::get_job(e.id, &mut self.write_jobs) {
                                 fs::remove_job(e.id, &mut self.write_jobs);
                             }
+                            // The receiver gave up, e.g. on a content hash mismatch.
+                            fs::remove_job(e.id, &mut self.read_jobs);
                             self.handle_job_status(e.id, e.file_num, Some(e.error));
                         }
                         _ => {}
@@ -1230,6 +1394,7 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 Some(message::Union::Misc(misc)) => match misc.union {
                     Some(misc::Union::AudioFormat(f)) => {
+                        self.audio_format = Some(f.clone());
                         self.audio_sender.send(MediaData::AudioFormat(f)).ok();
                     }
                     Some(misc::Union::ChatMessage(c)) => {
@@ -1407,6 +1572,14 @@ impl<T: InvokeUiSession> Remote<T> {
                 Some(message::Union::TestDelay(t)) => {
                     self.handler.handle_test_delay(t, peer).await;
                 }
+                Some(message::Union::Rekey(r)) if self.logged_in => {
+                    if let Err(err) = self.rekeying.handle(&r, peer).await {
+                        log::error!("Failed to rekey: {}", err);
+                        self.handler
+                            .msgbox("error", "Connection Error", &err.to_string(), "");
+                        return false;
+                    }
+                }
                 Some(message::Union::AudioFrame(frame)) => {
                     if !self.handler.lc.read().unwrap().disable_audio.v {
                         self.audio_sender
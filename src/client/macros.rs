@@ -0,0 +1,656 @@
+// Input macros: the key and mouse events of a session recorded with their
+// timing, kept as text so they can be read, edited and replayed against any
+// peer. One statement per line, lines starting with `#` are comments:
+//
+//   var NAME = VALUE       default of `${NAME}`, replay may override it
+//   wait MS                pause, scaled by the replay speed
+//   mode legacy|map|translate|auto
+//                          keyboard mode of the keys after it
+//   key down|up|press KEY [MODS]
+//   text TEXT              types TEXT, `\n`, `\\` and `\ ` are escapes
+//   mouse TYPE [BUTTONS] X Y [MODS]
+//
+// KEY is a control key like `Return` or `F5`, else `chr:N`, `unicode:N` or
+// `hotkey:N` with the code of the event. MODS are control keys joined by `+`,
+// like `Control+Shift`. TYPE is move, down, up, wheel or trackpad, BUTTONS are
+// left, right, wheel, back, forward or `btn:N` for any other button, N being
+// its bit index in the mask from 0 to 28, joined by `+`. For wheel and trackpad, X and Y are the deltas.
+//
+// `${NAME}` is replaced before a line is read, anywhere but in the name of a
+// `var`, and `$$` is a `$`.
+
+use crate::input::*;
+use hbb_common::{
+    bail,
+    config::Config,
+    log,
+    message_proto::*,
+    protobuf::{Enum, EnumOrUnknown},
+    ResultType,
+};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+const EXT: &str = "macro";
+/// Shorter pauses between recorded events are left out.
+const MIN_WAIT: u64 = 10;
+const MIN_SPEED: f64 = 0.1;
+const MAX_SPEED: f64 = 100.;
+
+const BUTTONS: &[(&str, i32)] = &[
+    ("left", MOUSE_BUTTON_LEFT),
+    ("right", MOUSE_BUTTON_RIGHT),
+    ("wheel", MOUSE_BUTTON_WHEEL),
+    ("back", MOUSE_BUTTON_BACK),
+    ("forward", MOUSE_BUTTON_FORWARD),
+];
+/// The buttons take the mask above its three type bits.
+const MAX_BUTTON_BITS: u32 = 29;
+
+const TYPES: &[(&str, i32)] = &[
+    ("move", MOUSE_TYPE_MOVE),
+    ("down", MOUSE_TYPE_DOWN),
+    ("up", MOUSE_TYPE_UP),
+    ("wheel", MOUSE_TYPE_WHEEL),
+    ("trackpad", MOUSE_TYPE_TRACKPAD),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// Milliseconds at speed 1.
+    Wait(u64),
+    Key(KeyEvent),
+    Mouse(MouseEvent),
+}
+
+impl Step {
+    pub fn message(&self) -> Option<Message> {
+        let mut msg = Message::new();
+        match self {
+            Self::Wait(_) => return None,
+            Self::Key(evt) => msg.set_key_event(evt.clone()),
+            Self::Mouse(evt) => msg.set_mouse_event(evt.clone()),
+        }
+        Some(msg)
+    }
+}
+
+/// How long to wait for `ms` of the macro when replayed at `speed`.
+fn scale(ms: u64, speed: f64) -> Duration {
+    let speed = if speed.is_finite() {
+        speed.clamp(MIN_SPEED, MAX_SPEED)
+    } else {
+        1.
+    };
+    Duration::from_secs_f64(ms as f64 / 1000. / speed)
+}
+
+/// Writes the key and mouse events sent to the peer as macro text.
+pub struct Recorder {
+    text: String,
+    last: Option<Instant>,
+    mode: KeyboardMode,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self {
+            text: "# recorded input macro\n".to_owned(),
+            last: None,
+            mode: KeyboardMode::Legacy,
+        }
+    }
+}
+
+impl Recorder {
+    pub fn record(&mut self, msg: &Message) {
+        match &msg.union {
+            Some(message::Union::KeyEvent(evt)) => self.key(evt),
+            Some(message::Union::MouseEvent(evt)) => self.mouse(evt),
+            _ => {}
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.text
+    }
+
+    fn wait(&mut self) {
+        let now = Instant::now();
+        if let Some(last) = self.last {
+            let ms = now.duration_since(last).as_millis() as u64;
+            if ms >= MIN_WAIT {
+                writeln!(self.text, "wait {}", ms).ok();
+            }
+        }
+        self.last = Some(now);
+    }
+
+    fn key(&mut self, evt: &KeyEvent) {
+        let key = match &evt.union {
+            Some(key_event::Union::ControlKey(k)) => match k.enum_value() {
+                Ok(k) => format!("{:?}", k),
+                Err(_) => return,
+            },
+            Some(key_event::Union::Chr(n)) => format!("chr:{}", n),
+            Some(key_event::Union::Unicode(n)) => format!("unicode:{}", n),
+            Some(key_event::Union::Win2winHotkey(n)) => format!("hotkey:{}", n),
+            Some(key_event::Union::Seq(_)) => String::new(),
+            _ => return,
+        };
+        self.wait();
+        let mode = evt.mode.enum_value_or_default();
+        if mode != self.mode {
+            writeln!(self.text, "mode {}", format!("{:?}", mode).to_lowercase()).ok();
+            self.mode = mode;
+        }
+        if let Some(key_event::Union::Seq(seq)) = &evt.union {
+            let mut seq = seq
+                .replace('\\', "\\\\")
+                .replace('\n', "\\n")
+                .replace('$', "$$");
+            // editors strip trailing spaces
+            if seq.ends_with(' ') {
+                seq.insert(seq.len() - 1, '\\');
+            }
+            writeln!(self.text, "text {}", seq).ok();
+            return;
+        }
+        let action = if evt.press {
+            "press"
+        } else if evt.down {
+            "down"
+        } else {
+            "up"
+        };
+        write!(self.text, "key {} {}", action, key).ok();
+        self.modifiers(&evt.modifiers);
+    }
+
+    fn mouse(&mut self, evt: &MouseEvent) {
+        let typ = match TYPES.iter().find(|x| x.1 == evt.mask & 0x7) {
+            Some(typ) => typ.0,
+            None => return,
+        };
+        self.wait();
+        write!(self.text, "mouse {}", typ).ok();
+        let buttons = evt.mask >> 3;
+        if buttons != 0 {
+            let names: Vec<String> = (0..MAX_BUTTON_BITS)
+                .filter(|i| buttons & 1 << i != 0)
+                .map(|i| match BUTTONS.iter().find(|x| x.1 == 1 << i) {
+                    Some(x) => x.0.to_owned(),
+                    None => format!("btn:{}", i),
+                })
+                .collect();
+            write!(self.text, " {}", names.join("+")).ok();
+        }
+        write!(self.text, " {} {}", evt.x, evt.y).ok();
+        self.modifiers(&evt.modifiers);
+    }
+
+    fn modifiers(&mut self, modifiers: &[EnumOrUnknown<ControlKey>]) {
+        let names: Vec<String> = modifiers
+            .iter()
+            .filter_map(|m| m.enum_value().ok())
+            .map(|m| format!("{:?}", m))
+            .collect();
+        if !names.is_empty() {
+            write!(self.text, " {}", names.join("+")).ok();
+        }
+        self.text.push('\n');
+    }
+}
+
+/// Reads macro text, `vars` take precedence over the `var` lines.
+pub fn parse(text: &str, vars: &HashMap<String, String>) -> ResultType<Vec<Step>> {
+    let mut vars = vars.clone();
+    let given: Vec<String> = vars.keys().cloned().collect();
+    let mut mode = KeyboardMode::Legacy;
+    let mut steps = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_start();
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_line(line, &mut vars, &given, &mut mode) {
+            Ok(Some(step)) => steps.push(step),
+            Ok(None) => {}
+            Err(err) => bail!("line {}: {}", i + 1, err),
+        }
+    }
+    Ok(steps)
+}
+
+fn parse_line(
+    line: &str,
+    vars: &mut HashMap<String, String>,
+    given: &[String],
+    mode: &mut KeyboardMode,
+) -> ResultType<Option<Step>> {
+    if let Some(var) = line.strip_prefix("var ") {
+        let (name, value) = match var.split_once('=') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => bail!("var needs NAME = VALUE"),
+        };
+        if name.is_empty() {
+            bail!("var needs a name");
+        }
+        if !given.iter().any(|x| x == name) {
+            let value = substitute(value, vars)?;
+            vars.insert(name.to_owned(), value);
+        }
+        return Ok(None);
+    }
+    let line = substitute(line, vars)?;
+    // `rest` is not trimmed, spaces are part of a text
+    let (cmd, rest) = match line.split_once(' ') {
+        Some((cmd, rest)) => (cmd, rest),
+        None => (line.trim_end(), ""),
+    };
+    let args: Vec<&str> = rest.split_whitespace().collect();
+    let step = match cmd {
+        "wait" => Step::Wait(number(rest)?),
+        "mode" => {
+            *mode = match KeyboardMode::VALUES
+                .iter()
+                .find(|m| format!("{:?}", m).eq_ignore_ascii_case(rest.trim()))
+            {
+                Some(m) => *m,
+                None => bail!("unknown mode {}", rest.trim()),
+            };
+            return Ok(None);
+        }
+        "text" => {
+            let mut evt = KeyEvent::new();
+            evt.set_seq(unescape(rest));
+            evt.mode = (*mode).into();
+            Step::Key(evt)
+        }
+        "key" => {
+            if args.len() < 2 || args.len() > 3 {
+                bail!("key needs down|up|press KEY [MODS]");
+            }
+            let mut evt = KeyEvent::new();
+            match args[0] {
+                "down" => evt.down = true,
+                "up" => {}
+                "press" => evt.press = true,
+                action => bail!("unknown key action {}", action),
+            }
+            match args[1].split_once(':') {
+                Some(("chr", n)) => evt.set_chr(number(n)?),
+                Some(("unicode", n)) => evt.set_unicode(number(n)?),
+                Some(("hotkey", n)) => evt.set_win2win_hotkey(number(n)?),
+                _ => evt.set_control_key(control_key(args[1])?),
+            }
+            if let Some(mods) = args.get(2) {
+                evt.modifiers = modifiers(mods)?;
+            }
+            evt.mode = (*mode).into();
+            Step::Key(evt)
+        }
+        "mouse" => {
+            let typ = match args.first().and_then(|a| TYPES.iter().find(|x| x.0 == *a)) {
+                Some(typ) => typ.1,
+                None => bail!("mouse needs move|down|up|wheel|trackpad"),
+            };
+            let mut args = &args[1..];
+            let mut buttons = 0;
+            if let Some(a) = args.first() {
+                if a.parse::<i32>().is_err() {
+                    for b in a.split('+') {
+                        buttons |= match BUTTONS.iter().find(|x| x.0 == b) {
+                            Some(x) => x.1,
+                            None => match b.strip_prefix("btn:") {
+                                Some(n) => match number::<u32>(n)? {
+                                    n if n < MAX_BUTTON_BITS => 1 << n,
+                                    _ => bail!("mouse button bit {} out of range", n),
+                                },
+                                None => bail!("unknown mouse button {}", b),
+                            },
+                        };
+                    }
+                    args = &args[1..];
+                }
+            }
+            if args.len() < 2 || args.len() > 3 {
+                bail!("mouse needs TYPE [BUTTONS] X Y [MODS]");
+            }
+            let mut evt = MouseEvent {
+                mask: buttons << 3 | typ,
+                x: number(args[0])?,
+                y: number(args[1])?,
+                ..Default::default()
+            };
+            if let Some(mods) = args.get(2) {
+                evt.modifiers = modifiers(mods)?;
+            }
+            Step::Mouse(evt)
+        }
+        _ => bail!("unknown statement {}", cmd),
+    };
+    Ok(Some(step))
+}
+
+fn number<T: std::str::FromStr>(s: &str) -> ResultType<T> {
+    match s.trim().parse() {
+        Ok(n) => Ok(n),
+        Err(_) => bail!("{} is not a number", s),
+    }
+}
+
+fn control_key(name: &str) -> ResultType<ControlKey> {
+    match ControlKey::VALUES
+        .iter()
+        .find(|k| format!("{:?}", k) == name)
+    {
+        Some(k) => Ok(*k),
+        None => bail!("unknown key {}", name),
+    }
+}
+
+fn modifiers(s: &str) -> ResultType<Vec<EnumOrUnknown<ControlKey>>> {
+    s.split('+').map(|x| Ok(control_key(x)?.into())).collect()
+}
+
+fn substitute(line: &str, vars: &HashMap<String, String>) -> ResultType<String> {
+    let mut res = String::new();
+    let mut rest = line;
+    while let Some(i) = rest.find('$') {
+        res.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        if let Some(r) = rest.strip_prefix('$') {
+            res.push('$');
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('{') {
+            let end = match r.find('}') {
+                Some(end) => end,
+                None => bail!("unclosed ${{"),
+            };
+            match vars.get(&r[..end]) {
+                Some(value) => res.push_str(value),
+                None => bail!("unknown variable {}", &r[..end]),
+            }
+            rest = &r[end + 1..];
+        } else {
+            res.push('$');
+        }
+    }
+    res.push_str(rest);
+    Ok(res)
+}
+
+fn unescape(s: &str) -> String {
+    let mut res = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => res.push('\n'),
+                Some(c) => res.push(c),
+                None => res.push('\\'),
+            }
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
+/// Keys and buttons held down by a replay, so they are let go of when it ends.
+#[derive(Default)]
+struct Held {
+    keys: Vec<KeyEvent>,
+    buttons: Vec<MouseEvent>,
+}
+
+impl Held {
+    fn update(&mut self, step: &Step) {
+        match step {
+            Step::Key(evt) if !evt.press => {
+                self.keys.retain(|k| k.union != evt.union);
+                if evt.down {
+                    self.keys.push(evt.clone());
+                }
+            }
+            Step::Mouse(evt) => {
+                let buttons = evt.mask >> 3;
+                match evt.mask & 0x7 {
+                    MOUSE_TYPE_DOWN => {
+                        self.buttons.retain(|b| b.mask >> 3 != buttons);
+                        self.buttons.push(evt.clone());
+                    }
+                    MOUSE_TYPE_UP => self.buttons.retain(|b| b.mask >> 3 != buttons),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The steps letting go of all held.
+    fn release(&mut self) -> Vec<Step> {
+        let mut steps: Vec<Step> = self
+            .keys
+            .drain(..)
+            .map(|mut evt| {
+                evt.down = false;
+                Step::Key(evt)
+            })
+            .collect();
+        steps.extend(self.buttons.drain(..).map(|mut evt| {
+            evt.mask = evt.mask & !0x7 | MOUSE_TYPE_UP;
+            Step::Mouse(evt)
+        }));
+        steps
+    }
+}
+
+/// Sends the events of `steps` in time with `send`, until they are done or
+/// `stop` is set. False if stopped.
+pub fn play(steps: &[Step], speed: f64, stop: &AtomicBool, mut send: impl FnMut(Message)) -> bool {
+    let mut held = Held::default();
+    let mut done = true;
+    'steps: for step in steps {
+        if stop.load(Ordering::SeqCst) {
+            done = false;
+            break;
+        }
+        if let Step::Wait(ms) = step {
+            let end = Instant::now() + scale(*ms, speed);
+            while let Some(left) = end.checked_duration_since(Instant::now()) {
+                if stop.load(Ordering::SeqCst) {
+                    done = false;
+                    break 'steps;
+                }
+                std::thread::sleep(left.min(Duration::from_millis(50)));
+            }
+        }
+        held.update(step);
+        if let Some(msg) = step.message() {
+            send(msg);
+        }
+    }
+    for step in held.release() {
+        if let Some(msg) = step.message() {
+            send(msg);
+        }
+    }
+    done
+}
+
+/// Saved macros go to `<config dir>/macros/<name>.macro`.
+pub fn path(name: &str) -> ResultType<PathBuf> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == ' ')
+    {
+        bail!("invalid macro name {:?}", name);
+    }
+    Ok(Config::path("macros").join(format!("{}.{}", name, EXT)))
+}
+
+pub fn save(name: &str, text: &str) -> ResultType<()> {
+    let path = path(name)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&path, text)?;
+    log::info!("Macro {} saved to {:?}", name, path);
+    Ok(())
+}
+
+pub fn load(name: &str) -> ResultType<String> {
+    Ok(std::fs::read_to_string(path(name)?)?)
+}
+
+pub fn remove(name: &str) -> ResultType<()> {
+    Ok(std::fs::remove_file(path(name)?)?)
+}
+
+/// Names of the saved macros, sorted.
+pub fn list() -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(Config::path("macros"))
+        .map(|dir| {
+            dir.filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.extension() == Some(EXT.as_ref()))
+                .filter_map(|p| Some(p.file_stem()?.to_string_lossy().into_owned()))
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut rec = Recorder::default();
+        let mut evt = KeyEvent::new();
+        evt.set_control_key(ControlKey::Return);
+        evt.press = true;
+        evt.modifiers = vec![ControlKey::Control.into(), ControlKey::Shift.into()];
+        let mut msgs = vec![Step::Key(evt)];
+        let mut evt = KeyEvent::new();
+        evt.set_chr(38);
+        evt.down = true;
+        evt.mode = KeyboardMode::Map.into();
+        msgs.push(Step::Key(evt));
+        let mut evt = KeyEvent::new();
+        evt.set_seq(" a\\b\n$x ".to_owned());
+        evt.mode = KeyboardMode::Map.into();
+        msgs.push(Step::Key(evt));
+        msgs.push(Step::Mouse(MouseEvent {
+            mask: MOUSE_BUTTON_LEFT << 3 | MOUSE_TYPE_DOWN,
+            x: 10,
+            y: -20,
+            ..Default::default()
+        }));
+        msgs.push(Step::Mouse(MouseEvent {
+            mask: (MOUSE_BUTTON_RIGHT | 32) << 3 | MOUSE_TYPE_UP,
+            x: 10,
+            y: -20,
+            ..Default::default()
+        }));
+        msgs.push(Step::Mouse(MouseEvent {
+            mask: MOUSE_TYPE_WHEEL,
+            x: 0,
+            y: -1,
+            modifiers: vec![ControlKey::Alt.into()],
+            ..Default::default()
+        }));
+        for step in msgs.iter() {
+            rec.record(&step.message().unwrap());
+        }
+        let text = rec.finish();
+        assert_eq!(
+            text,
+            "# recorded input macro\n\
+             key press Return Control+Shift\n\
+             mode map\n\
+             key down chr:38\n\
+             text  a\\\\b\\n$$x\\ \n\
+             mouse down left 10 -20\n\
+             mouse up right+btn:5 10 -20\n\
+             mouse wheel 0 -1 Alt\n"
+        );
+        assert_eq!(parse(&text, &Default::default()).unwrap(), msgs);
+        let err = parse("mouse down middle 0 0", &Default::default()).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: unknown mouse button middle");
+        let steps = parse("mouse down left+btn:7 1 2", &Default::default()).unwrap();
+        assert_eq!(
+            steps,
+            vec![Step::Mouse(MouseEvent {
+                mask: (MOUSE_BUTTON_LEFT | 1 << 7) << 3 | MOUSE_TYPE_DOWN,
+                x: 1,
+                y: 2,
+                ..Default::default()
+            })]
+        );
+        let err = parse("mouse down btn:29 0 0", &Default::default()).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: mouse button bit 29 out of range");
+    }
+
+    #[test]
+    fn test_vars() {
+        let text = "var user = admin\nvar greeting = hi ${user}\ntext ${greeting}\nwait ${delay}";
+        let mut vars = HashMap::new();
+        assert!(parse(text, &vars).is_err());
+        vars.insert("delay".to_owned(), "250".to_owned());
+        vars.insert("user".to_owned(), "bob".to_owned());
+        let steps = parse(text, &vars).unwrap();
+        let mut evt = KeyEvent::new();
+        evt.set_seq("hi bob".to_owned());
+        assert_eq!(steps, vec![Step::Key(evt), Step::Wait(250)]);
+        let err = parse("wait 1\nkey hold Return", &vars).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: unknown key action hold");
+    }
+
+    #[test]
+    fn test_play() {
+        let steps = parse(
+            "key press Tab\nwait 1000\nkey press Tab",
+            &Default::default(),
+        )
+        .unwrap();
+        let stop = AtomicBool::new(false);
+        let mut n = 0;
+        let start = Instant::now();
+        assert!(play(&steps, 100., &stop, |_| n += 1));
+        assert_eq!(n, 2);
+        assert!(start.elapsed() < Duration::from_millis(500));
+        let steps = parse(
+            "key down Shift\nmouse down left 1 2\nwait 1000",
+            &Default::default(),
+        )
+        .unwrap();
+        let mut msgs = Vec::new();
+        let stop = std::sync::Arc::new(AtomicBool::new(false));
+        let stop2 = stop.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            stop2.store(true, Ordering::SeqCst);
+        });
+        assert!(!play(&steps, 1., &stop, |msg| msgs.push(msg)));
+        assert!(start.elapsed() < Duration::from_millis(800));
+        let mut held = Held::default();
+        for step in steps.iter() {
+            held.update(step);
+        }
+        let released: Vec<Message> = held.release().iter().filter_map(|x| x.message()).collect();
+        assert_eq!(msgs[2..], released[..]);
+        assert_eq!(released.len(), 2);
+        assert!(held.release().is_empty());
+    }
+}
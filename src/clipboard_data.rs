@@ -0,0 +1,579 @@
+// Clipboard contents in several formats, named by MIME type. A copy goes to
+// peers which know them in `ClipboardChunk`s, so a large image does not hold up
+// the messages behind it, and its text to older peers in one `Clipboard`.
+//
+// arboard reads text and images and writes text, HTML and images. HTML and RTF
+// are read, and RTF written, with the platform API on Windows and macOS and
+// over X11 on Linux.
+
+#[cfg(target_os = "linux")]
+mod x11;
+
+use crate::common::create_clipboard_msg;
+use hbb_common::{
+    compress::{compress, decompress},
+    get_version_number, log,
+    message_proto::*,
+};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+pub const MIME_TEXT: &str = "text/plain";
+pub const MIME_HTML: &str = "text/html";
+pub const MIME_RTF: &str = "text/rtf";
+pub const MIME_PNG: &str = "image/png";
+
+const CHUNK_SIZE: usize = 256 * 1024;
+/// Largest copy synced, as sent.
+const MAX_SIZE: usize = 64 * 1024 * 1024;
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+/// Whether the peer puts `ClipboardChunk`s together, older ones only take
+/// `Clipboard`.
+#[inline]
+pub fn can_send_chunks(version: i64) -> bool {
+    version >= get_version_number("1.2.2")
+}
+
+/// Whether `msg` goes to a peer of `version`: of the two forms of a copy, only
+/// the one the peer takes.
+pub fn is_for_peer(msg: &Message, version: i64) -> bool {
+    match &msg.union {
+        Some(message::Union::Clipboard(_)) => !can_send_chunks(version),
+        Some(message::Union::ClipboardChunk(_)) => can_send_chunks(version),
+        _ => true,
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ClipboardFormat {
+    pub mime_type: String,
+    pub content: Vec<u8>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ClipboardData {
+    pub formats: Vec<ClipboardFormat>,
+}
+
+/// The last copy seen on or written to one side, so a synced copy is not
+/// taken for a new one and sent back.
+#[derive(Debug, Default)]
+pub struct LastClipboard {
+    // of what was read from the clipboard, see `RawClipboard::hash`
+    pub hash: u64,
+    pub data: ClipboardData,
+}
+
+impl ClipboardData {
+    /// Empty formats are left out.
+    pub fn push(&mut self, mime_type: &str, content: Vec<u8>) {
+        if !content.is_empty() {
+            self.formats.push(ClipboardFormat {
+                mime_type: mime_type.to_owned(),
+                content,
+            });
+        }
+    }
+
+    pub fn get(&self, mime_type: &str) -> Option<&[u8]> {
+        self.formats
+            .iter()
+            .find(|x| x.mime_type == mime_type)
+            .map(|x| &x.content[..])
+    }
+
+    pub fn get_text(&self) -> Option<&str> {
+        self.get(MIME_TEXT)
+            .and_then(|x| std::str::from_utf8(x).ok())
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.formats.is_empty()
+    }
+
+    pub fn from_legacy(clipboard: &Clipboard) -> Self {
+        let content = if clipboard.compress {
+            decompress(&clipboard.content)
+        } else {
+            clipboard.content.to_vec()
+        };
+        let mut data = Self::default();
+        if std::str::from_utf8(&content).is_ok() {
+            data.push(MIME_TEXT, content);
+        }
+        data
+    }
+
+    /// Both forms of the copy, the text in a `Clipboard` first and then all
+    /// formats in chunks, see `is_for_peer`.
+    pub fn to_messages(&self) -> Vec<Message> {
+        let mut msgs = Vec::new();
+        if let Some(text) = self.get_text() {
+            msgs.push(create_clipboard_msg(text.to_owned()));
+        }
+        let formats: Vec<(&str, bool, Vec<u8>)> = self
+            .formats
+            .iter()
+            .map(|f| {
+                // images come compressed
+                if !f.mime_type.starts_with("image/") {
+                    let compressed = compress(&f.content);
+                    if compressed.len() < f.content.len() {
+                        return (&f.mime_type[..], true, compressed);
+                    }
+                }
+                (&f.mime_type[..], false, f.content.clone())
+            })
+            .collect();
+        let size: usize = formats.iter().map(|x| x.2.len()).sum();
+        if size > MAX_SIZE {
+            log::warn!(
+                "Clipboard of {} bytes is over the limit of {} bytes, not synced",
+                size,
+                MAX_SIZE
+            );
+            return msgs;
+        }
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let n = formats.len();
+        for (i, (mime_type, compress, content)) in formats.iter().enumerate() {
+            let chunks: Vec<&[u8]> = content.chunks(CHUNK_SIZE).collect();
+            for (j, data) in chunks.iter().enumerate() {
+                let mut msg = Message::new();
+                msg.set_clipboard_chunk(ClipboardChunk {
+                    id,
+                    mime_type: mime_type.to_string(),
+                    compress: *compress,
+                    total_size: content.len() as _,
+                    offset: (j * CHUNK_SIZE) as _,
+                    data: data.to_vec().into(),
+                    last: i + 1 == n && j + 1 == chunks.len(),
+                    ..Default::default()
+                });
+                msgs.push(msg);
+            }
+        }
+        msgs
+    }
+}
+
+struct PendingFormat {
+    mime_type: String,
+    compress: bool,
+    total_size: usize,
+    content: Vec<u8>,
+}
+
+/// Puts the chunks of a copy back together, one per connection.
+#[derive(Default)]
+pub struct ChunkAssembler {
+    id: u32,
+    formats: Vec<PendingFormat>,
+    size: usize,
+    // a chunk was out of place, the rest of the copy is dropped
+    broken: bool,
+}
+
+impl ChunkAssembler {
+    /// Returns the copy with its last chunk.
+    pub fn add(&mut self, chunk: ClipboardChunk) -> Option<ClipboardData> {
+        if chunk.id != self.id {
+            *self = Self {
+                id: chunk.id,
+                ..Default::default()
+            };
+        }
+        if !self.broken {
+            if let Err(err) = self.append(&chunk) {
+                log::warn!("Clipboard copy {} dropped: {}", chunk.id, err);
+                self.broken = true;
+            }
+        }
+        if !chunk.last {
+            return None;
+        }
+        let formats = std::mem::take(&mut self.formats);
+        let broken = std::mem::replace(&mut self.broken, false);
+        self.size = 0;
+        if broken || formats.iter().any(|x| x.content.len() != x.total_size) {
+            return None;
+        }
+        let mut data = ClipboardData::default();
+        for f in formats {
+            let content = if f.compress {
+                decompress(&f.content)
+            } else {
+                f.content
+            };
+            data.push(&f.mime_type, content);
+        }
+        Some(data)
+    }
+
+    fn append(&mut self, chunk: &ClipboardChunk) -> Result<(), &'static str> {
+        let offset = chunk.offset as usize;
+        if offset == 0 {
+            let total_size = chunk.total_size as usize;
+            if total_size > MAX_SIZE - self.size {
+                return Err("too large");
+            }
+            if self
+                .formats
+                .last()
+                .map(|x| x.content.len() != x.total_size)
+                .unwrap_or(false)
+            {
+                return Err("format cut short");
+            }
+            self.size += total_size;
+            self.formats.push(PendingFormat {
+                mime_type: chunk.mime_type.clone(),
+                compress: chunk.compress,
+                total_size,
+                content: Vec::with_capacity(total_size),
+            });
+        }
+        let format = match self.formats.last_mut() {
+            Some(format) => format,
+            None => return Err("no format started"),
+        };
+        if format.mime_type != chunk.mime_type || format.content.len() != offset {
+            return Err("chunk out of order");
+        }
+        if offset + chunk.data.len() > format.total_size {
+            return Err("chunk beyond its format");
+        }
+        format.content.extend_from_slice(&chunk.data);
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub use desktop::*;
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod desktop {
+    use super::*;
+    use crate::common::ClipboardContext;
+    use arboard::ImageData;
+    use hbb_common::ResultType;
+    use std::{
+        borrow::Cow,
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+    };
+
+    /// The clipboard as read, images not encoded yet.
+    #[derive(Default)]
+    pub struct RawClipboard {
+        text: Option<String>,
+        image: Option<ImageData<'static>>,
+        // HTML and RTF
+        others: Vec<ClipboardFormat>,
+    }
+
+    impl RawClipboard {
+        pub fn read(ctx: &mut ClipboardContext) -> Self {
+            Self {
+                text: ctx.get_text().ok().filter(|x| !x.is_empty()),
+                image: ctx.get_image().ok(),
+                others: platform::read(),
+            }
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.text.is_none() && self.image.is_none() && self.others.is_empty()
+        }
+
+        /// Cheap next to encoding the image, for telling a new copy.
+        pub fn hash(&self) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            self.text.hash(&mut hasher);
+            if let Some(image) = &self.image {
+                (image.width, image.height).hash(&mut hasher);
+                image.bytes.hash(&mut hasher);
+            }
+            for f in &self.others {
+                f.mime_type.hash(&mut hasher);
+                f.content.hash(&mut hasher);
+            }
+            hasher.finish()
+        }
+
+        pub fn into_data(self) -> ClipboardData {
+            let mut data = ClipboardData::default();
+            if let Some(text) = self.text {
+                data.push(MIME_TEXT, text.into_bytes());
+            }
+            data.formats.extend(self.others);
+            if let Some(image) = self.image {
+                let mut png = Vec::new();
+                match repng::encode(&mut png, image.width as _, image.height as _, &image.bytes) {
+                    Ok(()) => data.push(MIME_PNG, png),
+                    Err(err) => log::error!("Failed to encode clipboard image: {}", err),
+                }
+            }
+            data
+        }
+    }
+
+    /// arboard replaces the whole clipboard with each write, so it writes the
+    /// richest of HTML with text, text or the image, the platform adds the rest.
+    pub fn write(ctx: &mut ClipboardContext, data: &ClipboardData) -> ResultType<()> {
+        let text = data.get_text();
+        let html = data
+            .get(MIME_HTML)
+            .and_then(|x| std::str::from_utf8(x).ok());
+        if let Some(html) = html {
+            ctx.set_html(html, text)?;
+        } else if let Some(text) = text {
+            // ctx.set_text may crash if content is empty
+            ctx.set_text(text)?;
+        } else if let Some(png) = data.get(MIME_PNG) {
+            let image =
+                image::load_from_memory_with_format(png, image::ImageFormat::Png)?.to_rgba8();
+            ctx.set_image(ImageData {
+                width: image.width() as _,
+                height: image.height() as _,
+                bytes: Cow::Owned(image.into_raw()),
+            })?;
+        } else {
+            return Ok(());
+        }
+        platform::add(data);
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    mod platform {
+        use super::*;
+        use clipboard_win::raw;
+
+        // registered clipboard format names
+        const HTML_FORMAT: &str = "HTML Format";
+        const RTF_FORMAT: &str = "Rich Text Format";
+        const PNG_FORMAT: &str = "PNG";
+
+        fn get(name: &str) -> Option<Vec<u8>> {
+            let format = raw::register_format(name)?.get();
+            let mut content = Vec::new();
+            raw::get_vec(format, &mut content).ok()?;
+            // the data ends with the terminating zero
+            while content.last() == Some(&0) {
+                content.pop();
+            }
+            Some(content)
+        }
+
+        pub fn read() -> Vec<ClipboardFormat> {
+            let mut data = ClipboardData::default();
+            let _clip = match clipboard_win::Clipboard::new_attempts(10) {
+                Ok(clip) => clip,
+                Err(_) => return data.formats,
+            };
+            if let Some(html) = get(HTML_FORMAT).and_then(|x| html_fragment(&x)) {
+                data.push(MIME_HTML, html);
+            }
+            if let Some(rtf) = get(RTF_FORMAT) {
+                data.push(MIME_RTF, rtf);
+            }
+            data.formats
+        }
+
+        pub fn add(data: &ClipboardData) {
+            let _clip = match clipboard_win::Clipboard::new_attempts(10) {
+                Ok(clip) => clip,
+                Err(_) => return,
+            };
+            let mut others = vec![(RTF_FORMAT, data.get(MIME_RTF))];
+            // arboard only writes the image when there is no text
+            if data.get_text().is_some() || data.get(MIME_HTML).is_some() {
+                others.push((PNG_FORMAT, data.get(MIME_PNG)));
+            }
+            for (name, content) in others {
+                if let (Some(format), Some(content)) = (raw::register_format(name), content) {
+                    raw::set_without_clear(format.get(), content).ok();
+                }
+            }
+        }
+
+        /// The copied part of CF_HTML, which puts a header of offsets before
+        /// the document.
+        fn html_fragment(cf_html: &[u8]) -> Option<Vec<u8>> {
+            let header = String::from_utf8_lossy(cf_html);
+            let offset = |key: &str| -> Option<usize> {
+                let start = header.find(key)? + key.len();
+                header[start..]
+                    .split(|c: char| !c.is_ascii_digit())
+                    .next()?
+                    .parse()
+                    .ok()
+            };
+            let (start, end) = (offset("StartFragment:")?, offset("EndFragment:")?);
+            cf_html.get(start..end).map(|x| x.to_vec())
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use super::*;
+
+            #[test]
+            fn test_html_fragment() {
+                let cf_html = b"Version:0.9\r\nStartHTML:0000000105\r\nEndHTML:0000000177\r\n\
+                    StartFragment:0000000137\r\nEndFragment:0000000145\r\n\
+                    <html><body><!--StartFragment--><b>x</b><!--EndFragment--></body></html>\0";
+                assert_eq!(html_fragment(cf_html), Some(b"<b>x</b>".to_vec()));
+                assert_eq!(html_fragment(b"<b>x</b>"), None);
+            }
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    mod platform {
+        use super::*;
+        use cocoa::{
+            appkit::{
+                NSPasteboard, NSPasteboardTypeHTML, NSPasteboardTypePNG, NSPasteboardTypeRTF,
+            },
+            base::{id, nil},
+            foundation::{NSArray, NSData},
+        };
+
+        unsafe fn get(pasteboard: id, ty: id) -> Option<Vec<u8>> {
+            let data = pasteboard.dataForType(ty);
+            if data == nil {
+                return None;
+            }
+            let bytes = data.bytes() as *const u8;
+            Some(std::slice::from_raw_parts(bytes, data.length() as _).to_vec())
+        }
+
+        pub fn read() -> Vec<ClipboardFormat> {
+            let mut data = ClipboardData::default();
+            unsafe {
+                let pasteboard = NSPasteboard::generalPasteboard(nil);
+                if let Some(html) = get(pasteboard, NSPasteboardTypeHTML) {
+                    data.push(MIME_HTML, html);
+                }
+                if let Some(rtf) = get(pasteboard, NSPasteboardTypeRTF) {
+                    data.push(MIME_RTF, rtf);
+                }
+            }
+            data.formats
+        }
+
+        pub fn add(data: &ClipboardData) {
+            let mut others = vec![(unsafe { NSPasteboardTypeRTF }, data.get(MIME_RTF))];
+            // arboard only writes the image when there is no text
+            if data.get_text().is_some() || data.get(MIME_HTML).is_some() {
+                others.push((unsafe { NSPasteboardTypePNG }, data.get(MIME_PNG)));
+            }
+            unsafe {
+                let pasteboard = NSPasteboard::generalPasteboard(nil);
+                for (ty, content) in others {
+                    if let Some(content) = content {
+                        pasteboard.addTypes_owner(NSArray::arrayWithObject(nil, ty), nil);
+                        let data = NSData::dataWithBytes_length_(
+                            nil,
+                            content.as_ptr() as _,
+                            content.len() as _,
+                        );
+                        pasteboard.setData_forType(data, ty);
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    use super::x11 as platform;
+
+    // Neither read nor written here, arboard writes HTML.
+    #[cfg(not(any(windows, target_os = "macos", target_os = "linux")))]
+    mod platform {
+        use super::*;
+
+        pub fn read() -> Vec<ClipboardFormat> {
+            Vec::new()
+        }
+
+        pub fn add(_data: &ClipboardData) {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_chunks(msgs: &[Message]) -> Vec<ClipboardChunk> {
+        msgs.iter()
+            .filter_map(|msg| match &msg.union {
+                Some(message::Union::ClipboardChunk(chunk)) => Some(chunk.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chunks() {
+        let mut data = ClipboardData::default();
+        data.push(MIME_TEXT, b"hello".to_vec());
+        let msgs = data.to_messages();
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(ClipboardData::from_legacy(msgs[0].clipboard()), data);
+        let mut assembler = ChunkAssembler::default();
+        assert_eq!(
+            assembler.add(msgs[1].clipboard_chunk().clone()),
+            Some(data.clone())
+        );
+
+        let png: Vec<u8> = (0..CHUNK_SIZE * 2 + 100).map(|x| x as u8).collect();
+        data.push(MIME_HTML, b"<b>hello</b>".to_vec());
+        data.push(MIME_PNG, png);
+        let msgs = data.to_messages();
+        assert!(msgs[0].has_clipboard());
+        let chunks = get_chunks(&msgs);
+        assert_eq!(chunks.len(), 5);
+        assert_eq!(chunks.iter().filter(|x| x.last).count(), 1);
+        for chunk in chunks[..4].iter() {
+            assert_eq!(assembler.add(chunk.clone()), None);
+        }
+        assert_eq!(assembler.add(chunks[4].clone()), Some(data.clone()));
+
+        // A lost chunk drops the copy, the next copy comes through.
+        for (i, chunk) in chunks.iter().enumerate() {
+            if i != 3 {
+                assert_eq!(assembler.add(chunk.clone()), None);
+            }
+        }
+        let mut chunks = get_chunks(&data.to_messages());
+        chunks[1].total_size = MAX_SIZE as _;
+        let copies: Vec<_> = chunks
+            .into_iter()
+            .filter_map(|x| assembler.add(x))
+            .collect();
+        assert!(copies.is_empty());
+        let copies: Vec<_> = get_chunks(&data.to_messages())
+            .into_iter()
+            .filter_map(|x| assembler.add(x))
+            .collect();
+        assert_eq!(copies, vec![data]);
+    }
+
+    #[test]
+    fn test_is_for_peer() {
+        let mut data = ClipboardData::default();
+        data.push(MIME_TEXT, b"hello".to_vec());
+        let msgs = data.to_messages();
+        let old = get_version_number("1.2.0");
+        let new = get_version_number("1.2.2");
+        let sent = |version| -> Vec<&Message> {
+            msgs.iter().filter(|x| is_for_peer(x, version)).collect()
+        };
+        assert!(matches!(&sent(old)[..], [msg] if msg.has_clipboard()));
+        assert!(matches!(&sent(new)[..], [msg] if msg.has_clipboard_chunk()));
+        assert!(is_for_peer(&Message::new(), old));
+    }
+}
@@ -0,0 +1,276 @@
+// HTML and RTF over the X11 CLIPBOARD selection, which arboard does not read
+// and, for RTF, does not write. Under Wayland this reaches X11 clients through
+// Xwayland only.
+
+use super::{ClipboardData, ClipboardFormat, MIME_HTML, MIME_PNG, MIME_RTF, MIME_TEXT};
+use crate::clipboard_watcher::poll_readable;
+use hbb_common::{bail, log, ResultType};
+use std::{
+    os::unix::io::AsRawFd,
+    time::{Duration, Instant},
+};
+use x11rb::{
+    connection::{Connection, RequestConnection},
+    protocol::{xproto::*, Event},
+    rust_connection::RustConnection,
+    wrapper::ConnectionExt as _,
+    COPY_DEPTH_FROM_PARENT, COPY_FROM_PARENT, CURRENT_TIME, NONE,
+};
+
+/// How long the owner of the clipboard gets to answer.
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+struct Atoms {
+    clipboard: Atom,
+    targets: Atom,
+    incr: Atom,
+    property: Atom,
+    utf8_string: Atom,
+    text_utf8: Atom,
+    html: Atom,
+    rtf: Atom,
+    png: Atom,
+}
+
+struct Client {
+    conn: RustConnection,
+    window: Window,
+    atoms: Atoms,
+}
+
+impl Client {
+    fn new() -> ResultType<Self> {
+        let (conn, screen) = x11rb::connect(None)?;
+        let root = conn.setup().roots[screen].root;
+        let window = conn.generate_id()?;
+        conn.create_window(
+            COPY_DEPTH_FROM_PARENT,
+            window,
+            root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            COPY_FROM_PARENT,
+            &CreateWindowAux::new(),
+        )?;
+        let intern = |name: &str| -> ResultType<Atom> {
+            Ok(conn.intern_atom(false, name.as_bytes())?.reply()?.atom)
+        };
+        let atoms = Atoms {
+            clipboard: intern("CLIPBOARD")?,
+            targets: intern("TARGETS")?,
+            incr: intern("INCR")?,
+            property: intern("RUSTDESK_CLIPBOARD")?,
+            utf8_string: intern("UTF8_STRING")?,
+            text_utf8: intern("text/plain;charset=utf-8")?,
+            html: intern(MIME_HTML)?,
+            rtf: intern(MIME_RTF)?,
+            png: intern(MIME_PNG)?,
+        };
+        Ok(Self {
+            conn,
+            window,
+            atoms,
+        })
+    }
+
+    /// The clipboard converted to `target`, none if the owner has no such
+    /// format. Transfers too large for one property are left out.
+    fn get(&self, target: Atom) -> ResultType<Option<Vec<u8>>> {
+        self.conn.convert_selection(
+            self.window,
+            self.atoms.clipboard,
+            target,
+            self.atoms.property,
+            CURRENT_TIME,
+        )?;
+        self.conn.flush()?;
+        let deadline = Instant::now() + READ_TIMEOUT;
+        loop {
+            let event = match self.conn.poll_for_event()? {
+                Some(event) => event,
+                None => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() || !poll_readable(self.conn.stream().as_raw_fd(), left)? {
+                        bail!("no answer from the clipboard owner");
+                    }
+                    continue;
+                }
+            };
+            match event {
+                Event::SelectionNotify(event) if event.target == target => {
+                    if event.property == NONE {
+                        return Ok(None);
+                    }
+                    let reply = self
+                        .conn
+                        .get_property(
+                            true,
+                            self.window,
+                            self.atoms.property,
+                            AtomEnum::ANY,
+                            0,
+                            u32::MAX / 4,
+                        )?
+                        .reply()?;
+                    if reply.type_ == self.atoms.incr {
+                        return Ok(None);
+                    }
+                    return Ok(Some(reply.value));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn targets(&self) -> ResultType<Vec<Atom>> {
+        Ok(match self.get(self.atoms.targets)? {
+            Some(value) => value
+                .chunks_exact(4)
+                .map(|x| u32::from_ne_bytes([x[0], x[1], x[2], x[3]]))
+                .collect(),
+            None => Vec::new(),
+        })
+    }
+}
+
+pub fn read() -> Vec<ClipboardFormat> {
+    let mut data = ClipboardData::default();
+    if let Err(err) = read_into(&mut data) {
+        log::debug!("Failed to read HTML and RTF from the clipboard: {}", err);
+    }
+    data.formats
+}
+
+fn read_into(data: &mut ClipboardData) -> ResultType<()> {
+    let client = Client::new()?;
+    let owner = client
+        .conn
+        .get_selection_owner(client.atoms.clipboard)?
+        .reply()?
+        .owner;
+    if owner == NONE {
+        return Ok(());
+    }
+    let targets = client.targets()?;
+    for (mime_type, target) in [(MIME_HTML, client.atoms.html), (MIME_RTF, client.atoms.rtf)] {
+        if targets.contains(&target) {
+            if let Some(content) = client.get(target)? {
+                data.push(mime_type, content);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// arboard owns the clipboard after writing text, HTML or an image. A copy
+/// with RTF is taken over and served whole here, until another copy.
+pub fn add(data: &ClipboardData) {
+    if data.get(MIME_RTF).is_none() {
+        return;
+    }
+    // owned before returning, so reading the clipboard back gets this copy
+    let client = match own() {
+        Ok(client) => client,
+        Err(err) => {
+            log::debug!("Failed to add RTF to the clipboard: {}", err);
+            return;
+        }
+    };
+    let data = data.clone();
+    std::thread::spawn(move || {
+        if let Err(err) = serve(&client, &data) {
+            log::debug!("Failed to serve the clipboard: {}", err);
+        }
+    });
+}
+
+fn own() -> ResultType<Client> {
+    let client = Client::new()?;
+    client
+        .conn
+        .set_selection_owner(client.window, client.atoms.clipboard, CURRENT_TIME)?;
+    let owner = client
+        .conn
+        .get_selection_owner(client.atoms.clipboard)?
+        .reply()?
+        .owner;
+    if owner != client.window {
+        bail!("the clipboard was not taken over");
+    }
+    Ok(client)
+}
+
+fn serve(client: &Client, data: &ClipboardData) -> ResultType<()> {
+    let atoms = &client.atoms;
+    let text = data.get(MIME_TEXT);
+    let formats = [
+        (atoms.utf8_string, text),
+        (atoms.text_utf8, text),
+        (atoms.html, data.get(MIME_HTML)),
+        (atoms.rtf, data.get(MIME_RTF)),
+        (atoms.png, data.get(MIME_PNG)),
+    ];
+    // larger ones would need INCR transfers
+    let max_size = client.conn.maximum_request_bytes().saturating_sub(64);
+    let formats: Vec<(Atom, &[u8])> = formats
+        .iter()
+        .filter_map(|(target, content)| content.map(|x| (*target, x)))
+        .filter(|(_, content)| content.len() <= max_size)
+        .collect();
+    let mut targets = vec![atoms.targets];
+    targets.extend(formats.iter().map(|x| x.0));
+    loop {
+        let request = match client.conn.wait_for_event()? {
+            Event::SelectionRequest(request) if request.selection == atoms.clipboard => request,
+            Event::SelectionClear(_) => return Ok(()),
+            _ => continue,
+        };
+        // old clients leave the property out
+        let mut property = if request.property == NONE {
+            request.target
+        } else {
+            request.property
+        };
+        if request.target == atoms.targets {
+            client.conn.change_property32(
+                PropMode::REPLACE,
+                request.requestor,
+                property,
+                AtomEnum::ATOM,
+                &targets,
+            )?;
+        } else {
+            match formats.iter().find(|x| x.0 == request.target) {
+                Some((target, content)) => {
+                    client.conn.change_property8(
+                        PropMode::REPLACE,
+                        request.requestor,
+                        property,
+                        *target,
+                        content,
+                    )?;
+                }
+                None => property = NONE,
+            }
+        }
+        client.conn.send_event(
+            false,
+            request.requestor,
+            EventMask::NO_EVENT,
+            SelectionNotifyEvent {
+                response_type: SELECTION_NOTIFY_EVENT,
+                sequence: 0,
+                time: request.time,
+                requestor: request.requestor,
+                selection: request.selection,
+                target: request.target,
+                property,
+            },
+        )?;
+        client.conn.flush()?;
+    }
+}
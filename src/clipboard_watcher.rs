@@ -0,0 +1,120 @@
+// Tells when the clipboard changes, so it is only read then. Linux has change
+// notifications over XFixes or the wlroots data-control protocol, Windows and
+// macOS count the copies. Where there is neither, the clipboard is polled.
+
+#[cfg(target_os = "linux")]
+mod wayland;
+#[cfg(target_os = "linux")]
+mod x11;
+
+use hbb_common::log;
+use std::time::Duration;
+
+/// Watchers stay on the thread which made them.
+pub trait ClipboardWatcher {
+    /// Waits for a change at most `timeout`. False if the clipboard surely did
+    /// not change, true if it may have.
+    fn wait(&mut self, timeout: Duration) -> bool;
+}
+
+/// Says the clipboard may have changed after each interval.
+pub struct Poller;
+
+impl ClipboardWatcher for Poller {
+    fn wait(&mut self, timeout: Duration) -> bool {
+        std::thread::sleep(timeout);
+        true
+    }
+}
+
+/// Checks the number the system bumps with each copy after each interval,
+/// which is cheap next to reading the clipboard.
+#[cfg(any(windows, target_os = "macos"))]
+#[derive(Default)]
+pub struct Counter {
+    last: Option<i64>,
+}
+
+#[cfg(any(windows, target_os = "macos"))]
+impl Counter {
+    #[cfg(windows)]
+    fn count() -> Option<i64> {
+        clipboard_win::raw::seq_num().map(|x| x.get() as _)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn count() -> Option<i64> {
+        use cocoa::{appkit::NSPasteboard, base::nil};
+        Some(unsafe { NSPasteboard::generalPasteboard(nil).changeCount() } as _)
+    }
+}
+
+#[cfg(any(windows, target_os = "macos"))]
+impl ClipboardWatcher for Counter {
+    fn wait(&mut self, timeout: Duration) -> bool {
+        std::thread::sleep(timeout);
+        let count = Self::count();
+        // without access to the count, the clipboard may have changed
+        count.is_none() || std::mem::replace(&mut self.last, count) != count
+    }
+}
+
+/// The watcher for where the clipboard is read from: arboard reads over
+/// data-control where the compositor has it, else over X11.
+pub fn new() -> Box<dyn ClipboardWatcher> {
+    #[cfg(any(windows, target_os = "macos"))]
+    {
+        log::info!("Clipboard copies counted for changes");
+        return Box::new(Counter::default());
+    }
+    #[cfg(target_os = "linux")]
+    {
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            match wayland::Watcher::new() {
+                Ok(watcher) => {
+                    log::info!("Clipboard changes watched over Wayland data-control");
+                    return Box::new(watcher);
+                }
+                Err(err) => log::debug!("No Wayland data-control: {}", err),
+            }
+        }
+        match x11::Watcher::new() {
+            Ok(watcher) => {
+                log::info!("Clipboard changes watched over XFixes");
+                return Box::new(watcher);
+            }
+            Err(err) => log::debug!("No XFixes: {}", err),
+        }
+    }
+    #[cfg(not(any(windows, target_os = "macos")))]
+    {
+        log::info!("Clipboard polled for changes");
+        Box::new(Poller)
+    }
+}
+
+/// Whether `fd` became readable within `timeout`.
+#[cfg(target_os = "linux")]
+pub(crate) fn poll_readable(
+    fd: std::os::unix::io::RawFd,
+    timeout: Duration,
+) -> std::io::Result<bool> {
+    use hbb_common::libc;
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    match unsafe { libc::poll(&mut pollfd, 1, timeout.as_millis() as _) } {
+        -1 => {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                Ok(false)
+            } else {
+                Err(err)
+            }
+        }
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
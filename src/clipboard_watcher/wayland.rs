@@ -0,0 +1,86 @@
+use super::{poll_readable, ClipboardWatcher};
+use hbb_common::{log, ResultType};
+use std::{io, time::Duration};
+use wayland_client::{protocol::wl_seat::WlSeat, Display, EventQueue, GlobalManager, Main};
+use wayland_protocols::wlr::unstable::data_control::v1::client::{
+    zwlr_data_control_device_v1::{Event as DeviceEvent, ZwlrDataControlDeviceV1},
+    zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+};
+
+/// The data-control device gets a selection event whenever the clipboard
+/// changes. Compositors without the protocol, like GNOME, fail `new`.
+pub struct Watcher {
+    display: Display,
+    queue: EventQueue,
+    _device: Main<ZwlrDataControlDeviceV1>,
+}
+
+impl Watcher {
+    pub fn new() -> ResultType<Self> {
+        let display = Display::connect_to_env()?;
+        let mut queue = display.create_event_queue();
+        let attached = display.attach(queue.token());
+        let globals = GlobalManager::new(&attached);
+        queue.sync_roundtrip(&mut (), |_, _, _| {})?;
+        let seat = globals.instantiate_exact::<WlSeat>(1)?;
+        let manager = globals.instantiate_exact::<ZwlrDataControlManagerV1>(1)?;
+        let device = manager.get_data_device(&seat);
+        device.quick_assign(|_, event, mut data| {
+            if let DeviceEvent::Selection { id } = event {
+                if let Some(changed) = data.get::<bool>() {
+                    *changed = true;
+                }
+                // the content is read by arboard, not through this offer
+                if let Some(offer) = id {
+                    offer.destroy();
+                }
+            }
+        });
+        // the first selection event is the current clipboard
+        queue.sync_roundtrip(&mut false, |_, _, _| {})?;
+        Ok(Self {
+            display,
+            queue,
+            _device: device,
+        })
+    }
+
+    fn dispatch(&mut self) -> io::Result<bool> {
+        let mut changed = false;
+        // offers and their MIME types go to the fallback
+        self.queue.dispatch_pending(&mut changed, |_, _, _| {})?;
+        Ok(changed)
+    }
+
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        if self.dispatch()? {
+            return Ok(true);
+        }
+        self.display.flush()?;
+        if let Some(guard) = self.queue.prepare_read() {
+            if poll_readable(self.display.get_connection_fd(), timeout)? {
+                match guard.read_events() {
+                    Err(err) if err.kind() != io::ErrorKind::WouldBlock => return Err(err),
+                    _ => {}
+                }
+            } else {
+                guard.cancel();
+            }
+        }
+        self.dispatch()
+    }
+}
+
+impl ClipboardWatcher for Watcher {
+    fn wait(&mut self, timeout: Duration) -> bool {
+        match self.poll(timeout) {
+            Ok(changed) => changed,
+            Err(err) => {
+                // the compositor is gone, poll from now on
+                log::debug!("Failed to watch the clipboard: {}", err);
+                std::thread::sleep(timeout);
+                true
+            }
+        }
+    }
+}
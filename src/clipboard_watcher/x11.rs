@@ -0,0 +1,70 @@
+use super::{poll_readable, ClipboardWatcher};
+use hbb_common::{log, ResultType};
+use std::{os::unix::io::AsRawFd, time::Duration};
+use x11rb::{
+    connection::Connection,
+    protocol::{
+        xfixes::{ConnectionExt as _, SelectionEventMask},
+        xproto::ConnectionExt as _,
+        Event,
+    },
+    rust_connection::RustConnection,
+};
+
+/// XFixes sends an event whenever the owner of the CLIPBOARD selection
+/// changes, which every copy does.
+pub struct Watcher {
+    conn: RustConnection,
+}
+
+impl Watcher {
+    pub fn new() -> ResultType<Self> {
+        let (conn, screen) = x11rb::connect(None)?;
+        conn.xfixes_query_version(5, 0)?.reply()?;
+        let root = conn.setup().roots[screen].root;
+        let clipboard = conn.intern_atom(false, b"CLIPBOARD")?.reply()?.atom;
+        conn.xfixes_select_selection_input(
+            root,
+            clipboard,
+            SelectionEventMask::SET_SELECTION_OWNER
+                | SelectionEventMask::SELECTION_WINDOW_DESTROY
+                | SelectionEventMask::SELECTION_CLIENT_CLOSE,
+        )?;
+        conn.flush()?;
+        Ok(Self { conn })
+    }
+
+    fn take_events(&self) -> ResultType<bool> {
+        let mut changed = false;
+        while let Some(event) = self.conn.poll_for_event()? {
+            if let Event::XfixesSelectionNotify(_) = event {
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+
+    fn poll(&self, timeout: Duration) -> ResultType<bool> {
+        if self.take_events()? {
+            return Ok(true);
+        }
+        if poll_readable(self.conn.stream().as_raw_fd(), timeout)? {
+            return self.take_events();
+        }
+        Ok(false)
+    }
+}
+
+impl ClipboardWatcher for Watcher {
+    fn wait(&mut self, timeout: Duration) -> bool {
+        match self.poll(timeout) {
+            Ok(changed) => changed,
+            Err(err) => {
+                // the X server is gone, poll from now on
+                log::debug!("Failed to watch the clipboard: {}", err);
+                std::thread::sleep(timeout);
+                true
+            }
+        }
+    }
+}
@@ -14,8 +14,9 @@ pub enum GrabState {
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub use arboard::Clipboard as ClipboardContext;
 
+use crate::clipboard_data::LastClipboard;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
-use hbb_common::compress::decompress;
+use crate::clipboard_data::{self, ClipboardData, RawClipboard};
 use hbb_common::{
     allow_err,
     compress::compress as compress_func,
@@ -69,7 +70,7 @@ pub mod input {
 }
 
 lazy_static::lazy_static! {
-    pub static ref CONTENT: Arc<Mutex<String>> = Default::default();
+    pub static ref CONTENT: Arc<Mutex<LastClipboard>> = Default::default();
     pub static ref SOFTWARE_UPDATE_URL: Arc<Mutex<String>> = Default::default();
 }
 
@@ -160,25 +161,37 @@ pub fn create_clipboard_msg(content: String) -> Message {
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub fn check_clipboard(
     ctx: &mut ClipboardContext,
-    old: Option<&Arc<Mutex<String>>>,
-) -> Option<Message> {
+    old: Option<&Arc<Mutex<LastClipboard>>>,
+) -> Option<Vec<Message>> {
+    check_clipboard_data(ctx, old).map(|data| data.to_messages())
+}
+
+/// The new copy, if the clipboard changed since `old`.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn check_clipboard_data(
+    ctx: &mut ClipboardContext,
+    old: Option<&Arc<Mutex<LastClipboard>>>,
+) -> Option<ClipboardData> {
     let side = if old.is_none() { "host" } else { "client" };
     let old = if let Some(old) = old { old } else { &CONTENT };
-    let content = {
+    let raw = {
         let _lock = ARBOARD_MTX.lock().unwrap();
-        ctx.get_text()
+        RawClipboard::read(ctx)
     };
-    if let Ok(content) = content {
-        if content.len() < 2_000_000 && !content.is_empty() {
-            let changed = content != *old.lock().unwrap();
-            if changed {
-                log::info!("{} update found on {}", CLIPBOARD_NAME, side);
-                *old.lock().unwrap() = content.clone();
-                return Some(create_clipboard_msg(content));
-            }
-        }
+    if raw.is_empty() {
+        return None;
     }
-    None
+    let hash = raw.hash();
+    if hash == old.lock().unwrap().hash {
+        return None;
+    }
+    log::info!("{} update found on {}", CLIPBOARD_NAME, side);
+    let data = raw.into_data();
+    *old.lock().unwrap() = LastClipboard {
+        hash,
+        data: data.clone(),
+    };
+    Some(data)
 }
 
 /// Set sound input device.
@@ -230,29 +243,31 @@ pub fn get_default_sound_input() -> Option<String> {
 }
 
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
-pub fn update_clipboard(clipboard: Clipboard, old: Option<&Arc<Mutex<String>>>) {
-    let content = if clipboard.compress {
-        decompress(&clipboard.content)
-    } else {
-        clipboard.content.into()
-    };
-    if let Ok(content) = String::from_utf8(content) {
-        if content.is_empty() {
-            // ctx.set_text may crash if content is empty
-            return;
-        }
-        match ClipboardContext::new() {
-            Ok(mut ctx) => {
-                let side = if old.is_none() { "host" } else { "client" };
-                let old = if let Some(old) = old { old } else { &CONTENT };
-                *old.lock().unwrap() = content.clone();
-                let _lock = ARBOARD_MTX.lock().unwrap();
-                allow_err!(ctx.set_text(content));
-                log::debug!("{} updated on {}", CLIPBOARD_NAME, side);
-            }
-            Err(err) => {
-                log::error!("Failed to create clipboard context: {}", err);
+pub fn update_clipboard(clipboard: Clipboard, old: Option<&Arc<Mutex<LastClipboard>>>) {
+    update_clipboard_data(ClipboardData::from_legacy(&clipboard), old);
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn update_clipboard_data(data: ClipboardData, old: Option<&Arc<Mutex<LastClipboard>>>) {
+    if data.is_empty() {
+        return;
+    }
+    match ClipboardContext::new() {
+        Ok(mut ctx) => {
+            let side = if old.is_none() { "host" } else { "client" };
+            let old = if let Some(old) = old { old } else { &CONTENT };
+            let _lock = ARBOARD_MTX.lock().unwrap();
+            if let Err(err) = clipboard_data::write(&mut ctx, &data) {
+                log::error!("Failed to update {}: {}", CLIPBOARD_NAME, err);
+                return;
             }
+            // read back, the system may have changed what was written a little
+            let hash = RawClipboard::read(&mut ctx).hash();
+            *old.lock().unwrap() = LastClipboard { hash, data };
+            log::debug!("{} updated on {}", CLIPBOARD_NAME, side);
+        }
+        Err(err) => {
+            log::error!("Failed to create clipboard context: {}", err);
         }
     }
 }
@@ -266,6 +266,40 @@ pub fn core_main() -> Option<Vec<String>> {
                 }
             }
             return None;
+        } else if args[0] == "--hash-password" {
+            // For the password of an account in the users file: --hash-password <password>
+            if args.len() == 2 {
+                println!("{}", crate::server::auth::hash_password(&args[1]));
+            }
+            return None;
+        } else if args[0] == "--verify-audit" {
+            // --verify-audit <file>..., the rotated files first from the oldest
+            if args.len() >= 2 {
+                let content: std::io::Result<String> =
+                    args[1..].iter().map(std::fs::read_to_string).collect();
+                match content.map(|content| crate::server::audit::verify(&content)) {
+                    Ok(Ok(Ok(n))) => println!("{n} records, chain intact"),
+                    Ok(Ok(Err(line))) => println!("Chain broken at line {line}"),
+                    Ok(Err(err)) => println!("{err}"),
+                    Err(err) => println!("{err}"),
+                }
+            }
+            return None;
+        } else if args[0] == "--lockouts" || args[0] == "--clear-lockouts" {
+            if crate::platform::is_installed() && is_root() {
+                let clear = if args[0] == "--clear-lockouts" {
+                    Some(args.get(1).cloned().unwrap_or_default())
+                } else {
+                    None
+                };
+                match crate::ipc::lockouts(clear) {
+                    Ok(v) => println!("{}", serde_json::to_string_pretty(&v).unwrap_or_default()),
+                    Err(err) => println!("{err}"),
+                }
+            } else {
+                println!("Installation and administrative privileges required!");
+            }
+            return None;
         } else if args[0] == "--get-id" {
             if crate::platform::is_installed() && is_root() {
                 println!("{}", crate::ipc::get_id());
@@ -372,6 +406,11 @@ pub fn core_main() -> Option<Vec<String>> {
                 println!("Installation and administrative privileges required!");
             }
             return None;
+        } else if args[0] == "--recording" {
+            if let Err(err) = handle_recording(&args[1..]) {
+                println!("{}", err);
+            }
+            return None;
         } else if args[0] == "--check-hwcodec-config" {
             #[cfg(feature = "hwcodec")]
             scrap::hwcodec::check_config();
@@ -555,3 +594,52 @@ fn is_root() -> bool {
     #[allow(unreachable_code)]
     crate::platform::is_root()
 }
+
+/// Headless review of recorded sessions, see `scrap::playback`.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn handle_recording(args: &[String]) -> hbb_common::ResultType<()> {
+    use scrap::playback::Playback;
+    const USAGE: &str = "Usage:
+  --recording info <file>
+  --recording keyframes <file>
+  --recording cut <file> <from-ms> <to-ms> <output>
+  --recording frame <file> <at-ms> <output.png>";
+    if args.len() < 2 {
+        hbb_common::bail!("{}", USAGE);
+    }
+    let mut playback = Playback::open(&args[1])?;
+    match (args[0].as_str(), args.len()) {
+        ("info", 2) => {
+            let info = serde_json::json!({
+                "codec": playback.format.to_string(),
+                "width": playback.width,
+                "height": playback.height,
+                "frames": playback.samples().len(),
+                "keyframes": playback.keyframes().count(),
+                "duration_ms": playback.duration(),
+            });
+            println!("{}", info);
+        }
+        ("keyframes", 2) => {
+            for sample in playback.keyframes() {
+                println!("{}", sample.pts);
+            }
+        }
+        ("cut", 5) => {
+            let n = playback.export_range(args[2].parse()?, args[3].parse()?, &args[4])?;
+            println!("{} frames written to {}", n, args[4]);
+        }
+        ("frame", 4) => {
+            let rgb = playback.export_frame(args[2].parse()?)?;
+            repng::encode(
+                std::fs::File::create(&args[3])?,
+                rgb.w as _,
+                rgb.h as _,
+                &rgb.raw,
+            )?;
+            println!("frame saved to {}", args[3]);
+        }
+        _ => hbb_common::bail!("{}", USAGE),
+    }
+    Ok(())
+}
@@ -16,7 +16,7 @@ use hbb_common::allow_err;
 use hbb_common::{
     config::{self, LocalConfig, PeerConfig, PeerInfoSerde},
     fs, log,
-    message_proto::KeyboardMode,
+    message_proto::{KeyboardMode, PenEvent, TouchContact},
     ResultType,
 };
 use std::{
@@ -388,6 +388,65 @@ pub fn session_input_string(session_id: SessionID, value: String) {
     }
 }
 
+pub fn session_start_macro_recording(session_id: SessionID) {
+    if let Some(session) = SESSIONS.read().unwrap().get(&session_id) {
+        session.start_macro_recording();
+    }
+}
+
+pub fn session_stop_macro_recording(session_id: SessionID) -> String {
+    if let Some(session) = SESSIONS.read().unwrap().get(&session_id) {
+        session.stop_macro_recording()
+    } else {
+        "".to_owned()
+    }
+}
+
+/// `vars` is a JSON object of strings. Returns the error, empty if the replay started.
+pub fn session_play_macro(session_id: SessionID, text: String, vars: String, speed: f64) -> String {
+    let vars = serde_json::from_str::<HashMap<String, String>>(&vars).unwrap_or_default();
+    if let Some(session) = SESSIONS.read().unwrap().get(&session_id) {
+        if let Err(err) = session.play_macro(&text, &vars, speed) {
+            return err.to_string();
+        }
+    }
+    "".to_owned()
+}
+
+pub fn session_stop_macro(session_id: SessionID) {
+    if let Some(session) = SESSIONS.read().unwrap().get(&session_id) {
+        session.stop_macro();
+    }
+}
+
+pub fn session_is_macro_playing(session_id: SessionID) -> SyncReturn<bool> {
+    if let Some(session) = SESSIONS.read().unwrap().get(&session_id) {
+        SyncReturn(session.is_macro_playing())
+    } else {
+        SyncReturn(false)
+    }
+}
+
+pub fn main_get_macros() -> String {
+    serde_json::to_string(&crate::client::macros::list()).unwrap_or_default()
+}
+
+pub fn main_load_macro(name: String) -> String {
+    crate::client::macros::load(&name).unwrap_or_default()
+}
+
+/// Returns the error, empty if saved.
+pub fn main_save_macro(name: String, text: String) -> String {
+    match crate::client::macros::save(&name, &text) {
+        Ok(()) => "".to_owned(),
+        Err(err) => err.to_string(),
+    }
+}
+
+pub fn main_remove_macro(name: String) {
+    hbb_common::allow_err!(crate::client::macros::remove(&name));
+}
+
 // chat_client_mode
 pub fn session_send_chat(session_id: SessionID, text: String) {
     if let Some(session) = SESSIONS.read().unwrap().get(&session_id) {
@@ -489,12 +548,48 @@ pub fn session_cancel_job(session_id: SessionID, act_id: i32) {
     }
 }
 
+pub fn session_pause_job(session_id: SessionID, act_id: i32, paused: bool) {
+    if let Some(session) = SESSIONS.read().unwrap().get(&session_id) {
+        session.pause_job(act_id, paused);
+    }
+}
+
+pub fn session_set_job_rate_limit(session_id: SessionID, act_id: i32, rate_limit: i32) {
+    if let Some(session) = SESSIONS.read().unwrap().get(&session_id) {
+        session.set_job_rate_limit(act_id, rate_limit);
+    }
+}
+
 pub fn session_create_dir(session_id: SessionID, act_id: i32, path: String, is_remote: bool) {
     if let Some(session) = SESSIONS.read().unwrap().get(&session_id) {
         session.create_dir(act_id, path, is_remote);
     }
 }
 
+pub fn session_rename_file(
+    session_id: SessionID,
+    act_id: i32,
+    path: String,
+    new_path: String,
+    is_remote: bool,
+) {
+    if let Some(session) = SESSIONS.read().unwrap().get(&session_id) {
+        session.rename_file(act_id, path, new_path, is_remote);
+    }
+}
+
+pub fn session_copy_file(
+    session_id: SessionID,
+    act_id: i32,
+    path: String,
+    new_path: String,
+    is_remote: bool,
+) {
+    if let Some(session) = SESSIONS.read().unwrap().get(&session_id) {
+        session.copy_file(act_id, path, new_path, is_remote);
+    }
+}
+
 pub fn session_read_local_dir_sync(
     _session_id: SessionID,
     path: String,
@@ -1127,6 +1222,10 @@ pub fn session_send_pointer(session_id: SessionID, msg: String) {
         let ctrl = m.get("ctrl").is_some();
         let shift = m.get("shift").is_some();
         let command = m.get("command").is_some();
+        let get_i32 =
+            |v: &serde_json::Value, k: &str| v.get(k).and_then(|x| x.as_i64()).unwrap_or(0) as i32;
+        let get_bool =
+            |v: &serde_json::Value, k: &str| v.get(k).and_then(|x| x.as_bool()).unwrap_or(false);
         if let Some(touch_event) = m.get("touch") {
             if let Some(scale) = touch_event.get("scale") {
                 if let Some(session) = SESSIONS.read().unwrap().get(&session_id) {
@@ -1135,6 +1234,38 @@ pub fn session_send_pointer(session_id: SessionID, msg: String) {
                     }
                 }
             }
+            if let Some(contacts) = touch_event.get("contacts").and_then(|x| x.as_array()) {
+                if let Some(session) = SESSIONS.read().unwrap().get(&session_id) {
+                    let contacts = contacts
+                        .iter()
+                        .map(|c| TouchContact {
+                            id: get_i32(c, "id"),
+                            x: get_i32(c, "x"),
+                            y: get_i32(c, "y"),
+                            pressure: get_i32(c, "pressure"),
+                            ..Default::default()
+                        })
+                        .collect();
+                    session.send_touch_contacts(contacts, alt, ctrl, shift, command);
+                }
+            }
+        }
+        if let Some(pen) = m.get("pen") {
+            if let Some(session) = SESSIONS.read().unwrap().get(&session_id) {
+                let pen = PenEvent {
+                    in_range: get_bool(pen, "in_range"),
+                    touching: get_bool(pen, "touching"),
+                    x: get_i32(pen, "x"),
+                    y: get_i32(pen, "y"),
+                    pressure: get_i32(pen, "pressure"),
+                    tilt_x: get_i32(pen, "tilt_x"),
+                    tilt_y: get_i32(pen, "tilt_y"),
+                    eraser: get_bool(pen, "eraser"),
+                    buttons: get_i32(pen, "buttons") as _,
+                    ..Default::default()
+                };
+                session.send_pen(pen, alt, ctrl, shift, command);
+            }
         }
     }
 }
@@ -20,6 +20,7 @@ use hbb_common::{
     allow_err, bail, bytes,
     bytes_codec::BytesCodec,
     config::{self, Config, Config2},
+    fs,
     futures::StreamExt as _,
     futures_util::sink::SinkExt,
     log, password_security as password, timeout, tokio,
@@ -29,6 +30,7 @@ use hbb_common::{
 };
 
 use crate::rendezvous_mediator::RendezvousMediator;
+use crate::server::lockout::{self, LockoutInfo};
 
 // State with timestamp, because std::time::Instant cannot be serialized
 #[derive(Debug, Serialize, Deserialize, Copy, Clone)]
@@ -70,6 +72,9 @@ pub enum FS {
         file_num: i32,
         files: Vec<(String, u64)>,
         overwrite_detection: bool,
+        // of `files`, in the same order
+        #[serde(default)]
+        attrs: Vec<fs::FileAttrs>,
     },
     CancelWrite {
         id: i32,
@@ -79,6 +84,8 @@ pub enum FS {
         file_num: i32,
         data: Bytes,
         compressed: bool,
+        #[serde(default)]
+        hash: Bytes,
     },
     WriteDone {
         id: i32,
@@ -100,6 +107,28 @@ pub enum FS {
         file_size: u64,
         last_modified: u64,
         is_upload: bool,
+        #[serde(default)]
+        supports_delta: bool,
+    },
+    Rename {
+        id: i32,
+        path: String,
+        new_path: String,
+    },
+    Copy {
+        id: i32,
+        path: String,
+        new_path: String,
+        overwrite: bool,
+    },
+    Stat {
+        id: i32,
+        path: String,
+    },
+    // The peer overwrites an uploaded file, answered by the confirm to send it.
+    ConfirmOverwrite {
+        id: i32,
+        file_num: i32,
     },
 }
 
@@ -134,6 +163,38 @@ pub enum DataMouse {
     Refresh,
 }
 
+/// A finger on the touch screen, in the coordinates of the mouse.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct DataTouchContact {
+    pub id: i32,
+    pub x: i32,
+    pub y: i32,
+    pub pressure: i32,
+}
+
+/// The state of the pen, see `PenEvent`.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct DataPen {
+    pub in_range: bool,
+    pub touching: bool,
+    pub x: i32,
+    pub y: i32,
+    pub pressure: i32,
+    pub tilt_x: i32,
+    pub tilt_y: i32,
+    pub eraser: bool,
+    pub buttons: u32,
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "t", content = "c")]
+pub enum DataPointer {
+    /// All the fingers on the screen.
+    Touch(Vec<DataTouchContact>),
+    Pen(DataPen),
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "t", content = "c")]
 pub enum DataControl {
@@ -213,6 +274,8 @@ pub enum Data {
     KeyboardResponse(DataKeyboardResponse),
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     Mouse(DataMouse),
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    Pointer(DataPointer),
     Control(DataControl),
     Theme(String),
     Language(String),
@@ -231,6 +294,9 @@ pub enum Data {
     Plugin(Plugin),
     #[cfg(windows)]
     SyncWinCpuUsage(Option<f64>),
+    Lockouts(Option<Vec<LockoutInfo>>),
+    /// Clear the lockout of a key like `ip:1.2.3.4`, all if empty.
+    ClearLockouts(String),
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -434,6 +500,14 @@ async fn handle(data: Data, stream: &mut Connection) {
                 allow_err!(stream.send(&Data::Options(None)).await);
             }
         },
+        Data::Lockouts(_) => {
+            allow_err!(stream.send(&Data::Lockouts(Some(lockout::list()))).await);
+        }
+        Data::ClearLockouts(key) => {
+            let n = lockout::clear(&key);
+            log::info!("{} lockouts cleared", n);
+            allow_err!(stream.send(&Data::Lockouts(Some(lockout::list()))).await);
+        }
         Data::NatType(_) => {
             let t = Config::get_nat_type();
             allow_err!(stream.send(&Data::NatType(Some(t))).await);
@@ -812,6 +886,20 @@ pub async fn set_options(value: HashMap<String, String>) -> ResultType<()> {
     Ok(())
 }
 
+/// Lockouts of the running server, `clear` first clears a key or all if empty.
+#[tokio::main(flavor = "current_thread")]
+pub async fn lockouts(clear: Option<String>) -> ResultType<Vec<LockoutInfo>> {
+    let mut c = connect(1000, "").await?;
+    match clear {
+        Some(key) => c.send(&Data::ClearLockouts(key)).await?,
+        None => c.send(&Data::Lockouts(None)).await?,
+    }
+    if let Some(Data::Lockouts(Some(v))) = c.next_timeout(1000).await? {
+        return Ok(v);
+    }
+    bail!("No lockouts response");
+}
+
 #[inline]
 async fn get_nat_type_(ms_timeout: u64) -> ResultType<i32> {
     let mut c = connect(ms_timeout, "").await?;
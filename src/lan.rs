@@ -14,6 +14,8 @@ use hbb_common::{
     ResultType,
 };
 
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
 use std::{
     collections::{HashMap, HashSet},
     net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
@@ -22,32 +24,36 @@ use std::{
 
 type Message = RendezvousMessage;
 
+/// DNS-SD service type of the discovery responder, its port is the one pings
+/// go to.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+const MDNS_SERVICE_TYPE: &str = "_rustdesk._udp.local.";
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+const MDNS_BROWSE_TIME: std::time::Duration = std::time::Duration::from_secs(3);
+/// How often the advertised service is checked against the options.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+const MDNS_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+/// At most a /20 worth of hosts is pinged by unicast per discovery.
+const MAX_SCAN_HOSTS: usize = 4096;
+
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub(super) fn start_listening() -> ResultType<()> {
     let addr = SocketAddr::from(([0, 0, 0, 0], get_broadcast_port()));
     let socket = std::net::UdpSocket::bind(addr)?;
     socket.set_read_timeout(Some(std::time::Duration::from_millis(1000)))?;
     log::info!("lan discovery listener started");
+    let mut mdns = MdnsAdvertiser::default();
     loop {
+        mdns.refresh();
         let mut buf = [0; 2048];
         if let Ok((len, addr)) = socket.recv_from(&mut buf) {
             if let Ok(msg_in) = Message::parse_from_bytes(&buf[0..len]) {
                 match msg_in.union {
                     Some(rendezvous_message::Union::PeerDiscovery(p)) => {
-                        if p.cmd == "ping" && Config::get_option("enable-lan-discovery").is_empty()
-                        {
+                        if p.cmd == "ping" && is_discovery_enabled() {
                             if let Some(self_addr) = get_ipaddr_by_peer(&addr) {
                                 let mut msg_out = Message::new();
-                                let peer = PeerDiscovery {
-                                    cmd: "pong".to_owned(),
-                                    mac: get_mac(&self_addr),
-                                    id: Config::get_id(),
-                                    hostname: whoami::hostname(),
-                                    username: crate::platform::get_active_username(),
-                                    platform: whoami::platform().to_string(),
-                                    ..Default::default()
-                                };
-                                msg_out.set_peer_discovery(peer);
+                                msg_out.set_peer_discovery(local_peer(Some(&self_addr)));
                                 socket.send_to(&msg_out.write_to_bytes()?, addr).ok();
                             }
                         }
@@ -59,6 +65,201 @@ pub(super) fn start_listening() -> ResultType<()> {
     }
 }
 
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+#[inline]
+fn is_discovery_enabled() -> bool {
+    Config::get_option("enable-lan-discovery").is_empty()
+}
+
+/// The answer to a ping, `self_addr` is the address the ping came in on.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn local_peer(self_addr: Option<&IpAddr>) -> PeerDiscovery {
+    use crate::server::Connection;
+    let mut conn_types = vec![ConnType::DEFAULT_CONN.into()];
+    if Connection::permission("enable-file-transfer") {
+        conn_types.push(ConnType::FILE_TRANSFER.into());
+    }
+    if Connection::permission("enable-tunnel") {
+        conn_types.push(ConnType::PORT_FORWARD.into());
+        #[cfg(windows)]
+        conn_types.push(ConnType::RDP.into());
+    }
+    PeerDiscovery {
+        cmd: "pong".to_owned(),
+        mac: self_addr.map(get_mac).unwrap_or_default(),
+        id: Config::get_id(),
+        hostname: whoami::hostname(),
+        username: crate::platform::get_active_username(),
+        platform: whoami::platform().to_string(),
+        version: crate::VERSION.to_owned(),
+        has_password: hbb_common::password_security::permanent_enabled()
+            && !Config::get_permanent_password().is_empty(),
+        conn_types,
+        ..Default::default()
+    }
+}
+
+fn conn_type_names(conn_types: &[hbb_common::protobuf::EnumOrUnknown<ConnType>]) -> Vec<String> {
+    conn_types
+        .iter()
+        .filter_map(|x| x.enum_value().ok())
+        .map(|x| format!("{:?}", x))
+        .collect()
+}
+
+#[inline]
+fn now_secs() -> i64 {
+    hbb_common::get_time() / 1000
+}
+
+/// Advertises the discovery responder over mDNS while discovery is enabled,
+/// with the answer to a ping in the TXT record.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+#[derive(Default)]
+struct MdnsAdvertiser {
+    daemon: Option<ServiceDaemon>,
+    // full name and TXT properties of the registered service
+    registered: Option<(String, HashMap<String, String>)>,
+    last_refresh: Option<Instant>,
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+impl MdnsAdvertiser {
+    fn refresh(&mut self) {
+        if self
+            .last_refresh
+            .map(|x| x.elapsed() < MDNS_REFRESH_INTERVAL)
+            .unwrap_or(false)
+        {
+            return;
+        }
+        self.last_refresh = Some(Instant::now());
+        let properties = if is_discovery_enabled() {
+            Some(mdns_properties(&local_peer(None)))
+        } else {
+            None
+        };
+        if self.registered.as_ref().map(|x| &x.1) == properties.as_ref() {
+            return;
+        }
+        let fullname = properties
+            .as_ref()
+            .map(|x| format!("{}.{}", x["id"], MDNS_SERVICE_TYPE));
+        if let (Some((old, _)), Some(daemon)) = (self.registered.take(), self.daemon.as_ref()) {
+            // registering again under the same name updates the record
+            if Some(&old) != fullname.as_ref() {
+                allow_err!(daemon.unregister(&old));
+                log::info!("mDNS service {} unregistered", old);
+            }
+        }
+        if let Some(properties) = properties {
+            if let Err(err) = self.register(properties) {
+                log::error!("Failed to register mDNS service: {}", err);
+            }
+        }
+    }
+
+    fn register(&mut self, properties: HashMap<String, String>) -> ResultType<()> {
+        if self.daemon.is_none() {
+            self.daemon = Some(ServiceDaemon::new()?);
+        }
+        let id = properties["id"].clone();
+        let info = ServiceInfo::new(
+            MDNS_SERVICE_TYPE,
+            &id,
+            &format!("{}.local.", id),
+            (),
+            get_broadcast_port(),
+            properties.clone(),
+        )?
+        .enable_addr_auto();
+        let fullname = info.get_fullname().to_owned();
+        if let Some(daemon) = self.daemon.as_ref() {
+            daemon.register(info)?;
+        }
+        log::info!("mDNS service {} registered", fullname);
+        self.registered = Some((fullname, properties));
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn mdns_properties(peer: &PeerDiscovery) -> HashMap<String, String> {
+    HashMap::from([
+        ("id".to_owned(), peer.id.clone()),
+        ("hostname".to_owned(), peer.hostname.clone()),
+        ("username".to_owned(), peer.username.clone()),
+        ("platform".to_owned(), peer.platform.clone()),
+        ("version".to_owned(), peer.version.clone()),
+        (
+            "password".to_owned(),
+            if peer.has_password { "1" } else { "0" }.to_owned(),
+        ),
+        (
+            "conn".to_owned(),
+            conn_type_names(&peer.conn_types).join(","),
+        ),
+    ])
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn peer_from_mdns(info: &ServiceInfo) -> Option<config::DiscoveryPeer> {
+    let property = |key| {
+        info.get_property_val_str(key)
+            .unwrap_or_default()
+            .to_owned()
+    };
+    let id = property("id");
+    if id.is_empty() {
+        return None;
+    }
+    let conn = property("conn");
+    Some(config::DiscoveryPeer {
+        id,
+        username: property("username"),
+        hostname: property("hostname"),
+        platform: property("platform"),
+        online: true,
+        // no MAC over mDNS, a ping answer from the same address fills it
+        ip_mac: info
+            .get_addresses()
+            .iter()
+            .map(|ip| (ip.to_string(), "".to_owned()))
+            .collect(),
+        version: property("version"),
+        has_password: property("password") == "1",
+        conn_types: conn
+            .split(',')
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_owned())
+            .collect(),
+        last_seen: now_secs(),
+    })
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn browse_mdns(tx: UnboundedSender<config::DiscoveryPeer>) -> ResultType<()> {
+    let daemon = ServiceDaemon::new()?;
+    let receiver = daemon.browse(MDNS_SERVICE_TYPE)?;
+    let my_id = Config::get_id();
+    let deadline = Instant::now() + MDNS_BROWSE_TIME;
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        match receiver.recv_timeout(left) {
+            Ok(ServiceEvent::ServiceResolved(info)) => {
+                if let Some(peer) = peer_from_mdns(&info) {
+                    if peer.id != my_id {
+                        allow_err!(tx.send(peer));
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    allow_err!(daemon.shutdown());
+    Ok(())
+}
+
 #[tokio::main(flavor = "current_thread")]
 pub async fn discover() -> ResultType<()> {
     let sockets = send_query()?;
@@ -168,7 +369,7 @@ fn create_broadcast_sockets() -> Vec<UdpSocket> {
 }
 
 fn send_query() -> ResultType<Vec<UdpSocket>> {
-    let sockets = create_broadcast_sockets();
+    let mut sockets = create_broadcast_sockets();
     if sockets.is_empty() {
         bail!("Found no bindable ipv4 addresses");
     }
@@ -185,9 +386,64 @@ fn send_query() -> ResultType<Vec<UdpSocket>> {
         allow_err!(socket.send_to(&out, maddr));
     }
     log::info!("discover ping sent");
+    let targets = scan_targets(&config::Config::get_option("lan-discovery-subnets"));
+    if !targets.is_empty() {
+        let socket = UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))?;
+        for ip in targets.iter() {
+            allow_err!(socket.send_to(&out, SocketAddr::from((*ip, get_broadcast_port()))));
+        }
+        log::info!("discover ping sent to {} hosts", targets.len());
+        sockets.push(socket);
+    }
     Ok(sockets)
 }
 
+/// Hosts to ping by unicast, from ranges like `10.1.0.0/24, 10.2.3.4`.
+/// Broadcasts do not cross routers, peers on routed subnets are only found so.
+fn scan_targets(ranges: &str) -> Vec<Ipv4Addr> {
+    let mut targets = Vec::new();
+    for range in ranges
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|x| !x.is_empty())
+    {
+        let (network, prefix) = match parse_cidr(range) {
+            Some(v) => v,
+            None => {
+                log::error!("Invalid subnet to discover: {}", range);
+                continue;
+            }
+        };
+        let size = 1u64 << (32 - prefix);
+        // without the network and broadcast addresses
+        let hosts = if prefix >= 31 { 0..size } else { 1..size - 1 };
+        for i in hosts {
+            if targets.len() >= MAX_SCAN_HOSTS {
+                log::warn!("Only the first {} hosts are discovered", MAX_SCAN_HOSTS);
+                return targets;
+            }
+            targets.push(Ipv4Addr::from((network as u64 + i) as u32));
+        }
+    }
+    targets
+}
+
+fn parse_cidr(range: &str) -> Option<(u32, u32)> {
+    let (ip, prefix) = match range.split_once('/') {
+        Some((ip, prefix)) => (ip, prefix.parse::<u32>().ok()?),
+        None => (range, 32),
+    };
+    if prefix > 32 {
+        return None;
+    }
+    let ip = u32::from(ip.parse::<Ipv4Addr>().ok()?);
+    let mask = if prefix == 0 {
+        0
+    } else {
+        u32::MAX << (32 - prefix)
+    };
+    Some((ip & mask, prefix))
+}
+
 fn wait_response(
     socket: UdpSocket,
     timeout: Option<std::time::Duration>,
@@ -242,6 +498,10 @@ fn wait_response(
                                     hostname: p.hostname.clone(),
                                     platform: p.platform.clone(),
                                     online: true,
+                                    version: p.version.clone(),
+                                    has_password: p.has_password,
+                                    conn_types: conn_type_names(&p.conn_types),
+                                    last_seen: now_secs(),
                                 }));
                             }
                         }
@@ -269,6 +529,10 @@ fn spawn_wait_responses(sockets: Vec<UdpSocket>) -> UnboundedReceiver<config::Di
             ));
         });
     }
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    std::thread::spawn(move || {
+        allow_err!(browse_mdns(tx));
+    });
     rx
 }
 
@@ -277,6 +541,7 @@ async fn handle_received_peers(mut rx: UnboundedReceiver<config::DiscoveryPeer>)
     peers.iter_mut().for_each(|peer| {
         peer.online = false;
     });
+    config::LanPeers::expire(&mut peers, now_secs());
 
     let mut response_set = HashSet::new();
     let mut last_write_time = Instant::now() - std::time::Duration::from_secs(4);
@@ -288,7 +553,13 @@ async fn handle_received_peers(mut rx: UnboundedReceiver<config::DiscoveryPeer>)
                     if let Some(pos) = peers.iter().position(|x| x.is_same_peer(&peer) ) {
                         let peer1 = peers.remove(pos);
                         if in_response_set {
-                            peer.ip_mac.extend(peer1.ip_mac);
+                            // mDNS answers carry no MAC, keep the one of a ping answer
+                            for (ip, mac) in peer1.ip_mac {
+                                let entry = peer.ip_mac.entry(ip).or_default();
+                                if entry.is_empty() {
+                                    *entry = mac;
+                                }
+                            }
                             peer.online = true;
                         }
                     }
@@ -312,3 +583,26 @@ async fn handle_received_peers(mut rx: UnboundedReceiver<config::DiscoveryPeer>)
     crate::flutter_ffi::main_load_lan_peers();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_targets() {
+        assert_eq!(parse_cidr("10.1.2.3/24"), Some((0x0a010200, 24)));
+        assert_eq!(parse_cidr("10.1.2.3"), Some((0x0a010203, 32)));
+        assert_eq!(parse_cidr("0.0.0.0/0"), Some((0, 0)));
+        assert_eq!(parse_cidr("10.1.2.3/33"), None);
+        assert_eq!(parse_cidr("10.1.2/24"), None);
+
+        let targets = scan_targets("10.1.2.0/30, 10.1.3.7 bad 10.1.4.0/31");
+        let expected: Vec<Ipv4Addr> = ["10.1.2.1", "10.1.2.2", "10.1.3.7", "10.1.4.0", "10.1.4.1"]
+            .iter()
+            .map(|x| x.parse().unwrap())
+            .collect();
+        assert_eq!(targets, expected);
+        assert_eq!(scan_targets("10.0.0.0/8").len(), MAX_SCAN_HOSTS);
+        assert!(scan_targets("").is_empty());
+    }
+}
@@ -524,5 +524,18 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("exceed_max_devices", "管理的设备数已达到最大值"),
         ("Sync with recent sessions", "同步最近会话"),
         ("Sort tags", "对标签进行排序"),
+        ("Move", "移动"),
+        ("Copy", "复制"),
+        ("Please enter the new path", "请输入新路径"),
+        ("Pause", "暂停"),
+        ("Resume", "继续"),
+        ("Rate limit", "限速"),
+        ("rate_limit_tip", "KiB/s，0 为不限速"),
+        ("Record macro", "录制宏"),
+        ("Stop recording macro", "停止录制宏"),
+        ("Play macro", "回放宏"),
+        ("Stop macro", "停止回放宏"),
+        ("Save macro", "保存宏"),
+        ("Forward touch", "转发触摸"),
     ].iter().cloned().collect();
 }
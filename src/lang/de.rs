@@ -524,5 +524,12 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("exceed_max_devices", "Sie haben die maximale Anzahl der verwalteten Geräte erreicht."),
         ("Sync with recent sessions", "Synchronisierung mit den letzten Sitzungen"),
         ("Sort tags", "Tags sortieren"),
+        ("Move", ""),
+        ("Copy", ""),
+        ("Please enter the new path", ""),
+        ("Pause", ""),
+        ("Resume", ""),
+        ("Rate limit", ""),
+        ("rate_limit_tip", ""),
     ].iter().cloned().collect();
 }
@@ -73,5 +73,7 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("clipboard_wait_response_timeout_tip", "Timed out waiting for copy response."),
         ("logout_tip", "Are you sure you want to log out?"),
         ("exceed_max_devices", "You have reached the maximum number of managed devices."),
+        ("rate_limit_tip", "KiB/s, 0 for unlimited"),
+        ("record_event_text_tip", "Off by default. Without it, the events file of a recording only keeps the size of copied and chat text, not the text itself."),
         ].iter().cloned().collect();
 }
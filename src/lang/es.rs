@@ -524,5 +524,12 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("exceed_max_devices", "Has alcanzado el máximo número de dispositivos administrados."),
         ("Sync with recent sessions", "Sincronizar con sesiones recientes"),
         ("Sort tags", "Ordenar etiquetas"),
+        ("Move", ""),
+        ("Copy", ""),
+        ("Please enter the new path", ""),
+        ("Pause", ""),
+        ("Resume", ""),
+        ("Rate limit", ""),
+        ("rate_limit_tip", ""),
     ].iter().cloned().collect();
 }
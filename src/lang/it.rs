@@ -524,5 +524,12 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("exceed_max_devices", "Hai raggiunto il numero massimo di dispositivi gestibili."),
         ("Sync with recent sessions", "Sincronizza con le sessioni recenti"),
         ("Sort tags", "Ordina etichette"),
+        ("Move", ""),
+        ("Copy", ""),
+        ("Please enter the new path", ""),
+        ("Pause", ""),
+        ("Resume", ""),
+        ("Rate limit", ""),
+        ("rate_limit_tip", ""),
     ].iter().cloned().collect();
 }
@@ -524,5 +524,12 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("exceed_max_devices", "Het maximum aantal gecontroleerde apparaten is bereikt."),
         ("Sync with recent sessions", "Recente sessies synchroniseren"),
         ("Sort tags", "Labels sorteren"),
+        ("Move", ""),
+        ("Copy", ""),
+        ("Please enter the new path", ""),
+        ("Pause", ""),
+        ("Resume", ""),
+        ("Rate limit", ""),
+        ("rate_limit_tip", ""),
     ].iter().cloned().collect();
 }
@@ -524,5 +524,12 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("exceed_max_devices", "Достигнуто максимальне количество управляемых устройств."),
         ("Sync with recent sessions", "Синхронизация последних сессий"),
         ("Sort tags", "Сортировка меток"),
+        ("Move", ""),
+        ("Copy", ""),
+        ("Please enter the new path", ""),
+        ("Pause", ""),
+        ("Resume", ""),
+        ("Rate limit", ""),
+        ("rate_limit_tip", ""),
     ].iter().cloned().collect();
 }
@@ -524,5 +524,18 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("exceed_max_devices", ""),
         ("Sync with recent sessions", ""),
         ("Sort tags", ""),
+        ("Move", ""),
+        ("Copy", ""),
+        ("Please enter the new path", ""),
+        ("Pause", ""),
+        ("Resume", ""),
+        ("Rate limit", ""),
+        ("rate_limit_tip", ""),
+        ("Record macro", ""),
+        ("Stop recording macro", ""),
+        ("Play macro", ""),
+        ("Stop macro", ""),
+        ("Save macro", ""),
+        ("Forward touch", ""),
     ].iter().cloned().collect();
 }
@@ -44,6 +44,7 @@ mod lang;
 mod license;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 mod port_forward;
+mod tunnel;
 
 #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -58,6 +59,11 @@ mod ui_session_interface;
 
 mod hbbs_http;
 
+pub mod clipboard_data;
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod clipboard_watcher;
+
 #[cfg(windows)]
 pub mod clipboard_file;
 
@@ -42,23 +42,254 @@ fn main() {
     if !common::global_init() {
         return;
     }
-    use clap::App;
+    use clap::{arg, value_parser, ArgAction, Command};
     use hbb_common::log;
-    let args = format!(
-        "-p, --port-forward=[PORT-FORWARD-OPTIONS] 'Format: remote-id:local-port:remote-port[:remote-host]'
-        -c, --connect=[REMOTE_ID] 'test only'
-        -k, --key=[KEY] ''
-       -s, --server=[] 'Start server'",
-    );
-    let matches = App::new("rustdesk")
+    let matches = Command::new("rustdesk")
         .version(crate::VERSION)
         .author("CarrieZ Studio<info@rustdesk.com>")
         .about("CYMTV Remote command line tool")
-        .args_from_usage(&args)
+        .arg(
+            arg!(-p --"port-forward" <PORT_FORWARD_OPTIONS> "Format: remote-id:local-port:remote-port[:remote-host]")
+                .required(false),
+        )
+        .arg(arg!(--udp "Forward UDP datagrams instead of TCP with --port-forward"))
+        .arg(
+            arg!(--"udp-idle-timeout" <SECS> "Seconds before an idle UDP flow is dropped")
+                .value_parser(value_parser!(u32))
+                .required(false),
+        )
+        .arg(
+            arg!(--"udp-bind" <IP> "Local address to receive UDP datagrams on, 127.0.0.1 by default")
+                .value_parser(value_parser!(std::net::IpAddr))
+                .required(false),
+        )
+        .arg(
+            arg!(-r --"reverse-forward" <REVERSE_FORWARD_OPTIONS> "Format: remote-id:remote-port:local-port[:local-host]")
+                .required(false),
+        )
+        .arg(
+            arg!(-f --"forward-profile" <FILE> "TOML file with many port-forward rules to one peer")
+                .required(false),
+        )
+        .arg(arg!(-k --key <KEY> "").required(false).global(true))
+        .arg(arg!(--password <PASSWORD> "Password of the peer, never prompted for if missing").required(false).global(true))
+        .arg(arg!(--account <NAME> "Log in to a named account of the peer").required(false).global(true))
+        .arg(arg!(-s --server "Start server"))
+        .subcommand(
+            Command::new("connect")
+                .about("Connect to a peer and verify the login")
+                .arg(arg!(<ID> "Remote id")),
+        )
+        .subcommand(
+            Command::new("info")
+                .about("Print the displays and peer info as JSON")
+                .arg(arg!(<ID> "Remote id")),
+        )
+        .subcommand(
+            Command::new("screenshot")
+                .about("Save a display of the peer as PNG")
+                .arg(arg!(<ID> "Remote id"))
+                .arg(arg!(-o --output <FILE> "PNG file to write"))
+                .arg(
+                    arg!(-d --display <INDEX> "Display index, the current one by default")
+                        .required(false)
+                        .value_parser(value_parser!(i32)),
+                ),
+        )
+        .subcommand(
+            Command::new("keys")
+                .about("Send key chords like ctrl+alt+VK_DELETE, then type --text")
+                .arg(arg!(<ID> "Remote id"))
+                .arg(arg!([CHORD] ... "Key chords, sent in order"))
+                .arg(arg!(--text <TEXT> "Text to type after the chords").required(false)),
+        )
+        .subcommand(
+            Command::new("macro")
+                .about("Replay an input macro, from a file or saved by name")
+                .arg(arg!(<ID> "Remote id"))
+                .arg(arg!(<FILE> "Macro file or name"))
+                .arg(
+                    arg!(--var <VAR> "NAME=VALUE for ${NAME} in the macro, can be repeated")
+                        .required(false)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    arg!(--speed <SPEED> "Replay speed, 2 is twice as fast")
+                        .required(false)
+                        .value_parser(value_parser!(f64)),
+                ),
+        )
+        .subcommand(
+            Command::new("push")
+                .about("Upload a file or directory")
+                .arg(arg!(<ID> "Remote id"))
+                .arg(arg!(<LOCAL> "Local path"))
+                .arg(arg!(<REMOTE> "Remote path"))
+                .arg(arg!(--"no-overwrite" "Skip files which already exist")),
+        )
+        .subcommand(
+            Command::new("pull")
+                .about("Download a file or directory")
+                .arg(arg!(<ID> "Remote id"))
+                .arg(arg!(<REMOTE> "Remote path"))
+                .arg(arg!(<LOCAL> "Local path"))
+                .arg(arg!(--"no-overwrite" "Skip files which already exist")),
+        )
+        .subcommand(
+            Command::new("mv")
+                .about("Rename or move a remote file or directory")
+                .arg(arg!(<ID> "Remote id"))
+                .arg(arg!(<FROM> "Remote path"))
+                .arg(arg!(<TO> "New remote path")),
+        )
+        .subcommand(
+            Command::new("cp")
+                .about("Copy a remote file or directory on the peer")
+                .arg(arg!(<ID> "Remote id"))
+                .arg(arg!(<FROM> "Remote path"))
+                .arg(arg!(<TO> "Remote path of the copy"))
+                .arg(arg!(--overwrite "Replace what is at the destination")),
+        )
+        .subcommand(
+            Command::new("stat")
+                .about("Print size, times, permissions and free disk space of a remote path")
+                .arg(arg!(<ID> "Remote id"))
+                .arg(arg!(<PATH> "Remote path")),
+        )
+        .subcommand(
+            Command::new("sync")
+                .about("Copy what differs from a local directory to a remote one, or back with --pull")
+                .arg(arg!(<ID> "Remote id"))
+                .arg(arg!(<LOCAL> "Local directory"))
+                .arg(arg!(<REMOTE> "Remote directory"))
+                .arg(arg!(--pull "Sync the local directory from the remote one"))
+                .arg(
+                    arg!(--include <GLOB> "Only sync matching files, can be repeated")
+                        .required(false)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    arg!(--exclude <GLOB> "Neither copy nor delete matching files, can be repeated")
+                        .required(false)
+                        .action(ArgAction::Append),
+                )
+                .arg(arg!(--delete "Delete files which are not in the source"))
+                .arg(arg!(--checksum "Compare content hashes instead of modification times"))
+                .arg(arg!(-n --"dry-run" "Only print what would be copied and deleted")),
+        )
         .get_matches();
     use hbb_common::{config::LocalConfig, env_logger::*};
     init_from_env(Env::default().filter_or(DEFAULT_FILTER_ENV, "info"));
-    if let Some(p) = matches.value_of("port-forward") {
+    let key = matches
+        .get_one::<String>("key")
+        .cloned()
+        .unwrap_or_default();
+    let token = LocalConfig::get_option("access_token");
+    if let Some((name, sub)) = matches.subcommand() {
+        common::test_rendezvous_server();
+        common::test_nat_type();
+        let arg = |name: &str| sub.get_one::<String>(name).cloned().unwrap_or_default();
+        let target = cli::Target {
+            id: arg("ID"),
+            password: sub.get_one::<String>("password").cloned(),
+            account: sub.get_one::<String>("account").cloned(),
+            key: sub.get_one::<String>("key").cloned().unwrap_or(key),
+            token,
+        };
+        let code = match name {
+            "connect" => cli::connect(target),
+            "info" => cli::info(target),
+            "screenshot" => cli::screenshot(
+                target,
+                sub.get_one::<i32>("display").cloned(),
+                arg("output"),
+            ),
+            "keys" => cli::send_keys(
+                target,
+                sub.get_many::<String>("CHORD")
+                    .map(|v| v.cloned().collect())
+                    .unwrap_or_default(),
+                sub.get_one::<String>("text").cloned(),
+            ),
+            "macro" => cli::play_macro(
+                target,
+                arg("FILE"),
+                sub.get_many::<String>("var")
+                    .map(|v| v.cloned().collect())
+                    .unwrap_or_default(),
+                sub.get_one::<f64>("speed").cloned().unwrap_or(1.),
+            ),
+            "push" => cli::push(
+                target,
+                arg("LOCAL"),
+                arg("REMOTE"),
+                !sub.get_flag("no-overwrite"),
+            ),
+            "pull" => cli::pull(
+                target,
+                arg("REMOTE"),
+                arg("LOCAL"),
+                !sub.get_flag("no-overwrite"),
+            ),
+            "mv" => cli::rename(target, arg("FROM"), arg("TO")),
+            "cp" => cli::copy(target, arg("FROM"), arg("TO"), sub.get_flag("overwrite")),
+            "stat" => cli::stat(target, arg("PATH")),
+            "sync" => {
+                let many = |name: &str| {
+                    sub.get_many::<String>(name)
+                        .map(|v| v.cloned().collect())
+                        .unwrap_or_default()
+                };
+                cli::sync(
+                    target,
+                    cli::SyncArgs {
+                        local: arg("LOCAL"),
+                        remote: arg("REMOTE"),
+                        pull: sub.get_flag("pull"),
+                        include: many("include"),
+                        exclude: many("exclude"),
+                        delete: sub.get_flag("delete"),
+                        checksum: sub.get_flag("checksum"),
+                        dry_run: sub.get_flag("dry-run"),
+                    },
+                )
+            }
+            _ => cli::EXIT_USAGE,
+        };
+        common::global_clean();
+        std::process::exit(code);
+    }
+    if let Some(path) = matches.get_one::<String>("forward-profile") {
+        let code = cli::start_port_forward_profile(path.clone(), key, token);
+        common::global_clean();
+        std::process::exit(code);
+    }
+    if let Some(p) = matches.get_one::<String>("reverse-forward") {
+        let options: Vec<&str> = p.split(':').collect();
+        let ports = (
+            options.get(1).and_then(|x| x.parse::<i32>().ok()),
+            options.get(2).and_then(|x| x.parse::<i32>().ok()),
+        );
+        match ports {
+            (Some(remote_port), Some(local_port)) => {
+                let local_host = options.get(3).unwrap_or(&"localhost").to_string();
+                common::test_rendezvous_server();
+                common::test_nat_type();
+                cli::start_one_reverse_port_forward(
+                    options[0].to_owned(),
+                    remote_port,
+                    local_host,
+                    local_port,
+                    key,
+                    token,
+                );
+            }
+            _ => log::error!("Wrong reverse-forward options"),
+        }
+        common::global_clean();
+        return;
+    }
+    if let Some(p) = matches.get_one::<String>("port-forward") {
         let options: Vec<String> = p.split(":").map(|x| x.to_owned()).collect();
         if options.len() < 3 {
             log::error!("Wrong port-forward options");
@@ -84,23 +315,36 @@ fn main() {
         }
         common::test_rendezvous_server();
         common::test_nat_type();
-        let key = matches.value_of("key").unwrap_or("").to_owned();
-        let token = LocalConfig::get_option("access_token");
-        cli::start_one_port_forward(
-            options[0].clone(),
-            port,
-            remote_host,
-            remote_port,
-            key,
-            token,
-        );
-    } else if let Some(p) = matches.value_of("connect") {
-        common::test_rendezvous_server();
-        common::test_nat_type();
-        let key = matches.value_of("key").unwrap_or("").to_owned();
-        let token = LocalConfig::get_option("access_token");
-        cli::connect_test(p, key, token);
-    } else if let Some(p) = matches.value_of("server") {
+        if matches.get_flag("udp") {
+            let idle_timeout = matches
+                .get_one::<u32>("udp-idle-timeout")
+                .cloned()
+                .unwrap_or_default();
+            let bind_ip = matches
+                .get_one::<std::net::IpAddr>("udp-bind")
+                .cloned()
+                .unwrap_or(std::net::Ipv4Addr::LOCALHOST.into());
+            cli::start_one_udp_port_forward(
+                options[0].clone(),
+                port,
+                remote_host,
+                remote_port,
+                idle_timeout,
+                bind_ip,
+                key,
+                token,
+            );
+        } else {
+            cli::start_one_port_forward(
+                options[0].clone(),
+                port,
+                remote_host,
+                remote_port,
+                key,
+                token,
+            );
+        }
+    } else if matches.get_flag("server") {
         log::info!("id={}", hbb_common::config::Config::get_id());
         crate::start_server(true);
     }
@@ -192,6 +192,9 @@ fn start_uinput_service() {
     std::thread::spawn(|| {
         service::start_service_mouse();
     });
+    std::thread::spawn(|| {
+        service::start_service_pointer();
+    });
 }
 
 #[inline]
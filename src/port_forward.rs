@@ -1,6 +1,17 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
 
-use crate::client::*;
+use crate::{
+    client::*,
+    tunnel::{self, Tunnels},
+};
 use hbb_common::{
     allow_err, bail,
     config::READ_TIMEOUT,
@@ -10,10 +21,175 @@ use hbb_common::{
     protobuf::Message as _,
     rendezvous_proto::ConnType,
     tcp, timeout,
-    tokio::{self, net::TcpStream, sync::mpsc},
+    tokio::{
+        self,
+        net::{TcpListener, TcpStream, UdpSocket},
+        sync::mpsc,
+        time::{self, Instant},
+    },
     tokio_util::codec::{BytesCodec, Framed},
-    ResultType, Stream,
+    toml, ResultType, Stream,
 };
+use serde_derive::Deserialize;
+
+const RETRY_INTERVAL_MIN: Duration = Duration::from_secs(1);
+// Local connections accepted while the session is being set up.
+const MAX_PENDING_CONNS: usize = 64;
+
+/// Many forwarding rules to one peer, loaded from a TOML file:
+///
+/// ```toml
+/// id = "123456789"
+/// max_retry_interval = 30
+///
+/// [[rule]]
+/// name = "postgres"
+/// bind = "127.0.0.1:15432"
+/// remote_host = "db.lan"
+/// remote_port = 5432
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ForwardProfile {
+    pub id: String,
+    pub password: String,
+    /// Upper bound in seconds of the backoff between reconnect attempts.
+    pub max_retry_interval: u64,
+    /// Seconds between byte counter reports, 0 disables them.
+    pub stats_interval: u64,
+    #[serde(rename = "rule")]
+    pub rules: Vec<ForwardRule>,
+}
+
+impl Default for ForwardProfile {
+    fn default() -> Self {
+        Self {
+            id: Default::default(),
+            password: Default::default(),
+            max_retry_interval: 30,
+            stats_interval: 60,
+            rules: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ForwardRule {
+    #[serde(default)]
+    pub name: String,
+    /// `ip:port`, or only a port to bind on loopback.
+    pub bind: String,
+    #[serde(default = "default_remote_host")]
+    pub remote_host: String,
+    pub remote_port: i32,
+}
+
+fn default_remote_host() -> String {
+    "localhost".to_owned()
+}
+
+impl ForwardProfile {
+    pub fn load(path: &str) -> ResultType<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(content: &str) -> ResultType<Self> {
+        let mut profile: Self = toml::from_str(content)?;
+        if profile.id.is_empty() {
+            bail!("missing peer id");
+        }
+        if profile.rules.is_empty() {
+            bail!("no forwarding rule");
+        }
+        let mut binds = HashSet::new();
+        for rule in profile.rules.iter_mut() {
+            let addr = match rule.bind.parse::<u16>() {
+                Ok(port) => SocketAddr::from(([127, 0, 0, 1], port)),
+                Err(_) => match rule.bind.parse::<SocketAddr>() {
+                    Ok(addr) => addr,
+                    Err(_) => bail!("invalid bind address {}", rule.bind),
+                },
+            };
+            if !binds.insert(addr) {
+                bail!("{} is bound twice", addr);
+            }
+            if rule.remote_port <= 0 || rule.remote_port > u16::MAX as i32 {
+                bail!("invalid remote port {} for {}", rule.remote_port, addr);
+            }
+            rule.bind = addr.to_string();
+            if rule.name.is_empty() {
+                rule.name = rule.bind.clone();
+            }
+        }
+        Ok(profile)
+    }
+}
+
+/// UDP clients of the local socket, each source address is one flow of the tunnel.
+#[derive(Default)]
+struct UdpFlows {
+    ids: HashMap<SocketAddr, i32>,
+    flows: HashMap<i32, (SocketAddr, Instant)>,
+    next_id: i32,
+}
+
+impl UdpFlows {
+    /// Id of the flow of `addr`, which is created on its first datagram.
+    /// None if there are already as many flows as the peer accepts.
+    fn get_or_insert(&mut self, addr: SocketAddr) -> Option<i32> {
+        let id = match self.ids.get(&addr) {
+            Some(id) => *id,
+            None => {
+                if self.flows.len() >= tunnel::MAX_UDP_FLOWS {
+                    return None;
+                }
+                self.next_id += 1;
+                self.ids.insert(addr, self.next_id);
+                self.next_id
+            }
+        };
+        self.flows.insert(id, (addr, Instant::now()));
+        Some(id)
+    }
+
+    fn touch(&mut self, id: i32) -> Option<SocketAddr> {
+        let flow = self.flows.get_mut(&id)?;
+        flow.1 = Instant::now();
+        Some(flow.0)
+    }
+
+    fn remove(&mut self, id: i32) -> bool {
+        match self.flows.remove(&id) {
+            Some((addr, _)) => {
+                self.ids.remove(&addr);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove_idle(&mut self, idle: Duration) -> Vec<i32> {
+        let ids: Vec<i32> = self
+            .flows
+            .iter()
+            .filter(|(_, (_, last))| last.elapsed() >= idle)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ids.iter() {
+            self.remove(*id);
+        }
+        ids
+    }
+}
+
+/// Per rule counters, `sent` is what went from the local side to the peer.
+#[derive(Debug, Default)]
+pub struct ForwardStats {
+    pub connections: AtomicUsize,
+    pub active: AtomicUsize,
+    pub sent: AtomicU64,
+    pub received: AtomicU64,
+}
 
 fn run_rdp(port: u16) {
     std::process::Command::new("cmdkey")
@@ -70,11 +246,12 @@ pub async fn listen(
                 let id = id.clone();
                 let password = password.clone();
                 let mut forward = Framed::new(forward, BytesCodec::new());
-                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), &mut forward, key, token, is_rdp).await {
+                let mut buffer = Vec::new();
+                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), Some(&mut forward), &mut buffer, key, token, is_rdp).await {
                     Ok(Some(stream)) => {
                         let interface = interface.clone();
                         tokio::spawn(async move {
-                            if let Err(err) = run_forward(forward, stream).await {
+                            if let Err(err) = run_forward(forward, stream, Default::default()).await {
                                interface.msgbox("error", "Error", &err.to_string(), "");
                             }
                             log::info!("connection from {:?} closed", addr);
@@ -103,12 +280,471 @@ pub async fn listen(
     Ok(())
 }
 
+/// Serve all rules of `profile` over one session with the peer, until a listener fails
+/// or the session is closed.
+///
+/// Every local connection is a tunnel of the session with its own target. A session
+/// which drops is logged in again with backoff, local clients wait for it meanwhile.
+pub async fn listen_profile(
+    profile: ForwardProfile,
+    password: String,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+) -> ResultType<()> {
+    let max_retry = Duration::from_secs(profile.max_retry_interval).max(RETRY_INTERVAL_MIN);
+    let mut listeners = Vec::new();
+    for rule in profile.rules.iter() {
+        let listener = tcp::new_listener(&rule.bind, true).await?;
+        log::info!(
+            "forwarding {} ({}) to {}:{}",
+            listener.local_addr()?,
+            rule.name,
+            rule.remote_host,
+            rule.remote_port
+        );
+        listeners.push((listener, rule.clone(), Arc::new(ForwardStats::default())));
+    }
+    let (tx_conn, rx_conn) = mpsc::channel(MAX_PENDING_CONNS);
+    let mut stats = Vec::new();
+    let mut tasks = Vec::new();
+    for (listener, rule, rule_stats) in listeners {
+        stats.push((rule.clone(), rule_stats.clone()));
+        tasks.push(tokio::spawn(serve_rule(
+            listener,
+            rule,
+            rule_stats,
+            tx_conn.clone(),
+        )));
+    }
+    let mut targets: Vec<String> = profile
+        .rules
+        .iter()
+        .map(|r| format!("{}:{}", r.remote_host, r.remote_port))
+        .collect();
+    targets.sort();
+    targets.dedup();
+    let mut session = tokio::spawn(run_session(
+        profile.id.clone(),
+        password,
+        targets,
+        interface,
+        ui_receiver,
+        key.to_owned(),
+        token.to_owned(),
+        max_retry,
+        rx_conn,
+    ));
+    let mut timer = time::interval(Duration::from_secs(profile.stats_interval.max(1)));
+    loop {
+        tokio::select! {
+            res = &mut session => {
+                tasks.iter().for_each(|t| t.abort());
+                return res?;
+            }
+            _ = timer.tick() => {}
+        }
+        if tasks.iter().any(|t| t.is_finished()) {
+            tasks.iter().for_each(|t| t.abort());
+            session.abort();
+            bail!("a forwarding listener stopped");
+        }
+        if profile.stats_interval == 0 {
+            continue;
+        }
+        for (rule, s) in stats.iter() {
+            log::info!(
+                "{}",
+                serde_json::json!({
+                    "rule": rule.name,
+                    "bind": rule.bind,
+                    "connections": s.connections.load(Ordering::Relaxed),
+                    "active": s.active.load(Ordering::Relaxed),
+                    "sent": s.sent.load(Ordering::Relaxed),
+                    "received": s.received.load(Ordering::Relaxed),
+                })
+            );
+        }
+    }
+}
+
+/// A local connection of a rule waiting to be tunnelled to the peer.
+struct LocalConn {
+    sock: TcpStream,
+    rule: ForwardRule,
+    stats: Arc<ForwardStats>,
+}
+
+async fn serve_rule(
+    listener: TcpListener,
+    rule: ForwardRule,
+    stats: Arc<ForwardStats>,
+    tx_conn: mpsc::Sender<LocalConn>,
+) {
+    loop {
+        let (sock, addr) = match listener.accept().await {
+            Ok(v) => v,
+            Err(err) => {
+                log::error!("{}: failed to accept: {}", rule.name, err);
+                return;
+            }
+        };
+        log::info!("{}: new connection from {:?}", rule.name, addr);
+        let conn = LocalConn {
+            sock,
+            rule: rule.clone(),
+            stats: stats.clone(),
+        };
+        if tx_conn.send(conn).await.is_err() {
+            return;
+        }
+    }
+}
+
+// Log in, carry the local connections until the session drops, and start over.
+#[allow(clippy::too_many_arguments)]
+async fn run_session(
+    id: String,
+    password: String,
+    targets: Vec<String>,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: String,
+    token: String,
+    max_retry: Duration,
+    mut rx_conn: mpsc::Receiver<LocalConn>,
+) -> ResultType<()> {
+    {
+        let lc = interface.get_login_config_handler();
+        let mut lc = lc.write().unwrap();
+        lc.port_forward_mux = true;
+        lc.port_forward_targets = targets;
+    }
+    let mut ui_receiver = ui_receiver;
+    let mut retry = RETRY_INTERVAL_MIN;
+    loop {
+        let mut buffer = Vec::new();
+        let res = connect_and_login(
+            &id,
+            &password,
+            &mut ui_receiver,
+            interface.clone(),
+            None,
+            &mut buffer,
+            &key,
+            &token,
+            false,
+        )
+        .await;
+        match res {
+            Ok(Some(stream)) => {
+                retry = RETRY_INTERVAL_MIN;
+                match run_mux(stream, interface.clone(), &mut ui_receiver, &mut rx_conn).await {
+                    Ok(()) => return Ok(()),
+                    Err(err) => log::error!("forwarding session lost: {}", err),
+                }
+            }
+            // login failed, retrying would not help
+            Ok(None) => return Ok(()),
+            Err(err) => interface.on_establish_connection_error(err.to_string()),
+        }
+        log::info!("reconnecting in {:?}", retry);
+        tokio::select! {
+            _ = time::sleep(retry) => {}
+            d = ui_receiver.recv() => {
+                if let Some(Data::Close) | None = d {
+                    return Ok(());
+                }
+            }
+        }
+        retry = (retry * 2).min(max_retry);
+    }
+}
+
+// Ok if closed from the ui, an error if the session dropped.
+async fn run_mux(
+    stream: Stream,
+    interface: impl Interface,
+    ui_receiver: &mut mpsc::UnboundedReceiver<Data>,
+    rx_conn: &mut mpsc::Receiver<LocalConn>,
+) -> ResultType<()> {
+    let mut stream = stream;
+    let mut interface = interface;
+    let (tx_frame, mut rx_frame) = tunnel::frame_channel();
+    let mut tunnels = Tunnels::default();
+    let mut stats: HashMap<i32, Arc<ForwardStats>> = HashMap::new();
+    let mut next_id = 0;
+    let res = async {
+        loop {
+            tokio::select! {
+                Some(conn) = rx_conn.recv() => {
+                    next_id += 1;
+                    conn.stats.connections.fetch_add(1, Ordering::Relaxed);
+                    conn.stats.active.fetch_add(1, Ordering::Relaxed);
+                    stats.insert(next_id, conn.stats);
+                    tunnels.add(next_id, conn.sock, tx_frame.clone());
+                    // Opened before any data, the protocol may be server first.
+                    stream.send(&tunnel::open_frame(next_id, &conn.rule.remote_host, conn.rule.remote_port)).await?;
+                }
+                Some(frame) = rx_frame.recv() => {
+                    if frame.close {
+                        if !tunnels.remove(frame.id) {
+                            continue;
+                        }
+                        if let Some(s) = stats.remove(&frame.id) {
+                            s.active.fetch_sub(1, Ordering::Relaxed);
+                        }
+                    } else if let Some(s) = stats.get(&frame.id) {
+                        s.sent.fetch_add(frame.data.len() as _, Ordering::Relaxed);
+                    }
+                    stream.send(&tunnel::new_frame(frame.id, frame.data, frame.close)).await?;
+                }
+                res = stream.next() => match res {
+                    Some(Ok(bytes)) => {
+                        let msg_in = Message::parse_from_bytes(&bytes)?;
+                        match msg_in.union {
+                            Some(message::Union::TunnelFrame(frame)) => {
+                                if frame.close {
+                                    if let Some(s) = stats.remove(&frame.id) {
+                                        s.active.fetch_sub(1, Ordering::Relaxed);
+                                    }
+                                } else if let Some(s) = stats.get(&frame.id) {
+                                    s.received.fetch_add(frame.data.len() as _, Ordering::Relaxed);
+                                }
+                                let id = frame.id;
+                                if !tunnels.handle_frame(frame) {
+                                    if let Some(s) = stats.remove(&id) {
+                                        s.active.fetch_sub(1, Ordering::Relaxed);
+                                    }
+                                    stream.send(&tunnel::new_frame(id, Default::default(), true)).await?;
+                                }
+                            }
+                            Some(message::Union::TestDelay(t)) => {
+                                interface.handle_test_delay(t, &mut stream).await;
+                            }
+                            _ => {}
+                        }
+                    }
+                    Some(Err(err)) => {
+                        bail!("Connection closed: {}", err);
+                    }
+                    None => {
+                        bail!("Reset by the peer");
+                    }
+                },
+                d = ui_receiver.recv() => {
+                    if let Some(Data::Close) | None = d {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+    .await;
+    // the local connections close with the tunnels
+    for s in stats.values() {
+        s.active.fetch_sub(1, Ordering::Relaxed);
+    }
+    res
+}
+
+/// Forward datagrams received on `bind_ip:port` to `remote_host:remote_port` of the peer,
+/// keeping their boundaries. Replies go back to the client address they belong to.
+#[allow(clippy::too_many_arguments)]
+pub async fn listen_udp(
+    id: String,
+    password: String,
+    port: i32,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+    remote_host: String,
+    remote_port: i32,
+    idle_timeout: u32,
+    bind_ip: IpAddr,
+) -> ResultType<()> {
+    let sock = UdpSocket::bind(SocketAddr::new(bind_ip, port as u16)).await?;
+    log::info!("listening on udp port {:?}", sock.local_addr()?);
+    {
+        let mut lc = lc.write().unwrap();
+        lc.port_forward = (remote_host, remote_port);
+        lc.port_forward_udp = true;
+        lc.udp_idle_timeout = idle_timeout;
+    }
+    let mut ui_receiver = ui_receiver;
+    let mut buffer = Vec::new();
+    let mut stream = match connect_and_login(
+        &id,
+        &password,
+        &mut ui_receiver,
+        interface.clone(),
+        None,
+        &mut buffer,
+        key,
+        token,
+        false,
+    )
+    .await?
+    {
+        Some(stream) => stream,
+        None => return Ok(()),
+    };
+    let idle = tunnel::udp_idle_timeout(idle_timeout);
+    let mut interface = interface;
+    let mut flows = UdpFlows::default();
+    let mut buf = vec![0u8; tunnel::MAX_DATAGRAM];
+    let mut timer = time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            res = sock.recv_from(&mut buf) => match res {
+                Ok((n, addr)) => match flows.get_or_insert(addr) {
+                    Some(id) => {
+                        let data = buf[..n].to_vec().into();
+                        stream.send(&tunnel::new_frame(id, data, false)).await?;
+                    }
+                    None => log::debug!("too many udp flows, dropping datagram from {}", addr),
+                },
+                Err(err) => log::debug!("udp recv failed: {}", err),
+            },
+            res = stream.next() => match res {
+                Some(Ok(bytes)) => {
+                    let msg_in = Message::parse_from_bytes(&bytes)?;
+                    match msg_in.union {
+                        Some(message::Union::TunnelFrame(frame)) => {
+                            if frame.close {
+                                flows.remove(frame.id);
+                            } else if let Some(addr) = flows.touch(frame.id) {
+                                allow_err!(sock.send_to(&frame.data, addr).await);
+                            }
+                        }
+                        Some(message::Union::TestDelay(t)) => {
+                            interface.handle_test_delay(t, &mut stream).await;
+                        }
+                        _ => {}
+                    }
+                }
+                Some(Err(err)) => {
+                    bail!("Connection closed: {}", err);
+                }
+                None => {
+                    bail!("Reset by the peer");
+                }
+            },
+            _ = timer.tick() => {
+                for id in flows.remove_idle(idle) {
+                    stream.send(&tunnel::new_frame(id, Default::default(), true)).await?;
+                }
+            }
+            d = ui_receiver.recv() => {
+                if let Some(Data::Close) | None = d {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Have the peer listen on `remote_port` of its loopback, every connection it accepts
+/// there is carried over the session and connected to `local_host:local_port` here.
+#[allow(clippy::too_many_arguments)]
+pub async fn listen_reverse(
+    id: String,
+    password: String,
+    remote_port: i32,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+    local_host: String,
+    local_port: i32,
+) -> ResultType<()> {
+    lc.write().unwrap().reverse_port_forward = Some(remote_port);
+    let mut ui_receiver = ui_receiver;
+    let mut buffer = Vec::new();
+    let mut stream = match connect_and_login(
+        &id,
+        &password,
+        &mut ui_receiver,
+        interface.clone(),
+        None,
+        &mut buffer,
+        key,
+        token,
+        false,
+    )
+    .await?
+    {
+        Some(stream) => stream,
+        None => return Ok(()),
+    };
+    let target = format!("{}:{}", local_host, local_port);
+    log::info!(
+        "peer listening on 127.0.0.1:{}, forwarding to {}",
+        remote_port,
+        target
+    );
+    let mut interface = interface;
+    let (tx_frame, mut rx_frame) = tunnel::frame_channel();
+    let mut tunnels = Tunnels::default();
+    loop {
+        tokio::select! {
+            res = stream.next() => match res {
+                Some(Ok(bytes)) => {
+                    let msg_in = Message::parse_from_bytes(&bytes)?;
+                    match msg_in.union {
+                        Some(message::Union::TunnelFrame(frame)) => {
+                            if !frame.close && !tunnels.contains(frame.id) {
+                                // First frame of a connection accepted by the peer.
+                                log::info!("reverse port forwarding connection #{} opened", frame.id);
+                                tunnels.connect(frame.id, target.clone(), tx_frame.clone());
+                            }
+                            let id = frame.id;
+                            if !tunnels.handle_frame(frame) {
+                                stream.send(&tunnel::new_frame(id, Default::default(), true)).await?;
+                            }
+                        }
+                        Some(message::Union::TestDelay(t)) => {
+                            interface.handle_test_delay(t, &mut stream).await;
+                        }
+                        _ => {}
+                    }
+                }
+                Some(Err(err)) => {
+                    bail!("Connection closed: {}", err);
+                }
+                None => {
+                    bail!("Reset by the peer");
+                }
+            },
+            Some(frame) = rx_frame.recv() => {
+                if frame.close && !tunnels.remove(frame.id) {
+                    continue;
+                }
+                stream.send(&tunnel::new_frame(frame.id, frame.data, frame.close)).await?;
+            }
+            d = ui_receiver.recv() => {
+                if let Some(Data::Close) | None = d {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn connect_and_login(
     id: &str,
     password: &str,
     ui_receiver: &mut mpsc::UnboundedReceiver<Data>,
     interface: impl Interface,
-    forward: &mut Framed<TcpStream, BytesCodec>,
+    mut forward: Option<&mut Framed<TcpStream, BytesCodec>>,
+    buffer: &mut Vec<u8>,
     key: &str,
     token: &str,
     is_rdp: bool,
@@ -122,7 +758,6 @@ async fn connect_and_login(
         Client::start(id, key, token, conn_type, interface.clone()).await?;
     interface.update_direct(Some(direct));
     let mut interface = interface;
-    let mut buffer = Vec::new();
     let mut received = false;
     loop {
         tokio::select! {
@@ -172,7 +807,12 @@ async fn connect_and_login(
                     _ => {}
                 }
             },
-            res = forward.next() => {
+            res = async {
+                match forward.as_mut() {
+                    Some(forward) => forward.next().await,
+                    None => std::future::pending().await,
+                }
+            } => {
                 if let Some(Ok(bytes)) = res {
                     buffer.extend(bytes);
                 } else {
@@ -181,14 +821,22 @@ async fn connect_and_login(
             },
         }
     }
-    stream.set_raw();
-    if !buffer.is_empty() {
-        allow_err!(stream.send_bytes(buffer.into()).await);
+    // A reverse forward keeps exchanging messages, only a direct one turns raw.
+    if forward.is_some() {
+        stream.set_raw();
+        if !buffer.is_empty() {
+            let buffer = std::mem::take(buffer);
+            allow_err!(stream.send_bytes(buffer.into()).await);
+        }
     }
     Ok(Some(stream))
 }
 
-async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) -> ResultType<()> {
+async fn run_forward(
+    forward: Framed<TcpStream, BytesCodec>,
+    stream: Stream,
+    stats: Arc<ForwardStats>,
+) -> ResultType<()> {
     log::info!("new port forwarding connection started");
     let mut forward = forward;
     let mut stream = stream;
@@ -196,6 +844,7 @@ async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) ->
         tokio::select! {
             res = forward.next() => {
                 if let Some(Ok(bytes)) = res {
+                    stats.sent.fetch_add(bytes.len() as _, Ordering::Relaxed);
                     allow_err!(stream.send_bytes(bytes.into()).await);
                 } else {
                     break;
@@ -203,6 +852,7 @@ async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) ->
             },
             res = stream.next() => {
                 if let Some(Ok(bytes)) = res {
+                    stats.received.fetch_add(bytes.len() as _, Ordering::Relaxed);
                     allow_err!(forward.send(bytes).await);
                 } else {
                     break;
@@ -212,3 +862,80 @@ async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) ->
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_udp_flows() {
+        let mut flows = UdpFlows::default();
+        let a: SocketAddr = "127.0.0.1:1000".parse().unwrap();
+        let b: SocketAddr = "127.0.0.1:1001".parse().unwrap();
+        assert_eq!(flows.get_or_insert(a), Some(1));
+        assert_eq!(flows.get_or_insert(b), Some(2));
+        assert_eq!(flows.get_or_insert(a), Some(1));
+        assert_eq!(flows.touch(2), Some(b));
+        assert!(flows.remove(1));
+        assert!(!flows.remove(1));
+        assert_eq!(flows.get_or_insert(a), Some(3));
+        assert!(flows.remove_idle(Duration::from_secs(60)).is_empty());
+        let mut idle = flows.remove_idle(Duration::ZERO);
+        idle.sort();
+        assert_eq!(idle, vec![2, 3]);
+        assert_eq!(flows.touch(2), None);
+
+        for port in 0..tunnel::MAX_UDP_FLOWS as u16 {
+            assert!(flows
+                .get_or_insert(SocketAddr::from(([127, 0, 0, 1], port)))
+                .is_some());
+        }
+        assert_eq!(flows.get_or_insert(b), None);
+        // known flows keep going
+        assert!(flows
+            .get_or_insert(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .is_some());
+    }
+
+    #[test]
+    fn test_parse_profile() {
+        let profile = ForwardProfile::parse(
+            r#"
+            id = "123456789"
+            stats_interval = 0
+
+            [[rule]]
+            bind = "15432"
+            remote_host = "db.lan"
+            remote_port = 5432
+
+            [[rule]]
+            name = "web"
+            bind = "0.0.0.0:8080"
+            remote_port = 80
+            "#,
+        )
+        .unwrap();
+        assert_eq!(profile.id, "123456789");
+        assert_eq!(profile.max_retry_interval, 30);
+        assert_eq!(profile.stats_interval, 0);
+        assert_eq!(profile.rules.len(), 2);
+        assert_eq!(profile.rules[0].bind, "127.0.0.1:15432");
+        assert_eq!(profile.rules[0].name, "127.0.0.1:15432");
+        assert_eq!(profile.rules[1].remote_host, "localhost");
+        assert_eq!(profile.rules[1].name, "web");
+
+        let dup = r#"
+            id = "1"
+            [[rule]]
+            bind = "8080"
+            remote_port = 80
+            [[rule]]
+            bind = "127.0.0.1:8080"
+            remote_port = 81
+        "#;
+        assert!(ForwardProfile::parse(dup).is_err());
+        assert!(ForwardProfile::parse("id = \"1\"").is_err());
+        assert!(ForwardProfile::parse("[[rule]]\nbind = \"1\"\nremote_port = 1").is_err());
+    }
+}
@@ -31,6 +31,9 @@ use service::{GenericService, Service, Subscriber};
 use crate::ipc::Data;
 
 pub mod audio_service;
+pub mod audit;
+pub mod auth;
+pub mod clipboard_policy;
 cfg_if::cfg_if! {
 if #[cfg(not(any(target_os = "android", target_os = "ios")))] {
 mod clipboard_service;
@@ -53,6 +56,7 @@ pub const NAME_POS: &'static str = "";
 }
 
 mod connection;
+pub mod lockout;
 #[cfg(windows)]
 pub mod portable_service;
 mod service;
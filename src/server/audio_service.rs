@@ -326,6 +326,7 @@ fn create_format_msg(sample_rate: u32, channels: u16) -> Message {
     misc.set_audio_format(format);
     let mut msg = Message::new();
     msg.set_misc(misc);
+    super::video_service::record_message(&msg);
     msg
 }
 
@@ -371,6 +372,7 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
                             data: data.into(),
                             ..Default::default()
                         });
+                        super::video_service::record_message(&msg_out);
                         sp.send(msg_out);
                     }
                     Err(_) => {}
@@ -390,6 +392,7 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
                 data: data.into(),
                 ..Default::default()
             });
+            super::video_service::record_message(&msg_out);
             sp.send(msg_out);
         }
         Err(_) => {}
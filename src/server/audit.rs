@@ -0,0 +1,735 @@
+// Audit trail of connection, file and alarm events. Events are handed to a worker
+// thread which writes them to the sinks named by the "audit-sinks" option,
+// comma separated, "http" when empty:
+//
+// - "file": JSON lines in "audit-file" (default `audit.jsonl` in the log folder),
+//   rotated at "audit-file-max-size" MB keeping "audit-file-keep" old files. Each
+//   line has a sequence number and the HMAC of the line before it in "prev". The
+//   key ("audit-key-file", default `audit.key` in the config folder) and the head
+//   of the chain are kept outside the log, so edits, truncation and removed
+//   files all break the chain.
+// - "syslog": the local syslog socket, which journald also reads, unix only.
+// - "http": the api-server audit endpoints, undelivered events are queued on disk
+//   and retried in order. The queue is only appended to, delivered events are
+//   skipped by count and dropped from the file once enough of them piled up.
+
+use hbb_common::{
+    allow_err, bail,
+    config::Config,
+    get_time, log,
+    rand::{self, Rng},
+    serde_json::{self, json, Value},
+    ResultType,
+};
+use hmac::{Hmac, Mac};
+use serde_derive::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, RecvTimeoutError, Sender},
+        Mutex,
+    },
+    time::Duration,
+};
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_SIZE_MB: u64 = 10;
+const DEFAULT_KEEP: usize = 5;
+const MAX_QUEUE: usize = 10_000;
+// Delivered events kept at the start of the queue file before it is rewritten.
+const COMPACT_AFTER: usize = 1_000;
+
+lazy_static::lazy_static! {
+    static ref SENDER: Mutex<Option<Sender<Event>>> = Default::default();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Conn,
+    File,
+    Alarm,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Conn => "conn",
+            Kind::File => "file",
+            Kind::Alarm => "alarm",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub kind: Kind,
+    /// Audit endpoint of the api server, empty if there is none.
+    pub url: String,
+    pub record: Value,
+    /// ms
+    pub time: i64,
+}
+
+pub trait AuditSink: Send {
+    fn name(&self) -> &'static str;
+
+    fn write(&mut self, event: &Event) -> ResultType<()>;
+
+    /// Called when idle, to retry what could not be delivered.
+    fn tick(&mut self) {}
+}
+
+/// Record an event on all configured sinks, without blocking the caller.
+pub fn record(kind: Kind, url: &str, record: Value) {
+    let event = Event {
+        kind,
+        url: url.to_owned(),
+        record,
+        time: get_time(),
+    };
+    let mut lock = SENDER.lock().unwrap();
+    let tx = lock.get_or_insert_with(|| {
+        let (tx, rx) = channel();
+        std::thread::spawn(move || run(rx));
+        tx
+    });
+    if let Err(err) = tx.send(event) {
+        log::error!("Audit worker is gone: {}", err);
+        lock.take();
+    }
+}
+
+fn run(rx: std::sync::mpsc::Receiver<Event>) {
+    let mut names: Option<String> = None;
+    let mut sinks: Vec<Box<dyn AuditSink>> = Vec::new();
+    loop {
+        let res = rx.recv_timeout(RETRY_INTERVAL);
+        // Rebuilt when the option changes, so no restart is needed.
+        let option = Config::get_option("audit-sinks");
+        if names.as_ref() != Some(&option) {
+            sinks = new_sinks(&option);
+            names = Some(option);
+        }
+        match res {
+            Ok(event) => {
+                for sink in sinks.iter_mut() {
+                    if let Err(err) = sink.write(&event) {
+                        log::error!("Audit sink {} failed: {}", sink.name(), err);
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => sinks.iter_mut().for_each(|s| s.tick()),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+fn new_sinks(names: &str) -> Vec<Box<dyn AuditSink>> {
+    let names = if names.trim().is_empty() {
+        "http"
+    } else {
+        names
+    };
+    let mut sinks: Vec<Box<dyn AuditSink>> = Vec::new();
+    for name in names.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        let sink: ResultType<Box<dyn AuditSink>> = match name {
+            "file" => FileSink::new().map(|s| Box::new(s) as _),
+            #[cfg(unix)]
+            "syslog" | "journald" => SyslogSink::new().map(|s| Box::new(s) as _),
+            "http" => Ok(Box::new(HttpSink::new())),
+            _ => Err(hbb_common::anyhow::anyhow!("unsupported")),
+        };
+        match sink {
+            Ok(sink) => sinks.push(sink),
+            Err(err) => log::error!("Failed to open audit sink {}: {}", name, err),
+        }
+    }
+    sinks
+}
+
+fn local_line(event: &Event) -> Value {
+    let mut v = event.record.clone();
+    v["kind"] = json!(event.kind.as_str());
+    v["time"] = json!(event.time);
+    v
+}
+
+fn key_path() -> PathBuf {
+    let path = Config::get_option("audit-key-file");
+    if path.is_empty() {
+        Config::path("audit.key")
+    } else {
+        PathBuf::from(path)
+    }
+}
+
+fn head_path() -> PathBuf {
+    Config::path("audit_head.json")
+}
+
+/// The chain key, a new one is made if there is none and `create` is set.
+fn load_key(path: &Path, create: bool) -> ResultType<Vec<u8>> {
+    match fs::read_to_string(path) {
+        Ok(s) => Ok(hex::decode(s.trim())?),
+        Err(err) if create && err.kind() == std::io::ErrorKind::NotFound => {
+            let key: [u8; 32] = rand::thread_rng().gen();
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options.open(path)?.write_all(hex::encode(key).as_bytes())?;
+            Ok(key.to_vec())
+        }
+        Err(err) => bail!("failed to read audit key {}: {}", path.display(), err),
+    }
+}
+
+fn mac_hex(key: &[u8], line: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes keys of any size");
+    mac.update(line.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Both ends of the chain: the first line still on disk and the last one written.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Head {
+    pub first_seq: u64,
+    pub first_prev: String,
+    /// Sequence number of the next line.
+    pub next: u64,
+    /// HMAC of the last line, empty if there is none.
+    pub mac: String,
+}
+
+impl Head {
+    fn load(path: &Path) -> Option<Self> {
+        serde_json::from_slice(&fs::read(path).ok()?).ok()
+    }
+
+    fn store(&self, path: &Path) -> ResultType<()> {
+        fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+}
+
+struct FileSink {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    file: File,
+    size: u64,
+    key: Vec<u8>,
+    head: Head,
+    head_path: PathBuf,
+}
+
+impl FileSink {
+    fn new() -> ResultType<Self> {
+        let path = Config::get_option("audit-file");
+        let path = if path.is_empty() {
+            Config::log_path().join("audit.jsonl")
+        } else {
+            PathBuf::from(path)
+        };
+        let max_size = Config::get_option("audit-file-max-size")
+            .parse()
+            .unwrap_or(DEFAULT_MAX_SIZE_MB)
+            .max(1)
+            * 1024
+            * 1024;
+        let keep = Config::get_option("audit-file-keep")
+            .parse()
+            .unwrap_or(DEFAULT_KEEP);
+        Self::open(path, max_size, keep, &key_path(), head_path())
+    }
+
+    fn open(
+        path: PathBuf,
+        max_size: u64,
+        keep: usize,
+        key_path: &Path,
+        head_path: PathBuf,
+    ) -> ResultType<Self> {
+        let key = load_key(key_path, true)?;
+        let head = Head::load(&head_path);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        let mut sink = Self {
+            path,
+            max_size,
+            keep,
+            file,
+            size,
+            key,
+            head: head.clone().unwrap_or_default(),
+            head_path,
+        };
+        // Lines from before the head was kept start no chain, move them away.
+        if head.is_none() && sink.size > 0 {
+            sink.rotate()?;
+        }
+        Ok(sink)
+    }
+
+    fn rotate(&mut self) -> ResultType<()> {
+        let name = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        if self.keep == 0 {
+            fs::remove_file(&self.path).ok();
+        } else {
+            fs::remove_file(name(self.keep)).ok();
+            for n in (1..self.keep).rev() {
+                fs::rename(name(n), name(n + 1)).ok();
+            }
+            fs::rename(&self.path, name(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        // The oldest line may be gone with the oldest file.
+        let first = (1..=self.keep)
+            .rev()
+            .filter_map(|n| fs::read_to_string(name(n)).ok())
+            .find_map(|s| {
+                let v: Value = serde_json::from_str(s.lines().next()?).ok()?;
+                Some((v["seq"].as_u64()?, v["prev"].as_str()?.to_owned()))
+            });
+        let (first_seq, first_prev) = first.unwrap_or((self.head.next, self.head.mac.clone()));
+        self.head.first_seq = first_seq;
+        self.head.first_prev = first_prev;
+        self.head.store(&self.head_path)
+    }
+}
+
+impl AuditSink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn write(&mut self, event: &Event) -> ResultType<()> {
+        let mut v = local_line(event);
+        v["seq"] = json!(self.head.next);
+        v["prev"] = json!(self.head.mac);
+        let line = v.to_string();
+        if self.size > 0 && self.size + line.len() as u64 >= self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(format!("{}\n", line).as_bytes())?;
+        self.file.flush()?;
+        self.size += line.len() as u64 + 1;
+        self.head.next += 1;
+        self.head.mac = mac_hex(&self.key, &line);
+        self.head.store(&self.head_path)
+    }
+}
+
+/// Check audit files against the key and head of this machine. `content` is the
+/// files joined, the rotated ones first from the oldest.
+pub fn verify(content: &str) -> ResultType<Result<usize, usize>> {
+    let key = load_key(&key_path(), false)?;
+    let head = match Head::load(&head_path()) {
+        Some(head) => head,
+        None => bail!("no audit head at {}", head_path().display()),
+    };
+    Ok(verify_chain(content, &key, &head))
+}
+
+/// Gives the number of lines, or the number of the first one which breaks the
+/// chain, one past the end if lines are missing there.
+pub fn verify_chain(content: &str, key: &[u8], head: &Head) -> Result<usize, usize> {
+    let mut seq = head.first_seq;
+    let mut prev = head.first_prev.clone();
+    let mut n = 0;
+    for (i, line) in content.lines().enumerate() {
+        let v: Value = serde_json::from_str(line).map_err(|_| i + 1)?;
+        if v["seq"].as_u64() != Some(seq) || v["prev"].as_str() != Some(&prev) {
+            return Err(i + 1);
+        }
+        seq += 1;
+        prev = mac_hex(key, line);
+        n += 1;
+    }
+    if seq != head.next || prev != head.mac {
+        return Err(n + 1);
+    }
+    Ok(n)
+}
+
+#[cfg(unix)]
+struct SyslogSink {
+    sock: std::os::unix::net::UnixDatagram,
+}
+
+#[cfg(unix)]
+impl SyslogSink {
+    // LOG_AUTHPRIV
+    const FACILITY: u8 = 10;
+
+    fn new() -> ResultType<Self> {
+        let sock = std::os::unix::net::UnixDatagram::unbound()?;
+        let mut res = Err(std::io::Error::from(std::io::ErrorKind::NotFound));
+        for path in ["/dev/log", "/var/run/syslog"] {
+            res = sock.connect(path);
+            if res.is_ok() {
+                break;
+            }
+        }
+        res?;
+        Ok(Self { sock })
+    }
+}
+
+#[cfg(unix)]
+impl AuditSink for SyslogSink {
+    fn name(&self) -> &'static str {
+        "syslog"
+    }
+
+    fn write(&mut self, event: &Event) -> ResultType<()> {
+        // warning for alarms, notice otherwise
+        let severity = if event.kind == Kind::Alarm { 4 } else { 5 };
+        let msg = format!(
+            "<{}>{}[{}]: {}",
+            Self::FACILITY * 8 + severity,
+            crate::get_app_name(),
+            std::process::id(),
+            local_line(event)
+        );
+        self.sock.send(msg.as_bytes())?;
+        Ok(())
+    }
+}
+
+struct HttpSink {
+    queue: PathBuf,
+    /// Lines in the queue file.
+    len: usize,
+    /// Lines at the start of the queue file which are delivered already.
+    sent: usize,
+}
+
+impl HttpSink {
+    fn new() -> Self {
+        Self::open(Config::path("audit_queue.jsonl"))
+    }
+
+    fn open(queue: PathBuf) -> Self {
+        let len = fs::read_to_string(&queue)
+            .map(|s| s.lines().count())
+            .unwrap_or_default();
+        let mut sink = Self {
+            queue,
+            len,
+            sent: 0,
+        };
+        sink.sent = fs::read_to_string(sink.sent_path())
+            .ok()
+            .and_then(|s| s.trim().parse::<usize>().ok())
+            .unwrap_or_default()
+            .min(len);
+        sink
+    }
+
+    fn sent_path(&self) -> PathBuf {
+        PathBuf::from(format!("{}.sent", self.queue.display()))
+    }
+
+    fn post(url: &str, body: &Value) -> ResultType<()> {
+        crate::post_request_sync(url.to_owned(), body.to_string(), "")?;
+        Ok(())
+    }
+
+    fn append(&mut self, v: &Value) -> ResultType<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.queue)?;
+        file.write_all(format!("{}\n", v).as_bytes())?;
+        self.len += 1;
+        if self.len - self.sent > MAX_QUEUE {
+            // trim below the limit, not to it, so that a full queue is not
+            // rewritten on every event
+            self.compact(MAX_QUEUE * 3 / 4)?;
+        }
+        Ok(())
+    }
+
+    // Rewrite the queue without the delivered events, and the oldest ones
+    // beyond `keep`.
+    fn compact(&mut self, keep: usize) -> ResultType<()> {
+        let content = fs::read_to_string(&self.queue).unwrap_or_default();
+        let left: Vec<&str> = content.lines().skip(self.sent).collect();
+        let skip = left.len().saturating_sub(keep);
+        if skip > 0 {
+            log::warn!("Audit queue full, {} oldest events dropped", skip);
+        }
+        if left.len() == skip {
+            if self.queue.exists() {
+                fs::remove_file(&self.queue)?;
+            }
+        } else {
+            let tmp = PathBuf::from(format!("{}.tmp", self.queue.display()));
+            let mut content = left[skip..].join("\n");
+            content.push('\n');
+            fs::write(&tmp, content)?;
+            fs::rename(&tmp, &self.queue)?;
+        }
+        fs::remove_file(self.sent_path()).ok();
+        self.len = left.len() - skip;
+        self.sent = 0;
+        Ok(())
+    }
+
+    /// Deliver queued events in order, `false` if some are left.
+    fn flush(&mut self) -> bool {
+        self.flush_with(Self::post)
+    }
+
+    fn flush_with(&mut self, mut post: impl FnMut(&str, &Value) -> ResultType<()>) -> bool {
+        if self.sent >= self.len {
+            return true;
+        }
+        let content = fs::read_to_string(&self.queue).unwrap_or_default();
+        let mut sent = 0;
+        for line in content.lines().skip(self.sent) {
+            // a broken line is skipped rather than blocking the queue
+            if let Ok(v) = serde_json::from_str::<Value>(line) {
+                if post(v["url"].as_str().unwrap_or_default(), &v["body"]).is_err() {
+                    break;
+                }
+            }
+            sent += 1;
+        }
+        if sent > 0 {
+            log::info!("{} queued audit events delivered", sent);
+            self.sent += sent;
+        }
+        if self.sent >= self.len || self.sent >= COMPACT_AFTER {
+            allow_err!(self.compact(MAX_QUEUE));
+        } else if sent > 0 {
+            allow_err!(fs::write(self.sent_path(), self.sent.to_string()));
+        }
+        self.sent >= self.len
+    }
+}
+
+impl AuditSink for HttpSink {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn write(&mut self, event: &Event) -> ResultType<()> {
+        if event.url.is_empty() {
+            return Ok(());
+        }
+        if self.flush() {
+            match Self::post(&event.url, &event.record) {
+                Ok(_) => return Ok(()),
+                Err(err) => log::warn!("Audit post failed, queued: {}", err),
+            }
+        }
+        if let Err(err) = self.append(&json!({"url": event.url, "body": event.record})) {
+            bail!("failed to queue audit event: {}", err);
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("audit_{}_{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn event(i: i64) -> Event {
+        Event {
+            kind: Kind::Conn,
+            url: "http://audit".to_owned(),
+            record: json!({"conn_id": i, "action": "new"}),
+            time: i,
+        }
+    }
+
+    fn file_sink(dir: &Path, keep: usize) -> FileSink {
+        FileSink::open(
+            dir.join("audit.jsonl"),
+            300,
+            keep,
+            &dir.join("audit.key"),
+            dir.join("head.json"),
+        )
+        .unwrap()
+    }
+
+    // The rotated files from the oldest, then the current one.
+    fn read_all(dir: &Path) -> String {
+        let mut content = String::new();
+        for n in (1..10).rev() {
+            if let Ok(s) = fs::read_to_string(dir.join(format!("audit.jsonl.{}", n))) {
+                content.push_str(&s);
+            }
+        }
+        content + &fs::read_to_string(dir.join("audit.jsonl")).unwrap()
+    }
+
+    #[test]
+    fn test_file_chain() {
+        let dir = test_dir("chain");
+        let mut sink = file_sink(&dir, 2);
+        for i in 0..6 {
+            sink.write(&event(i)).unwrap();
+        }
+        let key = load_key(&dir.join("audit.key"), false).unwrap();
+        let head = Head::load(&dir.join("head.json")).unwrap();
+        let all = read_all(&dir);
+        assert!(!fs::read_to_string(dir.join("audit.jsonl"))
+            .unwrap()
+            .is_empty());
+        assert_eq!(verify_chain(&all, &key, &head), Ok(all.lines().count()));
+
+        let tampered = all.replacen("\"new\"", "\"old\"", 1);
+        assert_eq!(verify_chain(&tampered, &key, &head), Err(2));
+        // the last line is covered by the head
+        let last = all.lines().last().unwrap();
+        let tampered = all.replace(last, &last.replace("\"new\"", "\"old\""));
+        assert_eq!(
+            verify_chain(&tampered, &key, &head),
+            Err(all.lines().count() + 1)
+        );
+        let mut lines: Vec<&str> = all.lines().collect();
+        lines.pop();
+        let truncated = format!("{}\n", lines.join("\n"));
+        assert_eq!(verify_chain(&truncated, &key, &head), Err(lines.len() + 1));
+        let current = fs::read_to_string(dir.join("audit.jsonl")).unwrap();
+        assert_eq!(verify_chain(&current, &key, &head), Err(1));
+        // a chain rebuilt without the key does not verify
+        assert_eq!(verify_chain(&all, &[0; 32], &head), Err(2));
+
+        // reopened it goes on from the head
+        drop(sink);
+        let mut sink = file_sink(&dir, 2);
+        sink.write(&event(6)).unwrap();
+        let head = Head::load(&dir.join("head.json")).unwrap();
+        assert_eq!(head.next, 7);
+        let all = read_all(&dir);
+        assert_eq!(verify_chain(&all, &key, &head), Ok(all.lines().count()));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_file_rotation_anchor() {
+        let dir = test_dir("anchor");
+        // a file from before the head was kept
+        fs::write(dir.join("audit.jsonl"), "{\"prev\":\"\"}\n").unwrap();
+        let mut sink = file_sink(&dir, 1);
+        for i in 0..12 {
+            sink.write(&event(i)).unwrap();
+        }
+        let key = load_key(&dir.join("audit.key"), false).unwrap();
+        let head = Head::load(&dir.join("head.json")).unwrap();
+        assert!(head.first_seq > 0);
+        // the oldest lines went with the rotated out file
+        let all = read_all(&dir);
+        assert_eq!(verify_chain(&all, &key, &head), Ok(all.lines().count()));
+        assert_eq!(head.next - head.first_seq, all.lines().count() as u64);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_http_queue() {
+        let dir = test_dir("queue");
+        let path = dir.join("queue.jsonl");
+        let mut sink = HttpSink::open(path.clone());
+        assert!(sink.flush_with(|_, _| bail!("unreachable")));
+        for i in 0..5 {
+            sink.append(&json!({"url": "http://audit", "body": {"n": i}}))
+                .unwrap();
+        }
+        let size = fs::metadata(&path).unwrap().len();
+        let mut posted = Vec::new();
+        let mut post = |_: &str, body: &Value| {
+            if posted.len() == 2 {
+                bail!("unreachable");
+            }
+            posted.push(body["n"].as_i64().unwrap());
+            Ok(())
+        };
+        assert!(!sink.flush_with(&mut post));
+        // delivered events are skipped, not rewritten
+        assert_eq!(fs::metadata(&path).unwrap().len(), size);
+        assert_eq!((sink.len, sink.sent), (5, 2));
+
+        let mut sink = HttpSink::open(path.clone());
+        assert_eq!((sink.len, sink.sent), (5, 2));
+        let mut posted = Vec::new();
+        assert!(sink.flush_with(|_, body| {
+            posted.push(body["n"].as_i64().unwrap());
+            Ok(())
+        }));
+        assert_eq!(posted, vec![2, 3, 4]);
+        assert!(!path.exists());
+        assert!(!sink.sent_path().exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_http_queue_compact() {
+        let dir = test_dir("compact");
+        let path = dir.join("queue.jsonl");
+        let mut sink = HttpSink::open(path.clone());
+        for i in 0..COMPACT_AFTER + 10 {
+            sink.append(&json!({"url": "http://audit", "body": {"n": i}}))
+                .unwrap();
+        }
+        let mut n = 0;
+        assert!(!sink.flush_with(|_, _| {
+            n += 1;
+            if n > COMPACT_AFTER {
+                bail!("unreachable");
+            }
+            Ok(())
+        }));
+        assert_eq!((sink.len, sink.sent), (10, 0));
+        let first: Value =
+            serde_json::from_str(fs::read_to_string(&path).unwrap().lines().next().unwrap())
+                .unwrap();
+        assert_eq!(first["body"]["n"], json!(COMPACT_AFTER));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_http_queue_full() {
+        let dir = test_dir("full");
+        let path = dir.join("queue.jsonl");
+        let mut sink = HttpSink::open(path.clone());
+        for i in 0..MAX_QUEUE + 1 {
+            sink.append(&json!({"url": "http://audit", "body": {"n": i}}))
+                .unwrap();
+        }
+        assert_eq!(sink.len, MAX_QUEUE * 3 / 4);
+        let first: Value =
+            serde_json::from_str(fs::read_to_string(&path).unwrap().lines().next().unwrap())
+                .unwrap();
+        assert_eq!(first["body"]["n"], json!(MAX_QUEUE + 1 - MAX_QUEUE * 3 / 4));
+        // room is left, the next event is appended without a rewrite
+        let size = fs::metadata(&path).unwrap().len();
+        sink.append(&json!({"url": "http://audit", "body": {"n": 0}}))
+            .unwrap();
+        assert!(fs::metadata(&path).unwrap().len() > size);
+        assert_eq!(sink.len, MAX_QUEUE * 3 / 4 + 1);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
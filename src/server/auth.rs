@@ -0,0 +1,386 @@
+// Authentication of named accounts for incoming connections, as an alternative
+// to the one shared password. The backend is picked by the "auth-provider" option:
+// "users" for a local users file, "pam" for OS accounts on Linux and "http" for a
+// callback to "auth-http-url". Each backend also decides the permissions granted.
+// The password is only taken over an encrypted connection.
+
+use super::Connection;
+use hbb_common::{
+    bail,
+    config::Config,
+    log,
+    rand::{self, RngCore},
+    serde_json::{self, json},
+    toml, ResultType,
+};
+use serde_derive::Deserialize;
+use std::path::PathBuf;
+use subtle::ConstantTimeEq;
+
+const HASH_PREFIX: &str = "pbkdf2-sha256";
+const HASH_ROUNDS: u32 = 100_000;
+
+/// What a peer presents for a named account login.
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    pub account: String,
+    pub password: String,
+    pub peer_id: String,
+    pub ip: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    pub keyboard: bool,
+    pub clipboard: bool,
+    pub audio: bool,
+    pub file: bool,
+    pub restart: bool,
+    pub recording: bool,
+    pub tunnel: bool,
+}
+
+impl Permissions {
+    /// What the local options allow, used when a backend does not list permissions.
+    pub fn from_config() -> Self {
+        Self {
+            keyboard: Connection::permission("enable-keyboard"),
+            clipboard: Connection::permission("enable-clipboard"),
+            audio: Connection::permission("enable-audio"),
+            file: Connection::permission("enable-file-transfer"),
+            restart: Connection::permission("enable-remote-restart"),
+            recording: Connection::permission("enable-record-session"),
+            tunnel: Connection::permission("enable-tunnel"),
+        }
+    }
+
+    fn none() -> Self {
+        Self {
+            keyboard: false,
+            clipboard: false,
+            audio: false,
+            file: false,
+            restart: false,
+            recording: false,
+            tunnel: false,
+        }
+    }
+
+    /// From names like `["keyboard", "file"]`, `"all"` grants everything.
+    pub fn from_names<S: AsRef<str>>(names: &[S]) -> ResultType<Self> {
+        let mut p = Self::none();
+        for name in names {
+            match name.as_ref() {
+                "all" => {
+                    p = Self {
+                        keyboard: true,
+                        clipboard: true,
+                        audio: true,
+                        file: true,
+                        restart: true,
+                        recording: true,
+                        tunnel: true,
+                    }
+                }
+                "keyboard" => p.keyboard = true,
+                "clipboard" => p.clipboard = true,
+                "audio" => p.audio = true,
+                "file" => p.file = true,
+                "restart" => p.restart = true,
+                "recording" => p.recording = true,
+                "tunnel" => p.tunnel = true,
+                name => bail!("unknown permission {}", name),
+            }
+        }
+        Ok(p)
+    }
+}
+
+pub trait AuthProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// `Ok(None)` for wrong credentials, otherwise what the account may do.
+    /// Called off the async runtime, so it may block.
+    fn authenticate(&self, creds: &Credentials) -> ResultType<Option<Permissions>>;
+}
+
+/// The backend chosen by the options, `None` if named accounts are not enabled.
+pub fn provider() -> ResultType<Option<Box<dyn AuthProvider>>> {
+    Ok(match Config::get_option("auth-provider").as_str() {
+        "" => None,
+        "users" => Some(Box::new(UsersFile {
+            path: users_file_path(),
+        })),
+        #[cfg(all(target_os = "linux", feature = "linux_headless"))]
+        #[cfg(not(any(feature = "flatpak", feature = "appimage")))]
+        "pam" => Some(Box::new(Pam)),
+        "http" => {
+            let url = Config::get_option("auth-http-url");
+            if url.is_empty() {
+                bail!("auth-http-url is not set");
+            }
+            Some(Box::new(HttpCallback { url }))
+        }
+        name => bail!("unsupported auth provider {}", name),
+    })
+}
+
+#[inline]
+pub fn enabled() -> bool {
+    !Config::get_option("auth-provider").is_empty()
+}
+
+fn users_file_path() -> PathBuf {
+    let path = Config::get_option("auth-users-file");
+    if path.is_empty() {
+        Config::path("users.toml")
+    } else {
+        PathBuf::from(path)
+    }
+}
+
+/// What to put in the users file for a password,
+/// `pbkdf2-sha256$<rounds>$<hex salt>$<hex hash>` with a random salt.
+pub fn hash_password(password: &str) -> String {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    encode_hash(&salt, HASH_ROUNDS, password)
+}
+
+fn encode_hash(salt: &[u8], rounds: u32, password: &str) -> String {
+    format!(
+        "{}${}${}${}",
+        HASH_PREFIX,
+        rounds,
+        hex::encode(salt),
+        hex::encode(pbkdf2_sha256(password, salt, rounds))
+    )
+}
+
+fn pbkdf2_sha256(password: &str, salt: &[u8], rounds: u32) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2::<hmac::Hmac<sha2::Sha256>>(password.as_bytes(), salt, rounds, &mut key);
+    key
+}
+
+fn verify_password(hash: &str, password: &str) -> bool {
+    let parts: Vec<&str> = hash.split('$').collect();
+    let parsed = match parts[..] {
+        [HASH_PREFIX, rounds, salt, key] => {
+            match (rounds.parse::<u32>(), hex::decode(salt), hex::decode(key)) {
+                (Ok(rounds), Ok(salt), Ok(key)) if rounds > 0 => Some((rounds, salt, key)),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    match parsed {
+        Some((rounds, salt, key)) => pbkdf2_sha256(password, &salt, rounds)
+            .ct_eq(&key[..])
+            .into(),
+        None => {
+            log::error!("Unsupported password hash in users file");
+            false
+        }
+    }
+}
+
+/// Accounts of the users file:
+///
+/// ```toml
+/// [[user]]
+/// name = "alice"
+/// password = "pbkdf2-sha256$..."
+/// permissions = ["keyboard", "clipboard", "file"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+struct Users {
+    #[serde(default, rename = "user")]
+    users: Vec<User>,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    name: String,
+    password: String,
+    #[serde(default)]
+    permissions: Option<Vec<String>>,
+    #[serde(default)]
+    disabled: bool,
+}
+
+struct UsersFile {
+    path: PathBuf,
+}
+
+impl UsersFile {
+    fn check(content: &str, creds: &Credentials) -> ResultType<Option<Permissions>> {
+        let users: Users = toml::from_str(content)?;
+        let user = match users.users.iter().find(|u| u.name == creds.account) {
+            Some(user) if !user.disabled => user,
+            _ => return Ok(None),
+        };
+        if !verify_password(&user.password, &creds.password) {
+            return Ok(None);
+        }
+        match user.permissions.as_ref() {
+            Some(names) => Permissions::from_names(names).map(Some),
+            None => Ok(Some(Permissions::from_config())),
+        }
+    }
+}
+
+impl AuthProvider for UsersFile {
+    fn name(&self) -> &'static str {
+        "users"
+    }
+
+    // Read on every login so edits apply without a restart.
+    fn authenticate(&self, creds: &Credentials) -> ResultType<Option<Permissions>> {
+        Self::check(&std::fs::read_to_string(&self.path)?, creds)
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "linux_headless"))]
+#[cfg(not(any(feature = "flatpak", feature = "appimage")))]
+struct Pam;
+
+#[cfg(all(target_os = "linux", feature = "linux_headless"))]
+#[cfg(not(any(feature = "flatpak", feature = "appimage")))]
+impl AuthProvider for Pam {
+    fn name(&self) -> &'static str {
+        "pam"
+    }
+
+    fn authenticate(&self, creds: &Credentials) -> ResultType<Option<Permissions>> {
+        let service = if std::path::Path::new("/etc/pam.d/rustdesk").is_file() {
+            "rustdesk"
+        } else {
+            "login"
+        };
+        let mut client = pam::Client::with_password(service)?;
+        client
+            .conversation_mut()
+            .set_credentials(&creds.account, &creds.password);
+        match client.authenticate() {
+            Ok(_) => Ok(Some(Permissions::from_config())),
+            Err(err) => {
+                log::info!("PAM rejected {}: {}", creds.account, err);
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Posts the credentials as JSON, the password included, so the url should be
+/// https. The endpoint answers `{"allow": true, "permissions": ["keyboard", ...]}`.
+struct HttpCallback {
+    url: String,
+}
+
+impl HttpCallback {
+    fn parse_response(body: &str) -> ResultType<Option<Permissions>> {
+        let v: serde_json::Value = serde_json::from_str(body)?;
+        if !v["allow"].as_bool().unwrap_or(false) {
+            return Ok(None);
+        }
+        match v["permissions"].as_array() {
+            Some(names) => {
+                let names: Vec<&str> = names.iter().filter_map(|n| n.as_str()).collect();
+                Permissions::from_names(&names).map(Some)
+            }
+            None => Ok(Some(Permissions::from_config())),
+        }
+    }
+}
+
+impl AuthProvider for HttpCallback {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn authenticate(&self, creds: &Credentials) -> ResultType<Option<Permissions>> {
+        let body = json!({
+            "id": Config::get_id(),
+            "account": creds.account,
+            "password": creds.password,
+            "peer_id": creds.peer_id,
+            "ip": creds.ip,
+        });
+        let resp = crate::post_request_sync(self.url.clone(), body.to_string(), "")?;
+        Self::parse_response(&resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_users_file() {
+        let key = encode_hash(b"salt", 1000, "secret");
+        let content = format!(
+            r#"
+            [[user]]
+            name = "alice"
+            password = "{}"
+            permissions = ["keyboard", "file"]
+
+            [[user]]
+            name = "bob"
+            password = "{}"
+            disabled = true
+            "#,
+            key,
+            hash_password("secret"),
+        );
+        let login = |account: &str, password: &str| Credentials {
+            account: account.to_owned(),
+            password: password.to_owned(),
+            ..Default::default()
+        };
+        let p = UsersFile::check(&content, &login("alice", "secret"))
+            .unwrap()
+            .unwrap();
+        assert!(p.keyboard && p.file);
+        assert!(!p.clipboard && !p.tunnel);
+        assert!(UsersFile::check(&content, &login("alice", "wrong"))
+            .unwrap()
+            .is_none());
+        // the hash is not the password
+        let hash = key.rsplit('$').next().unwrap();
+        assert!(UsersFile::check(&content, &login("alice", hash))
+            .unwrap()
+            .is_none());
+        assert!(UsersFile::check(&content, &login("bob", "secret"))
+            .unwrap()
+            .is_none());
+        let old = content.replace(&key, "pbkdf2-sha256$00");
+        assert!(UsersFile::check(&old, &login("alice", "secret"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_hash_password() {
+        let a = hash_password("secret");
+        let b = hash_password("secret");
+        // salted per account
+        assert_ne!(a, b);
+        assert!(verify_password(&a, "secret") && verify_password(&b, "secret"));
+        assert!(!verify_password(&a, "Secret"));
+        assert!(!verify_password(&a, ""));
+    }
+
+    #[test]
+    fn test_http_response() {
+        assert!(HttpCallback::parse_response(r#"{"allow": false}"#)
+            .unwrap()
+            .is_none());
+        let p = HttpCallback::parse_response(r#"{"allow": true, "permissions": ["all"]}"#)
+            .unwrap()
+            .unwrap();
+        assert!(p.keyboard && p.tunnel);
+        assert!(HttpCallback::parse_response(r#"{"allow": true, "permissions": ["x"]}"#).is_err());
+    }
+}
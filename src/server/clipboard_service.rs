@@ -1,51 +1,63 @@
 use super::*;
+use crate::clipboard_data::ClipboardData;
 pub use crate::common::{
-    check_clipboard, ClipboardContext, CLIPBOARD_INTERVAL as INTERVAL, CLIPBOARD_NAME as NAME,
+    check_clipboard_data, ClipboardContext, CLIPBOARD_INTERVAL as INTERVAL, CLIPBOARD_NAME as NAME,
     CONTENT,
 };
-
-struct State {
-    ctx: Option<ClipboardContext>,
-}
-
-impl Default for State {
-    fn default() -> Self {
-        let ctx = match ClipboardContext::new() {
-            Ok(ctx) => Some(ctx),
-            Err(err) => {
-                log::error!("Failed to start {}: {}", NAME, err);
-                None
-            }
-        };
-        Self { ctx }
-    }
-}
-
-impl super::service::Reset for State {
-    fn reset(&mut self) {
-        *CONTENT.lock().unwrap() = Default::default();
-    }
-}
+use clipboard_policy::Direction;
+use serde_json::json;
 
 pub fn new() -> GenericService {
     let sp = GenericService::new(NAME, true);
-    sp.repeat::<State, _>(INTERVAL, run);
+    sp.run(run);
     sp
 }
 
-fn run(sp: GenericService, state: &mut State) -> ResultType<()> {
-    if let Some(ctx) = state.ctx.as_mut() {
-        if let Some(msg) = check_clipboard(ctx, None) {
-            sp.send(msg);
+fn run(sp: GenericService) -> ResultType<()> {
+    let mut ctx = ClipboardContext::new()?;
+    let mut watcher = crate::clipboard_watcher::new();
+    let mut changed = true;
+    while sp.ok() {
+        if changed {
+            if let Some(data) = check_clipboard_data(&mut ctx, None) {
+                for msg in outgoing(data) {
+                    // recorded once, in the form current peers take
+                    if !msg.has_clipboard() {
+                        super::video_service::record_message(&msg);
+                    }
+                    sp.send(msg);
+                }
+            }
         }
         sp.snapshot(|sps| {
-            let txt = crate::CONTENT.lock().unwrap().clone();
-            if !txt.is_empty() {
-                let msg_out = crate::create_clipboard_msg(txt);
+            let data = CONTENT.lock().unwrap().data.clone();
+            for msg_out in outgoing(data) {
                 sps.send_shared(Arc::new(msg_out));
             }
             Ok(())
         })?;
+        // also bounds how long a new connection waits for the snapshot
+        changed = watcher.wait(Duration::from_millis(INTERVAL));
     }
+    *CONTENT.lock().unwrap() = Default::default();
     Ok(())
 }
+
+/// The messages of a copy going to peers, none if the policy blocks it.
+fn outgoing(data: ClipboardData) -> Vec<Message> {
+    let size: usize = data.formats.iter().map(|f| f.content.len()).sum();
+    match clipboard_policy::check(Direction::HostToClient, data) {
+        Ok(data) => data.to_messages(),
+        Err(blocked) => {
+            Connection::post_alarm_audit(
+                AlarmAuditType::ClipboardBlocked,
+                json!({
+                    "direction": Direction::HostToClient.name(),
+                    "reason": blocked.to_string(),
+                    "size": size,
+                }),
+            );
+            Vec::new()
+        }
+    }
+}
@@ -1,8 +1,10 @@
     use super::{input_service::*, *};
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+use crate::clipboard_data::{ChunkAssembler, ClipboardData};
 #[cfg(windows)]
 use crate::clipboard_file::*;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
-use crate::common::update_clipboard;
+use crate::common::update_clipboard_data;
 #[cfg(all(target_os = "linux", feature = "linux_headless"))]
 #[cfg(not(any(feature = "flatpak", feature = "appimage")))]
 use crate::platform::linux_desktop_manager;
@@ -13,6 +15,8 @@ use crate::{
         new_voice_call_request, new_voice_call_response, start_audio_thread, MediaData, MediaSender,
     },
     common::{get_default_sound_input, set_sound_input},
+    server::{audit, auth, lockout},
+    tunnel::{self, Tunnels},
     video_service,
 };
 #[cfg(any(target_os = "android", target_os = "ios"))]
@@ -24,15 +28,16 @@ use cidr_utils::cidr::IpCidr;
 use hbb_common::platform::linux::run_cmds;
 use hbb_common::{
     config::Config,
-    fs,
+    dir_sync, fs,
     fs::can_enable_overwrite_detection,
     futures::{SinkExt, StreamExt},
     get_time, get_version_number,
     message_proto::{option_message::BoolOption, permission_info::Permission},
     password_security::{self as password, ApproveMode},
+    rekey::Rekeying,
     sleep, timeout,
     tokio::{
-        net::TcpStream,
+        net::{lookup_host, TcpListener, TcpStream},
         sync::mpsc,
         time::{self, Duration, Instant, Interval},
     },
@@ -57,7 +62,6 @@ use std::collections::HashSet;
 pub type Sender = mpsc::UnboundedSender<(Instant, Arc<Message>)>;
 
 lazy_static::lazy_static! {
-    static ref LOGIN_FAILURES: Arc::<Mutex<HashMap<String, (i32, i32, i32)>>> = Default::default();
     static ref SESSIONS: Arc::<Mutex<HashMap<String, Session>>> = Default::default();
     static ref ALIVE_CONNS: Arc::<Mutex<Vec<i32>>> = Default::default();
     static ref SWITCH_SIDES_UUID: Arc::<Mutex<HashMap<String, (Instant, uuid::Uuid)>>> = Default::default();
@@ -143,8 +147,16 @@ pub struct Connection {
     read_jobs: Vec<fs::TransferJob>,
     timer: Interval,
     file_timer: Interval,
+    rekeying: Rekeying,
     file_transfer: Option<(String, bool)>,
     port_forward_socket: Option<Framed<TcpStream, BytesCodec>>,
+    // Port to listen on once the login is accepted, then the listener.
+    reverse_port_forward_port: Option<u16>,
+    reverse_port_forward_listener: Option<TcpListener>,
+    udp_port_forward_target: Option<(SocketAddr, Duration)>,
+    // Targets the peer declared for TCP connections multiplexed as TunnelFrame,
+    // empty unless multiplexed.
+    mux_port_forward: Vec<String>,
     port_forward_address: String,
     tx_to_cm: mpsc::UnboundedSender<ipc::Data>,
     authorized: bool,
@@ -189,6 +201,8 @@ pub struct Connection {
     options_in_login: Option<OptionMessage>,
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     pressed_modifiers: HashSet<rdev::Key>,
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    clipboard_chunks: ChunkAssembler,
     #[cfg(all(target_os = "linux", feature = "linux_headless"))]
     #[cfg(not(any(feature = "flatpak", feature = "appimage")))]
     linux_headless_handle: LinuxHeadlessHandle,
@@ -282,8 +296,13 @@ impl Connection {
             read_jobs: Vec::new(),
             timer: time::interval(SEC30),
             file_timer: time::interval(SEC30),
+            rekeying: Default::default(),
             file_transfer: None,
             port_forward_socket: None,
+            reverse_port_forward_port: None,
+            reverse_port_forward_listener: None,
+            udp_port_forward_target: None,
+            mux_port_forward: Vec::new(),
             port_forward_address: "".to_owned(),
             tx_to_cm,
             authorized: false,
@@ -321,6 +340,8 @@ impl Connection {
             options_in_login: None,
             #[cfg(not(any(target_os = "android", target_os = "ios")))]
             pressed_modifiers: Default::default(),
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            clipboard_chunks: Default::default(),
             #[cfg(all(target_os = "linux", feature = "linux_headless"))]
             #[cfg(not(any(feature = "flatpak", feature = "appimage")))]
             linux_headless_handle,
@@ -366,16 +387,15 @@ impl Connection {
             time::interval_at(Instant::now() + TEST_DELAY_TIMEOUT, TEST_DELAY_TIMEOUT);
         let mut last_recv_time = Instant::now();
 
-        conn.stream.set_send_timeout(
-            if conn.file_transfer.is_some() || conn.port_forward_socket.is_some() {
+        conn.stream
+            .set_send_timeout(if conn.file_transfer.is_some() || conn.is_port_forward() {
                 SEND_TIMEOUT_OTHER
             } else {
                 SEND_TIMEOUT_VIDEO
-            },
-        );
+            });
 
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
-        std::thread::spawn(move || Self::handle_input(_rx_input, tx_cloned));
+        std::thread::spawn(move || Self::handle_input(_rx_input, tx_cloned, id));
         let mut second_timer = time::interval(Duration::from_secs(1));
 
         loop {
@@ -386,7 +406,7 @@ impl Connection {
                     match data {
                         ipc::Data::Authorize => {
                             conn.send_logon_response().await;
-                            if conn.port_forward_socket.is_some() {
+                            if conn.is_port_forward() {
                                 break;
                             }
                         }
@@ -449,7 +469,9 @@ impl Connection {
                         }
                         #[cfg(windows)]
                         ipc::Data::ClipboardFile(clip) => {
-                            allow_err!(conn.stream.send(&clip_2_msg(clip)).await);
+                            if conn.allow_clip(clipboard_policy::Direction::HostToClient, &clip) {
+                                allow_err!(conn.stream.send(&clip_2_msg(clip)).await);
+                            }
                         }
                         ipc::Data::PrivacyModeState((_, state)) => {
                             let msg_out = match state {
@@ -528,7 +550,7 @@ impl Connection {
                     }
                 },
                 _ = conn.file_timer.tick() => {
-                    if !conn.read_jobs.is_empty() {
+                    if !fs::all_paused(&conn.read_jobs) {
                         if let Err(err) = fs::handle_read_jobs(&mut conn.read_jobs, &mut conn.stream).await {
                             conn.on_close(&err.to_string(), false).await;
                             break;
@@ -566,6 +588,10 @@ impl Connection {
                             _ => {}
                         }
                     }
+                    let version = get_version_number(&conn.lr.version);
+                    if !crate::clipboard_data::is_for_peer(msg, version) {
+                        continue;
+                    }
                     match &msg.union {
                         Some(message::Union::Misc(m)) => {
                             match &m.union {
@@ -625,6 +651,7 @@ impl Connection {
             conn.lr.my_id.clone(),
         );
         video_service::notify_video_frame_fetched(id, None);
+        video_service::remove_recorder(id);
         scrap::codec::Encoder::update(id, scrap::codec::EncodingUpdate::Remove);
         if conn.authorized {
             password::update_temporary_password();
@@ -647,7 +674,7 @@ impl Connection {
     }
 
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
-    fn handle_input(receiver: std_mpsc::Receiver<MessageInput>, tx: Sender) {
+    fn handle_input(receiver: std_mpsc::Receiver<MessageInput>, tx: Sender, id: i32) {
         let mut block_input_mode = false;
         #[cfg(target_os = "windows")]
         {
@@ -732,15 +759,33 @@ impl Connection {
                 }
             }
         }
+        release_pointer_as_mouse(id);
         #[cfg(target_os = "linux")]
         clear_remapped_keycode();
         log::info!("Input thread exited");
     }
 
+    #[inline]
+    fn is_port_forward(&self) -> bool {
+        self.port_forward_socket.is_some()
+            || self.reverse_port_forward_port.is_some()
+            || self.reverse_port_forward_listener.is_some()
+            || self.udp_port_forward_target.is_some()
+            || !self.mux_port_forward.is_empty()
+    }
+
     async fn try_port_forward_loop(
         &mut self,
         rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
     ) -> ResultType<()> {
+        let listener = self.reverse_port_forward_listener.take();
+        let udp_target = self.udp_port_forward_target.take();
+        let mux = std::mem::take(&mut self.mux_port_forward);
+        if listener.is_some() || udp_target.is_some() || !mux.is_empty() {
+            return self
+                .tunnel_port_forward_loop(listener, udp_target, mux, rx_from_cm)
+                .await;
+        }
         let mut last_recv_time = Instant::now();
         if let Some(mut forward) = self.port_forward_socket.take() {
             log::info!("Running port forwarding loop");
@@ -789,6 +834,119 @@ impl Connection {
         Ok(())
     }
 
+    // Unlike a TCP forward the stream stays framed, connections accepted on the
+    // reverse listener, UDP flows to the target or connections to the `mux`
+    // targets the peer declared are multiplexed as TunnelFrame.
+    async fn tunnel_port_forward_loop(
+        &mut self,
+        listener: Option<TcpListener>,
+        udp_target: Option<(SocketAddr, Duration)>,
+        mux: Vec<String>,
+        rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
+    ) -> ResultType<()> {
+        log::info!(
+            "Running tunnel port forwarding loop for {}",
+            self.port_forward_address
+        );
+        let mut last_recv_time = Instant::now();
+        let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
+        let (tx_frame, mut rx_frame) = tunnel::frame_channel();
+        let mut tunnels = Tunnels::default();
+        let mut next_id = 0;
+        loop {
+            tokio::select! {
+                Some(data) = rx_from_cm.recv() => {
+                    match data {
+                        ipc::Data::Close => {
+                            bail!("Close requested from selection manager");
+                        }
+                        _ => {}
+                    }
+                }
+                res = async {
+                    match listener.as_ref() {
+                        Some(listener) => listener.accept().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    let (sock, addr) = res?;
+                    next_id += 1;
+                    log::info!("reverse port forwarding connection #{} from {:?}", next_id, addr);
+                    tunnels.add(next_id, sock, tx_frame.clone());
+                    // Open it on the other side before any data, the protocol may be server first.
+                    self.stream.send(&tunnel::new_frame(next_id, Default::default(), false)).await?;
+                }
+                Some(frame) = rx_frame.recv() => {
+                    if frame.close && !tunnels.remove(frame.id) {
+                        continue;
+                    }
+                    self.stream.send(&tunnel::new_frame(frame.id, frame.data, frame.close)).await?;
+                }
+                res = self.stream.next() => {
+                    if let Some(res) = res {
+                        last_recv_time = Instant::now();
+                        let msg = Message::parse_from_bytes(&res?)?;
+                        if let Some(message::Union::TunnelFrame(frame)) = msg.union {
+                            if let Some((target, idle)) = udp_target {
+                                if !frame.close && !tunnels.contains(frame.id) {
+                                    // A datagram from a new client of the controlling side.
+                                    if tunnels.len() >= tunnel::MAX_UDP_FLOWS {
+                                        log::warn!("Too many udp flows to {}, refusing #{}", target, frame.id);
+                                        self.stream.send(&tunnel::new_frame(frame.id, Default::default(), true)).await?;
+                                        continue;
+                                    }
+                                    match tunnel::connect_udp(target).await {
+                                        Ok(sock) => tunnels.add_udp(frame.id, sock, tx_frame.clone(), idle),
+                                        Err(err) => {
+                                            log::error!("Failed to open udp flow to {}: {}", target, err);
+                                            self.stream.send(&tunnel::new_frame(frame.id, Default::default(), true)).await?;
+                                            continue;
+                                        }
+                                    }
+                                }
+                            } else if !mux.is_empty() && !frame.close && !tunnels.contains(frame.id) {
+                                let target = format!("{}:{}", frame.host, frame.port);
+                                if !mux.contains(&target) {
+                                    log::warn!("Refusing tunnel #{} to undeclared {}", frame.id, target);
+                                    self.stream.send(&tunnel::new_frame(frame.id, Default::default(), true)).await?;
+                                    continue;
+                                }
+                                if tunnels.len() >= tunnel::MAX_MUX_TUNNELS {
+                                    log::warn!("Too many tunnels, refusing #{} to {}", frame.id, target);
+                                    self.stream.send(&tunnel::new_frame(frame.id, Default::default(), true)).await?;
+                                    continue;
+                                }
+                                log::info!("Tunnel #{} to {}", frame.id, target);
+                                self.post_conn_audit(json!({
+                                    "action": "tunnel",
+                                    "tunnel_id": frame.id,
+                                    "target": target,
+                                }));
+                                tunnels.connect(frame.id, target, tx_frame.clone());
+                            }
+                            let id = frame.id;
+                            if !tunnels.handle_frame(frame) {
+                                self.stream.send(&tunnel::new_frame(id, Default::default(), true)).await?;
+                            }
+                        }
+                    } else {
+                        bail!("Stream reset by the peer");
+                    }
+                },
+                _ = self.timer.tick() => {
+                    if tunnels.len() == 0 && last_recv_time.elapsed() >= H1 {
+                        bail!("Timeout");
+                    }
+                }
+                Ok(conns) = hbbs_rx.recv() => {
+                    if conns.contains(&self.inner.id) {
+                        bail!("Closed manually by the web console");
+                    }
+                }
+            }
+        }
+    }
+
     async fn send_permission(&mut self, permission: Permission, enabled: bool) {
         let mut misc = Misc::new();
         misc.set_permission_info(PermissionInfo {
@@ -846,6 +1004,7 @@ impl Connection {
             return false;
         }
         self.ip = addr.ip().to_string();
+        self.hash.account_password = auth::enabled() && self.stream.is_secured();
         let mut msg_out = Message::new();
         msg_out.set_hash(self.hash.clone());
         self.send(msg_out).await;
@@ -871,18 +1030,12 @@ impl Connection {
     }
 
     fn post_conn_audit(&self, v: Value) {
-        if self.server_audit_conn.is_empty() {
-            return;
-        }
-        let url = self.server_audit_conn.clone();
         let mut v = v;
         v["id"] = json!(Config::get_id());
         v["uuid"] = json!(crate::encode64(hbb_common::get_uuid()));
         v["conn_id"] = json!(self.inner.id);
         v["session_id"] = json!(self.lr.session_id);
-        tokio::spawn(async move {
-            allow_err!(Self::post_audit_async(url, v).await);
-        });
+        audit::record(audit::Kind::Conn, &self.server_audit_conn, v);
     }
 
     fn post_file_audit(
@@ -892,10 +1045,6 @@ impl Connection {
         files: Vec<(String, i64)>,
         info: Value,
     ) {
-        if self.server_audit_file.is_empty() {
-            return;
-        }
-        let url = self.server_audit_file.clone();
         let file_num = files.len();
         let mut files = files;
         files.sort_by(|a, b| b.1.cmp(&a.1));
@@ -915,9 +1064,7 @@ impl Connection {
             "is_file":is_file,
             "info":json!(info).to_string(),
         });
-        tokio::spawn(async move {
-            allow_err!(Self::post_audit_async(url, v).await);
-        });
+        audit::record(audit::Kind::File, &self.server_audit_file, v);
     }
 
     pub fn post_alarm_audit(typ: AlarmAuditType, info: Value) {
@@ -926,22 +1073,81 @@ impl Connection {
             Config::get_option("custom-rendezvous-server"),
             "alarm".to_owned(),
         );
-        if url.is_empty() {
-            return;
-        }
         let mut v = Value::default();
         v["id"] = json!(Config::get_id());
         v["uuid"] = json!(crate::encode64(hbb_common::get_uuid()));
         v["typ"] = json!(typ as i8);
         v["info"] = serde_json::Value::String(info.to_string());
-        tokio::spawn(async move {
-            allow_err!(Self::post_audit_async(url, v).await);
-        });
+        audit::record(audit::Kind::Alarm, &url, v);
     }
 
-    #[inline]
-    async fn post_audit_async(url: String, v: Value) -> ResultType<String> {
-        crate::post_request(url, v.to_string(), "").await
+    /// Writes a copy of the peer to the clipboard, as far as the policy allows.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn paste(&self, data: ClipboardData) {
+        let size: usize = data.formats.iter().map(|f| f.content.len()).sum();
+        let direction = clipboard_policy::Direction::ClientToHost;
+        match clipboard_policy::check(direction, data) {
+            Ok(data) => {
+                // only the formats and sizes of what was written
+                for f in data.formats.iter() {
+                    let mut msg = Message::new();
+                    msg.set_clipboard_chunk(ClipboardChunk {
+                        mime_type: f.mime_type.clone(),
+                        total_size: f.content.len() as _,
+                        last: true,
+                        ..Default::default()
+                    });
+                    video_service::record_viewer_message(self.inner.id(), &msg);
+                }
+                update_clipboard_data(data, None);
+            }
+            Err(blocked) => Self::post_alarm_audit(
+                AlarmAuditType::ClipboardBlocked,
+                json!({
+                    "ip": self.ip,
+                    "id": self.lr.my_id,
+                    "name": self.lr.my_name,
+                    "direction": direction.name(),
+                    "reason": blocked.to_string(),
+                    "size": size,
+                }),
+            ),
+        }
+    }
+
+    /// Whether a message of the file clipboard may go `direction`. Only the
+    /// announcement of a copy is audited, the data asked for after it is
+    /// stopped quietly; requests and answers without data always pass.
+    #[cfg(windows)]
+    fn allow_clip(
+        &self,
+        direction: clipboard_policy::Direction,
+        clip: &ipc::ClipboardFile,
+    ) -> bool {
+        match clip {
+            ipc::ClipboardFile::FormatList { .. }
+            | ipc::ClipboardFile::FormatDataResponse { .. }
+            | ipc::ClipboardFile::FileContentsResponse { .. } => {}
+            _ => return true,
+        }
+        if clipboard_policy::allows(direction) {
+            return true;
+        }
+        if let ipc::ClipboardFile::FormatList { .. } = clip {
+            log::warn!("File clipboard {} blocked", direction.name());
+            Self::post_alarm_audit(
+                AlarmAuditType::ClipboardBlocked,
+                json!({
+                    "ip": self.ip,
+                    "id": self.lr.my_id,
+                    "name": self.lr.my_name,
+                    "direction": direction.name(),
+                    "reason": clipboard_policy::Blocked::Direction.to_string(),
+                    "files": true,
+                }),
+            );
+        }
+        false
     }
 
     async fn send_logon_response(&mut self) {
@@ -950,7 +1156,7 @@ impl Connection {
         }
         let conn_type = if self.file_transfer.is_some() {
             1
-        } else if self.port_forward_socket.is_some() {
+        } else if self.is_port_forward() {
             2
         } else {
             0
@@ -996,7 +1202,17 @@ impl Connection {
 
         pi.encoding = Some(scrap::codec::Encoder::supported_encoding()).into();
 
-        if self.port_forward_socket.is_some() {
+        if self.is_port_forward() {
+            if let Some(port) = self.reverse_port_forward_port.take() {
+                match TcpListener::bind(("127.0.0.1", port)).await {
+                    Ok(listener) => self.reverse_port_forward_listener = Some(listener),
+                    Err(err) => {
+                        self.send_login_error(format!("Failed to listen on {}: {}", port, err))
+                            .await;
+                        return;
+                    }
+                }
+            }
             let mut msg_out = Message::new();
             res.set_peer_info(pi);
             msg_out.set_login_response(res);
@@ -1004,7 +1220,7 @@ impl Connection {
             return;
         }
         #[cfg(target_os = "linux")]
-        if !self.file_transfer.is_some() && !self.port_forward_socket.is_some() {
+        if !self.file_transfer.is_some() && !self.is_port_forward() {
             let dtype = crate::platform::linux::get_display_server();
             if dtype != crate::platform::linux::DISPLAY_SERVER_X11
                 && dtype != crate::platform::linux::DISPLAY_SERVER_WAYLAND
@@ -1245,6 +1461,77 @@ impl Connection {
         false
     }
 
+    // A named account is checked by the configured provider, which also decides
+    // the permissions, otherwise the shared passwords are.
+    async fn authenticate(&mut self) -> Option<auth::Permissions> {
+        let account = match self.lr.account.as_ref() {
+            Some(account) if !account.name.is_empty() => account.clone(),
+            _ => {
+                return if self.validate_password() {
+                    Some(auth::Permissions::from_config())
+                } else {
+                    None
+                };
+            }
+        };
+        let provider = match auth::provider() {
+            Ok(Some(provider)) => provider,
+            Ok(None) => {
+                log::warn!(
+                    "Account login of {} while no auth provider is set",
+                    account.name
+                );
+                return None;
+            }
+            Err(err) => {
+                log::error!("Failed to load auth provider: {}", err);
+                return None;
+            }
+        };
+        if !self.stream.is_secured() {
+            log::warn!(
+                "Account login of {} over an unencrypted connection",
+                account.name
+            );
+            return None;
+        }
+        let creds = auth::Credentials {
+            account: account.name.clone(),
+            password: account.password.clone(),
+            peer_id: self.lr.my_id.clone(),
+            ip: self.ip.clone(),
+        };
+        let name = provider.name();
+        let res = tokio::task::spawn_blocking(move || provider.authenticate(&creds)).await;
+        let permissions = match res {
+            Ok(Ok(Some(permissions))) => permissions,
+            Ok(Ok(None)) => return None,
+            Ok(Err(err)) => {
+                log::error!("Auth provider {} failed: {}", name, err);
+                return None;
+            }
+            Err(err) => {
+                log::error!("Auth provider {} panicked: {}", name, err);
+                return None;
+            }
+        };
+        if self.is_port_forward() && !permissions.tunnel {
+            log::info!("Account {} is not allowed to tunnel", account.name);
+            return None;
+        }
+        log::info!("Account {} logged in with {}", account.name, name);
+        Some(permissions)
+    }
+
+    fn apply_permissions(&mut self, permissions: auth::Permissions) {
+        self.keyboard = permissions.keyboard;
+        self.clipboard = permissions.clipboard;
+        self.audio = permissions.audio;
+        self.file = permissions.file;
+        self.restart = permissions.restart;
+        self.recording = permissions.recording;
+    }
+
     fn is_recent_session(&mut self) -> bool {
         let session = SESSIONS
             .lock()
@@ -1354,23 +1641,78 @@ impl Connection {
                     }
                     let mut addr = format!("{}:{}", pf.host, pf.port);
                     self.port_forward_address = addr.clone();
-                    match timeout(3000, TcpStream::connect(&addr)).await {
-                        Ok(Ok(sock)) => {
-                            self.port_forward_socket = Some(Framed::new(sock, BytesCodec::new()));
-                        }
-                        _ => {
-                            if is_rdp {
-                                addr = "RDP".to_owned();
+                    if !is_rdp && pf.protocol.enum_value() == Ok(port_forward::Protocol::MULTIPLEX)
+                    {
+                        let valid = |t: &String| match t.rsplit_once(':') {
+                            Some((host, port)) => {
+                                !host.is_empty() && port.parse::<u16>().map_or(false, |p| p > 0)
                             }
-                            self.send_login_error(format!(
-                                "Failed to access remote {}, please make sure if it is open",
-                                addr
-                            ))
-                            .await;
+                            None => false,
+                        };
+                        if pf.targets.is_empty() || !pf.targets.iter().all(valid) {
+                            self.send_login_error("Invalid tunnel targets").await;
                             return false;
                         }
+                        self.port_forward_address =
+                            format!("{} (multiplexed)", pf.targets.join(", "));
+                        self.mux_port_forward = pf.targets.clone();
+                    } else if !is_rdp && pf.protocol.enum_value() == Ok(port_forward::Protocol::UDP)
+                    {
+                        let target = match timeout(3000, lookup_host(&addr)).await {
+                            Ok(Ok(mut addrs)) => addrs.next(),
+                            _ => None,
+                        };
+                        match target {
+                            Some(target) => {
+                                self.port_forward_address = format!("{} (udp)", addr);
+                                self.udp_port_forward_target =
+                                    Some((target, tunnel::udp_idle_timeout(pf.idle_timeout)));
+                            }
+                            None => {
+                                self.send_login_error(format!("Failed to resolve remote {}", addr))
+                                    .await;
+                                return false;
+                            }
+                        }
+                    } else {
+                        match timeout(3000, TcpStream::connect(&addr)).await {
+                            Ok(Ok(sock)) => {
+                                self.port_forward_socket =
+                                    Some(Framed::new(sock, BytesCodec::new()));
+                            }
+                            _ => {
+                                if is_rdp {
+                                    addr = "RDP".to_owned();
+                                }
+                                self.send_login_error(format!(
+                                    "Failed to access remote {}, please make sure if it is open",
+                                    addr
+                                ))
+                                .await;
+                                return false;
+                            }
+                        }
                     }
                 }
+                Some(login_request::Union::ReversePortForward(rpf)) => {
+                    // Listening on the controlled side is opt-in on top of the tunnel permission.
+                    if !Connection::permission("enable-tunnel")
+                        || Config::get_option("enable-reverse-tunnel") != "Y"
+                    {
+                        self.send_login_error("No permission of reverse IP tunneling")
+                            .await;
+                        sleep(1.).await;
+                        return false;
+                    }
+                    if rpf.port <= 0 || rpf.port > u16::MAX as i32 {
+                        self.send_login_error(format!("Invalid port {}", rpf.port))
+                            .await;
+                        return false;
+                    }
+                    self.port_forward_address = format!("127.0.0.1:{} (reverse)", rpf.port);
+                    // bound in send_logon_response, nothing listens before the login is accepted
+                    self.reverse_port_forward_port = Some(rpf.port as u16);
+                }
                 _ => {
                     if !self.check_privacy_mode_on().await {
                         return false;
@@ -1397,6 +1739,8 @@ impl Connection {
                 return true;
             }
 
+            // Named accounts stand in for the shared password.
+            let has_password = password::has_valid_password() || auth::enabled();
             if !hbb_common::is_ip_str(&lr.username)
                 && !hbb_common::is_domain_port_str(&lr.username)
                 && lr.username != Config::get_id()
@@ -1405,7 +1749,7 @@ impl Connection {
                     .await;
                 return false;
             } else if password::approve_mode() == ApproveMode::Click
-                || password::approve_mode() == ApproveMode::Both && !password::has_valid_password()
+                || password::approve_mode() == ApproveMode::Both && !has_password
             {
                 self.try_start_cm(lr.my_id, lr.my_name, false);
                 if hbb_common::get_version_number(&lr.version)
@@ -1415,9 +1759,7 @@ impl Connection {
                         .await;
                 }
                 return true;
-            } else if password::approve_mode() == ApproveMode::Password
-                && !password::has_valid_password()
-            {
+            } else if password::approve_mode() == ApproveMode::Password && !has_password {
                 self.send_login_error("Connection not allowed").await;
                 return false;
             } else if self.is_recent_session() {
@@ -1427,7 +1769,7 @@ impl Connection {
                     self.linux_headless_handle.wait_desktop_cm_ready().await;
                     self.try_start_cm(lr.my_id.clone(), lr.my_name.clone(), true);
                     self.send_logon_response().await;
-                    if self.port_forward_socket.is_some() {
+                    if self.is_port_forward() {
                         return false;
                     }
                 } else {
@@ -1443,69 +1785,74 @@ impl Connection {
                     .await;
                 }
             } else {
-                let mut failure = LOGIN_FAILURES
-                    .lock()
-                    .unwrap()
-                    .get(&self.ip)
-                    .map(|x| x.clone())
-                    .unwrap_or((0, 0, 0));
-                let time = (get_time() / 60_000) as i32;
-                if failure.2 > 30 {
-                    self.send_login_error("Too many wrong password attempts")
-                        .await;
-                    Self::post_alarm_audit(
-                        AlarmAuditType::ManyWrongPassword,
-                        json!({
-                                    "ip":self.ip,
-                        }),
-                    );
-                } else if time == failure.0 && failure.1 > 6 {
-                    self.send_login_error("Please try 1 minute later").await;
-                    Self::post_alarm_audit(
-                        AlarmAuditType::FrequentAttempt,
-                        json!({
-                                    "ip":self.ip,
-                        }),
-                    );
-                } else if !self.validate_password() {
-                    if failure.0 == time {
-                        failure.1 += 1;
-                        failure.2 += 1;
-                    } else {
-                        failure.0 = time;
-                        failure.1 = 1;
-                        failure.2 += 1;
-                    }
-                    LOGIN_FAILURES
-                        .lock()
-                        .unwrap()
-                        .insert(self.ip.clone(), failure);
-                    if err_msg.is_empty() {
-                        self.send_login_error(crate::client::LOGIN_MSG_PASSWORD_WRONG)
-                            .await;
-                        self.try_start_cm(lr.my_id, lr.my_name, false);
-                    } else {
-                        self.send_login_error(
-                            crate::client::LOGIN_MSG_DESKTOP_SESSION_NOT_READY_PASSWORD_WRONG,
-                        )
-                        .await;
-                    }
+                let ip_keys = if lockout::is_allowed(&self.ip) {
+                    vec![]
                 } else {
-                    if failure.0 != 0 {
-                        LOGIN_FAILURES.lock().unwrap().remove(&self.ip);
-                    }
+                    vec![lockout::ip_key(&self.ip)]
+                };
+                // The peer id is not verified, anyone can claim someone else's, so
+                // it is only counted for the alarms.
+                let mut keys = ip_keys.clone();
+                if !lr.my_id.is_empty() {
+                    keys.push(lockout::id_key(&lr.my_id));
+                }
+                if let Some(seconds) = lockout::locked(&ip_keys) {
+                    self.send_login_error(format!(
+                        "Too many wrong password attempts, please try {} seconds later",
+                        seconds
+                    ))
+                    .await;
+                } else if let Some(permissions) = self.authenticate().await {
+                    lockout::on_success(&keys);
                     if err_msg.is_empty() {
                         #[cfg(all(target_os = "linux", feature = "linux_headless"))]
                         #[cfg(not(any(feature = "flatpak", feature = "appimage")))]
                         self.linux_headless_handle.wait_desktop_cm_ready().await;
+                        // before the cm and the peer are told what is allowed
+                        self.apply_permissions(permissions);
                         self.send_logon_response().await;
                         self.try_start_cm(lr.my_id, lr.my_name, true);
-                        if self.port_forward_socket.is_some() {
+                        if self.is_port_forward() {
                             return false;
                         }
                     } else {
                         self.send_login_error(err_msg).await;
                     }
+                } else {
+                    for l in lockout::on_failure(&keys) {
+                        if ip_keys.contains(&l.key) {
+                            log::warn!("{} locked out for {}s", l.key, l.seconds);
+                        } else {
+                            log::warn!("Too many failed logins claiming {}", l.key);
+                        }
+                        let typ = match l.reason {
+                            lockout::Reason::Frequent => AlarmAuditType::FrequentAttempt,
+                            lockout::Reason::Many => AlarmAuditType::ManyWrongPassword,
+                        };
+                        Self::post_alarm_audit(
+                            typ,
+                            json!({
+                                "ip": self.ip,
+                                "id": lr.my_id,
+                                "key": l.key,
+                                "seconds": l.seconds,
+                            }),
+                        );
+                    }
+                    if err_msg.is_empty() {
+                        self.send_login_error(crate::client::LOGIN_MSG_PASSWORD_WRONG)
+                            .await;
+                        // Accepting a failed account login by click would grant the
+                        // default permissions instead of the account's.
+                        if lr.account.name.is_empty() {
+                            self.try_start_cm(lr.my_id, lr.my_name, false);
+                        }
+                    } else {
+                        self.send_login_error(
+                            crate::client::LOGIN_MSG_DESKTOP_SESSION_NOT_READY_PASSWORD_WRONG,
+                        )
+                        .await;
+                    }
                 }
             }
         } else if let Some(message::Union::TestDelay(t)) = msg.union {
@@ -1544,6 +1891,17 @@ impl Connection {
             }
         } else if self.authorized {
             match msg.union {
+                // recorded once the policy has run, see `paste`
+                Some(message::Union::Clipboard(_)) | Some(message::Union::ClipboardChunk(_)) => {}
+                _ => video_service::record_viewer_message(self.inner.id(), &msg),
+            }
+            match msg.union {
+                Some(message::Union::Rekey(r)) => {
+                    if let Err(err) = self.rekeying.handle(&r, &mut self.stream).await {
+                        log::error!("Failed to rekey: {}", err);
+                        return false;
+                    }
+                }
                 Some(message::Union::MouseEvent(me)) => {
                     #[cfg(any(target_os = "android", target_os = "ios"))]
                     if let Err(e) = call_main_service_mouse_input(me.mask, me.x, me.y) {
@@ -1626,14 +1984,25 @@ impl Connection {
                 {
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     if self.clipboard {
-                        update_clipboard(_cb, None);
+                        self.paste(ClipboardData::from_legacy(&_cb));
+                    }
+                }
+                Some(message::Union::ClipboardChunk(_chunk)) =>
+                {
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    if self.clipboard {
+                        if let Some(data) = self.clipboard_chunks.add(_chunk) {
+                            self.paste(data);
+                        }
                     }
                 }
                 Some(message::Union::Cliprdr(_clip)) =>
                 {
                     #[cfg(windows)]
                     if let Some(clip) = msg_2_clip(_clip) {
-                        self.send_to_cm(ipc::Data::ClipboardFile(clip))
+                        if self.allow_clip(clipboard_policy::Direction::ClientToHost, &clip) {
+                            self.send_to_cm(ipc::Data::ClipboardFile(clip))
+                        }
                     }
                 }
                 Some(message::Union::FileAction(fa)) => {
@@ -1643,12 +2012,32 @@ impl Connection {
                                 self.read_dir(&rd.path, rd.include_hidden);
                             }
                             Some(file_action::Union::AllFiles(f)) => {
-                                match fs::get_recursive_files(&f.path, f.include_hidden) {
-                                    Err(err) => {
-                                        self.send(fs::new_error(f.id, err, -1)).await;
-                                    }
-                                    Ok(files) => {
-                                        self.send(fs::new_dir(f.id, f.path, files)).await;
+                                if f.with_hash {
+                                    // Hashing a tree takes a while, the answer comes
+                                    // back through the connection's queue.
+                                    let mut inner = self.inner.clone();
+                                    tokio::spawn(async move {
+                                        let path = f.path.clone();
+                                        let hidden = f.include_hidden;
+                                        let res = tokio::task::spawn_blocking(move || {
+                                            dir_sync::get_recursive_files_with_hash(&path, hidden)
+                                        })
+                                        .await
+                                        .unwrap_or_else(|err| Err(err.into()));
+                                        let msg = match res {
+                                            Err(err) => fs::new_error(f.id, err, -1),
+                                            Ok(files) => fs::new_dir(f.id, f.path, files),
+                                        };
+                                        inner.send(msg.into());
+                                    });
+                                } else {
+                                    match fs::get_recursive_files(&f.path, f.include_hidden) {
+                                        Err(err) => {
+                                            self.send(fs::new_error(f.id, err, -1)).await;
+                                        }
+                                        Ok(files) => {
+                                            self.send(fs::new_dir(f.id, f.path, files)).await;
+                                        }
                                     }
                                 }
                             }
@@ -1670,7 +2059,22 @@ impl Connection {
                                     Err(err) => {
                                         self.send(fs::new_error(id, err, 0)).await;
                                     }
-                                    Ok(job) => {
+                                    Ok(mut job) => {
+                                        if !fs::can_send_links(get_version_number(&self.lr.version))
+                                        {
+                                            job.drop_links();
+                                        }
+                                        if !s.files.is_empty() {
+                                            let only: std::collections::HashSet<&String> =
+                                                s.files.iter().collect();
+                                            let files = job
+                                                .files()
+                                                .iter()
+                                                .filter(|f| only.contains(&f.name))
+                                                .cloned()
+                                                .collect();
+                                            job.set_files(files);
+                                        }
                                         self.send(fs::new_dir(id, path, job.files().to_vec()))
                                             .await;
                                         let mut files = job.files().to_owned();
@@ -1705,6 +2109,7 @@ impl Connection {
                                         .map(|f| (f.name, f.modified_time))
                                         .collect(),
                                     overwrite_detection: od,
+                                    attrs: r.files.iter().map(fs::FileAttrs::of).collect(),
                                 });
                                 self.post_file_audit(
                                     FileAuditType::RemoteReceive,
@@ -1737,6 +2142,54 @@ impl Connection {
                                     id: c.id,
                                 });
                             }
+                            Some(file_action::Union::Rename(r)) => {
+                                self.post_file_audit(
+                                    FileAuditType::RemoteRename,
+                                    &r.path,
+                                    Vec::new(),
+                                    json!({ "to": r.new_path }),
+                                );
+                                self.send_fs(ipc::FS::Rename {
+                                    id: r.id,
+                                    path: r.path,
+                                    new_path: r.new_path,
+                                });
+                            }
+                            Some(file_action::Union::Copy(c)) => {
+                                self.post_file_audit(
+                                    FileAuditType::RemoteCopy,
+                                    &c.path,
+                                    Vec::new(),
+                                    json!({ "to": c.new_path, "overwrite": c.overwrite }),
+                                );
+                                self.send_fs(ipc::FS::Copy {
+                                    id: c.id,
+                                    path: c.path,
+                                    new_path: c.new_path,
+                                    overwrite: c.overwrite,
+                                });
+                            }
+                            Some(file_action::Union::Stat(s)) => {
+                                self.post_file_audit(
+                                    FileAuditType::RemoteStat,
+                                    &s.path,
+                                    Vec::new(),
+                                    json!({}),
+                                );
+                                self.send_fs(ipc::FS::Stat {
+                                    id: s.id,
+                                    path: s.path,
+                                });
+                            }
+                            Some(file_action::Union::JobControl(c)) => {
+                                // Only downloads are read here, uploads are paced by the peer.
+                                if let Some(job) = fs::get_job(c.id, &mut self.read_jobs) {
+                                    job.control(&c);
+                                    if !job.is_paused() {
+                                        self.file_timer = time::interval(MILLI1);
+                                    }
+                                }
+                            }
                             Some(file_action::Union::Cancel(c)) => {
                                 self.send_fs(ipc::FS::CancelWrite { id: c.id });
                                 fs::remove_job(c.id, &mut self.read_jobs);
@@ -1744,6 +2197,11 @@ impl Connection {
                             Some(file_action::Union::SendConfirm(r)) => {
                                 if let Some(job) = fs::get_job(r.id, &mut self.read_jobs) {
                                     job.confirm(&r);
+                                } else if r.has_offset_blk() {
+                                    self.send_fs(ipc::FS::ConfirmOverwrite {
+                                        id: r.id,
+                                        file_num: r.file_num,
+                                    });
                                 }
                             }
                             _ => {}
@@ -1757,6 +2215,7 @@ impl Connection {
                             file_num: block.file_num,
                             data: block.data,
                             compressed: block.compressed,
+                            hash: block.hash,
                         });
                     }
                     Some(file_response::Union::Done(d)) => {
@@ -1771,6 +2230,7 @@ impl Connection {
                         file_size: d.file_size,
                         last_modified: d.last_modified,
                         is_upload: true,
+                        supports_delta: d.supports_delta,
                     }),
                     Some(file_response::Union::Error(e)) => {
                         self.send_fs(ipc::FS::WriteError {
@@ -2199,6 +2659,10 @@ impl Connection {
         let data = ipc::Data::Close;
         self.tx_to_cm.send(data).ok();
         self.port_forward_socket.take();
+        self.reverse_port_forward_port.take();
+        self.reverse_port_forward_listener.take();
+        self.udp_port_forward_target.take();
+        self.mux_port_forward.clear();
     }
 
     // The `reason` should be consistent with `check_if_retry` if not empty
@@ -2225,6 +2689,9 @@ impl Connection {
 
     #[inline]
     async fn send(&mut self, msg: Message) {
+        if self.authorized {
+            video_service::record_conn_message(self.inner.id(), &msg);
+        }
         allow_err!(self.stream.send(&msg).await);
     }
 
@@ -2234,10 +2701,7 @@ impl Connection {
 
     #[cfg(windows)]
     fn portable_check(&mut self) {
-        if self.portable.is_installed
-            || self.file_transfer.is_some()
-            || self.port_forward_socket.is_some()
-        {
+        if self.portable.is_installed || self.file_transfer.is_some() || self.is_port_forward() {
             return;
         }
         let running = portable_client::running();
@@ -2495,11 +2959,15 @@ pub enum AlarmAuditType {
     IpWhitelist = 0,
     ManyWrongPassword = 1,
     FrequentAttempt = 2,
+    ClipboardBlocked = 3,
 }
 
 pub enum FileAuditType {
     RemoteSend = 0,
     RemoteReceive = 1,
+    RemoteRename = 2,
+    RemoteCopy = 3,
+    RemoteStat = 4,
 }
 
 #[cfg(windows)]
@@ -9,7 +9,10 @@ use dispatch::Queue;
 use enigo::{Enigo, Key, KeyboardControllable, MouseButton, MouseControllable};
 use hbb_common::{
     get_time,
-    message_proto::{pointer_device_event::Union::TouchEvent, touch_event::Union::ScaleUpdate},
+    message_proto::{
+        pointer_device_event::Union::TouchEvent,
+        touch_event::Union::{Contacts, ScaleUpdate},
+    },
     protobuf::EnumOrUnknown,
 };
 use rdev::{self, EventType, Key as RdevKey, KeyCode, RawKey};
@@ -338,10 +341,18 @@ lazy_static::lazy_static! {
     static ref KEYS_DOWN: Arc<Mutex<HashMap<KeysDown, Instant>>> = Default::default();
     static ref LATEST_PEER_INPUT_CURSOR: Arc<Mutex<Input>> = Default::default();
     static ref LATEST_SYS_CURSOR_POS: Arc<Mutex<(Instant, (i32, i32))>> = Arc::new(Mutex::new((Instant::now().sub(MOUSE_MOVE_PROTECTION_TIMEOUT), (INVALID_CURSOR_POS, INVALID_CURSOR_POS))));
+    static ref POINTER_AS_MOUSE: Arc<Mutex<HashMap<i32, PointerAsMouse>>> = Default::default();
+}
+#[cfg(target_os = "linux")]
+lazy_static::lazy_static! {
+    // The uinput touch screen and tablet, and when it was last tried.
+    static ref UINPUT_POINTER: Mutex<(Option<super::uinput::client::UInputPointer>, Option<Instant>)> = Default::default();
 }
 static EXITING: AtomicBool = AtomicBool::new(false);
 
 const MOUSE_MOVE_PROTECTION_TIMEOUT: Duration = Duration::from_millis(1_000);
+#[cfg(target_os = "linux")]
+const UINPUT_POINTER_RETRY_TIMEOUT: Duration = Duration::from_secs(10);
 // Actual diff of (x,y) is (1,1) here. But 5 may be tolerant.
 const MOUSE_ACTIVE_DISTANCE: i32 = 5;
 
@@ -772,17 +783,226 @@ pub fn handle_pointer_(evt: &PointerDeviceEvent, conn: i32) {
     }
 
     match &evt.union {
-        Some(TouchEvent(evt)) => match &evt.union {
+        Some(TouchEvent(touch)) => match &touch.union {
             Some(ScaleUpdate(_scale_evt)) => {
                 #[cfg(target_os = "windows")]
                 handle_scale(_scale_evt.scale);
             }
+            Some(Contacts(contacts)) => {
+                handle_touch_contacts(&contacts.contacts, &evt.modifiers, conn)
+            }
             _ => {}
         },
+        Some(pointer_device_event::Union::PenEvent(pen)) => handle_pen(pen, &evt.modifiers, conn),
         _ => {}
     }
 }
 
+/// The left button held by a finger or the pen of a connection, where they are
+/// played as the mouse. Kept only while held.
+#[derive(Default)]
+struct PointerAsMouse {
+    /// Id of the finger moving the mouse.
+    touch: Option<i32>,
+    pen_down: bool,
+}
+
+impl PointerAsMouse {
+    fn is_up(&self) -> bool {
+        self.touch.is_none() && !self.pen_down
+    }
+}
+
+/// Lets go of the left button a finger or the pen of `conn` still holds.
+pub fn release_pointer_as_mouse(conn: i32) {
+    let state = POINTER_AS_MOUSE.lock().unwrap().remove(&conn);
+    if let Some(state) = state {
+        if !state.is_up() {
+            pointer_as_mouse(MOUSE_TYPE_UP, 0, 0, &[], conn);
+        }
+    }
+}
+
+fn handle_touch_contacts(
+    contacts: &[TouchContact],
+    modifiers: &[EnumOrUnknown<ControlKey>],
+    conn: i32,
+) {
+    if let Some(c) = contacts.first() {
+        update_latest_peer_input_cursor(conn, c.x, c.y);
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let data = crate::ipc::DataPointer::Touch(
+            contacts
+                .iter()
+                .map(|c| crate::ipc::DataTouchContact {
+                    id: c.id,
+                    x: c.x,
+                    y: c.y,
+                    pressure: c.pressure,
+                })
+                .collect(),
+        );
+        if send_uinput_pointer(data) {
+            // the mouse may still be held from before the device was there
+            release_pointer_as_mouse(conn);
+            return;
+        }
+    }
+    // the first finger down moves the mouse
+    let mut lock = POINTER_AS_MOUSE.lock().unwrap();
+    let state = lock.entry(conn).or_default();
+    if let Some(id) = state.touch {
+        match contacts.iter().find(|c| c.id == id) {
+            Some(c) => {
+                pointer_as_mouse(MOUSE_TYPE_MOVE, c.x, c.y, modifiers, conn);
+                return;
+            }
+            None => {
+                pointer_as_mouse(MOUSE_TYPE_UP, 0, 0, modifiers, conn);
+                state.touch = None;
+            }
+        }
+    }
+    if let Some(c) = contacts.first() {
+        pointer_as_mouse(MOUSE_TYPE_MOVE, c.x, c.y, modifiers, conn);
+        pointer_as_mouse(MOUSE_TYPE_DOWN, c.x, c.y, modifiers, conn);
+        state.touch = Some(c.id);
+    }
+    if state.is_up() {
+        lock.remove(&conn);
+    }
+}
+
+fn handle_pen(pen: &PenEvent, modifiers: &[EnumOrUnknown<ControlKey>], conn: i32) {
+    if pen.in_range {
+        update_latest_peer_input_cursor(conn, pen.x, pen.y);
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let data = crate::ipc::DataPointer::Pen(crate::ipc::DataPen {
+            in_range: pen.in_range,
+            touching: pen.touching,
+            x: pen.x,
+            y: pen.y,
+            pressure: pen.pressure,
+            tilt_x: pen.tilt_x,
+            tilt_y: pen.tilt_y,
+            eraser: pen.eraser,
+            buttons: pen.buttons,
+        });
+        if send_uinput_pointer(data) {
+            release_pointer_as_mouse(conn);
+            return;
+        }
+    }
+    // the tip is the left button
+    let mut lock = POINTER_AS_MOUSE.lock().unwrap();
+    let state = lock.entry(conn).or_default();
+    let touching = pen.in_range && pen.touching;
+    if pen.in_range {
+        pointer_as_mouse(MOUSE_TYPE_MOVE, pen.x, pen.y, modifiers, conn);
+    }
+    if touching != state.pen_down {
+        let evt_type = if touching {
+            MOUSE_TYPE_DOWN
+        } else {
+            MOUSE_TYPE_UP
+        };
+        pointer_as_mouse(evt_type, pen.x, pen.y, modifiers, conn);
+        state.pen_down = touching;
+    }
+    if state.is_up() {
+        lock.remove(&conn);
+    }
+}
+
+fn pointer_as_mouse(
+    evt_type: i32,
+    x: i32,
+    y: i32,
+    modifiers: &[EnumOrUnknown<ControlKey>],
+    conn: i32,
+) {
+    let buttons = if evt_type == MOUSE_TYPE_MOVE {
+        0
+    } else {
+        MOUSE_BUTTON_LEFT
+    };
+    let evt = MouseEvent {
+        mask: evt_type | (buttons << 3),
+        x,
+        y,
+        modifiers: modifiers.to_vec(),
+        ..Default::default()
+    };
+    handle_mouse_(&evt, conn);
+}
+
+#[inline]
+fn update_latest_peer_input_cursor(conn: i32, x: i32, y: i32) {
+    *LATEST_PEER_INPUT_CURSOR.lock().unwrap() = Input {
+        conn,
+        time: get_time(),
+        x,
+        y,
+    };
+}
+
+// False if there is no uinput touch screen and tablet yet. Only the OS service
+// runs the uinput services, see `start_uinput_service`, the client is then
+// created off the input thread and tried again after a while.
+#[cfg(target_os = "linux")]
+fn send_uinput_pointer(data: crate::ipc::DataPointer) -> bool {
+    let mut lock = UINPUT_POINTER.lock().unwrap();
+    let (pointer, last_try) = &mut *lock;
+    match pointer.as_mut() {
+        Some(p) => {
+            if let Err(err) = p.send(data) {
+                log::error!("Failed to send to uinput touchscreen and tablet: {}", err);
+                *pointer = None;
+                return false;
+            }
+            true
+        }
+        None => {
+            if !is_server() || !crate::platform::is_installed() {
+                return false;
+            }
+            if last_try.map_or(true, |t| t.elapsed() >= UINPUT_POINTER_RETRY_TIMEOUT) {
+                *last_try = Some(Instant::now());
+                std::thread::spawn(create_uinput_pointer);
+            }
+            false
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn create_uinput_pointer() {
+    // the resolution is set up with the uinput mouse on Wayland
+    let resolution = if *IS_X11 { get_x11_resolution() } else { None };
+    match super::uinput::client::UInputPointer::new(resolution) {
+        Ok(p) => {
+            log::info!("UInput touchscreen and tablet created");
+            UINPUT_POINTER.lock().unwrap().0 = Some(p);
+        }
+        Err(err) => log::info!("No uinput touchscreen and tablet: {}", err),
+    }
+}
+
+// All the displays, `((minx, maxx), (miny, maxy))`.
+#[cfg(target_os = "linux")]
+fn get_x11_resolution() -> Option<((i32, i32), (i32, i32))> {
+    let all = scrap::Display::all().ok()?;
+    let minx = all.iter().map(|d| d.origin().0).min()?;
+    let maxx = all.iter().map(|d| d.origin().0 + d.width() as i32).max()?;
+    let miny = all.iter().map(|d| d.origin().1).min()?;
+    let maxy = all.iter().map(|d| d.origin().1 + d.height() as i32).max()?;
+    Some(((minx, maxx), (miny, maxy)))
+}
+
 pub fn handle_mouse_(evt: &MouseEvent, conn: i32) {
     if !active_mouse_(conn) {
         return;
@@ -0,0 +1,345 @@
+// Brute-force lockout of incoming logins. Failures are tracked per IP and per
+// peer ID, and persisted in the background so a restart does not reset them.
+// Only IPs are locked out: a peer can claim any ID, so locking one out would let
+// anyone lock out its owner. The lockouts of an ID only raise the alarms.
+// Thresholds come from the options below, an empty option keeps the default:
+//
+// - "lockout-attempts-per-minute": failures within one minute before a lockout
+// - "lockout-attempts": consecutive failures before a lockout
+// - "lockout-duration": seconds of the first lockout, doubled on every next one
+// - "lockout-max-duration": cap of the doubled duration, also how long a quiet
+//   entry is remembered
+// - "lockout-allowlist": comma separated IPs or CIDRs which are never locked out
+
+use cidr_utils::cidr::IpCidr;
+use hbb_common::{config::Config, get_time, log, serde_json};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+const DEFAULT_ATTEMPTS_PER_MINUTE: u32 = 6;
+const DEFAULT_ATTEMPTS: u32 = 30;
+const DEFAULT_DURATION: i64 = 60;
+const DEFAULT_MAX_DURATION: i64 = 86_400;
+// Changes within this time are written together.
+const STORE_DELAY: Duration = Duration::from_secs(1);
+
+lazy_static::lazy_static! {
+    static ref LOCKOUTS: Mutex<Option<HashMap<String, Entry>>> = Default::default();
+    // Held while writing, so a newer state is never overwritten by an older one.
+    static ref STORE_LOCK: Mutex<()> = Default::default();
+}
+
+static STORE_PENDING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    pub attempts_per_minute: u32,
+    pub attempts: u32,
+    /// Seconds.
+    pub duration: i64,
+    /// Seconds.
+    pub max_duration: i64,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            attempts_per_minute: DEFAULT_ATTEMPTS_PER_MINUTE,
+            attempts: DEFAULT_ATTEMPTS,
+            duration: DEFAULT_DURATION,
+            max_duration: DEFAULT_MAX_DURATION,
+        }
+    }
+}
+
+impl Policy {
+    pub fn load() -> Self {
+        fn option<T: FromStr>(name: &str, default: T) -> T {
+            Config::get_option(name).trim().parse().unwrap_or(default)
+        }
+        let d = Self::default();
+        Self {
+            attempts_per_minute: option("lockout-attempts-per-minute", d.attempts_per_minute)
+                .max(1),
+            attempts: option("lockout-attempts", d.attempts).max(1),
+            duration: option("lockout-duration", d.duration).max(1),
+            max_duration: option("lockout-max-duration", d.max_duration).max(1),
+        }
+    }
+
+    /// Seconds of the lockout after `lockouts` earlier ones.
+    fn duration_of(&self, lockouts: u32) -> i64 {
+        self.duration
+            .saturating_mul(1 << lockouts.min(30))
+            .min(self.max_duration.max(self.duration))
+    }
+}
+
+/// Failures of one key, `ip:<addr>` or `id:<peer id>`. Times are in ms.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub failures: u32,
+    pub minute: i64,
+    pub minute_failures: u32,
+    pub lockouts: u32,
+    pub locked_until: i64,
+    pub last_failure: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// Too many attempts within a minute.
+    Frequent,
+    /// Too many consecutive attempts.
+    Many,
+}
+
+/// A lockout just started by a failure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lockout {
+    pub key: String,
+    pub reason: Reason,
+    pub seconds: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LockoutInfo {
+    pub key: String,
+    pub failures: u32,
+    pub lockouts: u32,
+    pub locked_until: i64,
+}
+
+#[inline]
+pub fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+/// Only to be counted, not passed to [`locked`].
+#[inline]
+pub fn id_key(peer_id: &str) -> String {
+    format!("id:{}", peer_id)
+}
+
+fn path() -> PathBuf {
+    Config::path("lockouts.json")
+}
+
+fn with_entries<T>(f: impl FnOnce(&mut HashMap<String, Entry>) -> T) -> T {
+    let mut lock = LOCKOUTS.lock().unwrap();
+    let entries = lock.get_or_insert_with(|| {
+        std::fs::read(path())
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    });
+    f(entries)
+}
+
+// Called with the entries locked, the write happens on another thread.
+fn store() {
+    if STORE_PENDING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    std::thread::spawn(|| {
+        std::thread::sleep(STORE_DELAY);
+        let _lock = STORE_LOCK.lock().unwrap();
+        // Changes from now on schedule another write.
+        STORE_PENDING.store(false, Ordering::SeqCst);
+        match with_entries(|entries| serde_json::to_vec(entries)) {
+            Ok(data) => {
+                if let Err(err) = std::fs::write(path(), data) {
+                    log::error!("Failed to store lockouts: {}", err);
+                }
+            }
+            Err(err) => log::error!("Failed to serialize lockouts: {}", err),
+        }
+    });
+}
+
+pub fn is_allowed(ip: &str) -> bool {
+    let ip = match ip.parse::<std::net::IpAddr>() {
+        Ok(ip) => ip,
+        Err(_) => return false,
+    };
+    Config::get_option("lockout-allowlist")
+        .split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .any(|x| IpCidr::from_str(x).map_or(false, |cidr| cidr.contains(ip)))
+}
+
+/// Seconds left of the longest lockout among `keys`, `None` if none is locked.
+pub fn locked(keys: &[String]) -> Option<i64> {
+    let now = get_time();
+    with_entries(|entries| {
+        keys.iter()
+            .filter_map(|key| entries.get(key))
+            .filter(|e| e.locked_until > now)
+            .map(|e| (e.locked_until - now + 999) / 1000)
+            .max()
+    })
+}
+
+/// Count a failed login of all `keys`, returns the lockouts it started.
+pub fn on_failure(keys: &[String]) -> Vec<Lockout> {
+    let policy = Policy::load();
+    let now = get_time();
+    with_entries(|entries| {
+        let mut res = Vec::new();
+        for key in keys {
+            let entry = entries.entry(key.clone()).or_default();
+            if let Some(lockout) = fail(&policy, entry, now) {
+                let (reason, seconds) = lockout;
+                res.push(Lockout {
+                    key: key.clone(),
+                    reason,
+                    seconds,
+                });
+            }
+        }
+        expire(&policy, entries, now);
+        store();
+        res
+    })
+}
+
+/// Forget `keys` after a successful login.
+pub fn on_success(keys: &[String]) {
+    with_entries(|entries| {
+        let len = entries.len();
+        for key in keys {
+            entries.remove(key);
+        }
+        if entries.len() != len {
+            store();
+        }
+    })
+}
+
+pub fn list() -> Vec<LockoutInfo> {
+    let policy = Policy::load();
+    with_entries(|entries| {
+        expire(&policy, entries, get_time());
+        let mut v: Vec<LockoutInfo> = entries
+            .iter()
+            .map(|(key, e)| LockoutInfo {
+                key: key.clone(),
+                failures: e.failures,
+                lockouts: e.lockouts,
+                locked_until: e.locked_until,
+            })
+            .collect();
+        v.sort_by(|a, b| a.key.cmp(&b.key));
+        v
+    })
+}
+
+/// Clear one key, or all of them if `key` is empty. Returns how many were cleared.
+pub fn clear(key: &str) -> usize {
+    with_entries(|entries| {
+        let n = if key.is_empty() {
+            let n = entries.len();
+            entries.clear();
+            n
+        } else {
+            entries.remove(key).is_some() as usize
+        };
+        store();
+        n
+    })
+}
+
+fn fail(policy: &Policy, e: &mut Entry, now: i64) -> Option<(Reason, i64)> {
+    let minute = now / 60_000;
+    if e.minute != minute {
+        e.minute = minute;
+        e.minute_failures = 0;
+    }
+    e.failures += 1;
+    e.minute_failures += 1;
+    e.last_failure = now;
+    let reason = if e.minute_failures > policy.attempts_per_minute {
+        Reason::Frequent
+    } else if e.failures >= policy.attempts {
+        Reason::Many
+    } else {
+        return None;
+    };
+    let seconds = policy.duration_of(e.lockouts);
+    e.lockouts += 1;
+    e.failures = 0;
+    e.minute_failures = 0;
+    e.locked_until = now + seconds * 1000;
+    Some((reason, seconds))
+}
+
+// Entries quiet for longer than the longest lockout start over.
+fn expire(policy: &Policy, entries: &mut HashMap<String, Entry>, now: i64) {
+    entries
+        .retain(|_, e| e.locked_until > now || now - e.last_failure < policy.max_duration * 1000);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = Policy {
+            attempts_per_minute: 3,
+            attempts: 5,
+            duration: 10,
+            max_duration: 35,
+        };
+        let mut e = Entry::default();
+        let mut now = 60_000;
+        for _ in 0..3 {
+            assert_eq!(fail(&policy, &mut e, now), None);
+        }
+        assert_eq!(fail(&policy, &mut e, now), Some((Reason::Frequent, 10)));
+        assert_eq!(e.locked_until, now + 10_000);
+        // Spread over minutes only the consecutive count applies.
+        for i in 0..4 {
+            now += 60_000;
+            assert_eq!(fail(&policy, &mut e, now), None, "{}", i);
+        }
+        now += 60_000;
+        assert_eq!(fail(&policy, &mut e, now), Some((Reason::Many, 20)));
+        assert_eq!(policy.duration_of(2), 35);
+        assert_eq!(policy.duration_of(100), 35);
+    }
+
+    #[test]
+    fn test_expire() {
+        let policy = Policy::default();
+        let mut entries = HashMap::new();
+        let now = 10_000_000_000;
+        entries.insert(
+            ip_key("1.2.3.4"),
+            Entry {
+                last_failure: now - policy.max_duration * 1000,
+                ..Default::default()
+            },
+        );
+        entries.insert(
+            ip_key("5.6.7.8"),
+            Entry {
+                last_failure: now,
+                ..Default::default()
+            },
+        );
+        expire(&policy, &mut entries, now);
+        assert_eq!(entries.len(), 1);
+        assert!(entries.contains_key("ip:5.6.7.8"));
+    }
+}
@@ -1,8 +1,11 @@
-use crate::ipc::{self, new_listener, Connection, Data, DataKeyboard, DataMouse};
+use crate::ipc::{
+    self, new_listener, Connection, Data, DataKeyboard, DataMouse, DataPen, DataPointer,
+    DataTouchContact,
+};
 use enigo::{Key, KeyboardControllable, MouseButton, MouseControllable};
 use evdev::{
     uinput::{VirtualDevice, VirtualDeviceBuilder},
-    AttributeSet, EventType, InputEvent,
+    AbsoluteAxisType, AttributeSet, EventType, InputEvent,
 };
 use hbb_common::{allow_err, bail, log, tokio::{self, runtime::Runtime}, ResultType};
 
@@ -10,6 +13,7 @@ static IPC_CONN_TIMEOUT: u64 = 1000;
 static IPC_REQUEST_TIMEOUT: u64 = 1000;
 static IPC_POSTFIX_KEYBOARD: &str = "_uinput_keyboard";
 static IPC_POSTFIX_MOUSE: &str = "_uinput_mouse";
+static IPC_POSTFIX_POINTER: &str = "_uinput_pointer";
 static IPC_POSTFIX_CONTROL: &str = "_uinput_control";
 
 pub mod client {
@@ -154,6 +158,29 @@ pub mod client {
         }
     }
 
+    /// Touch and pen events, for the touch screen and tablet of the service.
+    pub struct UInputPointer {
+        conn: Connection,
+        rt: Runtime,
+    }
+
+    impl UInputPointer {
+        /// Not in a tokio runtime. `resolution`, `((minx, maxx), (miny, maxy))`,
+        /// is set first if given.
+        pub fn new(resolution: Option<((i32, i32), (i32, i32))>) -> ResultType<Self> {
+            let rt = Runtime::new()?;
+            if let Some(((minx, maxx), (miny, maxy))) = resolution {
+                rt.block_on(set_resolution(minx, maxx, miny, maxy))?;
+            }
+            let conn = rt.block_on(ipc::connect(IPC_CONN_TIMEOUT, IPC_POSTFIX_POINTER))?;
+            Ok(Self { conn, rt })
+        }
+
+        pub fn send(&mut self, data: DataPointer) -> ResultType<()> {
+            self.rt.block_on(self.conn.send(&Data::Pointer(data)))
+        }
+    }
+
     pub async fn set_resolution(minx: i32, maxx: i32, miny: i32, maxy: i32) -> ResultType<()> {
         let mut conn = ipc::connect(IPC_CONN_TIMEOUT, IPC_POSTFIX_CONTROL).await?;
         conn.send(&Data::Control(ipc::DataControl::Resolution {
@@ -171,8 +198,9 @@ pub mod client {
 pub mod service {
     use super::*;
     use hbb_common::lazy_static;
+    use hbb_common::libc;
     use mouce::MouseActions;
-    use std::{collections::HashMap, sync::Mutex};
+    use std::{collections::HashMap, io::Write, os::unix::io::AsRawFd, sync::Mutex};
 
     lazy_static::lazy_static! {
     static ref KEY_MAP: HashMap<enigo::Key, evdev::Key> = HashMap::from(
@@ -519,6 +547,390 @@ pub mod service {
         }
     }
 
+    const MAX_TOUCH_SLOTS: usize = 10;
+    const MAX_PRESSURE: i32 = 1000;
+    const MAX_TILT: i32 = 90;
+    // units per mm, libinput wants one for tablets
+    const ABS_RESOLUTION: i32 = 10;
+
+    fn abs_setup(
+        axis: AbsoluteAxisType,
+        min: i32,
+        max: i32,
+        resolution: i32,
+    ) -> libc::uinput_abs_setup {
+        libc::uinput_abs_setup {
+            code: axis.0,
+            absinfo: libc::input_absinfo {
+                value: min,
+                minimum: min,
+                maximum: max,
+                fuzz: 0,
+                flat: 0,
+                resolution,
+            },
+        }
+    }
+
+    fn abs_event(axis: AbsoluteAxisType, value: i32) -> InputEvent {
+        InputEvent::new(EventType::ABSOLUTE, axis.0, value)
+    }
+
+    fn key_event(key: evdev::Key, down: bool) -> InputEvent {
+        InputEvent::new(EventType::KEY, key.code(), down as _)
+    }
+
+    // linux/uinput.h
+    nix::ioctl_none!(ui_dev_create, b'U', 1);
+    nix::ioctl_write_ptr!(ui_dev_setup, b'U', 3, libc::uinput_setup);
+    nix::ioctl_write_ptr!(ui_abs_setup, b'U', 4, libc::uinput_abs_setup);
+    nix::ioctl_write_int!(ui_set_evbit, b'U', 100);
+    nix::ioctl_write_int!(ui_set_keybit, b'U', 101);
+    nix::ioctl_write_int!(ui_set_absbit, b'U', 103);
+    nix::ioctl_write_int!(ui_set_propbit, b'U', 110);
+
+    const BUS_VIRTUAL: u16 = 0x06;
+
+    /// A uinput device on the screen, with INPUT_PROP_DIRECT, which the device
+    /// builder of evdev cannot set. libinput takes a touchscreen without it for
+    /// a touchpad and a tablet without it for one beside the screen.
+    struct DirectDevice {
+        file: std::fs::File,
+    }
+
+    impl DirectDevice {
+        fn new(
+            name: &str,
+            keys: &[evdev::Key],
+            axes: &[libc::uinput_abs_setup],
+        ) -> ResultType<Self> {
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .open("/dev/uinput")?;
+            let fd = file.as_raw_fd();
+            let mut setup: libc::uinput_setup = unsafe { std::mem::zeroed() };
+            setup.id.bustype = BUS_VIRTUAL;
+            for (c, b) in setup
+                .name
+                .iter_mut()
+                .zip(name.bytes().take(libc::UINPUT_MAX_NAME_SIZE - 1))
+            {
+                *c = b as _;
+            }
+            unsafe {
+                ui_set_propbit(fd, evdev::PropType::DIRECT.0 as _)?;
+                ui_set_evbit(fd, EventType::KEY.0 as _)?;
+                for key in keys {
+                    ui_set_keybit(fd, key.code() as _)?;
+                }
+                ui_set_evbit(fd, EventType::ABSOLUTE.0 as _)?;
+                for axis in axes {
+                    ui_set_absbit(fd, axis.code as _)?;
+                    ui_abs_setup(fd, axis)?;
+                }
+                ui_dev_setup(fd, &setup)?;
+                ui_dev_create(fd)?;
+            }
+            Ok(Self { file })
+        }
+
+        /// `events` and a SYN_REPORT, like `VirtualDevice::emit`.
+        fn emit(&mut self, events: &[InputEvent]) -> ResultType<()> {
+            let size = std::mem::size_of::<libc::input_event>();
+            let mut buf = Vec::with_capacity((events.len() + 1) * size);
+            let syn = InputEvent::new(EventType::SYNCHRONIZATION, 0, 0);
+            for evt in events.iter().chain(std::iter::once(&syn)) {
+                // the kernel sets the time
+                let mut raw: libc::input_event = unsafe { std::mem::zeroed() };
+                raw.type_ = evt.event_type().0;
+                raw.code = evt.code();
+                raw.value = evt.value();
+                buf.extend_from_slice(unsafe {
+                    std::slice::from_raw_parts(&raw as *const _ as *const u8, size)
+                });
+            }
+            self.file.write_all(&buf)?;
+            Ok(())
+        }
+    }
+
+    fn create_uinput_touchscreen(
+        (minx, maxx): (i32, i32),
+        (miny, maxy): (i32, i32),
+    ) -> ResultType<DirectDevice> {
+        DirectDevice::new(
+            "RustDesk UInput Touchscreen",
+            &[evdev::Key::BTN_TOUCH],
+            &[
+                abs_setup(AbsoluteAxisType::ABS_X, minx, maxx, ABS_RESOLUTION),
+                abs_setup(AbsoluteAxisType::ABS_Y, miny, maxy, ABS_RESOLUTION),
+                abs_setup(
+                    AbsoluteAxisType::ABS_MT_SLOT,
+                    0,
+                    MAX_TOUCH_SLOTS as i32 - 1,
+                    0,
+                ),
+                abs_setup(AbsoluteAxisType::ABS_MT_TRACKING_ID, 0, u16::MAX as _, 0),
+                abs_setup(
+                    AbsoluteAxisType::ABS_MT_POSITION_X,
+                    minx,
+                    maxx,
+                    ABS_RESOLUTION,
+                ),
+                abs_setup(
+                    AbsoluteAxisType::ABS_MT_POSITION_Y,
+                    miny,
+                    maxy,
+                    ABS_RESOLUTION,
+                ),
+                abs_setup(AbsoluteAxisType::ABS_MT_PRESSURE, 0, MAX_PRESSURE, 0),
+            ],
+        )
+    }
+
+    fn create_uinput_tablet(
+        (minx, maxx): (i32, i32),
+        (miny, maxy): (i32, i32),
+    ) -> ResultType<DirectDevice> {
+        DirectDevice::new(
+            "RustDesk UInput Tablet",
+            &[
+                evdev::Key::BTN_TOOL_PEN,
+                evdev::Key::BTN_TOOL_RUBBER,
+                evdev::Key::BTN_TOUCH,
+                evdev::Key::BTN_STYLUS,
+                evdev::Key::BTN_STYLUS2,
+            ],
+            &[
+                abs_setup(AbsoluteAxisType::ABS_X, minx, maxx, ABS_RESOLUTION),
+                abs_setup(AbsoluteAxisType::ABS_Y, miny, maxy, ABS_RESOLUTION),
+                abs_setup(AbsoluteAxisType::ABS_PRESSURE, 0, MAX_PRESSURE, 0),
+                abs_setup(AbsoluteAxisType::ABS_TILT_X, -MAX_TILT, MAX_TILT, 0),
+                abs_setup(AbsoluteAxisType::ABS_TILT_Y, -MAX_TILT, MAX_TILT, 0),
+            ],
+        )
+    }
+
+    /// Multi-touch slots, the id of the contact in each.
+    #[derive(Default)]
+    struct TouchSlots {
+        slots: [Option<i32>; MAX_TOUCH_SLOTS],
+        next_tracking_id: i32,
+    }
+
+    impl TouchSlots {
+        /// The events moving from the contacts before to `contacts`.
+        fn update(&mut self, contacts: &[DataTouchContact]) -> Vec<InputEvent> {
+            let mut events = Vec::new();
+            let touching = self.slots.iter().any(|s| s.is_some());
+            for (i, slot) in self.slots.iter_mut().enumerate() {
+                if let Some(id) = slot {
+                    if !contacts.iter().any(|c| c.id == *id) {
+                        events.push(abs_event(AbsoluteAxisType::ABS_MT_SLOT, i as _));
+                        events.push(abs_event(AbsoluteAxisType::ABS_MT_TRACKING_ID, -1));
+                        *slot = None;
+                    }
+                }
+            }
+            let mut first = None;
+            for c in contacts {
+                let i = match self.slots.iter().position(|s| *s == Some(c.id)) {
+                    Some(i) => {
+                        events.push(abs_event(AbsoluteAxisType::ABS_MT_SLOT, i as _));
+                        i
+                    }
+                    None => match self.slots.iter().position(|s| s.is_none()) {
+                        Some(i) => {
+                            self.slots[i] = Some(c.id);
+                            let tracking_id = self.next_tracking_id;
+                            self.next_tracking_id = (tracking_id + 1) & u16::MAX as i32;
+                            events.push(abs_event(AbsoluteAxisType::ABS_MT_SLOT, i as _));
+                            events
+                                .push(abs_event(AbsoluteAxisType::ABS_MT_TRACKING_ID, tracking_id));
+                            i
+                        }
+                        // more fingers than slots
+                        None => continue,
+                    },
+                };
+                events.push(abs_event(AbsoluteAxisType::ABS_MT_POSITION_X, c.x));
+                events.push(abs_event(AbsoluteAxisType::ABS_MT_POSITION_Y, c.y));
+                events.push(abs_event(
+                    AbsoluteAxisType::ABS_MT_PRESSURE,
+                    c.pressure.clamp(0, MAX_PRESSURE),
+                ));
+                if first.map_or(true, |(j, _)| i < j) {
+                    first = Some((i, c));
+                }
+            }
+            // single touch for the ones not reading slots
+            if let Some((_, c)) = first {
+                events.push(abs_event(AbsoluteAxisType::ABS_X, c.x));
+                events.push(abs_event(AbsoluteAxisType::ABS_Y, c.y));
+            }
+            if first.is_some() != touching {
+                events.push(key_event(evdev::Key::BTN_TOUCH, first.is_some()));
+            }
+            events
+        }
+    }
+
+    /// The events moving the tablet from pen state `last` to `pen`.
+    fn pen_events(last: &DataPen, pen: &DataPen) -> Vec<InputEvent> {
+        let tool = |pen: &DataPen| {
+            if pen.eraser {
+                evdev::Key::BTN_TOOL_RUBBER
+            } else {
+                evdev::Key::BTN_TOOL_PEN
+            }
+        };
+        let mut events = Vec::new();
+        if !pen.in_range {
+            if last.in_range {
+                if last.touching {
+                    events.push(key_event(evdev::Key::BTN_TOUCH, false));
+                }
+                events.push(abs_event(AbsoluteAxisType::ABS_PRESSURE, 0));
+                events.push(key_event(evdev::Key::BTN_STYLUS, false));
+                events.push(key_event(evdev::Key::BTN_STYLUS2, false));
+                events.push(key_event(tool(last), false));
+            }
+            return events;
+        }
+        if !last.in_range || tool(last) != tool(pen) {
+            if last.in_range {
+                events.push(key_event(tool(last), false));
+            }
+            events.push(key_event(tool(pen), true));
+        }
+        events.push(abs_event(AbsoluteAxisType::ABS_X, pen.x));
+        events.push(abs_event(AbsoluteAxisType::ABS_Y, pen.y));
+        let pressure = if pen.touching {
+            pen.pressure.clamp(1, MAX_PRESSURE)
+        } else {
+            0
+        };
+        events.push(abs_event(AbsoluteAxisType::ABS_PRESSURE, pressure));
+        events.push(abs_event(
+            AbsoluteAxisType::ABS_TILT_X,
+            pen.tilt_x.clamp(-MAX_TILT, MAX_TILT),
+        ));
+        events.push(abs_event(
+            AbsoluteAxisType::ABS_TILT_Y,
+            pen.tilt_y.clamp(-MAX_TILT, MAX_TILT),
+        ));
+        if !last.in_range || last.touching != pen.touching {
+            events.push(key_event(evdev::Key::BTN_TOUCH, pen.touching));
+        }
+        for (bit, key) in [(1, evdev::Key::BTN_STYLUS), (2, evdev::Key::BTN_STYLUS2)] {
+            let down = pen.buttons & bit != 0;
+            if !last.in_range || (last.buttons & bit != 0) != down {
+                events.push(key_event(key, down));
+            }
+        }
+        events
+    }
+
+    /// The touch screen and tablet over all the screens, made again when the
+    /// resolution changes.
+    struct PointerDevices {
+        resolution: ((i32, i32), (i32, i32)),
+        touchscreen: DirectDevice,
+        tablet: DirectDevice,
+        slots: TouchSlots,
+        pen: DataPen,
+    }
+
+    impl PointerDevices {
+        fn new(resolution: ((i32, i32), (i32, i32))) -> ResultType<Self> {
+            log::info!(
+                "Create uinput touchscreen and tablet with rng_x: ({}, {}), rng_y: ({}, {})",
+                resolution.0 .0,
+                resolution.0 .1,
+                resolution.1 .0,
+                resolution.1 .1
+            );
+            Ok(Self {
+                resolution,
+                touchscreen: create_uinput_touchscreen(resolution.0, resolution.1)?,
+                tablet: create_uinput_tablet(resolution.0, resolution.1)?,
+                slots: Default::default(),
+                pen: Default::default(),
+            })
+        }
+
+        fn handle(&mut self, data: &DataPointer) {
+            log::trace!("handle_pointer {:?}", &data);
+            let ((minx, maxx), (miny, maxy)) = self.resolution;
+            match data {
+                DataPointer::Touch(contacts) => {
+                    let contacts: Vec<DataTouchContact> = contacts
+                        .iter()
+                        .map(|c| DataTouchContact {
+                            x: c.x.clamp(minx, maxx),
+                            y: c.y.clamp(miny, maxy),
+                            ..*c
+                        })
+                        .collect();
+                    let events = self.slots.update(&contacts);
+                    if !events.is_empty() {
+                        allow_err!(self.touchscreen.emit(&events));
+                    }
+                }
+                DataPointer::Pen(pen) => {
+                    let pen = DataPen {
+                        x: pen.x.clamp(minx, maxx),
+                        y: pen.y.clamp(miny, maxy),
+                        ..*pen
+                    };
+                    let events = pen_events(&self.pen, &pen);
+                    if !events.is_empty() {
+                        allow_err!(self.tablet.emit(&events));
+                    }
+                    self.pen = pen;
+                }
+            }
+        }
+    }
+
+    fn handle_pointer(devices: &mut Option<PointerDevices>, data: &DataPointer) {
+        let resolution = *RESOLUTION.lock().unwrap();
+        if resolution.0 .0 == resolution.0 .1 || resolution.1 .0 == resolution.1 .1 {
+            return;
+        }
+        if devices
+            .as_ref()
+            .map_or(true, |d| d.resolution != resolution)
+        {
+            *devices = match PointerDevices::new(resolution) {
+                Ok(d) => Some(d),
+                Err(e) => {
+                    log::error!("Failed to create touchscreen and tablet, {}", e);
+                    None
+                }
+            };
+        }
+        if let Some(devices) = devices.as_mut() {
+            devices.handle(data);
+        }
+    }
+
+    fn spawn_pointer_handler(mut stream: Connection) {
+        tokio::spawn(async move {
+            let mut devices = None;
+            loop {
+                match stream.next().await {
+                    Err(err) => {
+                        log::info!("UInput pointer ipc connection closed: {}", err);
+                        break;
+                    }
+                    Ok(Some(Data::Pointer(data))) => handle_pointer(&mut devices, &data),
+                    _ => {}
+                }
+            }
+        });
+    }
+
     fn spawn_keyboard_handler(mut stream: Connection) {
         tokio::spawn(async move {
             let mut keyboard = match create_uinput_keyboard() {
@@ -691,6 +1103,13 @@ pub mod service {
         start_service(IPC_POSTFIX_MOUSE, spawn_mouse_handler).await;
     }
 
+    /// Start uinput touchscreen and tablet service.
+    #[tokio::main(flavor = "current_thread")]
+    pub async fn start_service_pointer() {
+        log::info!("start uinput pointer service");
+        start_service(IPC_POSTFIX_POINTER, spawn_pointer_handler).await;
+    }
+
     /// Start uinput mouse service.
     #[tokio::main(flavor = "current_thread")]
     pub async fn start_service_control() {
@@ -704,7 +1123,193 @@ pub mod service {
     pub fn stop_service_mouse() {
         log::info!("stop uinput mouse service");
     }
+    pub fn stop_service_pointer() {
+        log::info!("stop uinput pointer service");
+    }
     pub fn stop_service_control() {
         log::info!("stop uinput control service");
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn codes(events: &[InputEvent]) -> Vec<(u16, u16, i32)> {
+            events
+                .iter()
+                .map(|e| (e.event_type().0, e.code(), e.value()))
+                .collect()
+        }
+
+        fn abs(axis: AbsoluteAxisType, value: i32) -> (u16, u16, i32) {
+            (EventType::ABSOLUTE.0, axis.0, value)
+        }
+
+        fn key(key: evdev::Key, down: bool) -> (u16, u16, i32) {
+            (EventType::KEY.0, key.code(), down as _)
+        }
+
+        fn contact(id: i32, x: i32, y: i32) -> DataTouchContact {
+            DataTouchContact {
+                id,
+                x,
+                y,
+                pressure: 0,
+            }
+        }
+
+        // the slots in the order of their ABS_MT_SLOT events
+        fn slots_of(events: &[InputEvent]) -> Vec<i32> {
+            codes(events)
+                .into_iter()
+                .filter(|e| e.1 == AbsoluteAxisType::ABS_MT_SLOT.0)
+                .map(|e| e.2)
+                .collect()
+        }
+
+        fn tracking_ids_of(events: &[InputEvent]) -> Vec<i32> {
+            codes(events)
+                .into_iter()
+                .filter(|e| e.1 == AbsoluteAxisType::ABS_MT_TRACKING_ID.0)
+                .map(|e| e.2)
+                .collect()
+        }
+
+        #[test]
+        fn test_touch_slots() {
+            let mut slots = TouchSlots::default();
+            let events = slots.update(&[contact(5, 10, 20), contact(9, 30, 40)]);
+            assert_eq!(
+                codes(&events),
+                vec![
+                    abs(AbsoluteAxisType::ABS_MT_SLOT, 0),
+                    abs(AbsoluteAxisType::ABS_MT_TRACKING_ID, 0),
+                    abs(AbsoluteAxisType::ABS_MT_POSITION_X, 10),
+                    abs(AbsoluteAxisType::ABS_MT_POSITION_Y, 20),
+                    abs(AbsoluteAxisType::ABS_MT_PRESSURE, 0),
+                    abs(AbsoluteAxisType::ABS_MT_SLOT, 1),
+                    abs(AbsoluteAxisType::ABS_MT_TRACKING_ID, 1),
+                    abs(AbsoluteAxisType::ABS_MT_POSITION_X, 30),
+                    abs(AbsoluteAxisType::ABS_MT_POSITION_Y, 40),
+                    abs(AbsoluteAxisType::ABS_MT_PRESSURE, 0),
+                    abs(AbsoluteAxisType::ABS_X, 10),
+                    abs(AbsoluteAxisType::ABS_Y, 20),
+                    key(evdev::Key::BTN_TOUCH, true),
+                ]
+            );
+            // the first finger is lifted, the second keeps its slot and a new one takes the first
+            let events = slots.update(&[contact(9, 31, 41), contact(7, 50, 60)]);
+            assert_eq!(slots_of(&events), vec![0, 1, 0]);
+            assert_eq!(tracking_ids_of(&events), vec![-1, 2]);
+            assert!(!codes(&events).contains(&key(evdev::Key::BTN_TOUCH, true)));
+            assert!(codes(&events).contains(&abs(AbsoluteAxisType::ABS_X, 50)));
+            // no contacts lift them all
+            let events = slots.update(&[]);
+            assert_eq!(
+                codes(&events),
+                vec![
+                    abs(AbsoluteAxisType::ABS_MT_SLOT, 0),
+                    abs(AbsoluteAxisType::ABS_MT_TRACKING_ID, -1),
+                    abs(AbsoluteAxisType::ABS_MT_SLOT, 1),
+                    abs(AbsoluteAxisType::ABS_MT_TRACKING_ID, -1),
+                    key(evdev::Key::BTN_TOUCH, false),
+                ]
+            );
+            assert!(slots.update(&[]).is_empty());
+        }
+
+        #[test]
+        fn test_touch_slots_overflow() {
+            let mut slots = TouchSlots::default();
+            let contacts: Vec<_> = (0..MAX_TOUCH_SLOTS as i32 + 1)
+                .map(|id| contact(id, id, id))
+                .collect();
+            let events = slots.update(&contacts);
+            assert_eq!(tracking_ids_of(&events).len(), MAX_TOUCH_SLOTS);
+            assert!(!codes(&events).contains(&abs(
+                AbsoluteAxisType::ABS_MT_POSITION_X,
+                MAX_TOUCH_SLOTS as _
+            )));
+            // the finger left out gets the slot freed
+            let events = slots.update(&contacts[1..]);
+            assert_eq!(tracking_ids_of(&events), vec![-1, MAX_TOUCH_SLOTS as _]);
+            assert_eq!(slots.slots[0], Some(MAX_TOUCH_SLOTS as _));
+        }
+
+        #[test]
+        fn test_pen_events() {
+            let out = DataPen::default();
+            let hover = DataPen {
+                in_range: true,
+                x: 1,
+                y: 2,
+                ..Default::default()
+            };
+            let events = codes(&pen_events(&out, &hover));
+            assert_eq!(events[0], key(evdev::Key::BTN_TOOL_PEN, true));
+            assert!(events.contains(&key(evdev::Key::BTN_TOUCH, false)));
+            assert!(events.contains(&abs(AbsoluteAxisType::ABS_PRESSURE, 0)));
+            // touching keeps some pressure
+            let down = DataPen {
+                touching: true,
+                ..hover
+            };
+            let events = codes(&pen_events(&hover, &down));
+            assert!(events.contains(&abs(AbsoluteAxisType::ABS_PRESSURE, 1)));
+            assert!(events.contains(&key(evdev::Key::BTN_TOUCH, true)));
+            assert!(!events.contains(&key(evdev::Key::BTN_TOOL_PEN, true)));
+            // turning the pen over switches the tool
+            let eraser = DataPen {
+                eraser: true,
+                ..down
+            };
+            let events = codes(&pen_events(&down, &eraser));
+            assert_eq!(
+                events[..2],
+                [
+                    key(evdev::Key::BTN_TOOL_PEN, false),
+                    key(evdev::Key::BTN_TOOL_RUBBER, true)
+                ]
+            );
+            assert!(!events.iter().any(|e| e.1 == evdev::Key::BTN_TOUCH.code()));
+            // leaving lets go of all
+            let events = codes(&pen_events(&eraser, &out));
+            assert_eq!(
+                events,
+                vec![
+                    key(evdev::Key::BTN_TOUCH, false),
+                    abs(AbsoluteAxisType::ABS_PRESSURE, 0),
+                    key(evdev::Key::BTN_STYLUS, false),
+                    key(evdev::Key::BTN_STYLUS2, false),
+                    key(evdev::Key::BTN_TOOL_RUBBER, false),
+                ]
+            );
+            assert!(pen_events(&out, &out).is_empty());
+        }
+
+        #[test]
+        fn test_pen_buttons() {
+            let hover = DataPen {
+                in_range: true,
+                ..Default::default()
+            };
+            let first = DataPen {
+                buttons: 1,
+                ..hover
+            };
+            let events = codes(&pen_events(&hover, &first));
+            assert!(events.contains(&key(evdev::Key::BTN_STYLUS, true)));
+            assert!(!events.iter().any(|e| e.1 == evdev::Key::BTN_STYLUS2.code()));
+            let both = DataPen {
+                buttons: 3,
+                ..hover
+            };
+            let events = codes(&pen_events(&first, &both));
+            assert!(events.contains(&key(evdev::Key::BTN_STYLUS2, true)));
+            assert!(!events.iter().any(|e| e.1 == evdev::Key::BTN_STYLUS.code()));
+            let events = codes(&pen_events(&both, &hover));
+            assert!(events.contains(&key(evdev::Key::BTN_STYLUS, false)));
+            assert!(events.contains(&key(evdev::Key::BTN_STYLUS2, false)));
+        }
+    }
 }
@@ -164,6 +164,13 @@ impl VideoQoS {
         }
         self.fps = fps;
 
+        // file transfers yield to video and input while any user lags
+        hbb_common::fs::set_congested(self.users.values().any(|u| {
+            u.response_delayed
+                || u.delay
+                    .map_or(false, |d| d.state as u32 >= DelayState::HighDelay as u32)
+        }));
+
         // quality
         // latest image quality
         let latest_quality = self
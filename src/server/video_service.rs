@@ -67,6 +67,11 @@ lazy_static::lazy_static! {
     pub static ref IS_FOREGROUND_WINDOW_ELEVATED: Arc<Mutex<bool>> = Default::default();
     pub static ref LAST_SYNC_DISPLAYS: Arc<RwLock<Vec<DisplayInfo>>> = Default::default();
     static ref ORIGINAL_RESOLUTIONS: Arc<RwLock<HashMap<String, (i32, i32)>>> = Default::default();
+    // The recordings of the incoming sessions by connection, `None` if a connection isn't
+    // recorded, and the last audio format sent, so that audio and session events end up in
+    // the recording of the connection they belong to.
+    static ref RECORDERS: Mutex<HashMap<i32, Option<Recorder>>> = Default::default();
+    static ref RECORD_AUDIO_FORMAT: Mutex<Option<Message>> = Default::default();
 }
 
 // Not virtual display
@@ -598,7 +603,13 @@ fn run(sp: GenericService) -> ResultType<()> {
     #[cfg(windows)]
     log::info!("gdi: {}", c.is_gdi());
     let codec_name = Encoder::negotiated_codec();
-    let recorder = get_recorder(c.width, c.height, &codec_name);
+    // a new run starts new files
+    RECORDERS.lock().unwrap().clear();
+    let record = RecordSpec {
+        width: c.width,
+        height: c.height,
+        codec_name: codec_name.clone(),
+    };
     #[cfg(windows)]
     start_uac_elevation_check();
 
@@ -679,7 +690,7 @@ fn run(sp: GenericService) -> ResultType<()> {
                     scrap::Frame::RAW(data) => {
                         if data.len() != 0 {
                             let send_conn_ids =
-                                handle_one_frame(&sp, data, ms, &mut encoder, recorder.clone())?;
+                                handle_one_frame(&sp, data, ms, &mut encoder, &record)?;
                             frame_controller.set_send(now, send_conn_ids);
                         }
                     }
@@ -695,8 +706,7 @@ fn run(sp: GenericService) -> ResultType<()> {
             Ok(frame) => {
                 let time = now - start;
                 let ms = (time.as_secs() * 1000 + time.subsec_millis() as u64) as i64;
-                let send_conn_ids =
-                    handle_one_frame(&sp, &frame, ms, &mut encoder, recorder.clone())?;
+                let send_conn_ids = handle_one_frame(&sp, &frame, ms, &mut encoder, &record)?;
                 frame_controller.set_send(now, send_conn_ids);
                 #[cfg(windows)]
                 {
@@ -789,11 +799,14 @@ fn run(sp: GenericService) -> ResultType<()> {
     Ok(())
 }
 
-fn get_recorder(
+// What the recording of a connection of the running video service starts with.
+struct RecordSpec {
     width: usize,
     height: usize,
-    codec_name: &CodecName,
-) -> Arc<Mutex<Option<Recorder>>> {
+    codec_name: CodecName,
+}
+
+fn get_recorder(conn_id: i32, record: &RecordSpec) -> Option<Recorder> {
     #[cfg(not(target_os = "ios"))]
     let recorder = if !Config::get_option("allow-auto-record-incoming").is_empty() {
         use crate::hbbs_http::record_upload;
@@ -807,22 +820,70 @@ fn get_recorder(
         };
         Recorder::new(RecorderContext {
             server: true,
-            id: Config::get_id(),
+            // connections recorded at the same time must not share a file
+            id: format!("{}_{}", Config::get_id(), conn_id),
             default_dir: crate::ui_interface::default_video_save_directory(),
             filename: "".to_owned(),
-            width,
-            height,
-            format: codec_name.into(),
+            width: record.width,
+            height: record.height,
+            format: (&record.codec_name).into(),
             tx,
         })
-        .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))))
+        .ok()
     } else {
-        Default::default()
+        None
     };
     #[cfg(target_os = "ios")]
-    let recorder: Arc<Mutex<Option<Recorder>>> = Default::default();
+    let recorder: Option<Recorder> = None;
+
+    recorder.map(|mut r| {
+        if let Some(msg) = RECORD_AUDIO_FORMAT.lock().unwrap().as_ref() {
+            r.write_message(msg);
+        }
+        r
+    })
+}
+
+fn record_video_frame(conn_ids: &HashSet<i32>, msg: &Message, record: &RecordSpec) {
+    let mut recorders = RECORDERS.lock().unwrap();
+    for id in conn_ids {
+        recorders
+            .entry(*id)
+            .or_insert_with(|| get_recorder(*id, record))
+            .as_mut()
+            .map(|r| r.write_message(msg));
+    }
+}
 
-    recorder
+/// Writes a message a service sends to all connections into their recordings.
+pub fn record_message(msg: &Message) {
+    if let Some(message::Union::Misc(misc)) = &msg.union {
+        if let Some(misc::Union::AudioFormat(_)) = &misc.union {
+            *RECORD_AUDIO_FORMAT.lock().unwrap() = Some(msg.clone());
+        }
+    }
+    for r in RECORDERS.lock().unwrap().values_mut().flatten() {
+        r.write_message(msg);
+    }
+}
+
+/// Writes a message sent to one connection into its recording, if any.
+pub fn record_conn_message(conn_id: i32, msg: &Message) {
+    if let Some(Some(r)) = RECORDERS.lock().unwrap().get_mut(&conn_id) {
+        r.write_message(msg);
+    }
+}
+
+/// Writes a message received from a connection into its recording, if any.
+pub fn record_viewer_message(conn_id: i32, msg: &Message) {
+    if let Some(Some(r)) = RECORDERS.lock().unwrap().get_mut(&conn_id) {
+        r.write_viewer_message(msg);
+    }
+}
+
+/// Finishes the recording of a closed connection.
+pub fn remove_recorder(conn_id: i32) {
+    RECORDERS.lock().unwrap().remove(&conn_id);
 }
 
 fn check_privacy_mode_changed(sp: &GenericService, privacy_mode_id: i32) -> ResultType<()> {
@@ -845,7 +906,7 @@ fn handle_one_frame(
     frame: &[u8],
     ms: i64,
     encoder: &mut Encoder,
-    recorder: Arc<Mutex<Option<Recorder>>>,
+    record: &RecordSpec,
 ) -> ResultType<HashSet<i32>> {
     sp.snapshot(|sps| {
         // so that new sub and old sub share the same encoder after switch
@@ -857,13 +918,10 @@ fn handle_one_frame(
 
     let mut send_conn_ids: HashSet<i32> = Default::default();
     if let Ok(msg) = encoder.encode_to_message(frame, ms) {
+        let msg = Arc::new(msg);
+        send_conn_ids = sp.send_video_frame_shared(msg.clone());
         #[cfg(not(target_os = "ios"))]
-        recorder
-            .lock()
-            .unwrap()
-            .as_mut()
-            .map(|r| r.write_message(&msg));
-        send_conn_ids = sp.send_video_frame(msg);
+        record_video_frame(&send_conn_ids, &msg, record);
     }
     Ok(send_conn_ids)
 }
@@ -0,0 +1,373 @@
+// Connections multiplexed over one session as TunnelFrame messages. Used by
+// reverse port forwarding, where the controlled side accepts the connections
+// and the controlling side dials the real target for each of them, by
+// multiplexed port forwarding, where it is the other way around and every
+// connection names its own target, and by UDP forwarding, where every frame is
+// one datagram of a flow.
+
+use std::{collections::HashMap, io, net::SocketAddr, time::Duration};
+
+use hbb_common::{
+    bytes::Bytes,
+    futures::{SinkExt, StreamExt},
+    log,
+    message_proto::*,
+    timeout,
+    tokio::{
+        self,
+        net::{TcpStream, UdpSocket},
+        sync::mpsc::{channel, error::TrySendError, Receiver, Sender},
+        time::{self, Instant},
+    },
+    tokio_util::codec::{BytesCodec, Framed},
+};
+
+/// Largest UDP payload.
+pub const MAX_DATAGRAM: usize = 65_507;
+const UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const UDP_IDLE_TIMEOUT_MAX: Duration = Duration::from_secs(3600);
+const CONNECT_TIMEOUT: u64 = 3_000;
+/// Frames queued per connection in either direction.
+pub const TUNNEL_QUEUE: usize = 256;
+/// UDP flows one session may have open at once.
+pub const MAX_UDP_FLOWS: usize = 256;
+/// TCP connections one multiplexed session may have open at once.
+pub const MAX_MUX_TUNNELS: usize = 256;
+
+#[derive(Default)]
+pub struct Tunnels {
+    conns: HashMap<i32, Conn>,
+}
+
+struct Conn {
+    tx: Sender<Bytes>,
+    udp: bool,
+}
+
+/// The channel the sockets of [`Tunnels`] send their frames to.
+pub fn frame_channel() -> (Sender<TunnelFrame>, Receiver<TunnelFrame>) {
+    channel(TUNNEL_QUEUE)
+}
+
+impl Tunnels {
+    #[inline]
+    pub fn contains(&self, id: i32) -> bool {
+        self.conns.contains_key(&id)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.conns.len()
+    }
+
+    /// Pump `sock` in its own task, what it reads is sent to `tx` as frames of `id`.
+    pub fn add(&mut self, id: i32, sock: TcpStream, tx: Sender<TunnelFrame>) {
+        let rx_data = self.insert(id, false);
+        tokio::spawn(run_socket(id, sock, rx_data, tx));
+    }
+
+    /// Like [`Tunnels::add`], but the task connects to `target` first. Frames for the
+    /// connection are queued meanwhile, a failed connect closes it.
+    pub fn connect(&mut self, id: i32, target: String, tx: Sender<TunnelFrame>) {
+        let rx_data = self.insert(id, false);
+        tokio::spawn(async move {
+            match timeout(CONNECT_TIMEOUT, TcpStream::connect(&target)).await {
+                Ok(Ok(sock)) => {
+                    log::info!("tunnel #{} connected to {}", id, target);
+                    run_socket(id, sock, rx_data, tx).await;
+                }
+                res => {
+                    log::error!(
+                        "Failed to connect to {}: {:?}",
+                        target,
+                        res.map(|r| r.err())
+                    );
+                    tx.send(TunnelFrame {
+                        id,
+                        close: true,
+                        ..Default::default()
+                    })
+                    .await
+                    .ok();
+                }
+            }
+        });
+    }
+
+    /// Like [`Tunnels::add`] for a connected UDP socket, each frame is one datagram.
+    /// The flow closes itself after `idle` without a datagram in either direction.
+    pub fn add_udp(&mut self, id: i32, sock: UdpSocket, tx: Sender<TunnelFrame>, idle: Duration) {
+        let rx_data = self.insert(id, true);
+        tokio::spawn(run_udp_socket(id, sock, rx_data, tx, idle));
+    }
+
+    fn insert(&mut self, id: i32, udp: bool) -> Receiver<Bytes> {
+        let (tx, rx) = channel(TUNNEL_QUEUE);
+        self.conns.insert(id, Conn { tx, udp });
+        rx
+    }
+
+    /// Forget `id`, which closes its socket. Returns false if it was not open.
+    pub fn remove(&mut self, id: i32) -> bool {
+        self.conns.remove(&id).is_some()
+    }
+
+    /// Route a frame received from the peer to its socket. Returns false if the
+    /// connection was dropped as its socket doesn't keep up, the peer has to be told.
+    /// A UDP flow drops the datagram instead.
+    pub fn handle_frame(&mut self, frame: TunnelFrame) -> bool {
+        if frame.close {
+            self.remove(frame.id);
+            return true;
+        }
+        let conn = match self.conns.get(&frame.id) {
+            Some(conn) if !frame.data.is_empty() => conn,
+            _ => return true,
+        };
+        match conn.tx.try_send(frame.data) {
+            Err(TrySendError::Full(_)) if !conn.udp => {
+                log::warn!("tunnel #{} does not keep up, closing it", frame.id);
+                self.remove(frame.id);
+                false
+            }
+            // a closed socket sends its own close frame
+            _ => true,
+        }
+    }
+}
+
+/// Idle timeout of a UDP flow from the `idle_timeout` of [`PortForward`], 0 for the default.
+pub fn udp_idle_timeout(secs: u32) -> Duration {
+    match secs {
+        0 => UDP_IDLE_TIMEOUT,
+        secs => Duration::from_secs(secs as u64).min(UDP_IDLE_TIMEOUT_MAX),
+    }
+}
+
+/// A socket on an ephemeral port which only exchanges datagrams with `target`.
+pub async fn connect_udp(target: SocketAddr) -> io::Result<UdpSocket> {
+    let bind: SocketAddr = if target.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let sock = UdpSocket::bind(bind).await?;
+    sock.connect(target).await?;
+    Ok(sock)
+}
+
+pub fn new_frame(id: i32, data: Bytes, close: bool) -> Message {
+    let mut msg_out = Message::new();
+    msg_out.set_tunnel_frame(TunnelFrame {
+        id,
+        data,
+        close,
+        ..Default::default()
+    });
+    msg_out
+}
+
+/// The first frame of a multiplexed connection, the peer connects it to `host:port`.
+pub fn open_frame(id: i32, host: &str, port: i32) -> Message {
+    let mut msg_out = Message::new();
+    msg_out.set_tunnel_frame(TunnelFrame {
+        id,
+        host: host.to_owned(),
+        port,
+        ..Default::default()
+    });
+    msg_out
+}
+
+async fn run_socket(id: i32, sock: TcpStream, mut rx: Receiver<Bytes>, tx: Sender<TunnelFrame>) {
+    let mut sock = Framed::new(sock, BytesCodec::new());
+    loop {
+        tokio::select! {
+            res = sock.next() => match res {
+                Some(Ok(bytes)) => {
+                    let frame = TunnelFrame {
+                        id,
+                        data: bytes.freeze(),
+                        ..Default::default()
+                    };
+                    if tx.send(frame).await.is_err() {
+                        return;
+                    }
+                }
+                _ => break,
+            },
+            res = rx.recv() => match res {
+                Some(data) => {
+                    if let Err(err) = sock.send(data).await {
+                        log::debug!("tunnel #{} write failed: {}", id, err);
+                        break;
+                    }
+                }
+                // Closed by the peer.
+                None => break,
+            },
+        }
+    }
+    tx.send(TunnelFrame {
+        id,
+        close: true,
+        ..Default::default()
+    })
+    .await
+    .ok();
+}
+
+async fn run_udp_socket(
+    id: i32,
+    sock: UdpSocket,
+    mut rx: Receiver<Bytes>,
+    tx: Sender<TunnelFrame>,
+    idle: Duration,
+) {
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    let mut deadline = Instant::now() + idle;
+    loop {
+        tokio::select! {
+            res = sock.recv(&mut buf) => match res {
+                Ok(n) => {
+                    deadline = Instant::now() + idle;
+                    let frame = TunnelFrame {
+                        id,
+                        data: Bytes::copy_from_slice(&buf[..n]),
+                        ..Default::default()
+                    };
+                    if tx.send(frame).await.is_err() {
+                        return;
+                    }
+                }
+                // ICMP unreachable and the like, the flow may still recover.
+                Err(err) => log::debug!("udp flow #{} recv failed: {}", id, err),
+            },
+            res = rx.recv() => match res {
+                Some(data) => {
+                    deadline = Instant::now() + idle;
+                    if let Err(err) = sock.send(&data).await {
+                        log::debug!("udp flow #{} send failed: {}", id, err);
+                    }
+                }
+                None => break,
+            },
+            _ = time::sleep_until(deadline) => {
+                log::debug!("udp flow #{} idle for {:?}", id, idle);
+                break;
+            }
+        }
+    }
+    tx.send(TunnelFrame {
+        id,
+        close: true,
+        ..Default::default()
+    })
+    .await
+    .ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_udp_idle_timeout() {
+        assert_eq!(udp_idle_timeout(0), UDP_IDLE_TIMEOUT);
+        assert_eq!(udp_idle_timeout(5), Duration::from_secs(5));
+        assert_eq!(udp_idle_timeout(u32::MAX), UDP_IDLE_TIMEOUT_MAX);
+    }
+
+    #[tokio::test]
+    async fn test_udp_flow() {
+        let echo = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = echo.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            while let Ok((n, from)) = echo.recv_from(&mut buf).await {
+                echo.send_to(&buf[..n], from).await.ok();
+            }
+        });
+        let (tx, mut rx) = frame_channel();
+        let mut tunnels = Tunnels::default();
+        let sock = connect_udp(target).await.unwrap();
+        tunnels.add_udp(7, sock, tx, Duration::from_millis(200));
+        for data in ["a", "bc"] {
+            tunnels.handle_frame(TunnelFrame {
+                id: 7,
+                data: Bytes::from(data),
+                ..Default::default()
+            });
+            let frame = rx.recv().await.unwrap();
+            assert_eq!(frame.id, 7);
+            assert_eq!(&frame.data[..], data.as_bytes());
+        }
+        // Idle flows close themselves.
+        assert!(rx.recv().await.unwrap().close);
+    }
+
+    #[test]
+    fn test_slow_tunnel() {
+        let mut tunnels = Tunnels::default();
+        let _tcp = tunnels.insert(1, false);
+        let _udp = tunnels.insert(2, true);
+        for id in [1, 2] {
+            for _ in 0..TUNNEL_QUEUE {
+                assert!(tunnels.handle_frame(TunnelFrame {
+                    id,
+                    data: Bytes::from("x"),
+                    ..Default::default()
+                }));
+            }
+        }
+        let frame = |id| TunnelFrame {
+            id,
+            data: Bytes::from("x"),
+            ..Default::default()
+        };
+        // the datagram is dropped, the stream can't lose data and is closed
+        assert!(tunnels.handle_frame(frame(2)));
+        assert!(tunnels.contains(2));
+        assert!(!tunnels.handle_frame(frame(1)));
+        assert!(!tunnels.contains(1));
+    }
+
+    #[tokio::test]
+    async fn test_connect() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (sock, _) = listener.accept().await.unwrap();
+            let (mut r, mut w) = sock.into_split();
+            tokio::io::copy(&mut r, &mut w).await.ok();
+        });
+        let (tx, mut rx) = frame_channel();
+        let mut tunnels = Tunnels::default();
+        tunnels.connect(1, target, tx.clone());
+        // queued until the connect is done
+        tunnels.handle_frame(TunnelFrame {
+            id: 1,
+            data: Bytes::from("ping"),
+            ..Default::default()
+        });
+        let mut echoed = Vec::new();
+        while echoed.len() < 4 {
+            let frame = rx.recv().await.unwrap();
+            assert_eq!(frame.id, 1);
+            assert!(!frame.close);
+            echoed.extend_from_slice(&frame.data);
+        }
+        assert_eq!(echoed, b"ping");
+        assert!(tunnels.remove(1));
+        assert!(rx.recv().await.unwrap().close);
+
+        // nothing listens there any more
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = closed.local_addr().unwrap().to_string();
+        drop(closed);
+        tunnels.connect(2, target, tx);
+        let frame = rx.recv().await.unwrap();
+        assert_eq!(frame.id, 2);
+        assert!(frame.close);
+    }
+}
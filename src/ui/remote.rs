@@ -422,6 +422,8 @@ impl sciter::EventHandler for SciterSession {
         fn read_dir(String, bool);
         fn remove_dir(i32, String, bool);
         fn create_dir(i32, String, bool);
+        fn rename_file(i32, String, String, bool);
+        fn copy_file(i32, String, String, bool);
         fn remove_file(i32, String, i32, bool);
         fn read_remote_dir(String, bool);
         fn send_chat(String);
@@ -430,6 +432,8 @@ impl sciter::EventHandler for SciterSession {
         fn confirm_delete_files(i32, i32);
         fn set_no_confirm(i32);
         fn cancel_job(i32);
+        fn pause_job(i32, bool);
+        fn set_job_rate_limit(i32, i32);
         fn send_files(i32, String, String, i32, bool, bool);
         fn add_job(i32, String, String, i32, bool, bool);
         fn resume_job(i32, bool);
@@ -464,6 +468,14 @@ impl sciter::EventHandler for SciterSession {
         fn restart_remote_device();
         fn request_voice_call();
         fn close_voice_call();
+        fn start_macro_recording();
+        fn stop_macro_recording();
+        fn is_macro_recording();
+        fn get_macros();
+        fn save_macro(String, String);
+        fn play_saved_macro(String, f64);
+        fn stop_macro();
+        fn is_macro_playing();
     }
 }
 
@@ -752,6 +764,32 @@ impl SciterSession {
             log::error!("Failed to spawn IP tunneling: {}", err);
         }
     }
+
+    fn get_macros(&mut self) -> Value {
+        let mut v = Value::array(0);
+        for name in macros::list() {
+            v.push(name);
+        }
+        v
+    }
+
+    // The error, empty if saved.
+    fn save_macro(&mut self, name: String, text: String) -> String {
+        match macros::save(&name, &text) {
+            Ok(()) => "".to_owned(),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    // The error, empty if the replay started.
+    fn play_saved_macro(&mut self, name: String, speed: f64) -> String {
+        let res =
+            macros::load(&name).and_then(|text| self.play_macro(&text, &Default::default(), speed));
+        match res {
+            Ok(()) => "".to_owned(),
+            Err(err) => err.to_string(),
+        }
+    }
 }
 
 pub fn make_fd(id: i32, entries: &Vec<FileEntry>, only_count: bool) -> Value {
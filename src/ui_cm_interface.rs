@@ -673,7 +673,9 @@ async fn handle_fs(fs: ipc::FS, write_jobs: &mut Vec<fs::TransferJob>, tx: &Unbo
             file_num,
             mut files,
             overwrite_detection,
+            attrs,
         } => {
+            let mut attrs = attrs.into_iter();
             // cm has no show_hidden context
             // dummy remote, show_hidden, is_remote
             write_jobs.push(fs::TransferJob::new_write(
@@ -685,10 +687,16 @@ async fn handle_fs(fs: ipc::FS, write_jobs: &mut Vec<fs::TransferJob>, tx: &Unbo
                 false,
                 files
                     .drain(..)
-                    .map(|f| FileEntry {
-                        name: f.0,
-                        modified_time: f.1,
-                        ..Default::default()
+                    .map(|f| {
+                        let mut entry = FileEntry {
+                            name: f.0,
+                            modified_time: f.1,
+                            ..Default::default()
+                        };
+                        if let Some(attrs) = attrs.next() {
+                            attrs.apply(&mut entry);
+                        }
+                        entry
                     })
                     .collect(),
                 overwrite_detection,
@@ -718,6 +726,7 @@ async fn handle_fs(fs: ipc::FS, write_jobs: &mut Vec<fs::TransferJob>, tx: &Unbo
             file_num,
             data,
             compressed,
+            hash,
         } => {
             if let Some(job) = fs::get_job(id, write_jobs) {
                 if let Err(err) = job
@@ -726,11 +735,13 @@ async fn handle_fs(fs: ipc::FS, write_jobs: &mut Vec<fs::TransferJob>, tx: &Unbo
                         file_num,
                         data,
                         compressed,
+                        hash,
                         ..Default::default()
                     })
                     .await
                 {
                     send_raw(fs::new_error(id, err, file_num), &tx);
+                    fs::remove_job(id, write_jobs);
                 }
             }
         }
@@ -740,8 +751,10 @@ async fn handle_fs(fs: ipc::FS, write_jobs: &mut Vec<fs::TransferJob>, tx: &Unbo
             file_size,
             last_modified,
             is_upload,
+            supports_delta,
         } => {
             if let Some(job) = fs::get_job(id, write_jobs) {
+                job.set_peer_supports_delta(supports_delta);
                 let mut req = FileTransferSendConfirmRequest {
                     id,
                     file_num,
@@ -768,6 +781,7 @@ async fn handle_fs(fs: ipc::FS, write_jobs: &mut Vec<fs::TransferJob>, tx: &Unbo
                                 DigestCheckResult::NeedConfirm(mut digest) => {
                                     // upload to server, but server has the same file, request
                                     digest.is_upload = is_upload;
+                                    digest.supports_delta = true;
                                     let mut msg_out = Message::new();
                                     let mut fr = FileResponse::new();
                                     fr.set_digest(digest);
@@ -787,6 +801,40 @@ async fn handle_fs(fs: ipc::FS, write_jobs: &mut Vec<fs::TransferJob>, tx: &Unbo
                 }
             }
         }
+        ipc::FS::Rename { id, path, new_path } => {
+            handle_result(
+                spawn_blocking(move || fs::rename(&path, &new_path)).await,
+                id,
+                0,
+                tx,
+            )
+            .await;
+        }
+        ipc::FS::Copy {
+            id,
+            path,
+            new_path,
+            overwrite,
+        } => {
+            handle_result(
+                spawn_blocking(move || fs::copy(&path, &new_path, overwrite)).await,
+                id,
+                0,
+                tx,
+            )
+            .await;
+        }
+        ipc::FS::Stat { id, path } => match spawn_blocking(move || fs::stat(&path)).await {
+            Ok(Ok(stat)) => send_raw(fs::new_stat(id, stat), tx),
+            Ok(Err(err)) => send_raw(fs::new_error(id, err, 0), tx),
+            Err(err) => send_raw(fs::new_error(id, err, 0), tx),
+        },
+        ipc::FS::ConfirmOverwrite { id, file_num } => {
+            if let Some(job) = fs::get_job(id, write_jobs) {
+                let req = job.overwrite_confirm(file_num).await;
+                send_raw(new_send_confirm(req), &tx);
+            }
+        }
         _ => {}
     }
 }
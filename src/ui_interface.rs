@@ -633,6 +633,10 @@ pub fn get_lan_peers() -> Vec<HashMap<&'static str, String>> {
                 ("username", peer.username.clone()),
                 ("hostname", peer.hostname.clone()),
                 ("platform", peer.platform.clone()),
+                ("version", peer.version.clone()),
+                ("has_password", peer.has_password.to_string()),
+                ("conn_types", peer.conn_types.join(",")),
+                ("last_seen", peer.last_seen.to_string()),
             ])
         })
         .collect()
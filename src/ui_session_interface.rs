@@ -1,11 +1,10 @@
 use crate::input::{MOUSE_BUTTON_LEFT, MOUSE_TYPE_DOWN, MOUSE_TYPE_UP};
-#[cfg(not(any(target_os = "android", target_os = "ios")))]
-use std::{collections::HashMap, sync::atomic::AtomicBool};
 use std::{
+    collections::HashMap,
     ops::{Deref, DerefMut},
     str::FromStr,
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc, Mutex, RwLock,
     },
     time::SystemTime,
@@ -29,13 +28,13 @@ use hbb_common::{
         sync::mpsc,
         time::{Duration as TokioDuration, Instant},
     },
-    SessionID, Stream,
+    ResultType, SessionID, Stream,
 };
 
 use crate::client::io_loop::Remote;
 use crate::client::{
     check_if_retry, handle_hash, handle_login_error, handle_login_from_ui, handle_test_delay,
-    input_os_password, load_config, send_mouse, send_pointer_device_event,
+    input_os_password, load_config, macros, send_mouse, send_pointer_device_event,
     start_video_audio_threads, FileManager, Key, LoginConfigHandler, QualityStatus, KEY_MAP,
 };
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -62,6 +61,9 @@ pub struct Session<T: InvokeUiSession> {
     pub server_file_transfer_enabled: Arc<RwLock<bool>>,
     pub server_clipboard_enabled: Arc<RwLock<bool>>,
     pub last_change_display: Arc<Mutex<ChangeDisplayRecord>>,
+    pub macro_recorder: Arc<Mutex<Option<macros::Recorder>>>,
+    // set to stop the macro being replayed
+    pub macro_player: Arc<Mutex<Option<Arc<AtomicBool>>>>,
 }
 
 #[derive(Clone)]
@@ -601,6 +603,67 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg_out));
     }
 
+    /// Records the key and mouse events sent from now on as a macro.
+    pub fn start_macro_recording(&self) {
+        *self.macro_recorder.lock().unwrap() = Some(Default::default());
+    }
+
+    /// The text of the macro recorded, empty if none was.
+    pub fn stop_macro_recording(&self) -> String {
+        self.macro_recorder
+            .lock()
+            .unwrap()
+            .take()
+            .map(|r| r.finish())
+            .unwrap_or_default()
+    }
+
+    pub fn is_macro_recording(&self) -> bool {
+        self.macro_recorder.lock().unwrap().is_some()
+    }
+
+    /// Replays a macro in the background, stopping the one replayed before.
+    pub fn play_macro(
+        &self,
+        text: &str,
+        vars: &HashMap<String, String>,
+        speed: f64,
+    ) -> ResultType<()> {
+        let steps = macros::parse(text, vars)?;
+        self.stop_macro();
+        let stop = Arc::new(AtomicBool::new(false));
+        *self.macro_player.lock().unwrap() = Some(stop.clone());
+        let session = self.clone();
+        std::thread::spawn(move || {
+            // sent past the recorder, a replay is not part of the macro being recorded
+            let done = macros::play(&steps, speed, &stop, |msg| {
+                session.send_unrecorded(Data::Message(msg));
+            });
+            log::info!("Macro {}", if done { "replayed" } else { "stopped" });
+            let mut player = session.macro_player.lock().unwrap();
+            if player.as_ref().map_or(false, |x| Arc::ptr_eq(x, &stop)) {
+                *player = None;
+            }
+        });
+        Ok(())
+    }
+
+    pub fn stop_macro(&self) {
+        if let Some(stop) = self.macro_player.lock().unwrap().take() {
+            stop.store(true, Ordering::SeqCst);
+        }
+    }
+
+    pub fn is_macro_playing(&self) -> bool {
+        self.macro_player.lock().unwrap().is_some()
+    }
+
+    fn send_unrecorded(&self, data: Data) {
+        if let Some(sender) = self.sender.read().unwrap().as_ref() {
+            sender.send(data).ok();
+        }
+    }
+
     #[cfg(any(target_os = "ios"))]
     pub fn handle_flutter_key_event(
         &self,
@@ -702,6 +765,31 @@ impl<T: InvokeUiSession> Session<T> {
         send_pointer_device_event(evt, alt, ctrl, shift, command, self);
     }
 
+    /// All the fingers on the screen, none once the last is lifted.
+    pub fn send_touch_contacts(
+        &self,
+        contacts: Vec<TouchContact>,
+        alt: bool,
+        ctrl: bool,
+        shift: bool,
+        command: bool,
+    ) {
+        let mut touch_evt = TouchEvent::new();
+        touch_evt.set_contacts(TouchContacts {
+            contacts,
+            ..Default::default()
+        });
+        let mut evt = PointerDeviceEvent::new();
+        evt.set_touch_event(touch_evt);
+        send_pointer_device_event(evt, alt, ctrl, shift, command, self);
+    }
+
+    pub fn send_pen(&self, pen: PenEvent, alt: bool, ctrl: bool, shift: bool, command: bool) {
+        let mut evt = PointerDeviceEvent::new();
+        evt.set_pen_event(pen);
+        send_pointer_device_event(evt, alt, ctrl, shift, command, self);
+    }
+
     pub fn send_mouse(
         &self,
         mask: i32,
@@ -1039,9 +1127,12 @@ impl<T: InvokeUiSession> Interface for Session<T> {
     }
 
     fn send(&self, data: Data) {
-        if let Some(sender) = self.sender.read().unwrap().as_ref() {
-            sender.send(data).ok();
+        if let Data::Message(msg) = &data {
+            if let Some(recorder) = self.macro_recorder.lock().unwrap().as_mut() {
+                recorder.record(msg);
+            }
         }
+        self.send_unrecorded(data);
     }
 
     fn msgbox(&self, msgtype: &str, title: &str, text: &str, link: &str) {